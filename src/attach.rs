@@ -0,0 +1,317 @@
+//! Show the windows of a termui already running elsewhere (`--attach`),
+//! without starting a compositor
+//!
+//! We connect to its socket as a client, capture its output with
+//! wlr-screencopy and draw each new frame with Kitty graphics. Any
+//! compositor offering wlr-screencopy will do. A socket nobody listens on
+//! is an error; the compositor going away later ends the session like the
+//! quit keys do.
+
+use crate::cli::Options;
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::fs::FileExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use termui::terminal::{KittyGraphics, RenderBackend, TerminalInput, WaylandInputEvent};
+use tracing::info;
+use wayland_client::{
+    backend::WaylandError,
+    delegate_noop,
+    protocol::{wl_buffer, wl_output, wl_registry, wl_shm, wl_shm_pool},
+    Connection, Dispatch, EventQueue, QueueHandle,
+};
+use wayland_protocols_wlr::screencopy::v1::client::{zwlr_screencopy_frame_v1, zwlr_screencopy_manager_v1};
+
+/// Buffer formats `ShmBuffer::read_rgba()` understands; termui offers the first
+const READABLE_FORMATS: [wl_shm::Format; 4] =
+    [wl_shm::Format::Abgr8888, wl_shm::Format::Xbgr8888, wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888];
+
+/// How long to wait for the compositor before looking at the terminal's input again
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// View the output of the compositor at `socket`, a name in
+/// `XDG_RUNTIME_DIR` like `WAYLAND_DISPLAY`, or a path
+pub fn run(socket: &str, options: &Options) -> Result<()> {
+    let path = socket_path(socket)?;
+    let stream = UnixStream::connect(&path)
+        .map_err(|e| anyhow!("No compositor to attach to at {}: {}", path.display(), e))?;
+    let conn = Connection::from_socket(stream)
+        .map_err(|e| anyhow!("Failed to connect to {}: {}", path.display(), e))?;
+    let mut queue = conn.new_event_queue();
+    let _registry = conn.display().get_registry(&queue.handle(), ());
+    let mut viewer = Viewer::default();
+    queue.roundtrip(&mut viewer).map_err(|e| anyhow!("Failed to list the globals of {}: {}", path.display(), e))?;
+    let missing = |global| anyhow!("{} has no {}, so it can't be attached to", path.display(), global);
+    let shm = viewer.shm.clone().ok_or_else(|| missing("wl_shm"))?;
+    let output = viewer.output.clone().ok_or_else(|| missing("wl_output"))?;
+    let screencopy = viewer.screencopy.clone().ok_or_else(|| missing("zwlr_screencopy_manager_v1"))?;
+    info!("Attached to {}", path.display());
+
+    let mut kitty = KittyGraphics::new();
+    kitty.set_full_refresh(options.full_refresh);
+    kitty.set_compression(options.compression);
+    kitty.set_scale_mode(options.scale_quality);
+    let headless = options.headless;
+    if !headless {
+        kitty.setup_terminal()?;
+        TerminalInput::enable_mouse_capture()?;
+    }
+
+    let capture = Capture { conn: &conn, shm, output, screencopy };
+    let result = view(&capture, &mut queue, &mut viewer, &mut kitty, headless);
+
+    if !headless {
+        TerminalInput::disable_mouse_capture()?;
+        kitty.restore_terminal()?;
+    }
+
+    if let Ended::CompositorGone = result? {
+        info!("{} went away, detaching", path.display());
+    }
+    Ok(())
+}
+
+/// Why viewing stopped
+enum Ended {
+    QuitKeys,
+    CompositorGone,
+}
+
+/// Where the socket called `socket` is, the way clients find `WAYLAND_DISPLAY`
+fn socket_path(socket: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(socket);
+    if path.is_absolute() {
+        return Ok(path);
+    }
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .ok_or_else(|| anyhow!("XDG_RUNTIME_DIR isn't set; give --attach the socket's full path"))?;
+    Ok(PathBuf::from(runtime_dir).join(path))
+}
+
+/// The globals of the compositor attached to
+struct Capture<'a> {
+    conn: &'a Connection,
+    shm: wl_shm::WlShm,
+    output: wl_output::WlOutput,
+    screencopy: zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+}
+
+/// Show frames until the quit keys or the compositor going away
+fn view(
+    capture: &Capture,
+    queue: &mut EventQueue<Viewer>,
+    viewer: &mut Viewer,
+    kitty: &mut KittyGraphics,
+    headless: bool,
+) -> Result<Ended> {
+    let qh = queue.handle();
+    // Only used to recognize the quit chords
+    let mut term_input = TerminalInput::new(1, 1, 1, 1);
+    let mut buffer: Option<ShmBuffer> = None;
+    // The first frame is whatever is on screen; the ones after wait for changes
+    let mut first = true;
+
+    loop {
+        viewer.frame = FrameState::default();
+        let frame = capture.screencopy.capture_output(0, &capture.output, &qh, ());
+        let copied = loop {
+            match viewer.frame {
+                FrameState { failed: true, .. } => break false,
+                FrameState { ready: true, .. } => break true,
+                FrameState { buffer: None, buffer_done: true, .. } => {
+                    return Err(anyhow!("The compositor offers screen copies in no format we can read"));
+                }
+                FrameState { buffer: Some(format), buffer_done: true, copying: false, .. } => {
+                    if buffer.as_ref().is_none_or(|buffer| buffer.format != format) {
+                        buffer = Some(ShmBuffer::new(&capture.shm, format, &qh)?);
+                    }
+                    let buffer = &buffer.as_ref().unwrap().buffer;
+                    if first {
+                        frame.copy(buffer);
+                    } else {
+                        frame.copy_with_damage(buffer);
+                    }
+                    viewer.frame.copying = true;
+                }
+                _ => {}
+            }
+            if !dispatch(capture.conn, queue, viewer)? {
+                return Ok(Ended::CompositorGone);
+            }
+            if !headless && quit_requested(&mut term_input)? {
+                return Ok(Ended::QuitKeys);
+            }
+        };
+        frame.destroy();
+
+        // A failed copy is retried, with the output's new size if it changed
+        if copied {
+            let buffer = buffer.as_ref().unwrap();
+            let (rgba, opaque) = buffer.read_rgba()?;
+            kitty.display_frame(buffer.format.width, buffer.format.height, &rgba, opaque, &[])?;
+            first = false;
+        }
+    }
+}
+
+/// Wait a while for events from the compositor and handle them; false
+/// once the connection is gone
+fn dispatch(conn: &Connection, queue: &mut EventQueue<Viewer>, viewer: &mut Viewer) -> Result<bool> {
+    let gone = |e: &WaylandError| matches!(e, WaylandError::Io(e) if e.kind() != std::io::ErrorKind::WouldBlock);
+    match conn.flush() {
+        Err(e) if gone(&e) => return Ok(false),
+        Err(WaylandError::Protocol(e)) => return Err(anyhow!("Lost the connection to the compositor: {}", e)),
+        // Still sending; the rest goes with the next flush
+        _ => {}
+    }
+    if let Some(guard) = queue.prepare_read() {
+        let mut fds = [libc::pollfd { fd: guard.connection_fd().as_raw_fd(), events: libc::POLLIN, revents: 0 }];
+        // Safety: `fds` outlives the call
+        unsafe { libc::poll(fds.as_mut_ptr(), 1, POLL_INTERVAL.as_millis() as i32) };
+        match guard.read() {
+            Ok(_) => {}
+            Err(WaylandError::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) if gone(&e) => return Ok(false),
+            Err(e) => return Err(anyhow!("Lost the connection to the compositor: {}", e)),
+        }
+    }
+    queue.dispatch_pending(viewer).map_err(|e| anyhow!("Failed to handle the compositor's events: {}", e))?;
+    Ok(true)
+}
+
+/// Whether Ctrl+C or Ctrl+Q was pressed
+fn quit_requested(term_input: &mut TerminalInput) -> Result<bool> {
+    while let Some(event) = TerminalInput::poll_event(Duration::ZERO)? {
+        if term_input.translate_event(event).iter().any(|e| matches!(e, WaylandInputEvent::Quit)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[derive(Default)]
+struct Viewer {
+    shm: Option<wl_shm::WlShm>,
+    output: Option<wl_output::WlOutput>,
+    screencopy: Option<zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1>,
+    frame: FrameState,
+}
+
+/// Progress of the frame being captured
+#[derive(Default)]
+struct FrameState {
+    /// The first buffer layout offered that we can read
+    buffer: Option<BufferFormat>,
+    buffer_done: bool,
+    copying: bool,
+    ready: bool,
+    failed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct BufferFormat {
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+/// An shm buffer for copies, read back through its memfd
+struct ShmBuffer {
+    format: BufferFormat,
+    pool: wl_shm_pool::WlShmPool,
+    buffer: wl_buffer::WlBuffer,
+    file: File,
+}
+
+impl ShmBuffer {
+    fn new(shm: &wl_shm::WlShm, format: BufferFormat, qh: &QueueHandle<Viewer>) -> Result<Self> {
+        let len = format.stride * format.height;
+        let file = File::from(rustix::fs::memfd_create("termui-attach", rustix::fs::MemfdFlags::CLOEXEC)?);
+        file.set_len(len as u64)?;
+        let pool = shm.create_pool(file.as_fd(), len as i32, qh, ());
+        let (width, height, stride) = (format.width as i32, format.height as i32, format.stride as i32);
+        let buffer = pool.create_buffer(0, width, height, stride, format.format, qh, ());
+        Ok(Self { format, pool, buffer, file })
+    }
+
+    /// The copy as RGBA, and whether its format has no alpha
+    fn read_rgba(&self) -> Result<(Vec<u8>, bool)> {
+        let BufferFormat { format, width, height, stride } = self.format;
+        let mut data = vec![0; (stride * height) as usize];
+        self.file.read_exact_at(&mut data, 0)?;
+        let opaque = matches!(format, wl_shm::Format::Xbgr8888 | wl_shm::Format::Xrgb8888);
+        let bgr = matches!(format, wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888);
+        let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+        for row in data.chunks_exact(stride as usize) {
+            for pixel in row[..(width * 4) as usize].chunks_exact(4) {
+                let [r, g, b] = if bgr { [pixel[2], pixel[1], pixel[0]] } else { [pixel[0], pixel[1], pixel[2]] };
+                rgba.extend_from_slice(&[r, g, b, if opaque { 0xff } else { pixel[3] }]);
+            }
+        }
+        Ok((rgba, opaque))
+    }
+}
+
+impl Drop for ShmBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        self.pool.destroy();
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for Viewer {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global { name, interface, version } = event {
+            match interface.as_str() {
+                "wl_shm" => state.shm = Some(registry.bind(name, 1, qh, ())),
+                // The first output; termui has just the one
+                "wl_output" if state.output.is_none() => state.output = Some(registry.bind(name, 1, qh, ())),
+                "zwlr_screencopy_manager_v1" if version >= 3 => {
+                    state.screencopy = Some(registry.bind(name, 3, qh, ()));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Dispatch<zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1, ()> for Viewer {
+    fn event(
+        state: &mut Self,
+        _: &zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let frame = &mut state.frame;
+        match event {
+            zwlr_screencopy_frame_v1::Event::Buffer { format, width, height, stride } => {
+                if let Some(format) = format.into_result().ok().filter(|format| READABLE_FORMATS.contains(format)) {
+                    frame.buffer.get_or_insert(BufferFormat { format, width, height, stride });
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::BufferDone => frame.buffer_done = true,
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => frame.ready = true,
+            zwlr_screencopy_frame_v1::Event::Failed => frame.failed = true,
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(Viewer: ignore wl_shm::WlShm);
+delegate_noop!(Viewer: ignore wl_shm_pool::WlShmPool);
+delegate_noop!(Viewer: ignore wl_buffer::WlBuffer);
+delegate_noop!(Viewer: ignore wl_output::WlOutput);
+delegate_noop!(Viewer: ignore zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1);
@@ -168,10 +168,12 @@ impl Dispatch<wl_registry::WlRegistry, ()> for State {
         _: &Connection,
         qh: &QueueHandle<Self>,
     ) {
-        if let wl_registry::Event::Global { name, interface, .. } = event {
+        if let wl_registry::Event::Global { name, interface, version } = event {
             match interface.as_str() {
                 "wl_compositor" => {
-                    let compositor = registry.bind::<wl_compositor::WlCompositor, _, _>(name, 4, qh, ());
+                    // v6 for preferred_buffer_scale/transform
+                    let compositor =
+                        registry.bind::<wl_compositor::WlCompositor, _, _>(name, version.min(6), qh, ());
                     state.compositor = Some(compositor);
                 }
                 "wl_shm" => {
@@ -204,11 +206,20 @@ impl Dispatch<wl_surface::WlSurface, ()> for State {
     fn event(
         _: &mut Self,
         _: &wl_surface::WlSurface,
-        _: wl_surface::Event,
+        event: wl_surface::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
+        match event {
+            wl_surface::Event::PreferredBufferScale { factor } => {
+                println!("Preferred buffer scale: {}", factor);
+            }
+            wl_surface::Event::PreferredBufferTransform { transform } => {
+                println!("Preferred buffer transform: {:?}", transform);
+            }
+            _ => {}
+        }
     }
 }
 
@@ -293,12 +304,10 @@ impl Dispatch<xdg_toplevel::XdgToplevel, ()> for State {
         _: &QueueHandle<Self>,
     ) {
         match event {
-            xdg_toplevel::Event::Configure { width, height, .. } => {
-                if width > 0 && height > 0 {
-                    println!("Toplevel configured: {}x{}", width, height);
-                    state.width = width as u32;
-                    state.height = height as u32;
-                }
+            xdg_toplevel::Event::Configure { width, height, .. } if width > 0 && height > 0 => {
+                println!("Toplevel configured: {}x{}", width, height);
+                state.width = width as u32;
+                state.height = height as u32;
             }
             xdg_toplevel::Event::Close => {
                 println!("Close requested");
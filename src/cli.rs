@@ -0,0 +1,521 @@
+use anyhow::{anyhow, bail, Result};
+use smithay::output::Subpixel;
+use smithay::utils::Transform;
+use std::path::PathBuf;
+use std::time::Duration;
+use termui::compositor::cursor::CursorMode;
+use termui::compositor::ExitPolicy;
+use termui::terminal::{Backend, CellSize, Compression, FullRefresh, KeyChord, ScaleMode, ScrollSpeed, ZoomKeys};
+
+/// Terminal pixels per output pixel unless `--scale` or `TERMUI_SCALE` say otherwise
+const DEFAULT_SCALE: u32 = 4;
+/// Largest `--scale`; beyond it even small terminals leave apps no room
+const MAX_SCALE: u32 = 8;
+
+/// What triggers sending frames to the terminal (`--render-mode`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Check for a new frame at a fixed interval
+    Timer,
+    /// Send each frame as soon as it is captured, rate-limited to the same interval
+    OnDamage,
+}
+
+impl std::str::FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "timer" => Ok(RenderMode::Timer),
+            "on-damage" => Ok(RenderMode::OnDamage),
+            _ => Err(format!("expected timer or on-damage, got {:?}", s)),
+        }
+    }
+}
+
+/// Where the pointer starts (`--initial-pointer`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointerStart {
+    Center,
+    /// Output coordinates
+    At(f64, f64),
+}
+
+impl std::str::FromStr for PointerStart {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "center" {
+            return Ok(PointerStart::Center);
+        }
+        s.split_once(',')
+            .and_then(|(x, y)| Some(PointerStart::At(x.parse().ok()?, y.parse().ok()?)))
+            .filter(|&start| matches!(start, PointerStart::At(x, y) if x >= 0.0 && y >= 0.0))
+            .ok_or_else(|| format!("expected X,Y or center, got {:?}", s))
+    }
+}
+
+/// Command line options, parsed from the flags preceding the command
+pub struct Options {
+    /// Run without terminal graphics (for testing)
+    pub headless: bool,
+    /// Wait up to this long for the client's first frame before drawing
+    pub wait_first_frame: Option<Duration>,
+    /// Address to serve the debug HTTP endpoint on
+    pub debug_http: Option<String>,
+    /// Directory of numbered PNG frames to replay instead of running a command
+    pub replay: Option<PathBuf>,
+    /// Playback rate for --replay
+    pub replay_fps: u32,
+    /// Restart --replay from the first frame after the last one
+    pub replay_loop: bool,
+    /// Socket of a compositor to show the windows of instead of running a command
+    pub attach: Option<String>,
+    /// Periodically wipe and fully retransmit the terminal image
+    pub full_refresh: Option<FullRefresh>,
+    /// Center frames at the largest whole-number scale that fits
+    pub integer_scale: bool,
+    /// How frames are compressed for the terminal
+    pub compression: Compression,
+    /// How frames are resized to fit the terminal
+    pub scale_quality: ScaleMode,
+    /// Graphics protocol to send frames with; detected when `None`
+    pub backend: Option<Backend>,
+    /// What triggers sending frames to the terminal
+    pub render_mode: RenderMode,
+    /// Terminal pixels per output pixel; higher makes the app larger
+    pub scale: u32,
+    /// Output rotation
+    pub rotate: Transform,
+    /// Show only the non-transparent part of the window
+    pub crop_to_content: bool,
+    /// Log per-frame span timings
+    pub profile: bool,
+    /// Write a Chrome trace of the profiling spans to this file
+    #[cfg_attr(not(feature = "profile"), allow(dead_code))]
+    pub profile_trace: Option<PathBuf>,
+    /// Periodically log frame pipeline statistics
+    pub stats: bool,
+    /// Only serve the socket; clients are started by the user
+    pub no_spawn: bool,
+    /// Print the socket's `WAYLAND_DISPLAY` and `XDG_RUNTIME_DIR` to stderr
+    pub print_socket: bool,
+    /// What ends the session
+    pub exit_on: ExitPolicy,
+    /// Input script to run against the app once its first window appears
+    pub script: Option<PathBuf>,
+    /// Exit after this long without input or commits
+    pub idle_exit: Option<Duration>,
+    /// Chord that asks the focused window to close
+    pub close_key: KeyChord,
+    /// Chord that switches to the next graphics protocol; `None` disables it
+    pub backend_key: Option<KeyChord>,
+    /// Modifiers of the zoom/pan bindings; `None` disables them
+    pub zoom_keys: Option<ZoomKeys>,
+    /// How far a wheel notch scrolls, relative to the usual distance
+    pub scroll_speed: ScrollSpeed,
+    /// Cell size to size the terminal from when it doesn't report its pixel size
+    pub cell_size: CellSize,
+    /// Refuse connections beyond this many clients
+    pub max_clients: usize,
+    /// Wait for the terminal to acknowledge each frame before sending the next
+    pub ack_pacing: bool,
+    /// With `ack_pacing`, how many frames may await acknowledgement before captures are skipped
+    pub max_frames_behind: usize,
+    /// Have the terminal answer every frame, log its errors and fall back when they repeat
+    pub graphics_diagnostics: bool,
+    /// Save images copied to the clipboard into this directory
+    pub clipboard_image_dir: Option<PathBuf>,
+    /// Copy text the app puts on the clipboard to the terminal's clipboard with OSC 52
+    pub clipboard_osc52: bool,
+    /// Leave window decorations to the apps instead of drawing title bars
+    pub no_decorations: bool,
+    /// Leave the terminal's title alone instead of showing the focused window's
+    pub no_terminal_title: bool,
+    /// Also show frames in a window on the host Wayland desktop
+    pub mirror: bool,
+    /// Save the frames shown into this directory
+    pub record: Option<PathBuf>,
+    /// Most frames a second `record` saves
+    pub record_fps: u32,
+    /// How the pointer is shown
+    pub cursor: CursorMode,
+    /// Where the pointer enters the first window; by default it stays out until the mouse moves
+    pub initial_pointer: Option<PointerStart>,
+    /// Blink the software cursor, toggling it this often
+    pub cursor_blink: Option<Duration>,
+    /// Physical size of the output in millimeters, reported to clients for DPI
+    pub physical_size: Option<(u32, u32)>,
+    /// Subpixel layout reported to clients
+    pub subpixel: Subpixel,
+    /// How long the terminal size must hold still before apps are resized; zero for live resizing
+    pub resize_debounce: Duration,
+    /// Key repeat delay in milliseconds and rate in keys per second, for held keys
+    pub key_repeat: (i32, i32),
+    /// Command and arguments to run under the compositor
+    pub command: Vec<String>,
+}
+
+impl Options {
+    /// Parse options from the program arguments (without argv[0])
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = args.into_iter().peekable();
+        let mut options = Options {
+            headless: false,
+            wait_first_frame: None,
+            debug_http: None,
+            replay: None,
+            replay_fps: 30,
+            replay_loop: false,
+            attach: None,
+            full_refresh: None,
+            integer_scale: false,
+            compression: Compression::None,
+            scale_quality: ScaleMode::Bilinear,
+            backend: None,
+            render_mode: RenderMode::Timer,
+            scale: DEFAULT_SCALE,
+            rotate: Transform::Normal,
+            crop_to_content: false,
+            profile: false,
+            profile_trace: None,
+            stats: false,
+            no_spawn: false,
+            print_socket: false,
+            exit_on: ExitPolicy::LastWindow,
+            script: None,
+            idle_exit: None,
+            close_key: "ctrl+alt+w".parse().unwrap(),
+            backend_key: Some("ctrl+alt+b".parse().unwrap()),
+            zoom_keys: Some("ctrl+alt".parse().unwrap()),
+            scroll_speed: ScrollSpeed::default(),
+            cell_size: CellSize::default(),
+            max_clients: 64,
+            physical_size: None,
+            subpixel: Subpixel::Unknown,
+            key_repeat: (200, 25),
+            resize_debounce: Duration::from_millis(100),
+            cursor: CursorMode::Software,
+            initial_pointer: None,
+            cursor_blink: None,
+            mirror: false,
+            record: None,
+            record_fps: 10,
+            ack_pacing: false,
+            max_frames_behind: 1,
+            graphics_diagnostics: false,
+            clipboard_image_dir: None,
+            clipboard_osc52: false,
+            no_decorations: false,
+            no_terminal_title: false,
+            command: Vec::new(),
+        };
+
+        let mut scale = None;
+        let mut cell_size = None;
+        while let Some(arg) = args.next_if(|a| a.starts_with("--")) {
+            match arg.as_str() {
+                "--" => break,
+                "--headless" => options.headless = true,
+                "--wait-first-frame" => {
+                    let ms: u64 = parse_value(&arg, args.next())?;
+                    options.wait_first_frame = Some(Duration::from_millis(ms));
+                }
+                "--debug-http" => {
+                    if !cfg!(feature = "debug-http") {
+                        bail!("--debug-http requires termui to be built with the debug-http feature");
+                    }
+                    options.debug_http = Some(parse_value(&arg, args.next())?);
+                }
+                "--replay" => options.replay = Some(parse_value(&arg, args.next())?),
+                "--replay-fps" => {
+                    options.replay_fps = parse_value(&arg, args.next())?;
+                    if options.replay_fps == 0 {
+                        bail!("--replay-fps must be at least 1");
+                    }
+                }
+                "--replay-loop" => options.replay_loop = true,
+                "--attach" => options.attach = Some(parse_value(&arg, args.next())?),
+                "--full-refresh-interval" => {
+                    options.full_refresh = Some(parse_value(&arg, args.next())?);
+                }
+                "--integer-scale" => options.integer_scale = true,
+                "--compression" => options.compression = parse_value(&arg, args.next())?,
+                "--scale-quality" => options.scale_quality = parse_value(&arg, args.next())?,
+                "--backend" => options.backend = Some(parse_value(&arg, args.next())?),
+                "--render-mode" => options.render_mode = parse_value(&arg, args.next())?,
+                "--scale" => scale = Some(parse_scale(&arg, args.next())?),
+                "--rotate" => {
+                    let degrees: u32 = parse_value(&arg, args.next())?;
+                    options.rotate = match degrees {
+                        0 => Transform::Normal,
+                        90 => Transform::_90,
+                        180 => Transform::_180,
+                        270 => Transform::_270,
+                        _ => bail!("--rotate must be one of 0, 90, 180, 270"),
+                    };
+                }
+                "--crop-to-content" => options.crop_to_content = true,
+                "--profile" => options.profile = true,
+                "--profile-trace" => {
+                    if !cfg!(feature = "profile") {
+                        bail!("--profile-trace requires termui to be built with the profile feature");
+                    }
+                    options.profile = true;
+                    options.profile_trace = Some(parse_value(&arg, args.next())?);
+                }
+                "--stats" => options.stats = true,
+                "--no-spawn" => options.no_spawn = true,
+                "--print-socket" => options.print_socket = true,
+                "--keep-alive" => options.exit_on = ExitPolicy::Never,
+                "--exit-on" => options.exit_on = parse_value(&arg, args.next())?,
+                "--script" => options.script = Some(parse_value(&arg, args.next())?),
+                "--idle-exit" => {
+                    let secs: u64 = parse_value(&arg, args.next())?;
+                    if secs == 0 {
+                        bail!("--idle-exit must be at least 1 second");
+                    }
+                    options.idle_exit = Some(Duration::from_secs(secs));
+                }
+                "--close-key" => options.close_key = parse_value(&arg, args.next())?,
+                "--backend-key" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    options.backend_key = match value.as_str() {
+                        "none" => None,
+                        chord => Some(chord.parse().map_err(|e| anyhow!("Invalid value for --backend-key: {}", e))?),
+                    };
+                }
+                "--zoom-keys" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    options.zoom_keys = match value.as_str() {
+                        "none" => None,
+                        mods => Some(mods.parse().map_err(|e| anyhow!("Invalid value for --zoom-keys: {}", e))?),
+                    };
+                }
+                "--scroll-speed" => options.scroll_speed = parse_value(&arg, args.next())?,
+                "--cell-size" => cell_size = Some(parse_dimensions(&arg, args.next())?),
+                "--force-cell-size" => options.cell_size.forced = true,
+                "--max-clients" => {
+                    options.max_clients = parse_value(&arg, args.next())?;
+                    if options.max_clients == 0 {
+                        bail!("--max-clients must be at least 1");
+                    }
+                }
+                "--ack-pacing" => options.ack_pacing = true,
+                "--max-frames-behind" => {
+                    options.max_frames_behind = parse_value(&arg, args.next())?;
+                    if options.max_frames_behind == 0 {
+                        bail!("--max-frames-behind must be at least 1");
+                    }
+                    options.ack_pacing = true;
+                }
+                "--graphics-diagnostics" => options.graphics_diagnostics = true,
+                "--clipboard-image-dir" => {
+                    options.clipboard_image_dir = Some(parse_value(&arg, args.next())?);
+                }
+                "--clipboard-osc52" => options.clipboard_osc52 = true,
+                "--no-decorations" => options.no_decorations = true,
+                "--no-terminal-title" => options.no_terminal_title = true,
+                "--mirror" => {
+                    if !cfg!(feature = "mirror") {
+                        bail!("--mirror requires termui to be built with the mirror feature");
+                    }
+                    options.mirror = true;
+                }
+                "--record" => options.record = Some(parse_value(&arg, args.next())?),
+                "--record-fps" => {
+                    options.record_fps = parse_value(&arg, args.next())?;
+                    if options.record_fps == 0 {
+                        bail!("--record-fps must be at least 1");
+                    }
+                }
+                "--cursor" => options.cursor = parse_value(&arg, args.next())?,
+                "--initial-pointer" => options.initial_pointer = Some(parse_value(&arg, args.next())?),
+                "--cursor-blink" => {
+                    let ms: u64 = parse_value(&arg, args.next())?;
+                    if ms == 0 {
+                        bail!("--cursor-blink must be at least 1ms");
+                    }
+                    options.cursor_blink = Some(Duration::from_millis(ms));
+                }
+                "--physical-size" => {
+                    options.physical_size = Some(parse_dimensions(&arg, args.next())?);
+                }
+                "--subpixel" => options.subpixel = parse_subpixel(&arg, args.next())?,
+                "--resize-debounce" => {
+                    options.resize_debounce = Duration::from_millis(parse_value(&arg, args.next())?);
+                }
+                "--live-resize" => options.resize_debounce = Duration::ZERO,
+                "--key-repeat" => options.key_repeat = parse_key_repeat(&arg, args.next())?,
+                _ => bail!("Unknown option: {}", arg),
+            }
+        }
+
+        // The flag wins over the environment
+        options.scale = match (scale, std::env::var("TERMUI_SCALE")) {
+            (Some(scale), _) => scale,
+            (None, Ok(value)) => parse_scale("TERMUI_SCALE", Some(value))?,
+            (None, Err(_)) => DEFAULT_SCALE,
+        };
+        // Likewise for the cell size
+        {
+            let cell_size = match (cell_size, std::env::var("TERMUI_CELL_SIZE")) {
+                (Some(size), _) => Some(size),
+                (None, Ok(value)) => Some(parse_dimensions("TERMUI_CELL_SIZE", Some(value))?),
+                (None, Err(_)) => None,
+            };
+            if let Some((width, height)) = cell_size {
+                (options.cell_size.width, options.cell_size.height) = (width, height);
+            }
+        }
+
+        options.command = args.collect();
+        if options.no_spawn && !options.command.is_empty() {
+            bail!("--no-spawn doesn't take a command");
+        }
+        if options.no_spawn && options.exit_on == ExitPolicy::ChildExit {
+            bail!("--exit-on child-exit needs a command to run");
+        }
+        if options.attach.is_some() && (!options.command.is_empty() || options.no_spawn) {
+            bail!("--attach shows another compositor's windows, so it doesn't take a command");
+        }
+        if options.command.is_empty() && options.replay.is_none() && options.attach.is_none() && !options.no_spawn {
+            bail!("No command given");
+        }
+
+        Ok(options)
+    }
+
+    pub fn print_usage() {
+        eprintln!("Usage: termui [options] <command> [args...]");
+        eprintln!("       termui [options] --no-spawn");
+        eprintln!("       termui [options] --replay <dir>");
+        eprintln!("       termui [options] --attach <socket>");
+        eprintln!();
+        eprintln!("Run a graphical Wayland application in the terminal using Kitty graphics protocol.");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --headless              Run without terminal graphics (for testing)");
+        eprintln!("  --wait-first-frame <ms> Wait for the first frame before drawing, up to <ms>");
+        eprintln!("  --debug-http <addr>     Serve /frame.png and /status on <addr> (debug-http feature)");
+        eprintln!("  --replay <dir>          Play back numbered PNG frames from <dir> instead of a command");
+        eprintln!("  --replay-fps <n>        Playback rate for --replay (default 30)");
+        eprintln!("  --replay-loop           Loop --replay instead of stopping at the last frame");
+        eprintln!("  --attach <socket>       Show the windows of the termui serving <socket> (a name in");
+        eprintln!("                          $XDG_RUNTIME_DIR or a path) instead of running a command");
+        eprintln!("  --full-refresh-interval <N|Ns|Nms>");
+        eprintln!("                          Wipe and resend the image every N frames or N seconds/ms");
+        eprintln!("  --integer-scale         Center the app at the largest whole-number scale that fits");
+        eprintln!("  --compression <mode>    Send frames zlib-compressed (zlib) or raw (none, default)");
+        eprintln!("  --scale-quality <mode>  Resize frames that don't fit smoothly (bilinear, default) or fast");
+        eprintln!("                          and sharp (nearest)");
+        eprintln!("  --backend <protocol>    Send frames with Kitty graphics (kitty), Sixel (sixel) or iTerm2");
+        eprintln!("                          inline images (iterm2); by default Kitty or Sixel, whichever the");
+        eprintln!("                          terminal answers to");
+        eprintln!("  --render-mode <mode>    Send frames on a 30 fps timer (default) or on-damage, as soon as");
+        eprintln!("                          the app draws (still at most 30 fps)");
+        eprintln!("  --scale <n>             Show each app pixel as <n>x<n> terminal pixels, 1 to 8 (default 4,");
+        eprintln!("                          or $TERMUI_SCALE)");
+        eprintln!("  --rotate <deg>          Rotate the output by 90, 180 or 270 degrees");
+        eprintln!("  --crop-to-content       Show only the non-transparent part of the window");
+        eprintln!("  --profile               Log capture/scale/display timings");
+        eprintln!("  --profile-trace <file>  Also write a Chrome trace to <file> (profile feature)");
+        eprintln!("  --stats                 Log frame counts and average timings every second");
+        eprintln!("  --no-spawn              Don't run a command; print WAYLAND_DISPLAY for other clients");
+        eprintln!("  --print-socket          Print WAYLAND_DISPLAY and XDG_RUNTIME_DIR to stderr, as --no-spawn does");
+        eprintln!("  --exit-on <event>       Exit on last-window (default), child-exit or never");
+        eprintln!("  --keep-alive            Keep running after the last window closes (--exit-on never)");
+        eprintln!("  --script <file>         Inject the input commands in <file> once the first window appears");
+        eprintln!("  --idle-exit <secs>      Exit after <secs> without input or new frames");
+        eprintln!("  --close-key <chord>     Chord that closes the focused window (default ctrl+alt+w)");
+        eprintln!("  --backend-key <chord>   Chord that switches graphics protocol (default ctrl+alt+b), or none");
+        eprintln!("  --zoom-keys <mods>      Modifiers for zoom (=, -, 0) and pan (arrows); default ctrl+alt, or none");
+        eprintln!("  --max-clients <n>       Refuse connections beyond <n> clients (default 64)");
+        eprintln!("  --physical-size <WxH>   Output size in millimeters reported to clients (default: 96 DPI)");
+        eprintln!("  --subpixel <order>      Subpixel layout reported to clients: unknown (default), none,");
+        eprintln!("                          rgb, bgr, vrgb or vbgr");
+        eprintln!("  --scroll-speed <n|v,h>  Scroll n times as far per wheel notch, or v times vertically and");
+        eprintln!("                          h times horizontally (default 1)");
+        eprintln!("  --cell-size <WxH>       Terminal cell size in pixels, for terminals that don't report their");
+        eprintln!("                          pixel size (default 10x20, or $TERMUI_CELL_SIZE)");
+        eprintln!("  --force-cell-size       Size the terminal from --cell-size even when it reports a pixel size");
+        eprintln!("  --key-repeat <delay,rate>");
+        eprintln!("                          Key repeat for held keys: delay in ms, keys per second");
+        eprintln!("                          (default 200,25; a rate of 0 turns repeat off)");
+        eprintln!("  --resize-debounce <ms>  Resize apps once the terminal size holds still for <ms> (default 100)");
+        eprintln!("  --live-resize           Resize apps on every terminal size change (--resize-debounce 0)");
+        eprintln!("  --ack-pacing            Send a frame only once the terminal acknowledged the last one");
+        eprintln!("  --max-frames-behind <n> Let up to <n> frames await acknowledgement before new ones are");
+        eprintln!("                          skipped (default 1; implies --ack-pacing)");
+        eprintln!("  --graphics-diagnostics  Log the terminal's response to every frame, and send frames");
+        eprintln!("                          uncompressed or smaller when it keeps rejecting them");
+        eprintln!("  --clipboard-image-dir <dir>");
+        eprintln!("                          Save images copied in the app to <dir>");
+        eprintln!("  --clipboard-osc52       Copy text copied in the app to the terminal's clipboard (OSC 52)");
+        eprintln!("  --no-decorations        Let apps draw their own title bars instead of termui");
+        eprintln!("  --no-terminal-title     Don't set the terminal's title to the focused window's");
+        eprintln!("  --cursor <mode>         Show the pointer as a software cursor (default), the terminal's");
+        eprintln!("                          text cursor (text), or not at all (none)");
+        eprintln!("  --initial-pointer <X,Y|center>");
+        eprintln!("                          Put the pointer over the first window at X,Y (output coordinates)");
+        eprintln!("  --cursor-blink <ms>     Blink the software pointer, toggling it every <ms>");
+        eprintln!("  --mirror                Also show frames in a host Wayland window (mirror feature)");
+        eprintln!("  --record <dir>          Save the frames shown as numbered PNGs in <dir>, for --replay");
+        eprintln!("  --record-fps <n>        Most frames a second --record saves (default 10)");
+        eprintln!();
+        eprintln!("Examples:");
+        eprintln!("  termui foot");
+        eprintln!("  termui gtk4-demo");
+        eprintln!("  termui --no-spawn --keep-alive 2>termui.env");
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T> {
+    let value = value.ok_or_else(|| anyhow!("{} requires a value", flag))?;
+    value
+        .parse()
+        .map_err(|_| anyhow!("Invalid value for {}: {}", flag, value))
+}
+
+/// Parse a scale factor, from 1 to `MAX_SCALE`
+fn parse_scale(flag: &str, value: Option<String>) -> Result<u32> {
+    let scale: u32 = parse_value(flag, value)?;
+    if !(1..=MAX_SCALE).contains(&scale) {
+        bail!("{} must be between 1 and {}", flag, MAX_SCALE);
+    }
+    Ok(scale)
+}
+
+/// Parse `DELAY,RATE` key repeat settings
+fn parse_key_repeat(flag: &str, value: Option<String>) -> Result<(i32, i32)> {
+    let value = value.ok_or_else(|| anyhow!("{} requires a value", flag))?;
+    value
+        .split_once(',')
+        .and_then(|(delay, rate)| Some((delay.parse::<u16>().ok()?.into(), rate.parse::<u16>().ok()?.into())))
+        .ok_or_else(|| anyhow!("Invalid value for {}: {} (expected DELAY,RATE)", flag, value))
+}
+
+/// Parse a subpixel layout name
+fn parse_subpixel(flag: &str, value: Option<String>) -> Result<Subpixel> {
+    let value = value.ok_or_else(|| anyhow!("{} requires a value", flag))?;
+    Ok(match value.as_str() {
+        "unknown" => Subpixel::Unknown,
+        "none" => Subpixel::None,
+        "rgb" => Subpixel::HorizontalRgb,
+        "bgr" => Subpixel::HorizontalBgr,
+        "vrgb" => Subpixel::VerticalRgb,
+        "vbgr" => Subpixel::VerticalBgr,
+        _ => bail!("{} must be one of unknown, none, rgb, bgr, vrgb, vbgr", flag),
+    })
+}
+
+/// Parse a `WIDTHxHEIGHT` value with both sides non-zero
+fn parse_dimensions(flag: &str, value: Option<String>) -> Result<(u32, u32)> {
+    let value = value.ok_or_else(|| anyhow!("{} requires a value", flag))?;
+    let (w, h) = value
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+        .filter(|&(w, h)| w > 0 && h > 0)
+        .ok_or_else(|| anyhow!("Invalid value for {}: {} (expected WIDTHxHEIGHT)", flag, value))?;
+    Ok((w, h))
+}
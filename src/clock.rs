@@ -0,0 +1,47 @@
+//! Time source for the timestamps handed to clients
+//!
+//! Frame callbacks and input events carry timestamps. They come from a
+//! [`Clock`] so tests can substitute one that only moves when told to.
+
+use std::time::Duration;
+
+pub trait Clock: Send + Sync {
+    /// Time since a fixed epoch
+    fn now(&self) -> Duration;
+
+    /// Millisecond timestamp as carried by input events (wraps around)
+    fn now_millis(&self) -> u32 {
+        self.now().as_millis() as u32
+    }
+}
+
+/// Wall-clock time since the Unix epoch
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+}
+
+/// A clock that stands still until advanced, for deterministic tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct ManualClock(std::sync::Arc<std::sync::Mutex<Duration>>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,205 @@
+//! The clipboard selection, between clients and the terminal
+//!
+//! When a client sets the selection we look at every MIME type it offers,
+//! pick the most useful one and read it through a pipe watched by the event
+//! loop, so a slow or stuck client never blocks the compositor. Text can
+//! be passed on to the terminal's own clipboard with OSC 52.
+//!
+//! Text pasted into the terminal becomes the selection in turn, written to
+//! clients asking for it the same non-blocking way.
+
+use super::TermuiState;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use smithay::{
+    input::Seat,
+    reexports::calloop::{generic::Generic, Interest, Mode, PostAction},
+    wayland::selection::data_device::{request_data_device_client_selection, set_data_device_selection},
+};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::OwnedFd;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Text types in order of preference; the X11 names are offered by Xwayland clients
+const TEXT_MIME_TYPES: [&str; 5] = ["text/plain;charset=utf-8", "text/plain", "UTF8_STRING", "TEXT", "STRING"];
+
+/// Largest selection we are willing to buffer
+const MAX_SELECTION_BYTES: usize = 32 * 1024 * 1024;
+
+/// Longest base64 payload of an OSC 52 sequence; terminals commonly ignore longer ones
+const MAX_OSC52_PAYLOAD: usize = 100 * 1024;
+
+/// Pick the MIME type to read: text first, then an image if we may save one
+fn choose_mime_type(offered: &[String], save_images: bool) -> Option<String> {
+    let text = TEXT_MIME_TYPES.iter().find_map(|&mime| offered.iter().find(|o| o.as_str() == mime));
+    let image = || offered.iter().find(|o| o.starts_with("image/")).filter(|_| save_images);
+    text.or_else(image).cloned()
+}
+
+/// Start reading a new selection offering `mime_types`
+pub fn read_selection(state: &mut TermuiState, seat: &Seat<TermuiState>, mime_types: Vec<String>) {
+    tracing::debug!("Clipboard offers: {}", mime_types.join(", "));
+
+    let Some(mime_type) = choose_mime_type(&mime_types, state.clipboard_image_dir.is_some()) else {
+        tracing::info!("Clipboard holds no text (offered: {})", mime_types.join(", "));
+        return;
+    };
+
+    let (reader, writer) = match std::io::pipe() {
+        Ok(pipe) => pipe,
+        Err(e) => {
+            tracing::error!("Failed to create clipboard pipe: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = rustix::fs::fcntl_setfl(&reader, rustix::fs::OFlags::NONBLOCK) {
+        tracing::error!("Failed to make clipboard pipe non-blocking: {}", e);
+        return;
+    }
+    if let Err(e) = request_data_device_client_selection(seat, mime_type.clone(), OwnedFd::from(writer)) {
+        tracing::warn!("Failed to request clipboard contents: {:?}", e);
+        return;
+    }
+
+    let mut data = Vec::new();
+    let source = Generic::new(reader, Interest::READ, Mode::Level);
+    let inserted = state.loop_handle.insert_source(source, move |_, reader, state| {
+        let mut chunk = [0u8; 64 * 1024];
+        loop {
+            // Safety: we don't drop or replace the pipe
+            match unsafe { reader.get_mut() }.read(&mut chunk) {
+                Ok(0) => {
+                    selection_received(state, &mime_type, std::mem::take(&mut data));
+                    return Ok(PostAction::Remove);
+                }
+                Ok(n) if data.len() + n > MAX_SELECTION_BYTES => {
+                    tracing::warn!("Clipboard contents larger than {} bytes, ignoring", MAX_SELECTION_BYTES);
+                    return Ok(PostAction::Remove);
+                }
+                Ok(n) => data.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(PostAction::Continue),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    tracing::warn!("Failed to read clipboard contents: {}", e);
+                    return Ok(PostAction::Remove);
+                }
+            }
+        }
+    });
+    if let Err(e) = inserted {
+        tracing::error!("Failed to watch clipboard pipe: {}", e);
+    }
+}
+
+fn selection_received(state: &mut TermuiState, mime_type: &str, data: Vec<u8>) {
+    if !mime_type.starts_with("image/") {
+        let text = String::from_utf8_lossy(&data);
+        tracing::info!("Clipboard: {} characters of {}", text.chars().count(), mime_type);
+        if state.clipboard_osc52 {
+            let mut stdout = std::io::stdout().lock();
+            if let Err(e) = stdout.write_all(osc52(&text).as_bytes()).and_then(|()| stdout.flush()) {
+                tracing::warn!("Failed to copy to the terminal's clipboard: {}", e);
+            }
+        }
+        return;
+    }
+
+    let Some(dir) = &state.clipboard_image_dir else {
+        return;
+    };
+    let path = image_path(dir, mime_type);
+    match std::fs::write(&path, &data) {
+        Ok(()) => tracing::info!("Clipboard image ({}) saved to {}", mime_type, path.display()),
+        Err(e) => tracing::warn!("Failed to save clipboard image to {}: {}", path.display(), e),
+    }
+}
+
+/// Make text pasted into the terminal the selection, for the focused app to paste
+pub fn set_selection(state: &mut TermuiState, text: String) {
+    tracing::info!("Paste: {} characters offered as the clipboard", text.chars().count());
+    let mime_types = TEXT_MIME_TYPES.iter().map(|&mime| mime.to_owned()).collect();
+    set_data_device_selection(&state.display_handle, &state.seat, mime_types, Arc::from(text));
+}
+
+/// Write the selection set by `set_selection()` to a client asking for it as `mime_type`
+pub fn send_selection(state: &mut TermuiState, mime_type: &str, fd: OwnedFd, text: Arc<str>) {
+    if !TEXT_MIME_TYPES.contains(&mime_type) {
+        // Closing the pipe right away leaves the client reading nothing
+        tracing::debug!("Clipboard asked for {}, which isn't offered", mime_type);
+        return;
+    }
+    if let Err(e) = rustix::fs::fcntl_setfl(&fd, rustix::fs::OFlags::NONBLOCK) {
+        tracing::error!("Failed to make clipboard pipe non-blocking: {}", e);
+        return;
+    }
+
+    let mut written = 0;
+    let source = Generic::new(File::from(fd), Interest::WRITE, Mode::Level);
+    let inserted = state.loop_handle.insert_source(source, move |_, writer, _| {
+        while written < text.len() {
+            // Safety: we don't drop or replace the pipe
+            match unsafe { writer.get_mut() }.write(&text.as_bytes()[written..]) {
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(PostAction::Continue),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    tracing::warn!("Failed to write clipboard contents: {}", e);
+                    break;
+                }
+            }
+        }
+        Ok(PostAction::Remove)
+    });
+    if let Err(e) = inserted {
+        tracing::error!("Failed to watch clipboard pipe: {}", e);
+    }
+}
+
+/// OSC 52 sequence putting `text` on the terminal's clipboard, cut short to
+/// fit `MAX_OSC52_PAYLOAD`
+fn osc52(text: &str) -> String {
+    let mut end = text.len().min(MAX_OSC52_PAYLOAD / 4 * 3);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    if end < text.len() {
+        tracing::warn!(
+            "Clipboard text of {} bytes is too long for OSC 52, copying the first {}",
+            text.len(),
+            end
+        );
+    }
+    format!("\x1b]52;c;{}\x07", BASE64.encode(&text[..end]))
+}
+
+/// A fresh file name in `dir` for an image of `mime_type`
+fn image_path(dir: &std::path::Path, mime_type: &str) -> PathBuf {
+    let extension = mime_type.trim_start_matches("image/").split(';').next().unwrap_or("bin");
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    dir.join(format!("clipboard-{}.{}", stamp, extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn osc52_carries_the_text_base64_encoded() {
+        assert_eq!(osc52("hi"), "\x1b]52;c;aGk=\x07");
+    }
+
+    #[test]
+    fn long_osc52_text_is_cut_at_a_character() {
+        let text = "é".repeat(MAX_OSC52_PAYLOAD);
+        let sequence = osc52(&text);
+        let payload = sequence.trim_start_matches("\x1b]52;c;").trim_end_matches('\x07');
+        assert!(payload.len() <= MAX_OSC52_PAYLOAD);
+
+        let copied = String::from_utf8(BASE64.decode(payload).unwrap()).unwrap();
+        assert!(text.starts_with(&copied) && copied.len() > MAX_OSC52_PAYLOAD / 2);
+    }
+}
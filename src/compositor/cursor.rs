@@ -0,0 +1,242 @@
+//! Built-in cursor bitmaps for named cursor shapes
+//!
+//! Clients using cursor-shape-v1 (or the compositor itself) ask for a cursor
+//! by name instead of attaching a surface; these are what we draw for them.
+
+use super::FrameData;
+use smithay::input::pointer::CursorIcon;
+use std::time::{Duration, Instant};
+
+/// How the pointer position is shown (`--cursor`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorMode {
+    /// Cursor images drawn into the frame
+    Software,
+    /// The terminal's own text cursor, moved to the cell under the pointer
+    Text,
+    /// Not at all
+    None,
+}
+
+impl std::str::FromStr for CursorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "software" => Ok(CursorMode::Software),
+            "text" => Ok(CursorMode::Text),
+            "none" => Ok(CursorMode::None),
+            _ => Err(format!("expected software, text or none, got {:?}", s)),
+        }
+    }
+}
+
+/// A two-color cursor image
+pub struct CursorBitmap {
+    /// Rows of `#` (outline), `.` (fill) and ` ` (transparent), all equally wide
+    rows: &'static [&'static str],
+    /// Pixel that sits under the pointer position
+    pub hotspot: (u32, u32),
+}
+
+const OUTLINE: [u8; 4] = [0x00, 0x00, 0x00, 0xff];
+const FILL: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+
+pub const ARROW: CursorBitmap = CursorBitmap {
+    rows: &[
+        "#          ",
+        "##         ",
+        "#.#        ",
+        "#..#       ",
+        "#...#      ",
+        "#....#     ",
+        "#.....#    ",
+        "#......#   ",
+        "#.......#  ",
+        "#........# ",
+        "#.....#####",
+        "#..#..#    ",
+        "#.# #..#   ",
+        "##  #..#   ",
+        "#    #..#  ",
+        "     #..#  ",
+        "      ##   ",
+    ],
+    hotspot: (0, 0),
+};
+
+pub const HAND: CursorBitmap = CursorBitmap {
+    rows: &[
+        "     ##      ",
+        "    #..#     ",
+        "    #..#     ",
+        "    #..#     ",
+        "    #..###   ",
+        "    #..#..## ",
+        " ## #..#..#.#",
+        "#..##.......#",
+        "#...#.......#",
+        " #..........#",
+        "  #.........#",
+        "  #........# ",
+        "   #.......# ",
+        "   #......#  ",
+        "    #.....#  ",
+        "    #######  ",
+    ],
+    hotspot: (5, 0),
+};
+
+pub const BEAM: CursorBitmap = CursorBitmap {
+    rows: &[
+        "### ###",
+        "#..#..#",
+        "###.###",
+        "  #.#  ",
+        "  #.#  ",
+        "  #.#  ",
+        "  #.#  ",
+        "  #.#  ",
+        "  #.#  ",
+        "  #.#  ",
+        "  #.#  ",
+        "  #.#  ",
+        "  #.#  ",
+        "  #.#  ",
+        "###.###",
+        "#..#..#",
+        "### ###",
+    ],
+    hotspot: (3, 8),
+};
+
+pub const HOURGLASS: CursorBitmap = CursorBitmap {
+    rows: &[
+        "###########",
+        "#.........#",
+        " #.......# ",
+        "  #.....#  ",
+        "   #...#   ",
+        "    #.#    ",
+        "     #     ",
+        "    #.#    ",
+        "   #...#   ",
+        "  #.....#  ",
+        " #.......# ",
+        "#.........#",
+        "###########",
+    ],
+    hotspot: (5, 6),
+};
+
+/// The bitmap drawn for a named cursor; shapes without one of their own get the arrow
+pub fn for_icon(icon: CursorIcon) -> &'static CursorBitmap {
+    match icon {
+        CursorIcon::Pointer | CursorIcon::Grab | CursorIcon::Grabbing => &HAND,
+        CursorIcon::Text | CursorIcon::VerticalText => &BEAM,
+        CursorIcon::Wait | CursorIcon::Progress => &HOURGLASS,
+        _ => &ARROW,
+    }
+}
+
+impl CursorBitmap {
+    pub fn width(&self) -> u32 {
+        self.rows[0].len() as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.rows.len() as u32
+    }
+
+    /// Render to RGBA, transparent outside the shape
+    pub fn to_frame(&self) -> FrameData {
+        let data = self
+            .rows
+            .iter()
+            .flat_map(|row| row.bytes())
+            .flat_map(|cell| match cell {
+                b'#' => OUTLINE,
+                b'.' => FILL,
+                _ => [0; 4],
+            })
+            .collect();
+        FrameData { width: self.width(), height: self.height(), data, opaque: false, damage: Vec::new() }
+    }
+}
+
+/// Optional blinking of the software cursor, advanced by the frame timer
+pub struct CursorBlink {
+    /// Time between toggles; `None` keeps the cursor steady
+    interval: Option<Duration>,
+    visible: bool,
+    last_toggle: Instant,
+}
+
+impl CursorBlink {
+    pub fn new(interval: Option<Duration>) -> Self {
+        Self { interval, visible: true, last_toggle: Instant::now() }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Toggle visibility if the interval has passed; returns whether it changed
+    pub fn tick(&mut self, now: Instant) -> bool {
+        match self.interval {
+            Some(interval) if now.duration_since(self.last_toggle) >= interval => {
+                self.visible = !self.visible;
+                self.last_toggle = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Show the cursor for a full interval, so it doesn't vanish while moving
+    ///
+    /// Returns whether it was hidden.
+    pub fn reset(&mut self, now: Instant) -> bool {
+        let was_hidden = !self.visible;
+        self.visible = true;
+        self.last_toggle = now;
+        was_hidden
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitmaps_are_rectangular_with_the_hotspot_inside() {
+        for bitmap in [&ARROW, &HAND, &BEAM, &HOURGLASS] {
+            assert!(bitmap.rows.iter().all(|row| row.len() as u32 == bitmap.width()));
+            let (x, y) = bitmap.hotspot;
+            assert!(x < bitmap.width() && y < bitmap.height());
+
+            let frame = bitmap.to_frame();
+            assert_eq!(frame.data.len() as u32, frame.width * frame.height * 4);
+        }
+    }
+
+    #[test]
+    fn blink_toggles_each_interval_and_motion_resets_it() {
+        let start = Instant::now();
+        let interval = Duration::from_millis(500);
+        let mut blink = CursorBlink { interval: Some(interval), visible: true, last_toggle: start };
+
+        assert!(!blink.tick(start + interval / 2));
+        assert!(blink.tick(start + interval));
+        assert!(!blink.is_visible());
+
+        assert!(blink.reset(start + interval * 3 / 2));
+        assert!(blink.is_visible());
+        assert!(!blink.tick(start + interval * 2));
+        assert!(blink.tick(start + interval * 5 / 2));
+
+        let mut steady = CursorBlink::new(None);
+        assert!(!steady.tick(start + interval * 10));
+        assert!(steady.is_visible());
+    }
+}
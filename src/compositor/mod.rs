@@ -1,4 +1,11 @@
+pub mod clipboard;
+pub mod cursor;
+pub mod font;
 mod handlers;
+pub mod screencopy;
+mod shm_errors;
 mod state;
+pub mod transform;
+pub mod zoom;
 
 pub use state::*;
@@ -0,0 +1,210 @@
+//! wlr-screencopy: copies of the frames sent to the terminal, for
+//! `termui --attach` and screenshot tools like grim
+//!
+//! A copy is the output as composited, before `--rotate` or
+//! `--crop-to-content` apply, in the Abgr8888 layout frames are kept in.
+//! It is filled in with the next frame composited, which `copy` asks for
+//! right away; `copy_with_damage` waits until something changed. Whether
+//! the pointer is in it is up to `--cursor`, not the client.
+
+use super::{FrameData, TermuiState};
+use smithay::{
+    reexports::wayland_server::{
+        backend::ClientId,
+        protocol::{wl_buffer::WlBuffer, wl_shm},
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+    utils::{Buffer, Clock, Monotonic, Rectangle},
+    wayland::shm::{with_buffer_contents, with_buffer_contents_mut},
+};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use wayland_protocols_wlr::screencopy::v1::server::{
+    zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::{self, ZwlrScreencopyManagerV1},
+};
+
+const VERSION: u32 = 3;
+
+/// Screencopy frames waiting to be filled in
+pub struct ScreencopyState {
+    pending: RefCell<Vec<PendingCopy>>,
+    /// Timestamps of ready events, on the clock presentation feedback uses
+    clock: Clock<Monotonic>,
+}
+
+struct PendingCopy {
+    frame: ZwlrScreencopyFrameV1,
+    buffer: WlBuffer,
+    /// Wait for a frame in which something changed
+    with_damage: bool,
+}
+
+/// What a screencopy frame captures, as announced in its buffer event
+pub struct Capture {
+    /// Size of the composited frame when the capture was made; a frame of
+    /// another size fails the copy
+    frame_size: (u32, u32),
+    /// The part of the frame to copy
+    region: Rectangle<i32, Buffer>,
+    /// A copy was asked for; each capture takes one
+    used: AtomicBool,
+}
+
+impl ScreencopyState {
+    pub fn new(display_handle: &DisplayHandle) -> Self {
+        display_handle.create_global::<TermuiState, ZwlrScreencopyManagerV1, _>(VERSION, ());
+        Self { pending: RefCell::new(Vec::new()), clock: Clock::new() }
+    }
+
+    /// Fill in the copies waiting for `frame`, just composited
+    pub fn frame_composited(&self, frame: &FrameData) {
+        let mut pending = self.pending.borrow_mut();
+        if pending.is_empty() {
+            return;
+        }
+        let time = Duration::from(self.clock.now());
+        pending.retain(|copy| {
+            if copy.with_damage && frame.damage.is_empty() {
+                return true;
+            }
+            let capture = copy.frame.data::<Capture>().unwrap();
+            if (frame.width, frame.height) != capture.frame_size || !write_region(&copy.buffer, frame, capture.region) {
+                copy.frame.failed();
+                return false;
+            }
+            copy.frame.flags(zwlr_screencopy_frame_v1::Flags::empty());
+            if copy.with_damage {
+                // Frames only know their damage since the one before, and
+                // the client may have skipped some; all of it is safe
+                copy.frame.damage(0, 0, capture.region.size.w as u32, capture.region.size.h as u32);
+            }
+            let secs = time.as_secs();
+            copy.frame.ready((secs >> 32) as u32, secs as u32, time.subsec_nanos());
+            false
+        });
+    }
+
+    fn forget(&self, frame: &ZwlrScreencopyFrameV1) {
+        self.pending.borrow_mut().retain(|copy| copy.frame != *frame);
+    }
+}
+
+/// Copy `region` of `frame` into the Abgr8888 shm `buffer`; false if it
+/// doesn't fit, or the buffer went away
+fn write_region(buffer: &WlBuffer, frame: &FrameData, region: Rectangle<i32, Buffer>) -> bool {
+    let written = with_buffer_contents_mut(buffer, |ptr, len, data| {
+        let (row_bytes, rows) = (region.size.w as usize * 4, region.size.h as usize);
+        let offset = data.offset as usize;
+        if offset + data.stride as usize * rows > len {
+            return false;
+        }
+        // Safety: the pool holds `len` bytes from `ptr`, and this stays within them
+        let contents = unsafe { std::slice::from_raw_parts_mut(ptr.add(offset), len - offset) };
+        for row in 0..rows {
+            let start = ((region.loc.y as usize + row) * frame.width as usize + region.loc.x as usize) * 4;
+            let target = row * data.stride as usize;
+            contents[target..target + row_bytes].copy_from_slice(&frame.data[start..start + row_bytes]);
+        }
+        true
+    });
+    written.unwrap_or(false)
+}
+
+/// Whether `buffer` is an shm buffer `capture` can be copied into
+fn buffer_fits(buffer: &WlBuffer, capture: &Capture) -> bool {
+    let size = capture.region.size;
+    with_buffer_contents(buffer, |_, _, data| {
+        data.format == wl_shm::Format::Abgr8888
+            && (data.width, data.height) == (size.w, size.h)
+            && data.stride >= size.w * 4
+    })
+    .unwrap_or(false)
+}
+
+impl GlobalDispatch<ZwlrScreencopyManagerV1, ()> for TermuiState {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrScreencopyManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwlrScreencopyManagerV1, ()> for TermuiState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _manager: &ZwlrScreencopyManagerV1,
+        request: zwlr_screencopy_manager_v1::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let (width, height) = state.frame_size();
+        let whole = Rectangle::from_size((width as i32, height as i32).into());
+        let (frame, region) = match request {
+            zwlr_screencopy_manager_v1::Request::CaptureOutput { frame, .. } => (frame, Some(whole)),
+            zwlr_screencopy_manager_v1::Request::CaptureOutputRegion { frame, x, y, width, height, .. } => {
+                let scale = state.buffer_scale.max(1);
+                let asked = Rectangle::new((x * scale, y * scale).into(), (width * scale, height * scale).into());
+                (frame, asked.intersection(whole))
+            }
+            zwlr_screencopy_manager_v1::Request::Destroy => return,
+            _ => unreachable!(),
+        };
+        let region = region.unwrap_or_default();
+        let capture = Capture { frame_size: (width, height), region, used: AtomicBool::new(false) };
+        let frame = data_init.init(frame, capture);
+        if region.is_empty() {
+            frame.failed();
+            return;
+        }
+        let (width, height) = (region.size.w as u32, region.size.h as u32);
+        frame.buffer(wl_shm::Format::Abgr8888, width, height, width * 4);
+        if frame.version() >= 3 {
+            frame.buffer_done();
+        }
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, Capture> for TermuiState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        frame: &ZwlrScreencopyFrameV1,
+        request: zwlr_screencopy_frame_v1::Request,
+        capture: &Capture,
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let (buffer, with_damage) = match request {
+            zwlr_screencopy_frame_v1::Request::Copy { buffer } => (buffer, false),
+            zwlr_screencopy_frame_v1::Request::CopyWithDamage { buffer } => (buffer, true),
+            zwlr_screencopy_frame_v1::Request::Destroy => return,
+            _ => unreachable!(),
+        };
+        if capture.used.swap(true, Ordering::Relaxed) {
+            frame.post_error(zwlr_screencopy_frame_v1::Error::AlreadyUsed, "frame already copied");
+            return;
+        }
+        if !buffer_fits(&buffer, capture) {
+            let message = "expected an Abgr8888 shm buffer of the announced size";
+            frame.post_error(zwlr_screencopy_frame_v1::Error::InvalidBuffer, message);
+            return;
+        }
+        state.screencopy.pending.borrow_mut().push(PendingCopy { frame: frame.clone(), buffer, with_damage });
+        if !with_damage {
+            state.redraw();
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, frame: &ZwlrScreencopyFrameV1, _capture: &Capture) {
+        state.screencopy.forget(frame);
+    }
+}
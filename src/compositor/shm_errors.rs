@@ -0,0 +1,109 @@
+//! Rate-limited reporting of shm and dmabuf buffer access failures
+//!
+//! A client with a broken buffer usually commits it every frame, so each
+//! distinct (client, reason) pair is logged once and then at most every
+//! `REPEAT_INTERVAL`, with a count of the occurrences in between.
+
+use smithay::backend::allocator::Modifier;
+use smithay::wayland::shm::BufferAccessError;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use wayland_server::backend::ClientId;
+use wayland_server::protocol::wl_shm::Format;
+
+const REPEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Why a committed buffer could not be captured
+pub enum ShmError {
+    Access(BufferAccessError),
+    /// The buffer as described by the client doesn't fit its pool
+    OutOfPool { offset: usize, size: usize, pool_len: usize },
+    /// A pixel format we have no conversion for
+    UnsupportedFormat(Format),
+    /// A dmabuf laid out in tiles, or some other way only a GPU can read
+    TiledDmabuf(Modifier),
+    /// A dmabuf that could not be mapped into memory
+    DmabufMapping(String),
+}
+
+impl ShmError {
+    fn kind(&self) -> &'static str {
+        match self {
+            ShmError::Access(BufferAccessError::NotManaged) => "not-shm",
+            ShmError::Access(BufferAccessError::BadMap) => "bad-map",
+            ShmError::Access(BufferAccessError::NotReadable) => "not-readable",
+            ShmError::Access(BufferAccessError::NotWritable) => "not-writable",
+            ShmError::OutOfPool { .. } => "out-of-pool",
+            ShmError::UnsupportedFormat(_) => "unsupported-format",
+            ShmError::TiledDmabuf(_) => "tiled-dmabuf",
+            ShmError::DmabufMapping(_) => "dmabuf-mapping",
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            ShmError::Access(BufferAccessError::NotManaged) => {
+                "buffer is neither a wl_shm buffer nor a dmabuf; only buffers in memory can be shown \
+                 (try LIBGL_ALWAYS_SOFTWARE=1 or the app's software rendering option)"
+                    .into()
+            }
+            ShmError::Access(BufferAccessError::BadMap) => {
+                "shm pool could not be read (bad fd or pool smaller than advertised); \
+                 the client has been disconnected"
+                    .into()
+            }
+            ShmError::Access(e) => format!("buffer access failed: {}", e),
+            ShmError::OutOfPool { offset, size, pool_len } => format!(
+                "buffer at offset {} with {} bytes extends beyond its {}-byte pool",
+                offset, size, pool_len
+            ),
+            ShmError::UnsupportedFormat(format) => {
+                format!("buffer format {:?} is not supported; the frame was skipped", format)
+            }
+            ShmError::TiledDmabuf(modifier) => format!(
+                "dmabuf with modifier {:?} was rejected; only linear dmabufs can be read without a GPU \
+                 (try LIBGL_ALWAYS_SOFTWARE=1 or the app's software rendering option)",
+                modifier
+            ),
+            ShmError::DmabufMapping(e) => format!("dmabuf could not be mapped: {}", e),
+        }
+    }
+}
+
+struct Entry {
+    last_logged: Instant,
+    suppressed: u32,
+}
+
+#[derive(Default)]
+pub struct ShmErrorLog {
+    entries: HashMap<(ClientId, &'static str), Entry>,
+}
+
+impl ShmErrorLog {
+    /// Log `error` for `client` unless the same failure was logged recently
+    pub fn report(&mut self, client: ClientId, pid: Option<i32>, error: &ShmError) {
+        let now = Instant::now();
+        let client_desc = match pid {
+            Some(pid) => format!("{:?} (pid {})", client, pid),
+            None => format!("{:?}", client),
+        };
+
+        match self.entries.get_mut(&(client.clone(), error.kind())) {
+            Some(entry) if now.duration_since(entry.last_logged) < REPEAT_INTERVAL => {
+                entry.suppressed += 1;
+            }
+            Some(entry) => {
+                tracing::warn!(
+                    "Cannot capture frame from client {}: {} ({} similar errors suppressed)",
+                    client_desc, error.describe(), entry.suppressed
+                );
+                *entry = Entry { last_logged: now, suppressed: 0 };
+            }
+            None => {
+                tracing::warn!("Cannot capture frame from client {}: {}", client_desc, error.describe());
+                self.entries.insert((client, error.kind()), Entry { last_logged: now, suppressed: 0 });
+            }
+        }
+    }
+}
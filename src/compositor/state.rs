@@ -1,81 +1,273 @@
+use super::shm_errors::{ShmError, ShmErrorLog};
+use super::{clipboard, cursor, font, screencopy, transform, zoom};
+use crate::clock::{Clock, SystemClock};
+use crate::keymap::KeycodeMap;
+use crate::stats::FrameStats;
+use crate::terminal::{FramePacer, GraphicsDiagnostics, LockState};
 use smithay::{
-    delegate_compositor, delegate_data_device, delegate_output, delegate_seat, delegate_shm,
-    delegate_xdg_shell,
-    desktop::utils::send_frames_surface_tree,
-    input::{keyboard::XkbConfig, pointer::CursorImageStatus, Seat, SeatHandler, SeatState},
+    backend::{
+        allocator::{
+            dmabuf::{Dmabuf, DmabufMappingMode, DmabufSyncFlags},
+            Buffer as _, Format as DmabufFormat, Modifier,
+        },
+        input::KeyState,
+    },
+    delegate_compositor, delegate_cursor_shape, delegate_data_device, delegate_dmabuf, delegate_idle_inhibit,
+    delegate_layer_shell, delegate_output, delegate_presentation, delegate_seat, delegate_shm, delegate_viewporter,
+    delegate_xdg_decoration, delegate_xdg_shell,
+    desktop::{layer_map_for_output, utils::send_frames_surface_tree, LayerSurface, WindowSurfaceType},
+    input::{
+        keyboard::{FilterResult, Keycode, Keysym, LedState, XkbConfig},
+        pointer::{CursorImageStatus, CursorImageSurfaceData, MotionEvent},
+        Seat, SeatHandler, SeatState,
+    },
     output::{Output, PhysicalProperties, Scale, Subpixel},
     reexports::{
-        calloop::LoopSignal,
-        wayland_protocols::xdg::shell::server::xdg_toplevel,
+        calloop::{
+            ping::Ping,
+            timer::{TimeoutAction, Timer},
+            LoopHandle, LoopSignal, RegistrationToken,
+        },
+        wayland_protocols::{
+            wp::presentation_time::server::wp_presentation_feedback,
+            xdg::{
+                decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode as DecorationMode,
+                shell::server::xdg_toplevel,
+            },
+        },
         wayland_server::{
             backend::{ClientData, ClientId, DisconnectReason},
-            protocol::{wl_buffer, wl_seat, wl_surface::WlSurface},
+            protocol::{wl_buffer, wl_output::WlOutput, wl_seat, wl_shm, wl_surface::WlSurface},
             Display, DisplayHandle,
         },
     },
-    utils::{IsAlive, Logical, Point, Size, Transform},
+    utils::{Buffer, IsAlive, Logical, Monotonic, Physical, Point, Rectangle, Size, Transform, SERIAL_COUNTER},
     wayland::{
         buffer::BufferHandler,
         compositor::{
-            get_parent, is_sync_subsurface, with_states, CompositorClientState,
-            CompositorHandler, CompositorState, SurfaceAttributes,
+            get_parent, is_sync_subsurface, send_surface_state, with_states, with_surface_tree_upward,
+            CompositorClientState, CompositorHandler, CompositorState, RectangleKind, RegionAttributes,
+            BufferAssignment, SubsurfaceCachedState, SurfaceAttributes, SurfaceData, TraversalAction,
         },
+        cursor_shape::CursorShapeManagerState,
+        dmabuf::{get_dmabuf, DmabufGlobal, DmabufHandler, DmabufState, ImportNotifier},
+        idle_inhibit::{IdleInhibitHandler, IdleInhibitManagerState},
         output::{OutputHandler, OutputManagerState},
+        presentation::{PresentationFeedbackCachedState, PresentationFeedbackCallback, PresentationState, Refresh},
         selection::{
             data_device::{
-                ClientDndGrabHandler, DataDeviceHandler, DataDeviceState,
+                set_data_device_focus, ClientDndGrabHandler, DataDeviceHandler, DataDeviceState,
                 ServerDndGrabHandler,
             },
-            SelectionHandler,
+            SelectionHandler, SelectionSource, SelectionTarget,
+        },
+        shell::wlr_layer::{
+            KeyboardInteractivity, Layer, LayerSurface as WlrLayerSurface, LayerSurfaceData, WlrLayerShellHandler,
+            WlrLayerShellState,
         },
         shell::xdg::{
-            PopupSurface, PositionerState, ToplevelSurface,
-            XdgShellHandler, XdgShellState,
+            decoration::{XdgDecorationHandler, XdgDecorationState},
+            PopupSurface, PositionerState, SurfaceCachedState, ToplevelSurface,
+            XdgShellHandler, XdgShellState, XdgToplevelSurfaceData,
         },
-        shm::{ShmHandler, ShmState},
+        shm::{fourcc_to_shm_format, shm_format_to_fourcc, ShmHandler, ShmState},
+        tablet_manager::TabletSeatHandler,
+        viewporter::{ensure_viewport_valid, ViewportCachedState, ViewporterState},
     },
 };
-use std::time::Duration;
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::os::fd::OwnedFd;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use wayland_server::Client;
+use wayland_server::{Client, Resource};
 
 pub struct ClientState {
     pub compositor_state: CompositorClientState,
+    /// Shared count of connected clients, decremented when this one disconnects
+    pub live_clients: Arc<AtomicUsize>,
 }
 
 impl ClientData for ClientState {
     fn initialized(&self, _client_id: ClientId) {}
-    fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {}
+    fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {
+        self.live_clients.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 pub struct TermuiState {
     pub display_handle: DisplayHandle,
     pub loop_signal: LoopSignal,
+    pub loop_handle: LoopHandle<'static, TermuiState>,
     pub running: bool,
+    /// What ends the session
+    pub exit_policy: ExitPolicy,
+    /// How the spawned command ended, once it has; termui exits with the same status
+    pub child_status: Option<ExitStatus>,
+    /// When the last input event or surface commit happened, for --idle-exit
+    pub last_activity: Instant,
+    /// Number of currently connected clients
+    pub live_clients: Arc<AtomicUsize>,
+    /// Connections beyond this many clients are refused
+    pub max_clients: usize,
 
     // Smithay state objects
     pub compositor_state: CompositorState,
     pub xdg_shell_state: XdgShellState,
+    #[allow(dead_code)]
+    pub xdg_decoration_state: XdgDecorationState,
     pub shm_state: ShmState,
+    pub dmabuf_state: DmabufState,
     #[allow(dead_code)]
     pub output_manager_state: OutputManagerState,
     pub seat_state: SeatState<Self>,
     pub data_device_state: DataDeviceState,
+    #[allow(dead_code)]
+    pub cursor_shape_manager_state: CursorShapeManagerState,
+    #[allow(dead_code)]
+    pub presentation_state: PresentationState,
+    #[allow(dead_code)]
+    pub viewporter_state: ViewporterState,
+    #[allow(dead_code)]
+    pub idle_inhibit_manager_state: IdleInhibitManagerState,
+    pub layer_shell_state: WlrLayerShellState,
+    pub screencopy: screencopy::ScreencopyState,
 
     pub seat: Seat<Self>,
     pub output: Output,
 
     // Our window tracking
     pub toplevels: Vec<ToplevelSurface>,
+    /// Stacking order of `toplevels` as indices into it, bottom first
+    pub z_order: Vec<usize>,
+    /// Open menus, comboboxes and tooltips, in the order they appeared;
+    /// each is drawn above its window, after the popups opened before it
+    pub popups: Vec<PopupSurface>,
     pub pointer_location: Point<f64, Logical>,
+    /// Where the pointer enters the first window; until then it is on no surface
+    pub initial_pointer: Option<Point<f64, Logical>>,
     pub cursor_status: CursorImageStatus,
+    /// Lock LEDs of the seat keyboard, as last derived from its xkb state
+    pub lock_leds: LedState,
+    /// Where injected keys sit in the seat keyboard's keymap
+    pub keycodes: KeycodeMap,
+    /// Whether the terminal reports key releases; held keys only repeat
+    /// then, since nothing else would stop them
+    pub key_releases: bool,
+    /// Delay (ms) and rate (keys per second) held keys repeat at
+    key_repeat: (i32, i32),
+    /// The key repeating while held, and its repeat timer
+    repeating_key: Option<(Keycode, RegistrationToken)>,
 
     // Frame data for terminal rendering
     pub pending_frame: Arc<Mutex<Option<FrameData>>>,
+    /// Woken whenever a frame is queued, with `--render-mode on-damage`
+    pub frame_ready: Option<Ping>,
+    /// Presentation feedback waiting for the next frame to reach the terminal
+    presentation_feedback: Vec<PresentationFeedbackCallback>,
+    /// Frames sent to the terminal so far, the sequence number in presentation feedback
+    presented_frames: u64,
+    /// Timestamps for presentation feedback, matching the clock advertised to clients
+    monotonic_clock: smithay::utils::Clock<Monotonic>,
 
     // Terminal dimensions
     pub term_width: u32,
     pub term_height: u32,
+    /// Rotation applied to the output, and to frames on their way to the terminal
+    pub output_transform: Transform,
+    /// Buffer scale suggested to clients, so they render at the terminal's pixel density
+    pub buffer_scale: i32,
+    /// Rate limiter for buffer capture failures
+    shm_errors: RefCell<ShmErrorLog>,
+    /// Acknowledgement-based pacing of frames sent to the terminal
+    pub frame_pacer: Option<FramePacer>,
+    /// Watch the terminal's responses to frames for errors (`--graphics-diagnostics`)
+    pub graphics_diagnostics: Option<GraphicsDiagnostics>,
+    /// A commit wasn't captured because the terminal was behind
+    capture_skipped: bool,
+    /// Source of the timestamps sent with frame callbacks
+    pub clock: Arc<dyn Clock>,
+    /// Draw the pointer into frames (`--cursor software`); off by default
+    /// so frames hold only what clients drew
+    pub show_cursor: bool,
+    /// Where the pointer was drawn into the frame composited last
+    last_cursor: Cell<Option<Rectangle<i32, Buffer>>>,
+    /// Blink animation of the software cursor
+    pub cursor_blink: cursor::CursorBlink,
+    /// Magnification of the frame shown in the terminal
+    pub zoom: zoom::Zoom,
+    /// Show only the non-transparent part of the window
+    pub crop_to_content: bool,
+    /// Part of the display the shown frame was cropped to, with `crop_to_content`
+    content_crop: Cell<Option<Rectangle<f64, Logical>>>,
+    /// Windows and popups in the frame composited last, bottom first, and
+    /// where they were; a frame's damage is only meaningful relative to an
+    /// earlier frame of the same layout
+    last_composited: RefCell<Vec<(WlSurface, Point<i32, Logical>)>>,
+    /// Pixel buffers of frames done with, reused by later frames instead
+    /// of allocating new ones
+    frame_buffers: RefCell<Vec<Vec<u8>>>,
+    /// Surfaces with an idle inhibitor, once per inhibitor
+    idle_inhibitors: Vec<WlSurface>,
+    /// Fill for areas no window covers, matching the terminal's background
+    pub background: [u8; 3],
+    /// Height of the title bar drawn above windows, in output coordinates;
+    /// `None` leaves decorations to the windows (`--no-decorations`)
+    pub title_bar: Option<i32>,
+    /// Where images copied to the clipboard are saved, if anywhere
+    pub clipboard_image_dir: Option<PathBuf>,
+    /// Pass copied text on to the terminal's clipboard with OSC 52
+    pub clipboard_osc52: bool,
+    /// Set the terminal's title to the focused window's with OSC 0
+    pub terminal_title: bool,
+    /// Shown in brackets after the window's title in the terminal's
+    title_note: Option<String>,
+    /// Switch to the next graphics protocol before the next frame
+    pub cycle_backend: bool,
+    /// Frame pipeline counters for `--stats`
+    pub stats: RefCell<FrameStats>,
+    /// How long the terminal size must hold still before the output is
+    /// resized; zero resizes on every change
+    pub resize_debounce: Duration,
+    /// Latest size asked for by `request_resize()` and when, until applied
+    pending_resize: Option<(u32, u32, Instant)>,
+}
+
+/// The event that ends the session (`--exit-on`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitPolicy {
+    /// The last window closes
+    LastWindow,
+    /// The spawned command exits, however many windows come and go before
+    ChildExit,
+    /// Nothing; only the quit keys end the session
+    Never,
+}
+
+impl std::str::FromStr for ExitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "last-window" => Ok(ExitPolicy::LastWindow),
+            "child-exit" => Ok(ExitPolicy::ChildExit),
+            "never" => Ok(ExitPolicy::Never),
+            _ => Err(format!("expected last-window, child-exit or never, got {:?}", s)),
+        }
+    }
+}
+
+/// What the output reports about itself in the wl_output geometry event
+pub struct OutputIdentity {
+    /// Physical size in millimeters, from which clients work out the DPI
+    pub physical_size: (u32, u32),
+    /// Subpixel layout, used by some clients for font hinting
+    pub subpixel: Subpixel,
+    pub make: String,
+    pub model: String,
 }
 
 #[derive(Clone)]
@@ -83,23 +275,80 @@ pub struct FrameData {
     pub width: u32,
     pub height: u32,
     pub data: Vec<u8>, // RGBA
+    /// Every pixel is fully opaque, so the alpha bytes can be dropped on the wire
+    pub opaque: bool,
+    /// Parts that changed since the frame queued before this one; empty if
+    /// that isn't known, meaning all of it
+    pub damage: Vec<Rectangle<i32, Buffer>>,
+}
+
+impl FrameData {
+    /// A flat "Loading ..." frame shown until the client draws for real
+    pub fn placeholder(width: u32, height: u32, text: &str, background: [u8; 3]) -> Self {
+        let [r, g, b] = background;
+        let mut frame = FrameData {
+            width,
+            height,
+            data: [r, g, b, 0xff].repeat((width * height) as usize),
+            opaque: true,
+            damage: Vec::new(),
+        };
+        // Grey text, darker on light backgrounds
+        let luma = (r as u32 * 3 + g as u32 * 6 + b as u32) / 10;
+        let shade = if luma > 0x80 { 0x40 } else { 0xc0 };
+
+        // Largest integer scale (up to 4x) that keeps the text within 80% of the width
+        let scale = (1..=4)
+            .rev()
+            .find(|&s| font::text_width(text, s) * 5 <= width * 4)
+            .unwrap_or(1);
+        let x = (width as i32 - font::text_width(text, scale) as i32) / 2;
+        let y = (height as i32 - (font::GLYPH_HEIGHT * scale) as i32) / 2;
+        font::draw_text(&mut frame, x, y, text, [shade, shade, shade, 0xff], scale);
+
+        frame
+    }
+
+    /// Take on the damage of `earlier`, a frame this one replaces before it
+    /// was sent, so the changes in both are covered
+    pub fn add_damage_of(&mut self, earlier: &FrameData) {
+        if self.damage.is_empty() || earlier.damage.is_empty() || (self.width, self.height) != (earlier.width, earlier.height) {
+            self.damage.clear();
+        } else {
+            self.damage.extend_from_slice(&earlier.damage);
+        }
+    }
 }
 
 impl TermuiState {
     pub fn new(
         display: &Display<Self>,
         loop_signal: LoopSignal,
+        loop_handle: LoopHandle<'static, TermuiState>,
         term_width: u32,
         term_height: u32,
+        output_transform: Transform,
+        identity: OutputIdentity,
     ) -> Self {
         let display_handle = display.handle();
 
-        let compositor_state = CompositorState::new::<Self>(&display_handle);
+        let compositor_state = CompositorState::new_v6::<Self>(&display_handle);
         let xdg_shell_state = XdgShellState::new::<Self>(&display_handle);
-        let shm_state = ShmState::new::<Self>(&display_handle, vec![]);
+        let xdg_decoration_state = XdgDecorationState::new::<Self>(&display_handle);
+        // Argb8888 and Xrgb8888 are always advertised
+        let shm_state = ShmState::new::<Self>(&display_handle, EXTRA_SHM_FORMATS.to_vec());
+        // The same formats as dmabufs, linear only, since they are read like shm
+        let mut dmabuf_state = DmabufState::new();
+        dmabuf_state.create_global::<Self>(&display_handle, dmabuf_formats());
         let output_manager_state = OutputManagerState::new_with_xdg_output::<Self>(&display_handle);
         let mut seat_state = SeatState::new();
         let data_device_state = DataDeviceState::new::<Self>(&display_handle);
+        let cursor_shape_manager_state = CursorShapeManagerState::new::<Self>(&display_handle);
+        let presentation_state = PresentationState::new::<Self>(&display_handle, libc::CLOCK_MONOTONIC as u32);
+        let viewporter_state = ViewporterState::new::<Self>(&display_handle);
+        let idle_inhibit_manager_state = IdleInhibitManagerState::new::<Self>(&display_handle);
+        let layer_shell_state = WlrLayerShellState::new::<Self>(&display_handle);
+        let screencopy = screencopy::ScreencopyState::new(&display_handle);
 
         // Create seat with keyboard and pointer
         let mut seat = seat_state.new_wl_seat(&display_handle, "termui");
@@ -110,10 +359,10 @@ impl TermuiState {
         let output = Output::new(
             "TERMUI-1".into(),
             PhysicalProperties {
-                size: Size::from((0, 0)),
-                subpixel: Subpixel::Unknown,
-                make: "termui".into(),
-                model: "virtual".into(),
+                size: Size::from((identity.physical_size.0 as i32, identity.physical_size.1 as i32)),
+                subpixel: identity.subpixel,
+                make: identity.make,
+                model: identity.model,
             },
         );
 
@@ -121,28 +370,249 @@ impl TermuiState {
             size: Size::from((term_width as i32, term_height as i32)),
             refresh: 60_000, // 60 Hz
         };
-        output.change_current_state(Some(mode), Some(Transform::Normal), Some(Scale::Fractional(1.0)), None);
+        output.change_current_state(Some(mode), Some(output_transform), Some(Scale::Fractional(1.0)), None);
         output.set_preferred(mode);
         output.create_global::<Self>(&display_handle);
 
-        Self {
+        let mut state = Self {
             display_handle,
             loop_signal,
+            loop_handle,
             running: true,
+            exit_policy: ExitPolicy::LastWindow,
+            child_status: None,
+            last_activity: Instant::now(),
+            live_clients: Arc::new(AtomicUsize::new(0)),
+            max_clients: usize::MAX,
             compositor_state,
             xdg_shell_state,
+            xdg_decoration_state,
             shm_state,
+            dmabuf_state,
             output_manager_state,
             seat_state,
             data_device_state,
+            cursor_shape_manager_state,
+            presentation_state,
+            viewporter_state,
+            idle_inhibit_manager_state,
+            layer_shell_state,
+            screencopy,
             seat,
             output,
             toplevels: Vec::new(),
+            z_order: Vec::new(),
+            popups: Vec::new(),
             pointer_location: Point::from((0.0, 0.0)),
+            initial_pointer: None,
             cursor_status: CursorImageStatus::default_named(),
+            lock_leds: LedState::default(),
+            key_releases: false,
+            key_repeat: (0, 0),
+            repeating_key: None,
+            keycodes: KeycodeMap::default(),
             pending_frame: Arc::new(Mutex::new(None)),
+            frame_ready: None,
+            presentation_feedback: Vec::new(),
+            presented_frames: 0,
+            monotonic_clock: smithay::utils::Clock::new(),
             term_width,
             term_height,
+            output_transform,
+            buffer_scale: 1,
+            shm_errors: RefCell::new(ShmErrorLog::default()),
+            frame_pacer: None,
+            graphics_diagnostics: None,
+            capture_skipped: false,
+            clock: Arc::new(SystemClock),
+            show_cursor: false,
+            last_cursor: Cell::new(None),
+            cursor_blink: cursor::CursorBlink::new(None),
+            zoom: zoom::Zoom::default(),
+            crop_to_content: false,
+            content_crop: Cell::new(None),
+            last_composited: RefCell::new(Vec::new()),
+            frame_buffers: RefCell::new(Vec::new()),
+            idle_inhibitors: Vec::new(),
+            background: [0, 0, 0],
+            title_bar: None,
+            clipboard_image_dir: None,
+            clipboard_osc52: false,
+            terminal_title: false,
+            title_note: None,
+            cycle_backend: false,
+            stats: RefCell::new(FrameStats::default()),
+            resize_debounce: Duration::ZERO,
+            pending_resize: None,
+        };
+        state.refresh_keycodes();
+        state
+    }
+
+    /// Key repeat delay (ms) and rate (keys per second)
+    ///
+    /// Keyboards bound from now on get these in their repeat_info event,
+    /// and the ones already bound are sent an update. Held keys are
+    /// repeated at the same pace by `start_key_repeat()`.
+    pub fn set_key_repeat(&mut self, delay: i32, rate: i32) {
+        self.seat.get_keyboard().unwrap().change_repeat_info(rate, delay);
+        self.key_repeat = (delay, rate);
+    }
+
+    /// Send a key to the focused window, with the AltGr key `altgr` held around it
+    pub fn send_key(&mut self, keycode: Keycode, altgr: Option<Keycode>, key_state: KeyState, time: u32) {
+        let keyboard = self.seat.get_keyboard().unwrap();
+        let input = |state: &mut Self, keycode, key_state| {
+            let serial = SERIAL_COUNTER.next_serial();
+            keyboard.input::<(), _>(state, keycode, key_state, serial, time, |_, _, _| FilterResult::Forward);
+        };
+        if let Some(altgr) = altgr {
+            input(self, altgr, KeyState::Pressed);
+        }
+        input(self, keycode, key_state);
+        if let Some(altgr) = altgr {
+            input(self, altgr, KeyState::Released);
+        }
+    }
+
+    /// Toggle Caps/Num Lock through xkb until it matches `locks`, the host
+    /// keyboard's as the terminal reports it
+    ///
+    /// The lock key is tapped rather than the modifier mask being set
+    /// directly, so the toggle follows whatever the keymap binds to those
+    /// keys, and the focused client is sent the locked modifiers xkb ends
+    /// up with.
+    pub fn sync_locks(&mut self, locks: LockState, time: u32) {
+        let keyboard = self.seat.get_keyboard().unwrap();
+        let current = keyboard.modifier_state();
+        let toggles = [
+            (current.caps_lock != locks.caps_lock, Keysym::Caps_Lock),
+            (current.num_lock != locks.num_lock, Keysym::Num_Lock),
+        ];
+        for (differs, lock_keysym) in toggles {
+            if !differs {
+                continue;
+            }
+            let Some(keycode) = self.keycodes.keycode(lock_keysym, false) else {
+                tracing::debug!("No key for {:?} in the keymap, so clients can't follow it", lock_keysym);
+                continue;
+            };
+            tracing::debug!("Host keyboard's lock state is {:?}, tapping {:?}", locks, lock_keysym);
+            for key_state in [KeyState::Pressed, KeyState::Released] {
+                let serial = SERIAL_COUNTER.next_serial();
+                keyboard.input::<(), _>(self, keycode, key_state, serial, time, |_, _, _| FilterResult::Forward);
+            }
+        }
+    }
+
+    /// Keep pressing a key that was just pressed until `stop_key_repeat()`
+    ///
+    /// Terminals report a held key once, or without the Kitty keyboard
+    /// protocol as a press per repeat and never a release; in the latter
+    /// case nothing is repeated here.
+    pub fn start_key_repeat(&mut self, keycode: Keycode, altgr: Option<Keycode>) {
+        let keyboard = self.seat.get_keyboard().unwrap();
+        let repeats = keyboard.with_xkb_state(self, |context| {
+            let xkb = context.xkb().lock().unwrap();
+            // SAFETY: the keymap is only borrowed for the lookup
+            unsafe { xkb.keymap() }.key_repeats(keycode)
+        });
+        if !repeats {
+            return; // Modifiers and the like leave the held key repeating
+        }
+        self.stop_key_repeat(None);
+        let (delay, rate) = self.key_repeat;
+        if !self.key_releases || rate <= 0 {
+            return;
+        }
+
+        let interval = Duration::from_secs(1) / rate as u32;
+        let timer = Timer::from_duration(Duration::from_millis(delay.max(0) as u64));
+        let inserted = self.loop_handle.insert_source(timer, move |_, _, state| {
+            let time = state.clock.now_millis();
+            state.send_key(keycode, altgr, KeyState::Pressed, time);
+            let _ = state.display_handle.flush_clients();
+            TimeoutAction::ToDuration(interval)
+        });
+        match inserted {
+            Ok(token) => self.repeating_key = Some((keycode, token)),
+            Err(e) => tracing::error!("Failed to add key repeat timer to event loop: {}", e),
+        }
+    }
+
+    /// Stop repeating `released`, or whichever key repeats if `None`
+    pub fn stop_key_repeat(&mut self, released: Option<Keycode>) {
+        let Some((keycode, token)) = self.repeating_key else {
+            return;
+        };
+        if released.is_none_or(|released| released == keycode) {
+            self.loop_handle.remove(token);
+            self.repeating_key = None;
+        }
+    }
+
+    /// Re-index the seat keyboard's keymap, after it was created or replaced
+    pub fn refresh_keycodes(&mut self) {
+        let keyboard = self.seat.get_keyboard().unwrap();
+        self.keycodes = keyboard.with_xkb_state(self, |context| {
+            let xkb = context.xkb().lock().unwrap();
+            // SAFETY: the keymap is only borrowed while it is indexed
+            KeycodeMap::from_keymap(unsafe { xkb.keymap() })
+        });
+    }
+
+    /// Physical size in millimeters that makes `width`x`height` pixels come out at 96 DPI
+    pub fn default_physical_size(width: u32, height: u32) -> (u32, u32) {
+        let mm = |px: u32| ((px as f64 * 25.4 / 96.0).round() as u32).max(1);
+        (mm(width), mm(height))
+    }
+
+    /// Output size as seen by clients, i.e. the terminal size with the rotation undone
+    pub fn logical_size(&self) -> Size<i32, Logical> {
+        self.output_transform
+            .transform_size(Size::from((self.term_width as i32, self.term_height as i32)))
+    }
+
+    /// Map a point in terminal (display) coordinates to output coordinates
+    pub fn display_to_logical(&self, point: Point<f64, Logical>) -> Point<f64, Logical> {
+        let display_size = Size::from((self.term_width as f64, self.term_height as f64));
+        let shown = self.content_crop.get().unwrap_or_else(|| Rectangle::from_size(display_size));
+        let point = self.zoom.display_to_frame(point, shown.size) + shown.loc;
+        transform::display_to_logical(point, display_size, self.output_transform)
+    }
+
+    /// Resize the output once the size has held still for `resize_debounce`
+    ///
+    /// Dragging the terminal's edge reports a stream of sizes; only the one
+    /// it settles on reconfigures the output and the windows.
+    pub fn request_resize(&mut self, width: u32, height: u32) {
+        if self.resize_debounce.is_zero() {
+            self.resize_output(width, height);
+            return;
+        }
+        let timer_armed = self.pending_resize.is_some();
+        self.pending_resize = Some((width, height, Instant::now()));
+        if timer_armed {
+            return;
+        }
+
+        let timer = Timer::from_duration(self.resize_debounce);
+        let inserted = self.loop_handle.insert_source(timer, |_, _, state| {
+            let Some((width, height, requested)) = state.pending_resize else {
+                return TimeoutAction::Drop;
+            };
+            let settled = requested.elapsed();
+            if settled < state.resize_debounce {
+                return TimeoutAction::ToDuration(state.resize_debounce - settled);
+            }
+            state.pending_resize = None;
+            state.resize_output(width, height);
+            TimeoutAction::Drop
+        });
+        if let Err(e) = inserted {
+            tracing::error!("Failed to add resize timer to event loop: {}", e);
+            self.pending_resize = None;
+            self.resize_output(width, height);
         }
     }
 
@@ -156,229 +626,3080 @@ impl TermuiState {
         };
         self.output.change_current_state(Some(mode), None, None, None);
 
-        // Notify toplevels of new size
+        layer_map_for_output(&self.output).arrange();
+        self.configure_windows();
+    }
+
+    /// Tell toplevels the size they have now, after the output or the
+    /// space layer surfaces leave them changed
+    fn configure_windows(&self) {
         for toplevel in &self.toplevels {
+            let size = self.window_size(toplevel);
             toplevel.with_pending_state(|state| {
-                state.size = Some(Size::from((width as i32, height as i32)));
+                state.size = Some(size);
             });
             toplevel.send_configure();
         }
     }
 
+    /// Lay the layer surfaces out again, and the windows too if that changed
+    /// the space left for them
+    fn arrange_layers(&self) {
+        let mut map = layer_map_for_output(&self.output);
+        let area = map.non_exclusive_zone();
+        map.arrange();
+        let changed = map.non_exclusive_zone() != area;
+        drop(map);
+        if changed {
+            self.configure_windows();
+        }
+    }
+
+    /// Lay `layer` out after a commit, which may have changed its size,
+    /// anchors or exclusive zone, and send the first configure it awaits
+    fn layer_committed(&mut self, layer: &LayerSurface) {
+        self.arrange_layers();
+        let configured = with_states(layer.wl_surface(), |states| {
+            let data = states.data_map.get::<LayerSurfaceData>();
+            data.is_some_and(|data| data.lock().unwrap().initial_configure_sent)
+        });
+        if configured {
+            return;
+        }
+        layer.layer_surface().send_configure();
+        // Launchers and lock screens above the windows take the keyboard while open
+        let state = layer.cached_state();
+        if state.keyboard_interactivity == KeyboardInteractivity::Exclusive && LAYERS_ABOVE.contains(&state.layer) {
+            let keyboard = self.seat.get_keyboard().unwrap();
+            keyboard.set_focus(self, Some(layer.wl_surface().clone()), 0.into());
+        }
+    }
+
+    /// Part of the output windows are laid out in: all of it, less what
+    /// layer surfaces such as bars keep for themselves
+    fn window_area(&self) -> Rectangle<i32, Logical> {
+        layer_map_for_output(&self.output).non_exclusive_zone()
+    }
+
+    /// Layer surfaces on `layers`, bottom first, each with where its
+    /// surface sits on the output
+    fn layers_on(&self, layers: &[Layer]) -> Vec<(WlSurface, Point<i32, Logical>)> {
+        let map = layer_map_for_output(&self.output);
+        let placed = layers.iter().flat_map(|&layer| map.layers_on(layer)).filter_map(|layer| {
+            // The geometry's corner is its surface tree's, which subsurfaces may extend
+            let geometry = map.layer_geometry(layer)?;
+            Some((layer.wl_surface().clone(), geometry.loc - layer.bbox().loc))
+        });
+        placed.collect()
+    }
+
+    /// The topmost surface under the pointer, and where it sits on the output
+    ///
+    /// Windows and popups are tried from the top of the stack down, each
+    /// with its subsurfaces, between the layer surfaces above and below
+    /// them; `None` if the pointer is over none of them.
     pub fn surface_under_pointer(&self) -> Option<(WlSurface, Point<f64, Logical>)> {
-        // For MVP, just return the first toplevel's surface
-        self.toplevels.first().and_then(|tl| {
-            tl.wl_surface().alive().then(|| {
-                (tl.wl_surface().clone(), Point::from((0.0, 0.0)))
-            })
-        })
+        let location = self.pointer_location;
+        let windows = self.z_order.iter().flat_map(|&index| self.stacked_on(&self.toplevels[index]));
+        let stacked: Vec<_> = self
+            .layers_on(&LAYERS_BELOW)
+            .into_iter()
+            .chain(windows)
+            .chain(self.layers_on(&LAYERS_ABOVE))
+            .collect();
+        stacked
+            .into_iter()
+            .rev()
+            .filter(|(root, _)| root.alive())
+            .find_map(|(root, origin)| surface_at(&root, origin, location))
+            .map(|(surface, origin)| (surface, origin.to_f64()))
     }
 
-    pub fn capture_frame(&self, surface: &WlSurface) -> Option<FrameData> {
-        with_states(surface, |states| {
-            let mut attrs = states.cached_state.get::<SurfaceAttributes>();
-            let data = attrs.current();
+    /// `toplevel` and its popups, bottom first, each with where its surface
+    /// sits on the output
+    ///
+    /// Windows are maximized, so every one has its origin at the corner of
+    /// the window area, or just below its title bar.
+    fn stacked_on(&self, toplevel: &ToplevelSurface) -> Vec<(WlSurface, Point<i32, Logical>)> {
+        let root = toplevel.wl_surface();
+        let popups = self
+            .popups
+            .iter()
+            .filter(|popup| self.popup_root(popup).as_ref() == Some(root))
+            .map(|popup| (popup.wl_surface().clone(), self.surface_origin(popup.wl_surface())));
+        std::iter::once((root.clone(), self.surface_origin(root))).chain(popups).collect()
+    }
 
-            // Extract buffer from BufferAssignment
-            let buffer = match &data.buffer {
-                Some(smithay::wayland::compositor::BufferAssignment::NewBuffer(buffer)) => buffer,
-                _ => return None,
-            };
+    /// Height of the title bar drawn above `toplevel`: zero unless it
+    /// agreed to server-side decorations
+    fn title_bar_height(&self, toplevel: &ToplevelSurface) -> i32 {
+        let server_side = toplevel.current_state().decoration_mode == Some(DecorationMode::ServerSide);
+        self.title_bar.filter(|_| server_side).unwrap_or(0)
+    }
 
-            // Try to read the buffer data using shm
-            if let Ok(buffer_data) = smithay::wayland::shm::with_buffer_contents(
-                buffer,
-                |pool_ptr, pool_len, data| {
-                    let width = data.width as u32;
-                    let height = data.height as u32;
-                    let stride = data.stride as u32;
-                    let buffer_offset = data.offset as usize;
-
-                    // The ptr is the pool base, we need to add the buffer offset
-                    let ptr = unsafe { pool_ptr.add(buffer_offset) };
-                    let buffer_size = (height * stride) as usize;
-
-                    tracing::trace!(
-                        "Buffer: {}x{}, stride={}, format={:?}, offset={}",
-                        width, height, stride, data.format, buffer_offset
-                    );
-
-                    // Verify we're within bounds
-                    if buffer_offset + buffer_size > pool_len {
-                        tracing::error!("Buffer extends beyond pool!");
-                        return FrameData { width: 0, height: 0, data: vec![] };
-                    }
+    /// Size to configure `toplevel` to: the window area's, less the title
+    /// bar it is about to get
+    fn window_size(&self, toplevel: &ToplevelSurface) -> Size<i32, Logical> {
+        let decoration_mode = toplevel.with_pending_state(|state| state.decoration_mode);
+        let title_bar = self.title_bar.filter(|_| decoration_mode == Some(DecorationMode::ServerSide));
+        let size = self.window_area().size;
+        Size::from((size.w.max(1), (size.h - title_bar.unwrap_or(0)).max(1)))
+    }
 
-                    // Convert to RGBA
-                    let mut rgba = Vec::with_capacity((width * height * 4) as usize);
-
-                    for y in 0..height {
-                        for x in 0..width {
-                            let pixel_offset = (y * stride + x * 4) as usize;
-                            if pixel_offset + 4 <= buffer_size {
-                                // XRGB8888 format: B, G, R, X in memory (little-endian)
-                                let b = unsafe { *ptr.add(pixel_offset) };
-                                let g = unsafe { *ptr.add(pixel_offset + 1) };
-                                let r = unsafe { *ptr.add(pixel_offset + 2) };
-                                let a = unsafe { *ptr.add(pixel_offset + 3) };
-                                rgba.push(r);
-                                rgba.push(g);
-                                rgba.push(b);
-                                rgba.push(a);
-                            }
-                        }
-                    }
+    /// Decorate `toplevel` ourselves, unless `--no-decorations` leaves it
+    /// to the client, whichever mode it asked for
+    fn configure_decorations(&self, toplevel: &ToplevelSurface) {
+        let mode = match self.title_bar {
+            Some(_) => DecorationMode::ServerSide,
+            None => DecorationMode::ClientSide,
+        };
+        toplevel.with_pending_state(|state| state.decoration_mode = Some(mode));
+        let size = self.window_size(toplevel);
+        toplevel.with_pending_state(|state| state.size = Some(size));
+        toplevel.send_configure();
+    }
 
-                    FrameData { width, height, data: rgba }
-                },
-            ) {
-                return Some(buffer_data);
-            }
-            None
-        })
+    /// The window `popup` was opened from, through any menus in between
+    fn popup_root(&self, popup: &PopupSurface) -> Option<WlSurface> {
+        let parent = popup.get_parent_surface()?;
+        match self.popups.iter().find(|p| p.wl_surface() == &parent) {
+            Some(parent_popup) => self.popup_root(parent_popup),
+            None => Some(parent),
+        }
     }
-}
 
-// Implement required traits
-impl BufferHandler for TermuiState {
-    fn buffer_destroyed(&mut self, _buffer: &wl_buffer::WlBuffer) {}
-}
+    /// Where `surface` sits on the output: windows with their window
+    /// geometry at the window area's corner, or just below their title bar,
+    /// popups where their positioner put them relative to their parent
+    ///
+    /// Anything a window draws outside its geometry, such as a shadow, is
+    /// left off the window area's top and left edges.
+    fn surface_origin(&self, surface: &WlSurface) -> Point<i32, Logical> {
+        let Some(popup) = self.popups.iter().find(|popup| popup.wl_surface() == surface) else {
+            let toplevel = self.toplevels.iter().find(|tl| tl.wl_surface() == surface);
+            let title_bar = toplevel.map_or(0, |tl| self.title_bar_height(tl));
+            return self.window_area().loc + Point::from((0, title_bar)) - window_geometry_origin(surface);
+        };
+        let Some(parent) = popup.get_parent_surface() else {
+            return Point::default();
+        };
+        let geometry = popup.with_pending_state(|state| state.geometry);
+        self.surface_origin(&parent) + window_geometry_origin(&parent) + geometry.loc - window_geometry_origin(surface)
+    }
 
-impl ShmHandler for TermuiState {
-    fn shm_state(&self) -> &ShmState {
-        &self.shm_state
+    /// Where `positioner` places `popup` relative to its parent, flipped or
+    /// slid as it allows to stay on the output
+    fn popup_geometry(&self, popup: &PopupSurface, positioner: PositionerState) -> Rectangle<i32, Logical> {
+        let parent_origin = popup
+            .get_parent_surface()
+            .map(|parent| self.surface_origin(&parent) + window_geometry_origin(&parent))
+            .unwrap_or_default();
+        let output = Rectangle::new(Point::default() - parent_origin, self.logical_size());
+        positioner.get_unconstrained_geometry(output)
     }
-}
 
-impl CompositorHandler for TermuiState {
-    fn compositor_state(&mut self) -> &mut CompositorState {
-        &mut self.compositor_state
+    /// Whether an opaque window higher in the stack hides all of `root`
+    fn is_covered(&self, root: &WlSurface) -> bool {
+        let Some(position) = self.z_order.iter().position(|&index| self.toplevels[index].wl_surface() == root) else {
+            return false;
+        };
+        let Some((size, _)) = with_states(root, snapshot_extent) else {
+            return false;
+        };
+        self.z_order[position + 1..].iter().any(|&index| {
+            with_states(self.toplevels[index].wl_surface(), snapshot_extent)
+                .is_some_and(|(above, opaque)| opaque && above.w >= size.w && above.h >= size.h)
+        })
     }
 
-    fn client_compositor_state<'a>(&self, client: &'a Client) -> &'a CompositorClientState {
-        &client.get_data::<ClientState>().unwrap().compositor_state
+    /// The window at the top of the stack
+    pub fn topmost(&self) -> Option<&ToplevelSurface> {
+        self.z_order.last().map(|&index| &self.toplevels[index])
     }
 
-    fn commit(&mut self, surface: &WlSurface) {
-        tracing::trace!("Surface commit");
-        if !is_sync_subsurface(surface) {
-            let mut root = surface.clone();
-            while let Some(parent) = get_parent(&root) {
-                root = parent;
-            }
+    /// Move the pointer to `location` on the output, over the window under it
+    pub fn move_pointer(&mut self, location: Point<f64, Logical>, time: u32) {
+        self.pointer_location = location;
+        let pointer = self.seat.get_pointer().unwrap();
+        // Over no window the pointer leaves the one it was over
+        let focus = self.surface_under_pointer();
+        let serial = SERIAL_COUNTER.next_serial();
+        pointer.motion(self, focus, &MotionEvent { location, serial, time });
+        pointer.frame(self);
+    }
 
-            // Capture frame when a toplevel commits
-            for toplevel in &self.toplevels {
-                if toplevel.wl_surface() == &root {
-                    if let Some(frame_data) = self.capture_frame(&root) {
-                        tracing::trace!("Captured frame: {}x{}", frame_data.width, frame_data.height);
-                        *self.pending_frame.lock().unwrap() = Some(frame_data);
-                    }
+    /// The toplevel holding keyboard focus
+    pub fn focused_toplevel(&self) -> Option<&ToplevelSurface> {
+        let focus = self.seat.get_keyboard()?.current_focus()?;
+        self.toplevels.iter().find(|tl| tl.wl_surface() == &focus)
+    }
 
-                    // Send frame callbacks using smithay's proper mechanism
-                    let output = self.output.clone();
-                    let time = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap();
-
-                    send_frames_surface_tree(
-                        &root,
-                        &output,
-                        time,
-                        Some(Duration::ZERO), // Always send callbacks
-                        |_, _| Some(output.clone()),
-                    );
-                    break;
-                }
-            }
+    /// Built-in bitmap for the current cursor, unless the client draws its own or hid it
+    pub fn cursor_bitmap(&self) -> Option<&'static cursor::CursorBitmap> {
+        if !self.show_cursor || !self.cursor_blink.is_visible() {
+            return None;
+        }
+        match &self.cursor_status {
+            CursorImageStatus::Named(icon) => Some(cursor::for_icon(*icon)),
+            CursorImageStatus::Hidden | CursorImageStatus::Surface(_) => None,
         }
     }
-}
 
+    /// Titles of all tracked toplevels, in stacking order
+    pub fn toplevel_titles(&self) -> Vec<String> {
+        self.z_order.iter().map(|&index| toplevel_title(&self.toplevels[index])).collect()
+    }
 
-impl XdgShellHandler for TermuiState {
-    fn xdg_shell_state(&mut self) -> &mut XdgShellState {
-        &mut self.xdg_shell_state
+    /// Show `note` after the window's title in the terminal's, and update
+    /// the terminal's title now
+    pub fn set_title_note(&mut self, note: Option<&str>) {
+        self.title_note = note.map(str::to_owned);
+        self.show_terminal_title(self.focused_toplevel());
     }
 
-    fn new_toplevel(&mut self, surface: ToplevelSurface) {
-        tracing::info!("New toplevel surface created!");
-        // Configure the surface to our terminal size
-        surface.with_pending_state(|state| {
-            state.size = Some(Size::from((self.term_width as i32, self.term_height as i32)));
-            state.states.set(xdg_toplevel::State::Activated);
-            state.states.set(xdg_toplevel::State::Maximized);
+    /// Show `toplevel`'s title as the terminal's, with `terminal_title`;
+    /// with no toplevel, just the title note
+    fn show_terminal_title(&self, toplevel: Option<&ToplevelSurface>) {
+        if !self.terminal_title {
+            return;
+        }
+        let title = toplevel.map(toplevel_title).unwrap_or_default();
+        let mut stdout = std::io::stdout().lock();
+        let sequence = osc_title(&title_with_note(&title, self.title_note.as_deref()));
+        if let Err(e) = stdout.write_all(sequence.as_bytes()).and_then(|()| stdout.flush()) {
+            tracing::warn!("Failed to set the terminal's title: {}", e);
+        }
+    }
+
+    /// Suggest our buffer scale to `surface` (sent only when it changed)
+    ///
+    /// The preferred transform stays normal: rotation is applied to the
+    /// captured frame, not expected from the client.
+    fn send_preferred_buffer_state(&self, surface: &WlSurface) {
+        with_states(surface, |states| {
+            send_surface_state(surface, states, self.buffer_scale, Transform::Normal);
         });
-        surface.send_configure();
+    }
 
-        self.toplevels.push(surface.clone());
+    /// Shut down if `event` is what the exit policy waits for
+    pub fn exit_on(&mut self, event: ExitPolicy) {
+        if self.exit_policy != event {
+            return;
+        }
+        tracing::info!("Exiting: {:?} (--exit-on)", event);
+        self.running = false;
+        self.loop_signal.stop();
+    }
 
-        // Set keyboard focus to the new toplevel
-        let keyboard = self.seat.get_keyboard().unwrap();
-        keyboard.set_focus(self, Some(surface.wl_surface().clone()), 0.into());
+    /// Whether frame pacing is holding frames back
+    pub fn terminal_behind(&self) -> bool {
+        self.frame_pacer.as_ref().is_some_and(|pacer| pacer.is_behind())
+    }
 
-        // Note: Frame callbacks are sent in the commit handler after capture
-        // At this point the client hasn't committed yet, so no callbacks are registered
+    /// Whether a client asked to keep the session from going idle, through
+    /// an inhibitor on a surface it still has
+    pub fn is_inhibited(&self) -> bool {
+        self.idle_inhibitors.iter().any(|surface| surface.is_alive())
     }
 
-    fn new_popup(&mut self, _surface: PopupSurface, _positioner: PositionerState) {
-        // MVP: ignore popups
+    /// Exit if nothing happened for `idle_exit` (`--idle-exit`) and no client
+    /// inhibits idling; otherwise how long until the next check
+    pub fn check_idle(&mut self, idle_exit: Duration) -> Option<Duration> {
+        let idle = self.last_activity.elapsed();
+        if idle < idle_exit {
+            return Some(idle_exit - idle);
+        }
+        // A video player or a presentation keeps the session going
+        if self.is_inhibited() {
+            return Some(idle_exit);
+        }
+        tracing::info!("Exiting: no input or commits for {:.1}s (--idle-exit)", idle.as_secs_f64());
+        self.running = false;
+        self.loop_signal.stop();
+        None
     }
 
-    fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
-        self.toplevels.retain(|tl| tl != &surface);
+    /// Composite the windows again if a commit was skipped while the
+    /// terminal was behind and it has caught up since
+    pub fn catch_up(&mut self) {
+        if !self.capture_skipped || self.terminal_behind() {
+            return;
+        }
+        self.capture_skipped = false;
+        self.redraw();
+    }
 
-        // If no more toplevels, exit
-        if self.toplevels.is_empty() {
-            self.running = false;
-            self.loop_signal.stop();
+    /// Present the windows again, e.g. because something drawn over them
+    /// changed; deferred like a commit while the terminal is behind
+    pub fn redraw(&mut self) {
+        if self.terminal_behind() {
+            self.capture_skipped = true;
+            return;
         }
+        self.present();
     }
 
-    fn grab(&mut self, _surface: PopupSurface, _seat: wl_seat::WlSeat, _serial: smithay::utils::Serial) {}
+    /// Composite the windows and queue the result for the terminal
+    fn present(&self) {
+        let started = Instant::now();
+        if let Some(mut frame_data) = self.composite_frame() {
+            tracing::trace!("Composited frame: {}x{}", frame_data.width, frame_data.height);
+            self.draw_cursor(&mut frame_data);
+            self.screencopy.frame_composited(&frame_data);
+            if self.output_transform != Transform::Normal {
+                let transformed = transform::transform_frame(&frame_data, self.output_transform);
+                self.recycle_frame(std::mem::replace(&mut frame_data, transformed));
+            }
+            if self.crop_to_content {
+                frame_data = self.crop_frame_to_content(frame_data);
+            }
+            let replaced = self.queue_frame(frame_data);
 
-    fn reposition_request(
-        &mut self,
-        _surface: PopupSurface,
-        _positioner: PositionerState,
-        _token: u32,
-    ) {
+            let mut stats = self.stats.borrow_mut();
+            stats.captured += 1;
+            stats.capture_time += started.elapsed();
+            if let Some(replaced) = replaced {
+                stats.skipped += 1;
+                self.recycle_frame(replaced);
+            }
+        }
     }
-}
 
-impl SeatHandler for TermuiState {
-    type KeyboardFocus = WlSurface;
-    type PointerFocus = WlSurface;
-    type TouchFocus = WlSurface;
+    /// A zeroed buffer of `len` bytes, reusing one of a frame done with if
+    /// one is large enough
+    fn frame_buffer(&self, len: usize) -> Vec<u8> {
+        let mut buffers = self.frame_buffers.borrow_mut();
+        let mut buffer = match buffers.iter().position(|buffer| buffer.capacity() >= len) {
+            Some(index) => buffers.swap_remove(index),
+            None => buffers.pop().unwrap_or_default(),
+        };
+        buffer.clear();
+        buffer.resize(len, 0);
+        buffer
+    }
 
-    fn seat_state(&mut self) -> &mut SeatState<Self> {
-        &mut self.seat_state
+    /// Keep the pixel buffer of a frame done with for a later one
+    pub fn recycle_frame(&self, frame: FrameData) {
+        let mut buffers = self.frame_buffers.borrow_mut();
+        if buffers.len() < MAX_FRAME_BUFFERS {
+            buffers.push(frame.data);
+        }
     }
 
-    fn focus_changed(&mut self, _seat: &Seat<Self>, _focused: Option<&Self::KeyboardFocus>) {}
-    fn cursor_image(&mut self, _seat: &Seat<Self>, image: CursorImageStatus) {
-        self.cursor_status = image;
+    /// Cut `frame` down to its non-transparent part, remembering where that
+    /// lies on the display so pointer input can be offset by it
+    fn crop_frame_to_content(&self, mut frame: FrameData) -> FrameData {
+        let Some(bounds) = transform::content_bounds(&frame) else {
+            if self.content_crop.take().is_some() {
+                frame.damage.clear();
+            }
+            return frame;
+        };
+        // The frame may be at a higher density than the display
+        let scale = (
+            self.term_width as f64 / frame.width as f64,
+            self.term_height as f64 / frame.height as f64,
+        );
+        let bounds_f64 = bounds.to_f64();
+        let crop = Rectangle::new(
+            (bounds_f64.loc.x * scale.0, bounds_f64.loc.y * scale.1).into(),
+            (bounds_f64.size.w * scale.0, bounds_f64.size.h * scale.1).into(),
+        );
+        // Damage only carries over while the crop stays put
+        if self.content_crop.replace(Some(crop)) != Some(crop) {
+            frame.damage.clear();
+        }
+        transform::crop_frame(&frame, bounds)
     }
-}
 
-impl SelectionHandler for TermuiState {
-    type SelectionUserData = ();
-}
+    /// Queue `frame` for the terminal, returning the one it replaces
+    pub fn queue_frame(&self, mut frame: FrameData) -> Option<FrameData> {
+        let mut pending = self.pending_frame.lock().unwrap();
+        if let Some(replaced) = pending.as_ref() {
+            frame.add_damage_of(replaced);
+        }
+        let replaced = pending.replace(frame);
+        drop(pending);
+        self.frame_queued();
+        replaced
+    }
 
-impl DataDeviceHandler for TermuiState {
-    fn data_device_state(&self) -> &DataDeviceState {
-        &self.data_device_state
+    /// Wake the presenter, if it waits for frames rather than polling
+    pub fn frame_queued(&self) {
+        if let Some(ping) = &self.frame_ready {
+            ping.ping();
+        }
     }
-}
 
-impl ClientDndGrabHandler for TermuiState {}
-impl ServerDndGrabHandler for TermuiState {}
+    /// Tell clients waiting for presentation feedback that the frame queued
+    /// last has just been sent to the terminal
+    ///
+    /// The terminal shows it some time later, so the timestamp is only an
+    /// approximation of when it became visible.
+    pub fn frame_presented(&mut self, refresh: Refresh) {
+        if self.presentation_feedback.is_empty() {
+            return;
+        }
+        self.presented_frames += 1;
+        let time = self.monotonic_clock.now();
+        for feedback in self.presentation_feedback.drain(..) {
+            feedback.presented(&self.output, time, refresh, self.presented_frames, wp_presentation_feedback::Kind::empty());
+        }
+    }
 
-impl OutputHandler for TermuiState {}
+    /// Tell clients waiting for presentation feedback that the frame queued
+    /// last never made it to the terminal
+    pub fn frame_discarded(&mut self) {
+        self.presentation_feedback.drain(..).for_each(PresentationFeedbackCallback::discarded);
+    }
 
-delegate_compositor!(TermuiState);
-delegate_xdg_shell!(TermuiState);
-delegate_shm!(TermuiState);
-delegate_output!(TermuiState);
-delegate_seat!(TermuiState);
-delegate_data_device!(TermuiState);
+    /// Size of composited frames: the output's, in buffer pixels
+    pub fn frame_size(&self) -> (u32, u32) {
+        let scale = self.buffer_scale.max(1);
+        let size = self.logical_size();
+        ((size.w * scale).max(1) as u32, (size.h * scale).max(1) as u32)
+    }
+
+    /// Capture every window and blend them together in stacking order, each
+    /// with its popups above it
+    ///
+    /// The frame has the output's size in buffer pixels: the terminal's,
+    /// at the buffer scale suggested to clients. Windows are maximized, so
+    /// all of them sit at the window area's corner, or below the title bar
+    /// drawn for them; one drawn at another scale is resized to ours. Layer
+    /// surfaces go below or above them all, by their layer. `None` until a
+    /// window or layer surface has something to show.
+    ///
+    /// Like a capture's, the frame's damage is empty if nothing changed.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn composite_frame(&self) -> Option<FrameData> {
+        let (width, height) = self.frame_size();
+        let scale = self.buffer_scale.max(1);
+        let mut frame = FrameData {
+            width,
+            height,
+            data: self.frame_buffer((width * height * 4) as usize),
+            opaque: false,
+            damage: Vec::new(),
+        };
+
+        let mut shown = Vec::new();
+        for (root, origin) in self.layers_on(&LAYERS_BELOW) {
+            if let Some((layer, _)) = self.draw_surface(&mut frame, &root, origin, scale) {
+                shown.push((root, origin));
+                self.recycle_frame(layer);
+            }
+        }
+        let mut covered = false;
+        let top = self.window_area().loc.y * scale;
+        for &index in &self.z_order {
+            let toplevel = &self.toplevels[index];
+            // The title bar is opaque, so a window right below it covers as if at the top
+            let title_bar = self.title_bar_height(toplevel) * scale;
+            if title_bar > 0 {
+                draw_title_bar(&mut frame, top, title_bar, &toplevel_title(toplevel));
+            }
+            for (root, origin) in self.stacked_on(toplevel) {
+                let Some((window, location)) = self.draw_surface(&mut frame, &root, origin, scale) else {
+                    continue;
+                };
+                covered |= window.opaque
+                    && top <= 0
+                    && location.x <= 0
+                    && location.y <= top + title_bar
+                    && window.width as i32 + location.x >= width as i32
+                    && window.height as i32 + location.y >= height as i32;
+                shown.push((root, origin));
+                self.recycle_frame(window);
+            }
+        }
+        for (root, origin) in self.layers_on(&LAYERS_ABOVE) {
+            if let Some((layer, _)) = self.draw_surface(&mut frame, &root, origin, scale) {
+                shown.push((root, origin));
+                self.recycle_frame(layer);
+            }
+        }
+        if shown.is_empty() {
+            self.recycle_frame(frame);
+            return None;
+        }
+
+        let frame_rect = Rectangle::from_size((width as i32, height as i32).into());
+        frame.damage.retain_mut(|rect| match rect.intersection(frame_rect) {
+            Some(clipped) => {
+                *rect = clipped;
+                true
+            }
+            None => false,
+        });
+        // Damage doesn't cover windows that appeared, went away, moved or were restacked
+        if self.last_composited.replace(shown.clone()) != shown {
+            frame.damage = vec![frame_rect];
+        }
+        // Blending anything over an opaque window leaves it opaque
+        frame.opaque = covered;
+        Some(frame)
+    }
+
+    /// Blend `root`, captured at `scale`, into `frame` with its surface at
+    /// `origin`, adding its damage; the capture and where it went, or
+    /// `None` if it has nothing to show
+    fn draw_surface(
+        &self,
+        frame: &mut FrameData,
+        root: &WlSurface,
+        origin: Point<i32, Logical>,
+        scale: i32,
+    ) -> Option<(FrameData, Point<i32, Physical>)> {
+        let captured = self.capture_scaled(root, scale)?;
+        let location = origin.to_physical(scale);
+        blend(frame, &captured, location.x, location.y);
+        let offset = Point::<i32, Buffer>::from((location.x, location.y));
+        frame.damage.extend(captured.damage.iter().map(|rect| Rectangle::new(rect.loc + offset, rect.size)));
+        Some((captured, location))
+    }
+
+    /// Capture `root` like `capture_frame()`, resized from its buffer scale to `scale`
+    fn capture_scaled(&self, root: &WlSurface, scale: i32) -> Option<FrameData> {
+        let frame = self.capture_frame(root)?;
+        let frame_scale =
+            with_states(root, |states| states.cached_state.get::<SurfaceAttributes>().current().buffer_scale.max(1));
+        Some(self.rescale(frame, frame_scale, scale))
+    }
+
+    /// `frame`, drawn at buffer scale `from`, resized to scale `to`; all of
+    /// it is damaged if it had to be
+    fn rescale(&self, frame: FrameData, from: i32, to: i32) -> FrameData {
+        if from == to {
+            return frame;
+        }
+        let width = (frame.width * to as u32 / from as u32).max(1);
+        let height = (frame.height * to as u32 / from as u32).max(1);
+        let scaled = FrameData {
+            data: transform::scale_image(&frame.data, frame.width, frame.height, width, height, frame.opaque),
+            width,
+            height,
+            opaque: frame.opaque,
+            damage: vec![Rectangle::from_size((width as i32, height as i32).into())],
+        };
+        self.recycle_frame(frame);
+        scaled
+    }
+
+    /// Draw the pointer over a composited `frame`, damaging where it was
+    /// and where it is now
+    fn draw_cursor(&self, frame: &mut FrameData) {
+        let scale = self.buffer_scale.max(1);
+        let drawn = self.cursor_frame(scale).map(|(image, location)| {
+            blend(frame, &image, location.x, location.y);
+            let size = (image.width as i32, image.height as i32);
+            self.recycle_frame(image);
+            Rectangle::<i32, Buffer>::new((location.x, location.y).into(), size.into())
+        });
+        let previous = self.last_cursor.replace(drawn);
+        let frame_rect = Rectangle::from_size((frame.width as i32, frame.height as i32).into());
+        frame.damage.extend(previous.into_iter().chain(drawn).filter_map(|rect| rect.intersection(frame_rect)));
+    }
+
+    /// The cursor image at `scale`, and where its top-left corner goes for
+    /// the hotspot to sit under the pointer
+    fn cursor_frame(&self, scale: i32) -> Option<(FrameData, Point<i32, Physical>)> {
+        if !self.show_cursor {
+            return None;
+        }
+        let (image, hotspot) = match &self.cursor_status {
+            CursorImageStatus::Surface(surface) => {
+                let hotspot = with_states(surface, |states| {
+                    states.data_map.get::<CursorImageSurfaceData>().map(|data| data.lock().unwrap().hotspot)
+                })
+                .unwrap_or_default();
+                (self.capture_scaled(surface, scale)?, hotspot.to_physical(scale))
+            }
+            CursorImageStatus::Named(_) => {
+                let bitmap = self.cursor_bitmap()?;
+                let image = bitmap.to_frame();
+                let (x, y) = bitmap.hotspot;
+                let scaled = FrameData {
+                    data: transform::scale_nearest(&image.data, image.width, image.height, scale as u32),
+                    width: image.width * scale as u32,
+                    height: image.height * scale as u32,
+                    ..image
+                };
+                (scaled, Point::from((x as i32 * scale, y as i32 * scale)))
+            }
+            CursorImageStatus::Hidden => return None,
+        };
+        let pointer = self.pointer_location.to_physical(scale as f64).to_i32_round();
+        Some((image, pointer - hotspot))
+    }
+
+    /// Capture `root` together with its subsurfaces, in stacking order
+    ///
+    /// The frame has the size of the root's buffer, or of its viewport, and
+    /// subsurfaces drawn at another buffer scale are resized to the root's.
+    /// Smithay applies the cached state of sync subsurfaces when their
+    /// parent commits, so they show up here in lockstep with it; desync ones
+    /// show what they last committed. The frame's damage covers what changed
+    /// since the last capture, and is empty if nothing did.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn capture_frame(&self, root: &WlSurface) -> Option<FrameData> {
+        let mut root_layer = None;
+        let mut layers = Vec::new();
+        with_surface_tree_upward(
+            root,
+            Point::<i32, Logical>::from((0, 0)),
+            |_, states, parent| TraversalAction::DoChildren(*parent + subsurface_location(states)),
+            |surface, states, parent| {
+                let Some(layer) = self.buffer_snapshot(states) else {
+                    return;
+                };
+                let scale = states.cached_state.get::<SurfaceAttributes>().current().buffer_scale.max(1);
+                if surface == root {
+                    root_layer = Some((layers.len(), scale));
+                }
+                let damage = states.data_map.get::<BufferSnapshot>().map(BufferSnapshot::take_damage);
+                let viewport = *states.cached_state.get::<ViewportCachedState>().current();
+                let layer = self.apply_viewport(layer, viewport, scale);
+                // Cropped and resized, all of what the viewport shows counts as damaged
+                let damage = if viewport.size().is_some() { layer.damage.clone() } else { damage.unwrap_or_default() };
+                layers.push((layer, scale, *parent + subsurface_location(states), damage));
+            },
+            |_, _, _| true,
+        );
+
+        // Nothing to show until the root itself has a buffer
+        let (root_index, scale) = root_layer?;
+        let (base, _, _, _) = &layers[root_index];
+        let mut frame = FrameData {
+            width: base.width,
+            height: base.height,
+            data: self.frame_buffer(base.data.len()),
+            // Layers are copied without blending, so any translucent one shows through as is
+            opaque: layers.iter().all(|(layer, _, _, _)| layer.opaque),
+            damage: Vec::new(),
+        };
+        let mut layout = Vec::with_capacity(layers.len());
+        for (layer, layer_scale, location, damage) in layers {
+            let layer = self.rescale(layer, layer_scale, scale);
+            let damage = if layer_scale == scale { damage } else { layer.damage.clone() };
+            let location = location.to_physical(scale);
+            blit(&mut frame, &layer, location.x, location.y);
+            layout.push(Rectangle::<i32, Physical>::new(location, (layer.width as i32, layer.height as i32).into()));
+            let offset = Point::<i32, Buffer>::from((location.x, location.y));
+            frame.damage.extend(damage.iter().map(|rect| Rectangle::new(rect.loc + offset, rect.size)));
+            self.recycle_frame(layer);
+        }
+
+        // Damage doesn't cover surfaces that moved, appeared or went away
+        let layout_unchanged = with_states(root, |states| {
+            states.data_map.insert_if_missing(|| LayerLayout(RefCell::new(Vec::new())));
+            let previous = &states.data_map.get::<LayerLayout>().unwrap().0;
+            previous.replace(layout.clone()) == layout
+        });
+        let frame_rect = Rectangle::from_size((frame.width as i32, frame.height as i32).into());
+        frame.damage.retain_mut(|rect| match rect.intersection(frame_rect) {
+            Some(clipped) => {
+                *rect = clipped;
+                true
+            }
+            None => false,
+        });
+        if !layout_unchanged {
+            frame.damage = vec![frame_rect];
+        }
+        Some(frame)
+    }
+
+    /// Copy the buffers newly committed in `root`'s surface tree and release
+    /// them, so their clients can draw into them again right away
+    fn snapshot_buffers(&self, root: &WlSurface) {
+        with_surface_tree_upward(
+            root,
+            (),
+            |_, _, _| TraversalAction::DoChildren(()),
+            |surface, states, _| self.snapshot_buffer(surface, states),
+            |_, _, _| true,
+        );
+    }
+
+    fn snapshot_buffer(&self, surface: &WlSurface, states: &SurfaceData) {
+        let mut attrs = states.cached_state.get::<SurfaceAttributes>();
+        let data = attrs.current();
+        // smithay accumulates damage until it is taken
+        let damage = std::mem::take(&mut data.damage);
+        let (scale, transform) = (data.buffer_scale.max(1), Transform::from(data.buffer_transform));
+        states.data_map.insert_if_missing(BufferSnapshot::default);
+        let stored = states.data_map.get::<BufferSnapshot>().unwrap();
+        let previous_size = stored.frame.borrow().as_ref().map(|frame| (frame.width, frame.height));
+        // Taken, so smithay doesn't release it a second time once replaced
+        let snapshot = match data.buffer.take() {
+            Some(BufferAssignment::NewBuffer(buffer)) => {
+                // Read into the previous copy's memory, which is usually the right size already
+                let previous = stored.frame.borrow_mut().take();
+                if let Some(previous) = previous {
+                    self.recycle_frame(previous);
+                }
+                let frame = self.read_buffer(&buffer);
+                buffer.release();
+                match frame {
+                    Ok(frame) => {
+                        let frame = self.upright(frame, transform);
+                        let size = Size::<i32, Buffer>::from((frame.width as i32, frame.height as i32));
+                        ensure_viewport_valid(states, size.to_logical(scale, Transform::Normal));
+                        Some(frame)
+                    }
+                    Err(error) => {
+                        self.report_shm_error(surface, &error);
+                        None
+                    }
+                }
+            }
+            Some(BufferAssignment::Removed) => None,
+            // Nothing attached since the last commit
+            None => return,
+        };
+        let size = snapshot.as_ref().map(|frame| (frame.width, frame.height));
+        let mut pending = stored.damage.borrow_mut();
+        match size.or(previous_size) {
+            // A new size, or a buffer without damage: take it as all changed
+            Some((width, height)) if size != previous_size || damage.is_empty() => {
+                pending.push(Rectangle::from_size((width as i32, height as i32).into()));
+            }
+            Some((width, height)) => {
+                let frame_size = Size::<i32, Buffer>::from((width as i32, height as i32));
+                pending.extend(
+                    damage.into_iter().map(|damage| transform::damage_to_frame(damage, frame_size, scale, transform)),
+                );
+            }
+            None => {}
+        }
+        if let Some(replaced) = stored.frame.replace(snapshot) {
+            self.recycle_frame(replaced);
+        }
+    }
+
+    /// `frame` read from a buffer the client drew with `transform`, turned
+    /// the way the surface shows it; left as is in the usual, untransformed case
+    fn upright(&self, frame: FrameData, transform: Transform) -> FrameData {
+        if transform == Transform::Normal {
+            return frame;
+        }
+        let upright = transform::upright_buffer(&frame, transform);
+        self.recycle_frame(frame);
+        upright
+    }
+
+    /// `frame`, drawn at buffer scale `scale`, cropped to `viewport`'s source
+    /// rectangle and resized to its destination size; all of it is damaged
+    /// if it had a viewport to apply
+    fn apply_viewport(&self, frame: FrameData, viewport: ViewportCachedState, scale: i32) -> FrameData {
+        let Some(size) = viewport.size() else {
+            return frame;
+        };
+        let bounds = Rectangle::<i32, Physical>::from_size((frame.width as i32, frame.height as i32).into());
+        let source = viewport.src.map_or(bounds, |src| {
+            let src = src.to_physical(scale as f64);
+            Rectangle::new(src.loc.to_i32_round(), src.size.to_i32_round())
+        });
+        // Checked against the buffer when it was committed; a later crop may not fit
+        let Some(source) = source.intersection(bounds) else {
+            return frame;
+        };
+        let cropped = if source == bounds { frame } else { transform::crop_frame(&frame, source) };
+        let (width, height) = ((size.w * scale).max(1) as u32, (size.h * scale).max(1) as u32);
+        let data = if (cropped.width, cropped.height) == (width, height) {
+            cropped.data
+        } else {
+            transform::scale_image(&cropped.data, cropped.width, cropped.height, width, height, cropped.opaque)
+        };
+        FrameData {
+            width,
+            height,
+            data,
+            opaque: cropped.opaque,
+            damage: vec![Rectangle::from_size((width as i32, height as i32).into())],
+        }
+    }
+
+    /// Read `buffer` as RGBA
+    fn read_buffer(&self, buffer: &wl_buffer::WlBuffer) -> Result<FrameData, ShmError> {
+        if let Ok(dmabuf) = get_dmabuf(buffer) {
+            return self.read_dmabuf(dmabuf);
+        }
+        // Try to read the buffer data using shm
+        let result = smithay::wayland::shm::with_buffer_contents(
+            buffer,
+            |pool_ptr, pool_len, data| {
+                let width = data.width as u32;
+                let height = data.height as u32;
+                let stride = data.stride as u32;
+                let buffer_offset = data.offset as usize;
+
+                // The ptr is the pool base, we need to add the buffer offset
+                let ptr = unsafe { pool_ptr.add(buffer_offset) };
+                let buffer_size = (height * stride) as usize;
+
+                tracing::trace!(
+                    "Buffer: {}x{}, stride={}, format={:?}, offset={}",
+                    width, height, stride, data.format, buffer_offset
+                );
+
+                // Verify we're within bounds
+                if buffer_offset + buffer_size > pool_len {
+                    return Err(ShmError::OutOfPool { offset: buffer_offset, size: buffer_size, pool_len });
+                }
+
+                // SAFETY: the buffer lies within the pool, checked above, and
+                // smithay keeps the pool mapped while this closure runs
+                let pixels = unsafe { std::slice::from_raw_parts(ptr, buffer_size) };
+                self.convert_pixels(pixels, width, height, stride, data.format, false)
+            },
+        );
+
+        result.map_err(ShmError::Access).and_then(|frame| frame)
+    }
+
+    /// Read a linear `dmabuf` as RGBA, through a read-only mapping of its plane
+    fn read_dmabuf(&self, dmabuf: &Dmabuf) -> Result<FrameData, ShmError> {
+        let format = dmabuf_shm_format(dmabuf)?;
+        let (width, height) = (dmabuf.width(), dmabuf.height());
+        let stride = dmabuf.strides().next().unwrap_or(0);
+        let buffer_size = (height * stride) as usize;
+        tracing::trace!("Dmabuf: {}x{}, stride={}, format={:?}", width, height, stride, format);
+
+        let mapping = dmabuf
+            .map_plane(0, DmabufMappingMode::READ)
+            .map_err(|e| ShmError::DmabufMapping(e.to_string()))?;
+        if buffer_size > mapping.length() {
+            return Err(ShmError::OutOfPool { offset: 0, size: buffer_size, pool_len: mapping.length() });
+        }
+        // Wait for any rendering into it to finish; plain memory passed as a
+        // dmabuf can't be synced, and needn't be
+        if let Err(e) = dmabuf.sync_plane(0, DmabufSyncFlags::START | DmabufSyncFlags::READ) {
+            tracing::trace!("Not syncing dmabuf: {}", e);
+        }
+        // SAFETY: the buffer lies within the mapping, checked above, which
+        // stays mapped until dropped at the end of this function
+        let pixels = unsafe { std::slice::from_raw_parts(mapping.ptr() as *const u8, buffer_size) };
+        let frame = self.convert_pixels(pixels, width, height, stride, format, dmabuf.y_inverted());
+        let _ = dmabuf.sync_plane(0, DmabufSyncFlags::END | DmabufSyncFlags::READ);
+        frame
+    }
+
+    /// `height` rows of `stride` bytes of `format` pixels as RGBA, with the
+    /// last row first if `y_inverted`
+    fn convert_pixels(
+        &self,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        stride: u32,
+        format: wl_shm::Format,
+        y_inverted: bool,
+    ) -> Result<FrameData, ShmError> {
+        let Some((pixel_size, to_rgba)) = rgba_converter(format) else {
+            return Err(ShmError::UnsupportedFormat(format));
+        };
+
+        // Convert to RGBA, a row at a time
+        let mut rgba = self.frame_buffer((width * height * 4) as usize);
+        transform::fill_rows(&mut rgba, (width * 4) as usize, |y, row| {
+            let y = if y_inverted { height as usize - 1 - y } else { y };
+            let source = &pixels[y * stride as usize..];
+            for (out, pixel) in row.chunks_exact_mut(4).zip(source.chunks_exact(pixel_size)) {
+                // Formats are little-endian words of `pixel_size` bytes
+                let mut word = [0u8; 4];
+                word[..pixel_size].copy_from_slice(pixel);
+                out.copy_from_slice(&to_rgba(u32::from_le_bytes(word)));
+            }
+        });
+
+        // Alpha is padding in the X formats and missing in RGB565; a
+        // surface with alpha may still declare itself opaque, which
+        // buffer_snapshot() checks
+        let opaque = matches!(
+            format,
+            wl_shm::Format::Xrgb8888
+                | wl_shm::Format::Xbgr8888
+                | wl_shm::Format::Rgb565
+                | wl_shm::Format::Xrgb2101010
+                | wl_shm::Format::Xbgr2101010
+        );
+
+        Ok(FrameData { width, height, data: rgba, opaque, damage: Vec::new() })
+    }
+
+    /// A copy of the buffer last committed to a surface, if it has one
+    fn buffer_snapshot(&self, states: &SurfaceData) -> Option<FrameData> {
+        let (_, opaque) = snapshot_extent(states)?;
+        let stored = states.data_map.get::<BufferSnapshot>()?.frame.borrow();
+        let frame = stored.as_ref()?;
+        let mut data = self.frame_buffer(frame.data.len());
+        data.copy_from_slice(&frame.data);
+        Some(FrameData { data, opaque, damage: Vec::new(), ..*frame })
+    }
+
+    /// Log a capture failure along with the client it came from
+    fn report_shm_error(&self, surface: &WlSurface, error: &ShmError) {
+        if let Some(client) = surface.client() {
+            self.report_client_error(&client, error);
+        }
+    }
+
+    fn report_client_error(&self, client: &Client, error: &ShmError) {
+        let pid = client.get_credentials(&self.display_handle).ok().map(|c| c.pid);
+        self.shm_errors.borrow_mut().report(client.id(), pid, error);
+    }
+}
+
+/// Position of a subsurface relative to its parent; zero for other surfaces
+fn subsurface_location(states: &SurfaceData) -> Point<i32, Logical> {
+    states.cached_state.get::<SubsurfaceCachedState>().current().location
+}
+
+/// Whether `region` includes every point of a surface of `size`
+///
+/// Only recognizes the common case of one added rectangle spanning the surface.
+fn region_covers(region: &RegionAttributes, size: Size<i32, Logical>) -> bool {
+    let surface = Rectangle::from_size(size);
+    region.rects.iter().all(|(kind, _)| matches!(kind, RectangleKind::Add))
+        && region.rects.iter().any(|(_, rect)| rect.contains_rect(surface))
+}
+
+/// Formats advertised on top of the two every compositor supports; each
+/// must have a converter in `rgba_converter()`
+const EXTRA_SHM_FORMATS: [wl_shm::Format; 7] = [
+    wl_shm::Format::Abgr8888,
+    wl_shm::Format::Xbgr8888,
+    wl_shm::Format::Rgb565,
+    wl_shm::Format::Xrgb2101010,
+    wl_shm::Format::Argb2101010,
+    wl_shm::Format::Xbgr2101010,
+    wl_shm::Format::Abgr2101010,
+];
+
+/// Formats accepted as dmabufs: those of shm, in linear layout
+fn dmabuf_formats() -> Vec<DmabufFormat> {
+    [wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888]
+        .into_iter()
+        .chain(EXTRA_SHM_FORMATS)
+        .filter_map(shm_format_to_fourcc)
+        .map(|code| DmabufFormat { code, modifier: Modifier::Linear })
+        .collect()
+}
+
+/// The shm format `dmabuf`'s pixels are read as, if it is laid out the
+/// way shm buffers are: linear, in one plane
+fn dmabuf_shm_format(dmabuf: &Dmabuf) -> Result<wl_shm::Format, ShmError> {
+    let format = dmabuf.format();
+    if format.modifier != Modifier::Linear {
+        return Err(ShmError::TiledDmabuf(format.modifier));
+    }
+    // Clients can only import the formats advertised, all with converters
+    fourcc_to_shm_format(format.code)
+        .ok_or_else(|| ShmError::DmabufMapping(format!("no shm equivalent of {:?}", format.code)))
+}
+
+/// Layer-shell layers drawn below the windows and above them, bottom first
+const LAYERS_BELOW: [Layer; 2] = [Layer::Background, Layer::Bottom];
+const LAYERS_ABOVE: [Layer; 2] = [Layer::Top, Layer::Overlay];
+
+/// Frame buffers kept for reuse; a frame in flight needs about one per
+/// window layer, plus the composited frame and the one queued
+const MAX_FRAME_BUFFERS: usize = 4;
+
+/// Colors of the title bar drawn over server-side decorated windows
+const TITLE_BAR_BACKGROUND: [u8; 4] = [0x30, 0x30, 0x30, 0xff];
+const TITLE_BAR_TEXT: [u8; 4] = [0xe0, 0xe0, 0xe0, 0xff];
+
+/// Conversion of one pixel to RGBA
+type ToRgba = fn(u32) -> [u8; 4];
+
+/// Bytes per pixel of `format` and the conversion from such a pixel, read
+/// as a little-endian word, to RGBA
+///
+/// The 8-bit formats convert losslessly. RGB565 is widened to 8 bits by
+/// repeating each channel's high bits in the low ones, and the 10-bit formats
+/// lose their 2 low bits. The X formats' alpha bits are padding clients may
+/// leave as anything, so their pixels come out fully opaque.
+fn rgba_converter(format: wl_shm::Format) -> Option<(usize, ToRgba)> {
+    use wl_shm::Format;
+
+    /// The `bits`-wide channel at `shift` widened to 8 bits
+    fn widened(pixel: u32, shift: u32, bits: u32) -> u8 {
+        let channel = (pixel >> shift) & ((1 << bits) - 1);
+        ((channel << (8 - bits)) | (channel >> (2 * bits - 8))) as u8
+    }
+
+    /// Top 8 of the 10 bits at `shift`
+    fn ten_bits(pixel: u32, shift: u32) -> u8 {
+        (pixel >> (shift + 2)) as u8
+    }
+    /// The 2-bit alpha widened to 8 bits
+    fn two_bit_alpha(pixel: u32) -> u8 {
+        (pixel >> 30) as u8 * 0x55
+    }
+
+    let convert: ToRgba = match format {
+        Format::Argb8888 => |p| {
+            let [b, g, r, a] = p.to_le_bytes();
+            [r, g, b, a]
+        },
+        Format::Xrgb8888 => |p| {
+            let [b, g, r, _] = p.to_le_bytes();
+            [r, g, b, 0xff]
+        },
+        Format::Abgr8888 => |p| p.to_le_bytes(),
+        Format::Xbgr8888 => |p| {
+            let [r, g, b, _] = p.to_le_bytes();
+            [r, g, b, 0xff]
+        },
+        Format::Rgb565 => return Some((2, |p| [widened(p, 11, 5), widened(p, 5, 6), widened(p, 0, 5), 0xff])),
+        Format::Argb2101010 => |p| [ten_bits(p, 20), ten_bits(p, 10), ten_bits(p, 0), two_bit_alpha(p)],
+        Format::Xrgb2101010 => |p| [ten_bits(p, 20), ten_bits(p, 10), ten_bits(p, 0), 0xff],
+        Format::Abgr2101010 => |p| [ten_bits(p, 0), ten_bits(p, 10), ten_bits(p, 20), two_bit_alpha(p)],
+        Format::Xbgr2101010 => |p| [ten_bits(p, 0), ten_bits(p, 10), ten_bits(p, 20), 0xff],
+        _ => return None,
+    };
+    Some((4, convert))
+}
+
+/// Copy of a surface's last committed buffer
+///
+/// Buffers are released as soon as they are copied, so redraws read this
+/// instead of memory the client may already be drawing the next frame into.
+#[derive(Default)]
+struct BufferSnapshot {
+    frame: RefCell<Option<FrameData>>,
+    /// What changed since the surface was last captured, in buffer pixels
+    damage: RefCell<Vec<Rectangle<i32, Buffer>>>,
+}
+
+impl BufferSnapshot {
+    fn take_damage(&self) -> Vec<Rectangle<i32, Buffer>> {
+        self.damage.take()
+    }
+}
+
+/// Where each layer of a window was drawn in its last capture
+struct LayerLayout(RefCell<Vec<Rectangle<i32, Physical>>>);
+
+/// Logical size of the latest buffer copied from a surface, and whether it
+/// is opaque, without copying its pixels
+fn snapshot_extent(states: &SurfaceData) -> Option<(Size<i32, Logical>, bool)> {
+    let stored = states.data_map.get::<BufferSnapshot>()?.frame.borrow();
+    let frame = stored.as_ref()?;
+    // The opaque region may have changed without a new buffer
+    let mut attrs = states.cached_state.get::<SurfaceAttributes>();
+    let data = attrs.current();
+    let scale = data.buffer_scale.max(1);
+    let viewport = states.cached_state.get::<ViewportCachedState>().current().size();
+    let size = viewport.unwrap_or_else(|| Size::from((frame.width as i32 / scale, frame.height as i32 / scale)));
+    let opaque = frame.opaque || data.opaque_region.as_ref().is_some_and(|region| region_covers(region, size));
+    Some((size, opaque))
+}
+
+/// The title `toplevel` last set, or an empty one
+fn toplevel_title(toplevel: &ToplevelSurface) -> String {
+    with_states(toplevel.wl_surface(), |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()
+            .and_then(|data| data.lock().unwrap().title.clone())
+            .unwrap_or_default()
+    })
+}
+
+/// `title` followed by `note` in brackets, or either alone
+fn title_with_note(title: &str, note: Option<&str>) -> String {
+    match note {
+        Some(note) if title.is_empty() => format!("[{}]", note),
+        Some(note) => format!("{} [{}]", title, note),
+        None => title.to_owned(),
+    }
+}
+
+/// OSC 0 sequence setting the terminal's title to `title`
+///
+/// Control characters are dropped: a title is the client's to choose, and
+/// an escape in it could end the sequence and start another.
+fn osc_title(title: &str) -> String {
+    let title: String = title.chars().filter(|c| !c.is_control()).collect();
+    format!("\x1b]0;{}\x07", title)
+}
+
+/// The topmost surface in `root`'s tree that takes input at `location`,
+/// with `root` at `origin`, and where that surface sits
+///
+/// A surface takes input within its size and, if it set one, its input region.
+fn surface_at(
+    root: &WlSurface,
+    origin: Point<i32, Logical>,
+    location: Point<f64, Logical>,
+) -> Option<(WlSurface, Point<i32, Logical>)> {
+    let mut found = None;
+    with_surface_tree_upward(
+        root,
+        origin,
+        |_, states, parent| TraversalAction::DoChildren(*parent + subsurface_location(states)),
+        |surface, states, parent| {
+            let Some((size, _)) = snapshot_extent(states) else {
+                return;
+            };
+            let surface_origin = *parent + subsurface_location(states);
+            let local = location - surface_origin.to_f64();
+            let mut attrs = states.cached_state.get::<SurfaceAttributes>();
+            let input_region = attrs.current().input_region.as_ref();
+            let takes_input = input_region.is_none_or(|region| region.contains(local.to_i32_floor()));
+            // Drawn bottom first, so the last one found is on top
+            if takes_input && Rectangle::from_size(size).to_f64().contains(local) {
+                found = Some((surface.clone(), surface_origin));
+            }
+        },
+        |_, _, _| true,
+    );
+    found
+}
+
+/// Top-left corner of `surface`'s window geometry, the part of it that
+/// excludes decorations such as shadows
+fn window_geometry_origin(surface: &WlSurface) -> Point<i32, Logical> {
+    with_states(surface, |states| {
+        states.cached_state.get::<SurfaceCachedState>().current().geometry.map(|geometry| geometry.loc)
+    })
+    .unwrap_or_default()
+}
+
+/// Presentation feedback requested by the latest commits in `root`'s surface tree
+fn take_presentation_feedback(root: &WlSurface) -> Vec<PresentationFeedbackCallback> {
+    let mut feedback = Vec::new();
+    with_surface_tree_upward(
+        root,
+        (),
+        |_, _, _| TraversalAction::DoChildren(()),
+        |_, states, _| {
+            let mut cached = states.cached_state.get::<PresentationFeedbackCachedState>();
+            feedback.append(&mut cached.current().callbacks);
+        },
+        |_, _, _| true,
+    );
+    feedback
+}
+
+/// Fill `height` rows of `frame` from `top` with a title bar showing `title`,
+/// centered in the largest font that fits, or from the left if too long
+fn draw_title_bar(frame: &mut FrameData, top: i32, height: i32, title: &str) {
+    let row = |y: i32| (y.max(0) as u32).min(frame.height) as usize * frame.width as usize;
+    let (start, end) = (row(top), row(top + height));
+    frame.data[start * 4..end * 4].copy_from_slice(&TITLE_BAR_BACKGROUND.repeat(end - start));
+    // A pixel of padding above and below the text
+    let scale = height.saturating_sub(2) as u32 / font::GLYPH_HEIGHT;
+    if scale == 0 {
+        return;
+    }
+    let x = ((frame.width as i32 - font::text_width(title, scale) as i32) / 2).max(scale as i32 * 2);
+    let y = top + (height - (font::GLYPH_HEIGHT * scale) as i32) / 2;
+    font::draw_text(frame, x, y, title, TITLE_BAR_TEXT, scale);
+}
+
+/// Draw `layer` over `frame` with its top-left corner at (`x`, `y`),
+/// blending by the layer's alpha and clipped to the frame
+///
+/// The alpha of an opaque layer is padding and taken as full.
+fn blend(frame: &mut FrameData, layer: &FrameData, x: i32, y: i32) {
+    let left = x.max(0);
+    let right = (x + layer.width as i32).min(frame.width as i32);
+    if left >= right {
+        return;
+    }
+    let top = y.max(0);
+    let bottom = (y + layer.height as i32).min(frame.height as i32);
+    let len = ((right - left) * 4) as usize;
+    for row in top..bottom {
+        let src = (((row - y) as u32 * layer.width + (left - x) as u32) * 4) as usize;
+        let dst = ((row as u32 * frame.width + left as u32) * 4) as usize;
+        let pixels = frame.data[dst..dst + len].chunks_exact_mut(4).zip(layer.data[src..src + len].chunks_exact(4));
+        for (dst, src) in pixels {
+            let alpha = if layer.opaque { 0xff } else { src[3] };
+            match alpha {
+                0 => {}
+                0xff => {
+                    dst[..3].copy_from_slice(&src[..3]);
+                    dst[3] = 0xff;
+                }
+                _ => {
+                    // Porter-Duff "over" on straight alpha
+                    let (a, b) = (alpha as u32, dst[3] as u32 * (255 - alpha as u32) / 255);
+                    let out = a + b;
+                    for c in 0..3 {
+                        dst[c] = ((src[c] as u32 * a + dst[c] as u32 * b + out / 2) / out) as u8;
+                    }
+                    dst[3] = out as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Copy `layer` into `frame` with its top-left corner at (`x`, `y`), clipped
+/// to the frame
+///
+/// Pixels are copied as-is; alpha is not blended.
+fn blit(frame: &mut FrameData, layer: &FrameData, x: i32, y: i32) {
+    let left = x.max(0);
+    let right = (x + layer.width as i32).min(frame.width as i32);
+    if left >= right {
+        return;
+    }
+    let top = y.max(0);
+    let bottom = (y + layer.height as i32).min(frame.height as i32);
+    for row in top..bottom {
+        let src = (((row - y) as u32 * layer.width + (left - x) as u32) * 4) as usize;
+        let dst = ((row as u32 * frame.width + left as u32) * 4) as usize;
+        let len = ((right - left) * 4) as usize;
+        frame.data[dst..dst + len].copy_from_slice(&layer.data[src..src + len]);
+    }
+}
+
+// Implement required traits
+impl BufferHandler for TermuiState {
+    fn buffer_destroyed(&mut self, _buffer: &wl_buffer::WlBuffer) {}
+}
+
+impl ShmHandler for TermuiState {
+    fn shm_state(&self) -> &ShmState {
+        &self.shm_state
+    }
+}
+
+impl WlrLayerShellHandler for TermuiState {
+    fn shell_state(&mut self) -> &mut WlrLayerShellState {
+        &mut self.layer_shell_state
+    }
+
+    fn new_layer_surface(
+        &mut self,
+        surface: WlrLayerSurface,
+        _output: Option<WlOutput>,
+        _layer: Layer,
+        namespace: String,
+    ) {
+        tracing::info!("New layer surface: {}", namespace);
+        // Whichever output it asked for, there is only ours; it is laid out
+        // and configured once it first commits
+        let layer = LayerSurface::new(surface, namespace);
+        if let Err(e) = layer_map_for_output(&self.output).map_layer(&layer) {
+            tracing::warn!("Failed to map layer surface: {}", e);
+        }
+        self.send_preferred_buffer_state(layer.wl_surface());
+    }
+
+    fn layer_destroyed(&mut self, surface: WlrLayerSurface) {
+        let mut map = layer_map_for_output(&self.output);
+        let area = map.non_exclusive_zone();
+        let layer = map.layers().find(|layer| layer.layer_surface() == &surface).cloned();
+        if let Some(layer) = &layer {
+            map.unmap_layer(layer);
+        }
+        let changed = map.non_exclusive_zone() != area;
+        drop(map);
+        if changed {
+            self.configure_windows();
+        }
+
+        // Give the keyboard back to the windows
+        let keyboard = self.seat.get_keyboard().unwrap();
+        if keyboard.current_focus().as_ref() == Some(surface.wl_surface()) {
+            let top = self.topmost().map(|top| top.wl_surface().clone());
+            keyboard.set_focus(self, top, 0.into());
+        }
+        self.redraw();
+    }
+}
+
+impl IdleInhibitHandler for TermuiState {
+    fn inhibit(&mut self, surface: WlSurface) {
+        // Inhibitors aren't destroyed along with their surfaces
+        self.idle_inhibitors.retain(|surface| surface.is_alive());
+        self.idle_inhibitors.push(surface);
+    }
+
+    fn uninhibit(&mut self, surface: WlSurface) {
+        if let Some(index) = self.idle_inhibitors.iter().position(|inhibited| inhibited == &surface) {
+            self.idle_inhibitors.swap_remove(index);
+        }
+    }
+}
+
+impl DmabufHandler for TermuiState {
+    fn dmabuf_state(&mut self) -> &mut DmabufState {
+        &mut self.dmabuf_state
+    }
+
+    fn dmabuf_imported(&mut self, _global: &DmabufGlobal, dmabuf: Dmabuf, notifier: ImportNotifier) {
+        // The formats advertised all have a single plane
+        if dmabuf.num_planes() != 1 {
+            notifier.incomplete();
+            return;
+        }
+        match dmabuf_shm_format(&dmabuf) {
+            Ok(_) => {
+                let _ = notifier.successful::<Self>();
+            }
+            Err(error) => {
+                if let Some(client) = notifier.client() {
+                    self.report_client_error(&client, &error);
+                }
+                notifier.failed();
+            }
+        }
+    }
+}
+
+impl CompositorHandler for TermuiState {
+    fn compositor_state(&mut self) -> &mut CompositorState {
+        &mut self.compositor_state
+    }
+
+    fn client_compositor_state<'a>(&self, client: &'a Client) -> &'a CompositorClientState {
+        &client.get_data::<ClientState>().unwrap().compositor_state
+    }
+
+    fn commit(&mut self, surface: &WlSurface) {
+        tracing::trace!("Surface commit");
+        self.last_activity = Instant::now();
+        self.send_preferred_buffer_state(surface);
+        // A sync subsurface's commit is only cached; it is shown once its
+        // parent commits. Anything else updates the window right away.
+        if !is_sync_subsurface(surface) {
+            let mut root = surface.clone();
+            while let Some(parent) = get_parent(&root) {
+                root = parent;
+            }
+            // Whether shown or not, every new buffer is copied and handed back
+            self.snapshot_buffers(&root);
+
+            let layer =
+                layer_map_for_output(&self.output).layer_for_surface(&root, WindowSurfaceType::TOPLEVEL).cloned();
+            if let Some(layer) = &layer {
+                self.layer_committed(layer);
+            }
+
+            // Composite a new frame when a toplevel, popup, layer surface or drawn cursor commits
+            let is_cursor = matches!(&self.cursor_status, CursorImageStatus::Surface(cursor) if cursor == &root);
+            let is_shown = self.toplevels.iter().any(|toplevel| toplevel.wl_surface() == &root)
+                || self.popups.iter().any(|popup| popup.wl_surface() == &root)
+                || layer.is_some()
+                || (is_cursor && self.show_cursor);
+            if is_shown {
+                let feedback = take_presentation_feedback(&root);
+                if self.is_covered(&root) {
+                    // Hidden under another window, so it won't be seen
+                    feedback.into_iter().for_each(PresentationFeedbackCallback::discarded);
+                } else if self.terminal_behind() {
+                    self.presentation_feedback.extend(feedback);
+                    // Skip the capture; catch_up() redoes it later
+                    self.capture_skipped = true;
+                    self.stats.borrow_mut().skipped += 1;
+                } else {
+                    self.presentation_feedback.extend(feedback);
+                    self.present();
+                }
+
+                // Send frame callbacks using smithay's proper mechanism, also
+                // for commits without a buffer (see new_toplevel)
+                let output = self.output.clone();
+                let time = self.clock.now();
+
+                send_frames_surface_tree(
+                    &root,
+                    &output,
+                    time,
+                    Some(Duration::ZERO), // Always send callbacks
+                    |_, _| Some(output.clone()),
+                );
+            }
+
+            // Bring the pointer in once the first window has something to show
+            if let Some(location) = self.initial_pointer {
+                let shown = self.topmost().is_some_and(|top| top.wl_surface() == &root)
+                    && with_states(&root, |states| snapshot_extent(states).is_some());
+                if shown {
+                    self.initial_pointer = None;
+                    self.move_pointer(location, self.clock.now_millis());
+                }
+            }
+        }
+    }
+}
+
+
+impl XdgShellHandler for TermuiState {
+    fn xdg_shell_state(&mut self) -> &mut XdgShellState {
+        &mut self.xdg_shell_state
+    }
+
+    fn new_toplevel(&mut self, surface: ToplevelSurface) {
+        tracing::info!("New toplevel surface created!");
+        // Configure the surface to our terminal size
+        let size = self.window_size(&surface);
+        surface.with_pending_state(|state| {
+            state.size = Some(size);
+            state.states.set(xdg_toplevel::State::Activated);
+            state.states.set(xdg_toplevel::State::Maximized);
+        });
+        surface.send_configure();
+        self.send_preferred_buffer_state(surface.wl_surface());
+
+        self.toplevels.push(surface.clone());
+        self.z_order.push(self.toplevels.len() - 1);
+
+        // Set keyboard focus to the new toplevel
+        let keyboard = self.seat.get_keyboard().unwrap();
+        keyboard.set_focus(self, Some(surface.wl_surface().clone()), 0.into());
+
+        // Frame callbacks are sent from the commit handler, for every commit
+        // of a toplevel's tree whether or not it brought a buffer: clients
+        // that wait for one before drawing their first frame would hang otherwise
+    }
+
+    fn new_popup(&mut self, surface: PopupSurface, positioner: PositionerState) {
+        tracing::debug!("New popup surface");
+        self.popups.push(surface.clone());
+        let geometry = self.popup_geometry(&surface, positioner);
+        surface.with_pending_state(|state| {
+            state.geometry = geometry;
+            state.positioner = positioner;
+        });
+        if let Err(e) = surface.send_configure() {
+            tracing::warn!("Failed to configure popup: {}", e);
+        }
+        self.send_preferred_buffer_state(surface.wl_surface());
+    }
+
+    fn popup_destroyed(&mut self, surface: PopupSurface) {
+        self.popups.retain(|popup| popup != &surface);
+        self.redraw();
+    }
+
+    fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
+        if let Some(removed) = self.toplevels.iter().position(|tl| tl == &surface) {
+            self.toplevels.remove(removed);
+            self.z_order.retain(|&index| index != removed);
+            for index in &mut self.z_order {
+                if *index > removed {
+                    *index -= 1;
+                }
+            }
+        }
+
+        // Hand focus to the window underneath, and show what is left
+        if let Some(top) = self.topmost() {
+            let top = top.wl_surface().clone();
+            let keyboard = self.seat.get_keyboard().unwrap();
+            keyboard.set_focus(self, Some(top), 0.into());
+            self.present();
+            return;
+        }
+
+        // If no more toplevels, exit (or go back to waiting for clients)
+        self.exit_on(ExitPolicy::LastWindow);
+        if self.running {
+            tracing::info!("Last window closed, waiting for new clients");
+            let (width, height) = (self.term_width, self.term_height);
+            self.content_crop.set(None);
+            self.queue_frame(FrameData::placeholder(width, height, "Waiting for clients...", self.background));
+        }
+    }
+
+    fn grab(&mut self, _surface: PopupSurface, _seat: wl_seat::WlSeat, _serial: smithay::utils::Serial) {}
+
+    fn title_changed(&mut self, surface: ToplevelSurface) {
+        if self.focused_toplevel() == Some(&surface) {
+            self.show_terminal_title(Some(&surface));
+        }
+        if self.title_bar_height(&surface) == 0 {
+            return;
+        }
+        // Title bars are no window's damage; forgetting the layout resends the whole frame
+        self.last_composited.borrow_mut().clear();
+        self.redraw();
+    }
+
+    fn reposition_request(&mut self, surface: PopupSurface, positioner: PositionerState, token: u32) {
+        let geometry = self.popup_geometry(&surface, positioner);
+        surface.with_pending_state(|state| {
+            state.geometry = geometry;
+            state.positioner = positioner;
+        });
+        // Configures the popup along with the repositioned event
+        surface.send_repositioned(token);
+    }
+}
+
+impl XdgDecorationHandler for TermuiState {
+    fn new_decoration(&mut self, toplevel: ToplevelSurface) {
+        self.configure_decorations(&toplevel);
+    }
+
+    fn request_mode(&mut self, toplevel: ToplevelSurface, _mode: DecorationMode) {
+        self.configure_decorations(&toplevel);
+    }
+
+    fn unset_mode(&mut self, toplevel: ToplevelSurface) {
+        self.configure_decorations(&toplevel);
+    }
+}
+
+impl SeatHandler for TermuiState {
+    type KeyboardFocus = WlSurface;
+    type PointerFocus = WlSurface;
+    type TouchFocus = WlSurface;
+
+    fn seat_state(&mut self) -> &mut SeatState<Self> {
+        &mut self.seat_state
+    }
+
+    fn focus_changed(&mut self, seat: &Seat<Self>, focused: Option<&Self::KeyboardFocus>) {
+        // The focused client is offered the selection, pasted text included
+        let client = focused.and_then(|surface| self.display_handle.get_client(surface.id()).ok());
+        set_data_device_focus(&self.display_handle, seat, client);
+
+        let toplevel = focused.and_then(|surface| self.toplevels.iter().find(|tl| tl.wl_surface() == surface));
+        if toplevel.is_some() {
+            self.show_terminal_title(toplevel);
+        }
+    }
+    fn cursor_image(&mut self, _seat: &Seat<Self>, image: CursorImageStatus) {
+        if let CursorImageStatus::Named(icon) = &image {
+            tracing::debug!("Cursor shape: {}", icon.name());
+        }
+        self.cursor_status = image;
+        if self.show_cursor {
+            self.redraw();
+        }
+    }
+}
+
+// Tablets aren't supported, but cursor-shape-v1 needs the handler
+impl TabletSeatHandler for TermuiState {}
+
+impl SelectionHandler for TermuiState {
+    /// Text pasted into the terminal, when it is the selection
+    type SelectionUserData = Arc<str>;
+
+    fn new_selection(&mut self, ty: SelectionTarget, source: Option<SelectionSource>, seat: Seat<Self>) {
+        if let (SelectionTarget::Clipboard, Some(source)) = (ty, source) {
+            clipboard::read_selection(self, &seat, source.mime_types());
+        }
+    }
+
+    fn send_selection(
+        &mut self,
+        _ty: SelectionTarget,
+        mime_type: String,
+        fd: OwnedFd,
+        _seat: Seat<Self>,
+        text: &Arc<str>,
+    ) {
+        clipboard::send_selection(self, &mime_type, fd, text.clone());
+    }
+}
+
+impl DataDeviceHandler for TermuiState {
+    fn data_device_state(&self) -> &DataDeviceState {
+        &self.data_device_state
+    }
+}
+
+impl ClientDndGrabHandler for TermuiState {}
+impl ServerDndGrabHandler for TermuiState {}
+
+impl OutputHandler for TermuiState {}
+
+delegate_compositor!(TermuiState);
+delegate_xdg_shell!(TermuiState);
+delegate_xdg_decoration!(TermuiState);
+delegate_shm!(TermuiState);
+delegate_dmabuf!(TermuiState);
+delegate_output!(TermuiState);
+delegate_seat!(TermuiState);
+delegate_data_device!(TermuiState);
+delegate_cursor_shape!(TermuiState);
+delegate_presentation!(TermuiState);
+delegate_viewporter!(TermuiState);
+delegate_idle_inhibit!(TermuiState);
+delegate_layer_shell!(TermuiState);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smithay::backend::allocator::Fourcc;
+    use smithay::reexports::calloop::EventLoop;
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::fd::AsFd;
+    use std::os::unix::net::UnixStream;
+    use wayland_client::{
+        backend::WaylandError,
+        delegate_noop,
+        protocol::{
+            wl_buffer, wl_callback, wl_compositor, wl_data_device, wl_data_device_manager, wl_data_offer, wl_keyboard,
+            wl_output, wl_pointer, wl_region, wl_registry, wl_seat, wl_shm, wl_shm_pool, wl_subcompositor, wl_subsurface,
+            wl_surface,
+        },
+        event_created_child, Connection, Dispatch, EventQueue, QueueHandle, WEnum,
+    };
+    use wayland_protocols::wp::idle_inhibit::zv1::client::{zwp_idle_inhibit_manager_v1, zwp_idle_inhibitor_v1};
+    use wayland_protocols::wp::linux_dmabuf::zv1::client::{zwp_linux_buffer_params_v1, zwp_linux_dmabuf_v1};
+    use wayland_protocols::wp::presentation_time::client::{wp_presentation, wp_presentation_feedback};
+    use wayland_protocols::wp::viewporter::client::{wp_viewport, wp_viewporter};
+    use wayland_protocols::xdg::decoration::zv1::client::{zxdg_decoration_manager_v1, zxdg_toplevel_decoration_v1};
+    use wayland_protocols::xdg::shell::client::{xdg_popup, xdg_positioner, xdg_surface, xdg_toplevel, xdg_wm_base};
+    use wayland_protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
+
+    const RED: [u8; 3] = [0xff, 0, 0];
+    const GREEN: [u8; 3] = [0, 0xff, 0];
+    const BLUE: [u8; 3] = [0, 0, 0xff];
+
+    /// Globals bound by the in-process test client
+    #[derive(Default)]
+    struct Globals {
+        compositor: Option<wl_compositor::WlCompositor>,
+        subcompositor: Option<wl_subcompositor::WlSubcompositor>,
+        shm: Option<wl_shm::WlShm>,
+        wm_base: Option<xdg_wm_base::XdgWmBase>,
+        decoration_manager: Option<zxdg_decoration_manager_v1::ZxdgDecorationManagerV1>,
+        presentation: Option<wp_presentation::WpPresentation>,
+        seat: Option<wl_seat::WlSeat>,
+        data_device_manager: Option<wl_data_device_manager::WlDataDeviceManager>,
+        viewporter: Option<wp_viewporter::WpViewporter>,
+        dmabuf: Option<zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1>,
+        idle_inhibit_manager: Option<zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1>,
+        layer_shell: Option<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+        /// Sizes from the zwlr_layer_surface_v1 configure events, each acknowledged
+        layer_configures: Vec<(u32, u32)>,
+        /// Dmabuf imports that failed, from zwp_linux_buffer_params_v1 failed events
+        failed_imports: usize,
+        /// The clipboard selection last offered
+        selection: Option<wl_data_offer::WlDataOffer>,
+        /// Timestamps of the frame callbacks received so far
+        frame_times: Vec<u32>,
+        /// Subpixel layout, make and model from the last wl_output geometry event
+        output_geometry: Option<(wl_output::Subpixel, String, String)>,
+        /// Presentation feedback received: the sequence number, or `None` if discarded
+        presentation_feedback: Vec<Option<u64>>,
+        /// Delay and rate from each wl_keyboard repeat_info event
+        repeat_info: Vec<(i32, i32)>,
+        /// Keys of the wl_keyboard key press events
+        key_presses: Vec<u32>,
+        /// Locked modifiers from the last wl_keyboard modifiers event
+        mods_locked: u32,
+        /// Buffers released by the compositor, in order
+        released: Vec<wl_buffer::WlBuffer>,
+        /// Surface-local positions of the wl_pointer enter events
+        pointer_enters: Vec<(f64, f64)>,
+    }
+
+    /// The compositor and a single client on a socket pair, both driven
+    /// from the test thread
+    struct Harness {
+        display: Display<TermuiState>,
+        state: TermuiState,
+        event_loop: EventLoop<'static, TermuiState>,
+        conn: Connection,
+        queue: EventQueue<Globals>,
+        globals: Globals,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let event_loop = EventLoop::try_new().unwrap();
+            let display = Display::new().unwrap();
+            let state = TermuiState::new(
+                &display,
+                event_loop.get_signal(),
+                event_loop.handle(),
+                8,
+                8,
+                Transform::Normal,
+                OutputIdentity {
+                    physical_size: (2, 2),
+                    subpixel: Subpixel::HorizontalBgr,
+                    make: "termui".into(),
+                    model: "test".into(),
+                },
+            );
+
+            let (server, client) = UnixStream::pair().unwrap();
+            client.set_nonblocking(true).unwrap();
+            state.live_clients.fetch_add(1, Ordering::Relaxed);
+            let client_state = ClientState {
+                compositor_state: CompositorClientState::default(),
+                live_clients: state.live_clients.clone(),
+            };
+            display.handle().insert_client(server, Arc::new(client_state)).unwrap();
+
+            let conn = Connection::from_socket(client).unwrap();
+            let queue = conn.new_event_queue();
+            conn.display().get_registry(&queue.handle(), ());
+
+            let mut harness = Self {
+                display,
+                state,
+                event_loop,
+                conn,
+                queue,
+                globals: Globals::default(),
+            };
+            harness.roundtrip();
+            harness
+        }
+
+        /// Let both sides handle everything the other has sent
+        fn roundtrip(&mut self) {
+            for _ in 0..4 {
+                self.conn.flush().unwrap();
+                self.display.dispatch_clients(&mut self.state).unwrap();
+                self.display.flush_clients().unwrap();
+                if let Some(guard) = self.queue.prepare_read() {
+                    match guard.read() {
+                        Ok(_) => {}
+                        Err(WaylandError::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                        Err(e) => panic!("Client read failed: {}", e),
+                    }
+                }
+                self.queue.dispatch_pending(&mut self.globals).unwrap();
+            }
+        }
+
+        fn create_surface(&self) -> wl_surface::WlSurface {
+            self.globals.compositor.as_ref().unwrap().create_surface(&self.queue.handle(), ())
+        }
+
+        /// A configured, still empty toplevel
+        fn toplevel(&mut self) -> (wl_surface::WlSurface, xdg_toplevel::XdgToplevel) {
+            let qh = self.queue.handle();
+            let surface = self.create_surface();
+            let xdg_surface = self.globals.wm_base.as_ref().unwrap().get_xdg_surface(&surface, &qh, ());
+            let toplevel = xdg_surface.get_toplevel(&qh, ());
+            surface.commit();
+            self.roundtrip();
+            (surface, toplevel)
+        }
+
+        fn subsurface(
+            &self,
+            surface: &wl_surface::WlSurface,
+            parent: &wl_surface::WlSurface,
+        ) -> wl_subsurface::WlSubsurface {
+            let subcompositor = self.globals.subcompositor.as_ref().unwrap();
+            subcompositor.get_subsurface(surface, parent, &self.queue.handle(), ())
+        }
+
+        /// An XRGB8888 buffer filled with one color
+        fn buffer(&self, width: u32, height: u32, rgb: [u8; 3]) -> wl_buffer::WlBuffer {
+            self.buffer_with_format(width, height, rgb, wl_shm::Format::Xrgb8888)
+        }
+
+        /// A buffer filled with one fully opaque color
+        fn buffer_with_format(
+            &self,
+            width: u32,
+            height: u32,
+            rgb: [u8; 3],
+            format: wl_shm::Format,
+        ) -> wl_buffer::WlBuffer {
+            self.buffer_with_alpha(width, height, rgb, 0xff, format)
+        }
+
+        /// A buffer filled with one color at the given alpha
+        fn buffer_with_alpha(
+            &self,
+            width: u32,
+            height: u32,
+            rgb: [u8; 3],
+            alpha: u8,
+            format: wl_shm::Format,
+        ) -> wl_buffer::WlBuffer {
+            let pixel = u32::from_le_bytes([rgb[2], rgb[1], rgb[0], alpha]);
+            self.buffer_with_pixel(width, height, pixel, format)
+        }
+
+        /// A buffer filled with one 32-bit pixel value in `format`
+        fn buffer_with_pixel(
+            &self,
+            width: u32,
+            height: u32,
+            pixel: u32,
+            format: wl_shm::Format,
+        ) -> wl_buffer::WlBuffer {
+            self.buffer_with_pixels(width, height, &vec![pixel; (width * height) as usize], format)
+        }
+
+        /// A buffer of 32-bit `pixels` in `format`, row by row
+        fn buffer_with_pixels(
+            &self,
+            width: u32,
+            height: u32,
+            pixels: &[u32],
+            format: wl_shm::Format,
+        ) -> wl_buffer::WlBuffer {
+            let qh = self.queue.handle();
+            let len = (width * height * 4) as usize;
+            let mut file =
+                File::from(rustix::fs::memfd_create("termui-test", rustix::fs::MemfdFlags::CLOEXEC).unwrap());
+            file.write_all(&pixels.iter().flat_map(|pixel| pixel.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+
+            let pool = self.globals.shm.as_ref().unwrap().create_pool(file.as_fd(), len as i32, &qh, ());
+            let buffer = pool.create_buffer(
+                0,
+                width as i32,
+                height as i32,
+                width as i32 * 4,
+                format,
+                &qh,
+                (),
+            );
+            pool.destroy();
+            buffer
+        }
+
+        /// Dmabuf parameters for one plane of 32-bit `pixels`, held in plain
+        /// memory, laid out as `modifier` says
+        fn dmabuf_params(
+            &self,
+            pixels: &[u32],
+            stride: u32,
+            modifier: Modifier,
+        ) -> zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1 {
+            let mut file =
+                File::from(rustix::fs::memfd_create("termui-test", rustix::fs::MemfdFlags::CLOEXEC).unwrap());
+            file.write_all(&pixels.iter().flat_map(|pixel| pixel.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+            let params = self.globals.dmabuf.as_ref().unwrap().create_params(&self.queue.handle(), ());
+            let modifier = u64::from(modifier);
+            params.add(file.as_fd(), 0, 0, stride, (modifier >> 32) as u32, modifier as u32);
+            params
+        }
+
+        /// Take the frame queued for the terminal, if any
+        fn take_frame(&self) -> Option<FrameData> {
+            self.state.pending_frame.lock().unwrap().take()
+        }
+    }
+
+    fn pixel(frame: &FrameData, x: u32, y: u32) -> [u8; 3] {
+        let offset = ((y * frame.width + x) * 4) as usize;
+        frame.data[offset..offset + 3].try_into().unwrap()
+    }
+
+    #[test]
+    fn sync_subsurface_updates_with_its_parent() {
+        let mut harness = Harness::new();
+        let (parent, _toplevel) = harness.toplevel();
+        let child = harness.create_surface();
+        let subsurface = harness.subsurface(&child, &parent);
+        subsurface.set_position(2, 2);
+
+        child.attach(Some(&harness.buffer(2, 2, BLUE)), 0, 0);
+        child.commit();
+        parent.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        parent.commit();
+        harness.roundtrip();
+
+        let frame = harness.take_frame().expect("parent commit shows a frame");
+        assert_eq!(pixel(&frame, 0, 0), RED);
+        assert_eq!(pixel(&frame, 2, 2), BLUE);
+        assert_eq!(pixel(&frame, 3, 3), BLUE);
+        assert_eq!(pixel(&frame, 4, 4), RED);
+
+        // The child's new content waits for the parent's next commit
+        child.attach(Some(&harness.buffer(2, 2, GREEN)), 0, 0);
+        child.commit();
+        harness.roundtrip();
+        assert!(harness.take_frame().is_none(), "sync subsurface commit was shown on its own");
+
+        parent.commit();
+        harness.roundtrip();
+        let frame = harness.take_frame().expect("parent commit shows a frame");
+        assert_eq!(pixel(&frame, 2, 2), GREEN);
+        assert_eq!(pixel(&frame, 0, 0), RED);
+    }
+
+    #[test]
+    fn buffers_are_shown_at_their_logical_size() {
+        let mut harness = Harness::new();
+        let (parent, _toplevel) = harness.toplevel();
+        let child = harness.create_surface();
+        let subsurface = harness.subsurface(&child, &parent);
+        subsurface.set_position(1, 1);
+
+        // A HiDPI window at twice the output's scale, with a subsurface at the output's
+        child.attach(Some(&harness.buffer(2, 2, BLUE)), 0, 0);
+        child.commit();
+        parent.set_buffer_scale(2);
+        parent.attach(Some(&harness.buffer(16, 16, RED)), 0, 0);
+        parent.commit();
+        harness.roundtrip();
+
+        let frame = harness.take_frame().unwrap();
+        assert_eq!((frame.width, frame.height), (8, 8));
+        assert_eq!(pixel(&frame, 0, 0), RED);
+        assert_eq!(pixel(&frame, 1, 1), BLUE);
+        assert_eq!(pixel(&frame, 2, 2), BLUE);
+        assert_eq!(pixel(&frame, 3, 3), RED);
+        assert_eq!(pixel(&frame, 7, 7), RED);
+
+        // The pointer finds the window across all of its logical size
+        harness.state.move_pointer(Point::from((7.5, 7.5)), 0);
+        let under = harness.state.surface_under_pointer().map(|(surface, origin)| (surface.id(), origin));
+        assert_eq!(under, Some((harness.state.toplevels[0].wl_surface().id(), Point::from((0.0, 0.0)))));
+    }
+
+    #[test]
+    fn rotated_buffers_are_shown_upright() {
+        let mut harness = Harness::new();
+        let (surface, _toplevel) = harness.toplevel();
+        // A 2x4 window drawn into a 4x2 buffer turned a quarter
+        surface.set_buffer_transform(wl_output::Transform::_90);
+        surface.attach(Some(&harness.buffer(4, 2, RED)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+
+        let frame = harness.take_frame().unwrap();
+        assert_eq!(pixel(&frame, 1, 3), RED);
+        assert_eq!(pixel(&frame, 3, 1), [0, 0, 0]);
+    }
+
+    #[test]
+    fn viewports_crop_and_resize_buffers() {
+        let mut harness = Harness::new();
+        let (surface, _toplevel) = harness.toplevel();
+        let viewport = harness.globals.viewporter.as_ref().unwrap().get_viewport(&surface, &harness.queue.handle(), ());
+
+        // Red on the left, green on the right; only the green half shown, at 8x4
+        let [red, green] = [RED, GREEN].map(|[r, g, b]| u32::from_le_bytes([b, g, r, 0xff]));
+        let pixels = [red, red, green, green].repeat(2);
+        viewport.set_source(2.0, 0.0, 2.0, 2.0);
+        viewport.set_destination(8, 4);
+        surface.attach(Some(&harness.buffer_with_pixels(4, 2, &pixels, wl_shm::Format::Xrgb8888)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+
+        let frame = harness.take_frame().unwrap();
+        assert_eq!(pixel(&frame, 0, 0), GREEN);
+        assert_eq!(pixel(&frame, 7, 3), GREEN);
+        assert_eq!(pixel(&frame, 0, 4), [0, 0, 0]);
+    }
+
+    #[test]
+    fn linear_dmabufs_are_read_like_shm() {
+        let mut harness = Harness::new();
+        let (surface, _toplevel) = harness.toplevel();
+        let qh = harness.queue.handle();
+        let [red, green] = [RED, GREEN].map(|[r, g, b]| u32::from_le_bytes([b, g, r, 0xff]));
+        let xrgb = Fourcc::Xrgb8888 as u32;
+
+        // Red on top and green below, drawn bottom-up
+        let pixels = [vec![red; 32], vec![green; 32]].concat();
+        let params = harness.dmabuf_params(&pixels, 8 * 4, Modifier::Linear);
+        let buffer = params.create_immed(8, 8, xrgb, zwp_linux_buffer_params_v1::Flags::YInvert, &qh, ());
+        surface.attach(Some(&buffer), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        let frame = harness.take_frame().unwrap();
+        assert_eq!(pixel(&frame, 0, 0), GREEN);
+        assert_eq!(pixel(&frame, 7, 7), RED);
+
+        // Tiled buffers are refused rather than shown scrambled
+        let params = harness.dmabuf_params(&pixels, 8 * 4, Modifier::I915_x_tiled);
+        params.create(8, 8, xrgb, zwp_linux_buffer_params_v1::Flags::empty());
+        harness.roundtrip();
+        assert_eq!(harness.globals.failed_imports, 1);
+    }
+
+    #[test]
+    fn frames_carry_the_damage_since_the_last_one() {
+        let mut harness = Harness::new();
+        let (parent, _toplevel) = harness.toplevel();
+        let child = harness.create_surface();
+        let subsurface = harness.subsurface(&child, &parent);
+        subsurface.set_desync();
+        subsurface.set_position(4, 4);
+
+        child.attach(Some(&harness.buffer(2, 2, BLUE)), 0, 0);
+        child.commit();
+        parent.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        parent.commit();
+        harness.roundtrip();
+        let rect = |x, y, w, h| Rectangle::<i32, Buffer>::new((x, y).into(), (w, h).into());
+        // The first frame is damaged everywhere
+        assert_eq!(harness.take_frame().unwrap().damage, vec![rect(0, 0, 8, 8)]);
+
+        parent.attach(Some(&harness.buffer(8, 8, GREEN)), 0, 0);
+        parent.damage_buffer(1, 2, 2, 1);
+        parent.commit();
+        harness.roundtrip();
+        assert_eq!(harness.take_frame().unwrap().damage, vec![rect(1, 2, 2, 1)]);
+
+        // Damage piles up while frames wait, and follows the subsurface
+        parent.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        parent.damage_buffer(0, 0, 1, 1);
+        parent.commit();
+        harness.roundtrip();
+        child.attach(Some(&harness.buffer(2, 2, GREEN)), 0, 0);
+        child.damage_buffer(0, 0, 2, 2);
+        child.commit();
+        harness.roundtrip();
+        let frame = harness.take_frame().unwrap();
+        assert_eq!(frame.damage, vec![rect(4, 4, 2, 2), rect(0, 0, 1, 1)]);
+        assert_eq!(pixel(&frame, 4, 4), GREEN);
+    }
+
+    #[test]
+    fn repeated_captures_reuse_frame_buffers() {
+        let mut harness = Harness::new();
+        let (surface, _toplevel) = harness.toplevel();
+        let mut pooled = Vec::new();
+        for color in [RED, GREEN, BLUE, RED, GREEN, BLUE] {
+            surface.attach(Some(&harness.buffer(8, 8, color)), 0, 0);
+            surface.commit();
+            harness.roundtrip();
+            let frame = harness.take_frame().unwrap();
+            assert_eq!(pixel(&frame, 4, 4), color);
+            harness.state.recycle_frame(frame);
+            let buffers = harness.state.frame_buffers.borrow();
+            pooled.push((buffers.len(), buffers.iter().map(Vec::capacity).sum::<usize>()));
+        }
+        // Once the first frame has filled the pool, later ones only take from
+        // it: a frame allocating anew would leave one more buffer behind
+        assert!(pooled[0].0 < MAX_FRAME_BUFFERS);
+        assert!(pooled.iter().all(|&buffers| buffers == pooled[0]), "{:?}", pooled);
+    }
+
+    #[test]
+    fn pointer_input_goes_to_the_topmost_surface_under_it() {
+        let mut harness = Harness::new();
+        let (below, _below_toplevel) = harness.toplevel();
+        let qh = harness.queue.handle();
+        let above = harness.create_surface();
+        let xdg_surface = harness.globals.wm_base.as_ref().unwrap().get_xdg_surface(&above, &qh, ());
+        let _above_toplevel = xdg_surface.get_toplevel(&qh, ());
+        above.commit();
+        harness.roundtrip();
+
+        // A 4x4 window in an 8x8 buffer with a shadow around it, which takes no input
+        xdg_surface.set_window_geometry(2, 2, 4, 4);
+        let region = harness.globals.compositor.as_ref().unwrap().create_region(&qh, ());
+        region.add(2, 2, 4, 4);
+        above.set_input_region(Some(&region));
+        // A subsurface at the window's top-left corner
+        let child = harness.create_surface();
+        let subsurface = harness.subsurface(&child, &above);
+        subsurface.set_position(2, 2);
+        child.attach(Some(&harness.buffer(2, 2, BLUE)), 0, 0);
+        child.commit();
+        above.attach(Some(&harness.buffer(8, 8, GREEN)), 0, 0);
+        above.commit();
+        below.attach(Some(&harness.buffer(6, 6, RED)), 0, 0);
+        below.commit();
+        harness.roundtrip();
+
+        // The window geometry sits at the output's origin
+        let frame = harness.take_frame().unwrap();
+        assert_eq!(pixel(&frame, 0, 0), BLUE);
+        assert_eq!(pixel(&frame, 3, 3), GREEN);
+
+        // The one client's surfaces, by protocol id
+        let mut under = |x: f64, y: f64| {
+            harness.state.move_pointer(Point::from((x, y)), 0);
+            let under = harness.state.surface_under_pointer();
+            let focus = harness.state.seat.get_pointer().unwrap().current_focus();
+            assert_eq!(focus, under.as_ref().map(|(surface, _)| surface.clone()));
+            under.map(|(surface, origin)| (surface.id().protocol_id(), origin))
+        };
+        let id = |surface: &wl_surface::WlSurface| wayland_client::Proxy::id(surface).protocol_id();
+        assert_eq!(under(0.5, 0.5), Some((id(&child), Point::from((0.0, 0.0)))));
+        assert_eq!(under(3.5, 3.5), Some((id(&above), Point::from((-2.0, -2.0)))));
+        // Through the shadow to the window below
+        assert_eq!(under(4.5, 4.5), Some((id(&below), Point::from((0.0, 0.0)))));
+        // Over no window at all, the pointer leaves the last one
+        assert_eq!(under(7.5, 7.5), None);
+    }
+
+    #[test]
+    fn overlapping_windows_are_composited_top_first() {
+        let mut harness = Harness::new();
+        let (below, _below_toplevel) = harness.toplevel();
+        let (above, _above_toplevel) = harness.toplevel();
+
+        below.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        below.commit();
+        above.attach(Some(&harness.buffer(4, 4, GREEN)), 0, 0);
+        above.commit();
+        harness.roundtrip();
+
+        let frame = harness.take_frame().unwrap();
+        assert_eq!((frame.width, frame.height), (8, 8));
+        assert_eq!(pixel(&frame, 0, 0), GREEN);
+        assert_eq!(pixel(&frame, 3, 3), GREEN);
+        assert_eq!(pixel(&frame, 4, 4), RED);
+        assert!(frame.opaque);
+
+        // Half-transparent blue over red blends the two
+        let translucent = harness.buffer_with_alpha(4, 4, BLUE, 0x80, wl_shm::Format::Argb8888);
+        above.attach(Some(&translucent), 0, 0);
+        above.commit();
+        harness.roundtrip();
+        let frame = harness.take_frame().unwrap();
+        assert_eq!(pixel(&frame, 1, 1), [0x7f, 0, 0x80]);
+
+        // Clicks reach whichever window is on top where they land
+        let pointer = harness.state.seat.get_pointer().unwrap();
+        let window = |index: usize| harness.state.toplevels[index].wl_surface().clone();
+        let (below, above) = (window(0), window(1));
+        harness.state.move_pointer(Point::from((1.0, 1.0)), 0);
+        assert_eq!(pointer.current_focus(), Some(above));
+        harness.state.move_pointer(Point::from((6.0, 6.0)), 0);
+        assert_eq!(pointer.current_focus(), Some(below));
+    }
+
+    #[test]
+    fn layer_surfaces_are_drawn_by_layer_and_keep_their_zone() {
+        let mut harness = Harness::new();
+        let qh = harness.queue.handle();
+        let (window, _toplevel) = harness.toplevel();
+        let [red, blue] = [RED, BLUE].map(|[r, g, b]| u32::from_le_bytes([b, g, r, 0xff]));
+        let pixels = [vec![red; 32], vec![blue; 32]].concat();
+        window.attach(Some(&harness.buffer_with_pixels(8, 8, &pixels, wl_shm::Format::Xrgb8888)), 0, 0);
+        window.commit();
+        harness.roundtrip();
+
+        // A bar along the top, two rows high, that windows stay out from under
+        let bar = harness.create_surface();
+        let layer_shell = harness.globals.layer_shell.clone().unwrap();
+        let top = zwlr_layer_shell_v1::Layer::Top;
+        let bar_layer = layer_shell.get_layer_surface(&bar, None, top, "bar".into(), &qh, ());
+        bar_layer.set_size(0, 2);
+        let anchor = zwlr_layer_surface_v1::Anchor::Top | zwlr_layer_surface_v1::Anchor::Left;
+        bar_layer.set_anchor(anchor | zwlr_layer_surface_v1::Anchor::Right);
+        bar_layer.set_exclusive_zone(2);
+        bar.commit();
+        harness.roundtrip();
+        assert_eq!(harness.globals.layer_configures, vec![(8, 2)]);
+        bar.attach(Some(&harness.buffer(8, 2, GREEN)), 0, 0);
+        bar.commit();
+        harness.roundtrip();
+
+        let frame = harness.take_frame().unwrap();
+        assert_eq!(pixel(&frame, 0, 1), GREEN);
+        assert_eq!(pixel(&frame, 0, 5), RED);
+        assert_eq!(pixel(&frame, 0, 6), BLUE);
+
+        // A background goes below the window
+        let wallpaper = harness.create_surface();
+        let background = zwlr_layer_shell_v1::Layer::Background;
+        let wallpaper_layer = layer_shell.get_layer_surface(&wallpaper, None, background, "wallpaper".into(), &qh, ());
+        wallpaper_layer.set_size(8, 8);
+        wallpaper.commit();
+        harness.roundtrip();
+        wallpaper.attach(Some(&harness.buffer(8, 8, GREEN)), 0, 0);
+        wallpaper.commit();
+        harness.roundtrip();
+        let frame = harness.take_frame().unwrap();
+        assert_eq!(pixel(&frame, 0, 5), RED);
+    }
+
+    #[test]
+    fn popups_are_drawn_where_their_positioner_puts_them() {
+        let mut harness = Harness::new();
+        let qh = harness.queue.handle();
+        let wm_base = harness.globals.wm_base.clone().unwrap();
+        let parent = harness.create_surface();
+        let parent_xdg = wm_base.get_xdg_surface(&parent, &qh, ());
+        let _toplevel = parent_xdg.get_toplevel(&qh, ());
+        parent.commit();
+        harness.roundtrip();
+        parent.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        parent.commit();
+        harness.roundtrip();
+
+        // A 3x2 menu dropping down from a button at (1, 2)-(3, 4)
+        let positioner = wm_base.create_positioner(&qh, ());
+        positioner.set_size(3, 2);
+        positioner.set_anchor_rect(1, 2, 2, 2);
+        positioner.set_anchor(xdg_positioner::Anchor::BottomLeft);
+        positioner.set_gravity(xdg_positioner::Gravity::BottomRight);
+        let menu = harness.create_surface();
+        let menu_xdg = wm_base.get_xdg_surface(&menu, &qh, ());
+        let popup = menu_xdg.get_popup(Some(&parent_xdg), &positioner, &qh, ());
+        menu.commit();
+        harness.roundtrip();
+        menu.attach(Some(&harness.buffer(3, 2, GREEN)), 0, 0);
+        menu.commit();
+        harness.roundtrip();
+
+        let frame = harness.take_frame().expect("popup commit shows a frame");
+        assert_eq!(pixel(&frame, 1, 4), GREEN);
+        assert_eq!(pixel(&frame, 3, 5), GREEN);
+        assert_eq!(pixel(&frame, 1, 3), RED);
+        assert_eq!(pixel(&frame, 4, 4), RED);
+
+        // The menu gets the clicks over it
+        let pointer = harness.state.seat.get_pointer().unwrap();
+        harness.state.move_pointer(Point::from((2.0, 5.0)), 0);
+        assert_eq!(pointer.current_focus(), Some(harness.state.popups[0].wl_surface().clone()));
+
+        // and is gone once destroyed
+        popup.destroy();
+        menu_xdg.destroy();
+        menu.destroy();
+        harness.roundtrip();
+        assert!(harness.state.popups.is_empty());
+        assert_eq!(pixel(&harness.take_frame().unwrap(), 1, 4), RED);
+    }
+
+    #[test]
+    fn decorated_windows_sit_below_a_title_bar() {
+        let mut harness = Harness::new();
+        harness.state.title_bar = Some(2);
+        let qh = harness.queue.handle();
+        let _pointer = harness.globals.seat.as_ref().unwrap().get_pointer(&qh, ());
+        let surface = harness.create_surface();
+        let xdg_surface = harness.globals.wm_base.as_ref().unwrap().get_xdg_surface(&surface, &qh, ());
+        let toplevel = xdg_surface.get_toplevel(&qh, ());
+        let decoration_manager = harness.globals.decoration_manager.as_ref().unwrap();
+        let _decoration = decoration_manager.get_toplevel_decoration(&toplevel, &qh, ());
+        surface.commit();
+        harness.roundtrip();
+
+        // The window is configured to the room left below the bar
+        let size = harness.state.toplevels[0].with_pending_state(|state| state.size);
+        assert_eq!(size, Some(Size::from((8, 6))));
+        surface.attach(Some(&harness.buffer(8, 6, RED)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+
+        let frame = harness.take_frame().expect("commit shows a frame");
+        let bar = [TITLE_BAR_BACKGROUND[0], TITLE_BAR_BACKGROUND[1], TITLE_BAR_BACKGROUND[2]];
+        assert_eq!(pixel(&frame, 0, 0), bar);
+        assert_eq!(pixel(&frame, 7, 1), bar);
+        assert_eq!(pixel(&frame, 0, 2), RED);
+        assert_eq!(pixel(&frame, 7, 7), RED);
+        assert!(frame.opaque);
+
+        // Clicks land on the window at its own coordinates
+        harness.state.move_pointer(Point::from((1.0, 3.0)), 0);
+        harness.roundtrip();
+        assert_eq!(harness.globals.pointer_enters.last(), Some(&(1.0, 1.0)));
+
+        // A new title resends the whole frame
+        toplevel.set_title("new".into());
+        harness.roundtrip();
+        let frame = harness.take_frame().expect("title change shows a frame");
+        assert_eq!(frame.damage, vec![Rectangle::from_size((8, 8).into())]);
+    }
+
+    #[test]
+    fn terminal_titles_carry_no_control_characters() {
+        assert_eq!(osc_title("vim"), "\x1b]0;vim\x07");
+        assert_eq!(osc_title("a\x07\x1b]0;b\nc"), "\x1b]0;a]0;bc\x07");
+    }
+
+    #[test]
+    fn the_title_note_follows_the_window_title() {
+        assert_eq!(title_with_note("vim", Some("Sixel")), "vim [Sixel]");
+        assert_eq!(title_with_note("", Some("Sixel")), "[Sixel]");
+        assert_eq!(title_with_note("vim", None), "vim");
+    }
+
+    #[test]
+    fn the_cursor_is_drawn_over_the_windows() {
+        let mut harness = Harness::new();
+        harness.state.show_cursor = true;
+        let (surface, _toplevel) = harness.toplevel();
+        surface.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+
+        // The arrow's tip is its hotspot
+        harness.state.move_pointer(Point::from((2.0, 2.0)), 0);
+        harness.state.redraw();
+        let frame = harness.take_frame().unwrap();
+        assert_eq!(pixel(&frame, 2, 2), [0, 0, 0]);
+        assert_eq!(pixel(&frame, 3, 4), [0xff, 0xff, 0xff]);
+        assert_eq!(pixel(&frame, 1, 1), RED);
+
+        // Moving it damages where it was and where it is
+        harness.state.move_pointer(Point::from((5.0, 5.0)), 0);
+        harness.state.redraw();
+        let frame = harness.take_frame().unwrap();
+        let rect = |x, y, w, h| Rectangle::<i32, Buffer>::new((x, y).into(), (w, h).into());
+        assert_eq!(frame.damage, vec![rect(2, 2, 6, 6), rect(5, 5, 3, 3)]);
+        assert_eq!(pixel(&frame, 2, 2), RED);
+
+        harness.state.cursor_status = CursorImageStatus::Hidden;
+        harness.state.redraw();
+        assert_eq!(pixel(&harness.take_frame().unwrap(), 5, 5), RED);
+    }
+
+    #[test]
+    fn cropping_to_content_offsets_the_pointer() {
+        let mut harness = Harness::new();
+        harness.state.crop_to_content = true;
+        let (parent, _toplevel) = harness.toplevel();
+        let child = harness.create_surface();
+        let subsurface = harness.subsurface(&child, &parent);
+        subsurface.set_position(2, 3);
+
+        // A 4x2 dialog in a transparent maximized window
+        child.attach(Some(&harness.buffer(4, 2, BLUE)), 0, 0);
+        child.commit();
+        let transparent = harness.buffer_with_alpha(8, 8, RED, 0, wl_shm::Format::Argb8888);
+        parent.attach(Some(&transparent), 0, 0);
+        parent.commit();
+        harness.roundtrip();
+
+        let frame = harness.take_frame().unwrap();
+        assert_eq!((frame.width, frame.height), (4, 2));
+        assert_eq!(pixel(&frame, 0, 0), BLUE);
+        let point = harness.state.display_to_logical(Point::from((1.0, 1.0)));
+        assert_eq!(point, Point::from((3.0, 4.0)));
+    }
+
+    #[test]
+    fn buffers_are_released_once_copied() {
+        let mut harness = Harness::new();
+        let (surface, _toplevel) = harness.toplevel();
+        let (front, back) = (harness.buffer(8, 8, RED), harness.buffer(8, 8, GREEN));
+
+        surface.attach(Some(&front), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        assert_eq!(harness.globals.released, vec![front.clone()], "released before the next commit");
+        assert_eq!(pixel(&harness.take_frame().unwrap(), 0, 0), RED);
+
+        // Redraws show the copy, not the released buffer the client may be reusing
+        surface.attach(Some(&back), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        assert_eq!(harness.globals.released, vec![front, back]);
+        harness.take_frame();
+        harness.state.redraw();
+        assert_eq!(pixel(&harness.take_frame().unwrap(), 0, 0), GREEN);
+    }
+
+    #[test]
+    fn desync_subsurface_updates_on_its_own_commit() {
+        let mut harness = Harness::new();
+        let (parent, _toplevel) = harness.toplevel();
+        let child = harness.create_surface();
+        let subsurface = harness.subsurface(&child, &parent);
+        subsurface.set_position(4, 0);
+        subsurface.set_desync();
+
+        parent.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        parent.commit();
+        harness.roundtrip();
+        harness.take_frame().expect("parent commit shows a frame");
+
+        child.attach(Some(&harness.buffer(2, 2, GREEN)), 0, 0);
+        child.commit();
+        harness.roundtrip();
+        let frame = harness.take_frame().expect("desync subsurface commit shows a frame");
+        assert_eq!(pixel(&frame, 4, 0), GREEN);
+        assert_eq!(pixel(&frame, 5, 1), GREEN);
+        assert_eq!(pixel(&frame, 3, 0), RED);
+    }
+
+    #[test]
+    fn frames_are_opaque_for_xrgb_or_a_full_opaque_region() {
+        let mut harness = Harness::new();
+        let (surface, _toplevel) = harness.toplevel();
+
+        surface.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        assert!(harness.take_frame().unwrap().opaque);
+
+        surface.attach(Some(&harness.buffer_with_format(8, 8, RED, wl_shm::Format::Argb8888)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        assert!(!harness.take_frame().unwrap().opaque);
+
+        let region = harness.globals.compositor.as_ref().unwrap().create_region(&harness.queue.handle(), ());
+        region.add(0, 0, 8, 8);
+        surface.set_opaque_region(Some(&region));
+        surface.commit();
+        harness.roundtrip();
+        assert!(harness.take_frame().unwrap().opaque);
+    }
+
+    #[test]
+    fn ten_bit_buffers_are_downconverted() {
+        let mut harness = Harness::new();
+        let (surface, _toplevel) = harness.toplevel();
+
+        // 10-bit channels 0x3ff, 0x200 and 0x004 become 0xff, 0x80 and 0x01
+        let xrgb = (0x3ff << 20) | (0x200 << 10) | 0x004;
+        surface.attach(Some(&harness.buffer_with_pixel(8, 8, xrgb, wl_shm::Format::Xrgb2101010)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        let frame = harness.take_frame().expect("10-bit buffer is shown");
+        assert_eq!(pixel(&frame, 1, 1), [0xff, 0x80, 0x01]);
+        assert!(frame.opaque);
+
+        // Red and blue trade places in ABGR; 2-bit alpha 0b01 becomes 0x55
+        let abgr = (0b01 << 30) | (0x004 << 20) | (0x200 << 10) | 0x3ff;
+        surface.attach(Some(&harness.buffer_with_pixel(8, 8, abgr, wl_shm::Format::Abgr2101010)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        let frame = harness.take_frame().expect("10-bit buffer is shown");
+        assert_eq!(&frame.data[..4], &[0xff, 0x80, 0x01, 0x55]);
+        assert!(!frame.opaque);
+    }
+
+    #[test]
+    fn alpha_is_kept_only_for_formats_that_have_it() {
+        let mut harness = Harness::new();
+        let (surface, _toplevel) = harness.toplevel();
+        let root = harness.state.toplevels[0].wl_surface().clone();
+
+        let mut captured_alpha = |pixel, format| {
+            surface.attach(Some(&harness.buffer_with_pixel(8, 8, pixel, format)), 0, 0);
+            surface.commit();
+            harness.roundtrip();
+            harness.state.capture_frame(&root).expect("buffer is captured").data[3]
+        };
+        let red_at_half = u32::from_le_bytes([0, 0, 0xff, 0x80]);
+        assert_eq!(captured_alpha(red_at_half, wl_shm::Format::Argb8888), 0x80);
+        // The same bits as padding
+        assert_eq!(captured_alpha(red_at_half, wl_shm::Format::Xrgb8888), 0xff);
+        assert_eq!(captured_alpha(0b01 << 30, wl_shm::Format::Argb2101010), 0x55);
+        assert_eq!(captured_alpha(0b01 << 30, wl_shm::Format::Xbgr2101010), 0xff);
+    }
+
+    #[test]
+    fn every_advertised_format_converts() {
+        use super::wl_shm::Format;
+
+        let convert = |format, pixel| rgba_converter(format).map(|(_, to_rgba)| to_rgba(pixel));
+        for format in EXTRA_SHM_FORMATS {
+            assert!(convert(format, 0).is_some(), "{:?} has no converter", format);
+        }
+
+        let rgba = u32::from_le_bytes([0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(convert(Format::Abgr8888, rgba), Some([0x11, 0x22, 0x33, 0x44]));
+        assert_eq!(convert(Format::Xbgr8888, rgba), Some([0x11, 0x22, 0x33, 0xff]));
+        assert_eq!(convert(Format::Argb8888, rgba), Some([0x33, 0x22, 0x11, 0x44]));
+        // Full channels stay full and the high bits repeat into the low ones
+        let rgb565 = (0b11111 << 11) | (0b100000 << 5) | 0b00001;
+        assert_eq!(convert(Format::Rgb565, rgb565), Some([0xff, 0x82, 0x08, 0xff]));
+    }
+
+    #[test]
+    fn rgb565_buffers_are_read_two_bytes_a_pixel() {
+        let mut harness = Harness::new();
+        let (surface, _toplevel) = harness.toplevel();
+
+        // Green and blue, alternating; the stride leaves each row half unused
+        let pixels = 0b11111 | (0b111111 << 5 << 16);
+        surface.attach(Some(&harness.buffer_with_pixel(8, 8, pixels, wl_shm::Format::Rgb565)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        let frame = harness.take_frame().expect("RGB565 buffer is shown");
+        assert_eq!((pixel(&frame, 0, 3), pixel(&frame, 1, 3)), (BLUE, GREEN));
+        assert_eq!(pixel(&frame, 7, 3), GREEN);
+        assert!(frame.opaque);
+    }
+
+    #[test]
+    fn commits_count_as_activity() {
+        let mut harness = Harness::new();
+        let (surface, _toplevel) = harness.toplevel();
+        let before = Instant::now();
+        harness.state.last_activity = before - Duration::from_secs(60);
+
+        surface.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        assert!(harness.state.last_activity >= before);
+    }
+
+    #[test]
+    fn output_geometry_reports_the_identity() {
+        let harness = Harness::new();
+        let (subpixel, make, model) = harness.globals.output_geometry.clone().expect("geometry event");
+        assert_eq!(subpixel, wl_output::Subpixel::HorizontalBgr);
+        assert_eq!((make.as_str(), model.as_str()), ("termui", "test"));
+    }
+
+    #[test]
+    fn frame_callbacks_carry_the_clock_time() {
+        let mut harness = Harness::new();
+        let clock = crate::clock::ManualClock::default();
+        harness.state.clock = Arc::new(clock.clone());
+        let (surface, _toplevel) = harness.toplevel();
+        let qh = harness.queue.handle();
+
+        clock.advance(Duration::from_millis(1000));
+        surface.frame(&qh, ());
+        surface.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        assert_eq!(harness.globals.frame_times, vec![1000]);
+
+        clock.advance(Duration::from_millis(16));
+        surface.frame(&qh, ());
+        surface.commit();
+        harness.roundtrip();
+        assert_eq!(harness.globals.frame_times, vec![1000, 1016]);
+    }
+
+    #[test]
+    fn queued_frames_wake_the_presenter() {
+        use smithay::reexports::calloop::ping::make_ping;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let mut harness = Harness::new();
+        let (ping, source) = make_ping().unwrap();
+        let wakeups = Rc::new(Cell::new(0));
+        let counter = wakeups.clone();
+        harness
+            .event_loop
+            .handle()
+            .insert_source(source, move |_, _, _| counter.set(counter.get() + 1))
+            .unwrap();
+        harness.state.frame_ready = Some(ping);
+
+        let (surface, _toplevel) = harness.toplevel();
+        surface.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        harness.event_loop.dispatch(Duration::ZERO, &mut harness.state).unwrap();
+        assert_eq!(wakeups.get(), 1);
+        assert!(harness.take_frame().is_some());
+
+        // Nothing new, nothing to wake up for
+        harness.event_loop.dispatch(Duration::ZERO, &mut harness.state).unwrap();
+        assert_eq!(wakeups.get(), 1);
+    }
+
+    #[test]
+    fn resizes_apply_once_they_settle() {
+        let mut harness = Harness::new();
+        harness.state.resize_debounce = Duration::from_millis(20);
+        let initial = (harness.state.term_width, harness.state.term_height);
+
+        harness.state.request_resize(300, 200);
+        harness.state.request_resize(320, 240);
+        harness.event_loop.dispatch(Duration::ZERO, &mut harness.state).unwrap();
+        assert_eq!((harness.state.term_width, harness.state.term_height), initial);
+
+        std::thread::sleep(Duration::from_millis(30));
+        harness.event_loop.dispatch(Duration::ZERO, &mut harness.state).unwrap();
+        assert_eq!((harness.state.term_width, harness.state.term_height), (320, 240));
+    }
+
+    #[test]
+    fn first_commit_without_a_buffer_gets_a_frame_callback() {
+        let mut harness = Harness::new();
+        let qh = harness.queue.handle();
+
+        // A client that draws only once told to, starting with the initial commit
+        let surface = harness.create_surface();
+        let xdg_surface = harness.globals.wm_base.as_ref().unwrap().get_xdg_surface(&surface, &qh, ());
+        let _toplevel = xdg_surface.get_toplevel(&qh, ());
+        surface.frame(&qh, ());
+        surface.commit();
+        harness.roundtrip();
+        assert_eq!(harness.globals.frame_times.len(), 1);
+        assert!(harness.take_frame().is_none(), "nothing to show yet");
+
+        surface.frame(&qh, ());
+        surface.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        assert_eq!(harness.globals.frame_times.len(), 2);
+        assert!(harness.take_frame().is_some());
+    }
+
+    #[test]
+    fn presentation_feedback_follows_the_shown_window() {
+        let mut harness = Harness::new();
+        let qh = harness.queue.handle();
+        let (below, _below_toplevel) = harness.toplevel();
+        let (above, _above_toplevel) = harness.toplevel();
+        let presentation = harness.globals.presentation.clone().unwrap();
+
+        // The top window's frame is shown once it goes out to the terminal
+        presentation.feedback(&above, &qh, ());
+        above.attach(Some(&harness.buffer(8, 8, GREEN)), 0, 0);
+        above.commit();
+        harness.roundtrip();
+        assert!(harness.globals.presentation_feedback.is_empty());
+
+        // The covered window's never is
+        presentation.feedback(&below, &qh, ());
+        below.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        below.commit();
+        harness.roundtrip();
+        assert_eq!(harness.globals.presentation_feedback, vec![None]);
+
+        harness.state.frame_presented(Refresh::fixed(Duration::from_millis(33)));
+        harness.roundtrip();
+        assert_eq!(harness.globals.presentation_feedback, vec![None, Some(1)]);
+
+        // A frame the terminal refused wasn't presented, and doesn't count
+        presentation.feedback(&above, &qh, ());
+        above.attach(Some(&harness.buffer(8, 8, BLUE)), 0, 0);
+        above.commit();
+        harness.roundtrip();
+        harness.state.frame_discarded();
+        harness.state.frame_presented(Refresh::fixed(Duration::from_millis(33)));
+        harness.roundtrip();
+        assert_eq!(harness.globals.presentation_feedback, vec![None, Some(1), None]);
+    }
+
+    #[test]
+    fn idle_inhibitors_end_with_them_or_their_surface() {
+        let mut harness = Harness::new();
+        let qh = harness.queue.handle();
+        let manager = harness.globals.idle_inhibit_manager.clone().unwrap();
+        let (surface, _toplevel) = harness.toplevel();
+        assert!(!harness.state.is_inhibited());
+
+        let inhibitor = manager.create_inhibitor(&surface, &qh, ());
+        harness.roundtrip();
+        assert!(harness.state.is_inhibited());
+        inhibitor.destroy();
+        harness.roundtrip();
+        assert!(!harness.state.is_inhibited());
+
+        // An inhibitor left behind by a destroyed surface no longer counts
+        let other = harness.create_surface();
+        manager.create_inhibitor(&other, &qh, ());
+        harness.roundtrip();
+        assert!(harness.state.is_inhibited());
+        other.destroy();
+        harness.roundtrip();
+        assert!(!harness.state.is_inhibited());
+    }
+
+    #[test]
+    fn inhibited_sessions_outlast_the_idle_exit() {
+        let mut harness = Harness::new();
+        let qh = harness.queue.handle();
+        let manager = harness.globals.idle_inhibit_manager.clone().unwrap();
+        let (surface, _toplevel) = harness.toplevel();
+        let idle_exit = Duration::from_secs(30);
+        harness.state.last_activity = Instant::now() - Duration::from_secs(60);
+
+        let inhibitor = manager.create_inhibitor(&surface, &qh, ());
+        harness.roundtrip();
+        assert_eq!(harness.state.check_idle(idle_exit), Some(idle_exit));
+        assert!(harness.state.running);
+
+        inhibitor.destroy();
+        harness.roundtrip();
+        assert_eq!(harness.state.check_idle(idle_exit), None);
+        assert!(!harness.state.running);
+    }
+
+    #[test]
+    fn clients_get_the_configured_key_repeat() {
+        let mut harness = Harness::new();
+        harness.state.set_key_repeat(500, 30);
+        let _keyboard = harness.globals.seat.as_ref().unwrap().get_keyboard(&harness.queue.handle(), ());
+        harness.roundtrip();
+        assert_eq!(harness.globals.repeat_info, vec![(500, 30)]);
+
+        // Keyboards already bound hear about changes
+        harness.state.set_key_repeat(300, 0);
+        harness.roundtrip();
+        assert_eq!(harness.globals.repeat_info, vec![(500, 30), (300, 0)]);
+    }
+
+    #[test]
+    fn pasted_text_is_offered_as_the_clipboard() {
+        let mut harness = Harness::new();
+        let qh = harness.queue.handle();
+        let seat = harness.globals.seat.clone().unwrap();
+        let _data_device = harness.globals.data_device_manager.as_ref().unwrap().get_data_device(&seat, &qh, ());
+        let _window = harness.toplevel();
+
+        clipboard::set_selection(&mut harness.state, "pasted".into());
+        harness.roundtrip();
+        let offer = harness.globals.selection.clone().expect("no selection offered");
+
+        let receive = |harness: &mut Harness, mime_type: &str| {
+            let (mut reader, writer) = std::io::pipe().unwrap();
+            offer.receive(mime_type.into(), writer.as_fd());
+            drop(writer);
+            harness.roundtrip();
+            harness.event_loop.dispatch(Duration::ZERO, &mut harness.state).unwrap();
+            let mut received = String::new();
+            std::io::Read::read_to_string(&mut reader, &mut received).unwrap();
+            received
+        };
+        assert_eq!(receive(&mut harness, "text/plain;charset=utf-8"), "pasted");
+        // Asking for something else reads nothing rather than hanging
+        assert_eq!(receive(&mut harness, "image/png"), "");
+    }
+
+    #[test]
+    fn held_keys_repeat_until_released() {
+        use smithay::input::keyboard::Keysym;
+
+        let mut harness = Harness::new();
+        harness.state.key_releases = true;
+        harness.state.set_key_repeat(0, 1000);
+        let _keyboard = harness.globals.seat.as_ref().unwrap().get_keyboard(&harness.queue.handle(), ());
+        let _window = harness.toplevel();
+        harness.roundtrip();
+        let keycode = |c: char| harness.state.keycodes.keycode(Keysym::new(c as u32), false).unwrap();
+        let (a, b, shift) = (keycode('a'), keycode('b'), keycode('\u{ffe1}'));
+
+        let hold = |harness: &mut Harness, keycode| {
+            harness.state.send_key(keycode, None, KeyState::Pressed, 0);
+            harness.state.start_key_repeat(keycode, None);
+            for _ in 0..3 {
+                harness.event_loop.dispatch(Duration::from_millis(5), &mut harness.state).unwrap();
+            }
+            harness.roundtrip();
+            std::mem::take(&mut harness.globals.key_presses)
+        };
+        let presses = hold(&mut harness, a);
+        assert!(presses.len() > 2 && presses.iter().all(|&key| key + 8 == a.raw()), "{:?}", presses);
+
+        // Modifiers don't repeat, nor stop the key held before them
+        let presses = hold(&mut harness, shift);
+        assert!(presses.len() > 2 && presses[1..].iter().all(|&key| key + 8 == a.raw()), "{:?}", presses);
+
+        // Another key takes over
+        let presses = hold(&mut harness, b);
+        assert!(presses.len() > 2 && presses.iter().all(|&key| key + 8 == b.raw()), "{:?}", presses);
+
+        harness.state.stop_key_repeat(Some(a));
+        harness.state.stop_key_repeat(Some(b));
+        harness.event_loop.dispatch(Duration::from_millis(5), &mut harness.state).unwrap();
+        harness.roundtrip();
+        assert!(harness.globals.key_presses.is_empty());
+    }
+
+    #[test]
+    fn clients_follow_the_host_keyboards_locks() {
+        // Real modifier masks of the default keymap
+        const LOCK: u32 = 1 << 1;
+        const NUM_LOCK: u32 = 1 << 4;
+
+        let mut harness = Harness::new();
+        let _keyboard = harness.globals.seat.as_ref().unwrap().get_keyboard(&harness.queue.handle(), ());
+        let _window = harness.toplevel();
+        harness.roundtrip();
+        assert_eq!(harness.globals.mods_locked, 0);
+
+        let mut sync = |caps_lock, num_lock| {
+            harness.state.sync_locks(LockState { caps_lock, num_lock }, 0);
+            harness.roundtrip();
+            harness.globals.mods_locked
+        };
+        assert_eq!(sync(true, false), LOCK);
+        assert_eq!(sync(true, true), LOCK | NUM_LOCK);
+        // Already in step: nothing is tapped
+        assert_eq!(sync(true, true), LOCK | NUM_LOCK);
+        assert_eq!(sync(false, true), NUM_LOCK);
+        assert_eq!(sync(false, false), 0);
+    }
+
+    #[test]
+    fn initial_pointer_enters_once_the_window_shows() {
+        let mut harness = Harness::new();
+        harness.state.initial_pointer = Some(Point::from((3.0, 4.0)));
+        let _pointer = harness.globals.seat.as_ref().unwrap().get_pointer(&harness.queue.handle(), ());
+        let (surface, _toplevel) = harness.toplevel();
+        assert!(harness.globals.pointer_enters.is_empty(), "nothing to hover yet");
+
+        surface.attach(Some(&harness.buffer(8, 8, RED)), 0, 0);
+        surface.commit();
+        harness.roundtrip();
+        assert_eq!(harness.globals.pointer_enters, vec![(3.0, 4.0)]);
+        assert_eq!(harness.state.initial_pointer, None);
+    }
+
+    #[test]
+    fn injected_keys_read_back_through_a_non_us_keymap() {
+        use smithay::backend::input::KeyState;
+        use smithay::input::keyboard::{FilterResult, Keysym};
+
+        let mut harness = Harness::new();
+        let state = &mut harness.state;
+        let keyboard = state.seat.get_keyboard().unwrap();
+        let config = XkbConfig { layout: "de", ..XkbConfig::default() };
+        keyboard.set_xkb_config(state, config).unwrap();
+        state.refresh_keycodes();
+        let shift = state.keycodes.keycode(Keysym::new(0xffe1), false).unwrap();
+        let altgr = state.keycodes.keycode(Keysym::ISO_Level3_Shift, false).unwrap();
+
+        let mut typed = String::new();
+        for c in ' '..='~' {
+            let keysym = Keysym::new(c as u32);
+            let Some(needs_shift) = state.keycodes.needs_shift(keysym) else {
+                continue; // Dead key on this layout
+            };
+            let keycode = state.keycodes.keycode(keysym, needs_shift).unwrap();
+            let needs_altgr = state.keycodes.needs_altgr(keysym, needs_shift);
+
+            let mut key = |keycode, key_state| {
+                let serial = SERIAL_COUNTER.next_serial();
+                keyboard.input(state, keycode, key_state, serial, 0, |_, _, handle| {
+                    FilterResult::Intercept(handle.modified_sym())
+                })
+            };
+            for (modifier, held) in [(shift, needs_shift), (altgr, needs_altgr)] {
+                if held {
+                    key(modifier, KeyState::Pressed);
+                }
+            }
+            let read_back = key(keycode, KeyState::Pressed).unwrap();
+            key(keycode, KeyState::Released);
+            for (modifier, held) in [(shift, needs_shift), (altgr, needs_altgr)] {
+                if held {
+                    key(modifier, KeyState::Released);
+                }
+            }
+
+            assert_eq!(read_back, keysym, "{:?} came out as {:?}", c, read_back);
+            typed.push(c);
+        }
+        assert!(typed.contains("yz") && typed.contains('/'), "typed only {:?}", typed);
+        assert!("@[\\]{|}~".chars().all(|c| typed.contains(c)), "typed only {:?}", typed);
+    }
+
+    impl Dispatch<wl_registry::WlRegistry, ()> for Globals {
+        fn event(
+            globals: &mut Self,
+            registry: &wl_registry::WlRegistry,
+            event: wl_registry::Event,
+            _: &(),
+            _: &Connection,
+            qh: &QueueHandle<Self>,
+        ) {
+            if let wl_registry::Event::Global { name, interface, .. } = event {
+                match interface.as_str() {
+                    "wl_compositor" => globals.compositor = Some(registry.bind(name, 4, qh, ())),
+                    "wl_subcompositor" => globals.subcompositor = Some(registry.bind(name, 1, qh, ())),
+                    "wl_shm" => globals.shm = Some(registry.bind(name, 1, qh, ())),
+                    "xdg_wm_base" => globals.wm_base = Some(registry.bind(name, 1, qh, ())),
+                    "zxdg_decoration_manager_v1" => globals.decoration_manager = Some(registry.bind(name, 1, qh, ())),
+                    "wp_presentation" => globals.presentation = Some(registry.bind(name, 1, qh, ())),
+                    "wp_viewporter" => globals.viewporter = Some(registry.bind(name, 1, qh, ())),
+                    "zwp_linux_dmabuf_v1" => globals.dmabuf = Some(registry.bind(name, 3, qh, ())),
+                    "zwlr_layer_shell_v1" => globals.layer_shell = Some(registry.bind(name, 1, qh, ())),
+                    "zwp_idle_inhibit_manager_v1" => {
+                        globals.idle_inhibit_manager = Some(registry.bind(name, 1, qh, ()))
+                    }
+                    "wl_seat" => globals.seat = Some(registry.bind(name, 4, qh, ())),
+                    "wl_data_device_manager" => globals.data_device_manager = Some(registry.bind(name, 3, qh, ())),
+                    "wl_output" => {
+                        registry.bind::<wl_output::WlOutput, _, _>(name, 2, qh, ());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for Globals {
+        fn event(
+            globals: &mut Self,
+            layer_surface: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+            event: zwlr_layer_surface_v1::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            if let zwlr_layer_surface_v1::Event::Configure { serial, width, height } = event {
+                layer_surface.ack_configure(serial);
+                globals.layer_configures.push((width, height));
+            }
+        }
+    }
+
+    impl Dispatch<xdg_surface::XdgSurface, ()> for Globals {
+        fn event(
+            _: &mut Self,
+            xdg_surface: &xdg_surface::XdgSurface,
+            event: xdg_surface::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            if let xdg_surface::Event::Configure { serial } = event {
+                xdg_surface.ack_configure(serial);
+            }
+        }
+    }
+
+    impl Dispatch<wl_output::WlOutput, ()> for Globals {
+        fn event(
+            globals: &mut Self,
+            _: &wl_output::WlOutput,
+            event: wl_output::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            if let wl_output::Event::Geometry { subpixel, make, model, .. } = event {
+                globals.output_geometry = Some((subpixel.into_result().unwrap(), make, model));
+            }
+        }
+    }
+
+    impl Dispatch<wl_callback::WlCallback, ()> for Globals {
+        fn event(
+            globals: &mut Self,
+            _: &wl_callback::WlCallback,
+            event: wl_callback::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            if let wl_callback::Event::Done { callback_data } = event {
+                globals.frame_times.push(callback_data);
+            }
+        }
+    }
+
+    impl Dispatch<wp_presentation_feedback::WpPresentationFeedback, ()> for Globals {
+        fn event(
+            globals: &mut Self,
+            _: &wp_presentation_feedback::WpPresentationFeedback,
+            event: wp_presentation_feedback::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            match event {
+                wp_presentation_feedback::Event::Presented { seq_hi, seq_lo, .. } => {
+                    globals.presentation_feedback.push(Some(((seq_hi as u64) << 32) | seq_lo as u64));
+                }
+                wp_presentation_feedback::Event::Discarded => globals.presentation_feedback.push(None),
+                _ => {}
+            }
+        }
+    }
+
+    impl Dispatch<wl_keyboard::WlKeyboard, ()> for Globals {
+        fn event(
+            globals: &mut Self,
+            _: &wl_keyboard::WlKeyboard,
+            event: wl_keyboard::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            match event {
+                wl_keyboard::Event::RepeatInfo { rate, delay } => globals.repeat_info.push((delay, rate)),
+                wl_keyboard::Event::Key { key, state: WEnum::Value(wl_keyboard::KeyState::Pressed), .. } => {
+                    globals.key_presses.push(key)
+                }
+                wl_keyboard::Event::Modifiers { mods_locked, .. } => globals.mods_locked = mods_locked,
+                _ => {}
+            }
+        }
+    }
+
+    impl Dispatch<wl_pointer::WlPointer, ()> for Globals {
+        fn event(
+            globals: &mut Self,
+            _: &wl_pointer::WlPointer,
+            event: wl_pointer::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            if let wl_pointer::Event::Enter { surface_x, surface_y, .. } = event {
+                globals.pointer_enters.push((surface_x, surface_y));
+            }
+        }
+    }
+
+    impl Dispatch<wl_buffer::WlBuffer, ()> for Globals {
+        fn event(
+            globals: &mut Self,
+            buffer: &wl_buffer::WlBuffer,
+            event: wl_buffer::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            if let wl_buffer::Event::Release = event {
+                globals.released.push(buffer.clone());
+            }
+        }
+    }
+
+    delegate_noop!(Globals: ignore wl_compositor::WlCompositor);
+    delegate_noop!(Globals: ignore wl_subcompositor::WlSubcompositor);
+    delegate_noop!(Globals: ignore wl_subsurface::WlSubsurface);
+    delegate_noop!(Globals: ignore wl_surface::WlSurface);
+    delegate_noop!(Globals: ignore wl_region::WlRegion);
+    delegate_noop!(Globals: ignore wl_shm::WlShm);
+    delegate_noop!(Globals: ignore wl_shm_pool::WlShmPool);
+    delegate_noop!(Globals: ignore xdg_wm_base::XdgWmBase);
+    delegate_noop!(Globals: ignore xdg_toplevel::XdgToplevel);
+    delegate_noop!(Globals: ignore xdg_popup::XdgPopup);
+    delegate_noop!(Globals: ignore xdg_positioner::XdgPositioner);
+    delegate_noop!(Globals: ignore zxdg_decoration_manager_v1::ZxdgDecorationManagerV1);
+    delegate_noop!(Globals: ignore zxdg_toplevel_decoration_v1::ZxdgToplevelDecorationV1);
+    delegate_noop!(Globals: ignore wp_presentation::WpPresentation);
+    delegate_noop!(Globals: ignore wp_viewporter::WpViewporter);
+    delegate_noop!(Globals: ignore wp_viewport::WpViewport);
+    delegate_noop!(Globals: ignore zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1);
+    delegate_noop!(Globals: ignore zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1);
+    delegate_noop!(Globals: ignore zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1);
+    delegate_noop!(Globals: ignore zwlr_layer_shell_v1::ZwlrLayerShellV1);
+
+    impl Dispatch<zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1, ()> for Globals {
+        fn event(
+            globals: &mut Self,
+            _: &zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1,
+            event: zwp_linux_buffer_params_v1::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            if let zwp_linux_buffer_params_v1::Event::Failed = event {
+                globals.failed_imports += 1;
+            }
+        }
+
+        wayland_client::event_created_child!(Globals, zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1, [
+            zwp_linux_buffer_params_v1::EVT_CREATED_OPCODE => (wl_buffer::WlBuffer, ()),
+        ]);
+    }
+    delegate_noop!(Globals: ignore wl_seat::WlSeat);
+    delegate_noop!(Globals: ignore wl_data_device_manager::WlDataDeviceManager);
+    delegate_noop!(Globals: ignore wl_data_offer::WlDataOffer);
+
+    impl Dispatch<wl_data_device::WlDataDevice, ()> for Globals {
+        fn event(
+            globals: &mut Self,
+            _: &wl_data_device::WlDataDevice,
+            event: wl_data_device::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            if let wl_data_device::Event::Selection { id } = event {
+                globals.selection = id;
+            }
+        }
+
+        event_created_child!(Globals, wl_data_device::WlDataDevice, [
+            wl_data_device::EVT_DATA_OFFER_OPCODE => (wl_data_offer::WlDataOffer, ()),
+        ]);
+    }
+}
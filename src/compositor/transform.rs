@@ -0,0 +1,514 @@
+//! Applying smithay `Transform`s and scaling to RGBA frames and pointer coordinates
+//!
+//! Pixel placement follows `Transform::transform_rect_in` for 1x1 rectangles,
+//! so frames and coordinates agree with smithay's own geometry helpers.
+
+use super::FrameData;
+use smithay::utils::{Buffer, Logical, Physical, Point, Rectangle, Size, Transform};
+use smithay::wayland::compositor::Damage;
+
+/// Apply `transform` to an RGBA frame, swapping width and height for 90/270
+pub fn transform_frame(frame: &FrameData, transform: Transform) -> FrameData {
+    if transform == Transform::Normal {
+        return frame.clone();
+    }
+
+    let (w, h) = (frame.width as usize, frame.height as usize);
+    let out_size = transform.transform_size(Size::<i32, Logical>::from((w as i32, h as i32)));
+    let out_w = out_size.w as usize;
+    let mut data = vec![0u8; frame.data.len()];
+
+    for y in 0..h {
+        for x in 0..w {
+            let (dx, dy) = match transform {
+                Transform::Normal => (x, y),
+                Transform::_90 => (h - y - 1, x),
+                Transform::_180 => (w - x - 1, h - y - 1),
+                Transform::_270 => (y, w - x - 1),
+                Transform::Flipped => (w - x - 1, y),
+                Transform::Flipped90 => (h - y - 1, w - x - 1),
+                Transform::Flipped180 => (x, h - y - 1),
+                Transform::Flipped270 => (y, x),
+            };
+            let src = (y * w + x) * 4;
+            let dst = (dy * out_w + dx) * 4;
+            data[dst..dst + 4].copy_from_slice(&frame.data[src..src + 4]);
+        }
+    }
+
+    let size = Size::<i32, Buffer>::from((w as i32, h as i32));
+    FrameData {
+        width: out_size.w as u32,
+        height: out_size.h as u32,
+        data,
+        opaque: frame.opaque,
+        damage: frame.damage.iter().map(|&rect| transform.transform_rect_in(rect, &size)).collect(),
+    }
+}
+
+/// Turn a buffer the client drew with `buffer_transform` the way its
+/// surface shows it
+///
+/// This applies the inverse transform, which is how smithay maps buffer
+/// coordinates to surface ones (`Rectangle::to_logical`), so pixels and
+/// damage agree.
+pub fn upright_buffer(frame: &FrameData, buffer_transform: Transform) -> FrameData {
+    transform_frame(frame, buffer_transform.invert())
+}
+
+/// Bounding box of the pixels that aren't fully transparent
+///
+/// `None` if the frame is opaque, so all of it counts, or if nothing in it
+/// is visible.
+pub fn content_bounds(frame: &FrameData) -> Option<Rectangle<i32, Physical>> {
+    if frame.opaque {
+        return None;
+    }
+    let (mut left, mut top, mut right, mut bottom) = (frame.width, frame.height, 0, 0);
+    for (y, row) in frame.data.chunks_exact((frame.width * 4) as usize).enumerate() {
+        let mut visible = row.chunks_exact(4).enumerate().filter(|(_, pixel)| pixel[3] != 0).map(|(x, _)| x as u32);
+        let Some(first) = visible.next() else {
+            continue;
+        };
+        let last = visible.next_back().unwrap_or(first);
+        left = left.min(first);
+        right = right.max(last + 1);
+        top = top.min(y as u32);
+        bottom = y as u32 + 1;
+    }
+    (left < right).then(|| {
+        Rectangle::new((left as i32, top as i32).into(), ((right - left) as i32, (bottom - top) as i32).into())
+    })
+}
+
+/// Copy the part of `frame` inside `rect`, which must lie within the frame
+pub fn crop_frame(frame: &FrameData, rect: Rectangle<i32, Physical>) -> FrameData {
+    let (x, width) = (rect.loc.x as u32, rect.size.w as u32);
+    let mut data = Vec::with_capacity((width * rect.size.h as u32 * 4) as usize);
+    for row in rect.loc.y as u32..(rect.loc.y + rect.size.h) as u32 {
+        let start = ((row * frame.width + x) * 4) as usize;
+        data.extend_from_slice(&frame.data[start..start + (width * 4) as usize]);
+    }
+    // Damage moves with the crop (none left inside means the whole frame
+    // counts as damaged, like any frame without damage)
+    let crop = Rectangle::<i32, Buffer>::new((rect.loc.x, rect.loc.y).into(), (rect.size.w, rect.size.h).into());
+    let damage = frame
+        .damage
+        .iter()
+        .filter_map(|damage| damage.intersection(crop))
+        .map(|damage| Rectangle::new(damage.loc - crop.loc, damage.size))
+        .collect();
+    FrameData {
+        width,
+        height: rect.size.h as u32,
+        data,
+        opaque: frame.opaque,
+        damage,
+    }
+}
+
+/// Map a point on the transformed (displayed) plane back to the logical plane
+///
+/// `display_size` is the size of the plane after the transform was applied.
+/// Only pure rotations are supported, since `Transform::invert` does not
+/// invert the flipped variants.
+pub fn display_to_logical(
+    point: Point<f64, Logical>,
+    display_size: Size<f64, Logical>,
+    transform: Transform,
+) -> Point<f64, Logical> {
+    transform.invert().transform_point_in(point, &display_size)
+}
+
+/// Map a commit's damage onto the frame read from its buffer, in the
+/// frame's pixels
+///
+/// Frames are turned upright as they are read (`upright_buffer`), so
+/// `damage_buffer` rectangles go through the inverse of the buffer transform,
+/// while surface-local `damage` only needs scaling up by the buffer scale.
+/// `frame_size` is the size of the upright frame.
+pub fn damage_to_frame(
+    damage: Damage,
+    frame_size: Size<i32, Buffer>,
+    buffer_scale: i32,
+    buffer_transform: Transform,
+) -> Rectangle<i32, Buffer> {
+    match damage {
+        Damage::Buffer(rect) => {
+            let buffer_size = buffer_transform.transform_size(frame_size);
+            buffer_transform.invert().transform_rect_in(rect, &buffer_size)
+        }
+        Damage::Surface(rect) => {
+            let scale = buffer_scale.max(1);
+            rect.to_buffer(scale, Transform::Normal, &frame_size.to_logical(scale, Transform::Normal))
+        }
+    }
+}
+
+/// Enlarge RGBA image data by a whole `factor`, each pixel becoming a block
+pub fn scale_nearest(data: &[u8], width: u32, height: u32, factor: u32) -> Vec<u8> {
+    let mut result = Vec::new();
+    scale_nearest_into(data, width, height, factor, &mut result);
+    result
+}
+
+/// `scale_nearest()` into `result`, reusing its memory
+pub fn scale_nearest_into(data: &[u8], width: u32, height: u32, factor: u32, result: &mut Vec<u8>) {
+    result.clear();
+    if factor <= 1 {
+        result.extend_from_slice(data);
+        return;
+    }
+    let row_len = (width * factor * 4) as usize;
+    result.reserve(row_len * (height * factor) as usize);
+    for row in data.chunks_exact((width * 4) as usize) {
+        let start = result.len();
+        for pixel in row.chunks_exact(4) {
+            for _ in 0..factor {
+                result.extend_from_slice(pixel);
+            }
+        }
+        for _ in 1..factor {
+            result.extend_from_within(start..start + row_len);
+        }
+    }
+}
+
+/// Scale RGBA image data to any size by picking the source pixel nearest
+/// each destination pixel's center, into `result`
+///
+/// Much cheaper than `scale_image()`, at the cost of jagged edges and
+/// dropped detail when shrinking.
+#[tracing::instrument(level = "debug", skip(data, result))]
+pub fn scale_image_nearest_into(
+    data: &[u8],
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+    result: &mut Vec<u8>,
+) {
+    result.clear();
+    if src_width == 0 || src_height == 0 {
+        result.resize((dst_width * dst_height * 4) as usize, 0);
+        return;
+    }
+    let nearest = |dst: u32, src_len: u32, dst_len: u32| {
+        ((2 * dst as u64 + 1) * src_len as u64 / (2 * dst_len as u64)) as usize
+    };
+    let columns: Vec<usize> = (0..dst_width).map(|x| nearest(x, src_width, dst_width) * 4).collect();
+
+    result.reserve((dst_width * dst_height * 4) as usize);
+    for dst_y in 0..dst_height {
+        let row = &data[nearest(dst_y, src_height, dst_height) * src_width as usize * 4..];
+        for &column in &columns {
+            result.extend_from_slice(&row[column..column + 4]);
+        }
+    }
+}
+
+/// Scale RGBA image data using bilinear interpolation
+///
+/// Colors are interpolated premultiplied by alpha, so transparent pixels
+/// don't darken the edges of what they border. Opaque images, whose alpha
+/// may just be padding, and images without any transparency skip that.
+pub fn scale_image(
+    data: &[u8],
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+    opaque: bool,
+) -> Vec<u8> {
+    let mut result = Vec::new();
+    scale_image_into(data, src_width, src_height, dst_width, dst_height, opaque, &mut result);
+    result
+}
+
+/// `scale_image()` into `result`, reusing its memory
+#[tracing::instrument(level = "debug", skip(data, result))]
+pub fn scale_image_into(
+    data: &[u8],
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+    opaque: bool,
+    result: &mut Vec<u8>,
+) {
+    result.clear();
+    result.resize((dst_width * dst_height * 4) as usize, 0);
+    if src_width == 0 || src_height == 0 {
+        return;
+    }
+
+    let opaque = opaque || data.chunks_exact(4).all(|pixel| pixel[3] == 0xff);
+    let x_ratio = src_width as f32 / dst_width as f32;
+    let y_ratio = src_height as f32 / dst_height as f32;
+
+    fill_rows(result, (dst_width * 4) as usize, |dst_y, row| {
+        let src_y = dst_y as f32 * y_ratio;
+        let y0 = src_y.floor() as u32;
+        let y1 = (y0 + 1).min(src_height - 1);
+        let y_frac = src_y - y0 as f32;
+
+        for (dst_x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let src_x = dst_x as f32 * x_ratio;
+            let x0 = src_x.floor() as u32;
+            let x1 = (x0 + 1).min(src_width - 1);
+            let x_frac = src_x - x0 as f32;
+
+            let corners = [
+                ((y0 * src_width + x0) * 4) as usize,
+                ((y0 * src_width + x1) * 4) as usize,
+                ((y1 * src_width + x0) * 4) as usize,
+                ((y1 * src_width + x1) * 4) as usize,
+            ];
+            // Channel `c` at the four corners, scaled by alpha unless opaque
+            let sample = |c: usize| {
+                let value = |i: usize| {
+                    if opaque || c == 3 {
+                        data[i + c] as f32
+                    } else {
+                        data[i + c] as f32 * data[i + 3] as f32 / 255.0
+                    }
+                };
+                let [p00, p10, p01, p11] = corners.map(value);
+                let top = p00 * (1.0 - x_frac) + p10 * x_frac;
+                let bottom = p01 * (1.0 - x_frac) + p11 * x_frac;
+                top * (1.0 - y_frac) + bottom * y_frac
+            };
+
+            if opaque {
+                for (c, channel) in pixel.iter_mut().enumerate() {
+                    *channel = sample(c) as u8;
+                }
+            } else {
+                let alpha = sample(3);
+                if alpha > 0.0 {
+                    for (c, channel) in pixel[..3].iter_mut().enumerate() {
+                        *channel = (sample(c) * 255.0 / alpha).round().min(255.0) as u8;
+                    }
+                }
+                pixel[3] = alpha as u8;
+            }
+        }
+    });
+}
+
+/// Call `fill_row(y, row)` for every `row_len` bytes long row of `data`
+///
+/// Rows are filled on rayon's thread pool with the `parallel` feature, and
+/// one after another without it.
+pub fn fill_rows(data: &mut [u8], row_len: usize, fill_row: impl Fn(usize, &mut [u8]) + Send + Sync) {
+    if row_len == 0 {
+        return;
+    }
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        data.par_chunks_mut(row_len).enumerate().for_each(|(y, row)| fill_row(y, row));
+    }
+    #[cfg(not(feature = "parallel"))]
+    data.chunks_mut(row_len).enumerate().for_each(|(y, row)| fill_row(y, row));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn content_bounds_skip_transparent_margins() {
+        // 5x4 frame with visible pixels at (1, 1) and (3, 2)
+        let mut frame = FrameData { width: 5, height: 4, data: vec![0; 5 * 4 * 4], opaque: false, damage: Vec::new() };
+        frame.data[(5 + 1) * 4 + 3] = 0xff;
+        frame.data[(2 * 5 + 3) * 4..(2 * 5 + 3) * 4 + 4].copy_from_slice(&[7, 7, 7, 0x80]);
+
+        let bounds = content_bounds(&frame).unwrap();
+        assert_eq!(bounds, Rectangle::new((1, 1).into(), (3, 2).into()));
+        let cropped = crop_frame(&frame, bounds);
+        assert_eq!((cropped.width, cropped.height), (3, 2));
+        assert_eq!(&cropped.data[(3 + 2) * 4..], &[7, 7, 7, 0x80]);
+
+        assert_eq!(content_bounds(&FrameData { opaque: true, ..frame.clone() }), None);
+        assert_eq!(content_bounds(&FrameData { data: vec![0; 5 * 4 * 4], ..frame }), None);
+    }
+
+    #[test]
+    fn buffers_are_turned_upright_for_every_transform() {
+        // 3x2 buffer of pixels numbered 1 to 6, row by row
+        let buffer = FrameData {
+            width: 3,
+            height: 2,
+            data: (1..=6).flat_map(|value| [value, 0, 0, 0xff]).collect(),
+            opaque: true,
+            damage: Vec::new(),
+        };
+        let cases: [(Transform, &[&[u8]]); 8] = [
+            (Transform::Normal, &[&[1, 2, 3], &[4, 5, 6]]),
+            (Transform::_90, &[&[3, 6], &[2, 5], &[1, 4]]),
+            (Transform::_180, &[&[6, 5, 4], &[3, 2, 1]]),
+            (Transform::_270, &[&[4, 1], &[5, 2], &[6, 3]]),
+            (Transform::Flipped, &[&[3, 2, 1], &[6, 5, 4]]),
+            (Transform::Flipped90, &[&[1, 4], &[2, 5], &[3, 6]]),
+            (Transform::Flipped180, &[&[4, 5, 6], &[1, 2, 3]]),
+            (Transform::Flipped270, &[&[6, 3], &[5, 2], &[4, 1]]),
+        ];
+        for (transform, rows) in cases {
+            let upright = upright_buffer(&buffer, transform);
+            let shown: Vec<Vec<u8>> = upright
+                .data
+                .chunks(upright.width as usize * 4)
+                .map(|row| row.chunks(4).map(|pixel| pixel[0]).collect())
+                .collect();
+            assert_eq!(shown, rows, "{:?}", transform);
+        }
+    }
+
+    #[test]
+    fn click_round_trips_through_90_degree_rotation() {
+        // Logical 4x2 frame with a single marked pixel at (3, 0)
+        let mut frame = FrameData { width: 4, height: 2, data: vec![0; 4 * 2 * 4], opaque: true, damage: Vec::new() };
+        let (lx, ly) = (3usize, 0usize);
+        frame.data[(ly * 4 + lx) * 4] = 0xff;
+        frame.damage.push(Rectangle::new((lx as i32, ly as i32).into(), (1, 1).into()));
+
+        let shown = transform_frame(&frame, Transform::_90);
+        assert_eq!((shown.width, shown.height), (2, 4));
+
+        // Find where the marked pixel ended up on the displayed plane
+        let index = shown.data.chunks(4).position(|px| px[0] == 0xff).unwrap();
+        let (sx, sy) = (index % 2, index / 2);
+        // Its damage moved with it
+        assert_eq!(shown.damage, vec![Rectangle::new((sx as i32, sy as i32).into(), (1, 1).into())]);
+
+        // Clicking the centre of that pixel must land on the original pixel
+        let click = Point::from((sx as f64 + 0.5, sy as f64 + 0.5));
+        let logical = display_to_logical(click, Size::from((2.0, 4.0)), Transform::_90);
+        assert_eq!((logical.x.floor() as usize, logical.y.floor() as usize), (lx, ly));
+    }
+
+    #[test]
+    fn nearest_scaling_turns_pixels_into_blocks() {
+        let data = [1, 1, 1, 1, 2, 2, 2, 2];
+        let scaled = scale_nearest(&data, 2, 1, 2);
+        let pixels: Vec<u8> = scaled.chunks(4).map(|p| p[0]).collect();
+        assert_eq!(pixels, vec![1, 1, 2, 2, 1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn nearest_scaling_picks_the_closest_pixel() {
+        let data: Vec<u8> = (1..=4).flat_map(|value| [value; 4]).collect();
+        let mut scaled = vec![0xff; 64];
+        let mut pixels = |width, height, dst_width, dst_height| {
+            scale_image_nearest_into(&data, width, height, dst_width, dst_height, &mut scaled);
+            scaled.chunks(4).map(|p| p[0]).collect::<Vec<u8>>()
+        };
+        assert_eq!(pixels(4, 1, 2, 1), vec![2, 4]);
+        assert_eq!(pixels(4, 1, 6, 1), vec![1, 2, 2, 3, 4, 4]);
+        assert_eq!(pixels(2, 2, 1, 1), vec![4]);
+    }
+
+    /// Scaling a 1920x1080 frame down to 1280x720, each way
+    ///
+    /// `cargo test --release scaling_cost -- --ignored --nocapture`; a
+    /// release build here took about 66ms bilinear and 2ms nearest.
+    #[test]
+    #[ignore]
+    fn scaling_cost() {
+        let (width, height) = (1920, 1080);
+        let frame: Vec<u8> = (0..width * height).flat_map(|i| [(i % 251) as u8, (i % 241) as u8, 0x80, 0xff]).collect();
+        let runs = 10;
+
+        let started = Instant::now();
+        for _ in 0..runs {
+            std::hint::black_box(scale_image(&frame, width, height, 1280, 720, true));
+        }
+        println!("bilinear: {:?} per frame", started.elapsed() / runs);
+        let started = Instant::now();
+        for _ in 0..runs {
+            let mut scaled = Vec::new();
+            scale_image_nearest_into(&frame, width, height, 1280, 720, &mut scaled);
+            std::hint::black_box(scaled);
+        }
+        println!("nearest: {:?} per frame", started.elapsed() / runs);
+    }
+
+    /// Scaling a 1920x1080 frame down to 1280x720 on one thread and on all of them
+    ///
+    /// `cargo test --release --features parallel parallel_scaling -- --ignored --nocapture`;
+    /// the rows are independent, so the time should divide by the number of
+    /// cores. The single-core machine this was written on only has one thread
+    /// to offer, and both took about 60ms.
+    #[cfg(feature = "parallel")]
+    #[test]
+    #[ignore]
+    fn parallel_scaling() {
+        let (width, height) = (1920, 1080);
+        let frame: Vec<u8> = (0..width * height).flat_map(|i| [(i % 251) as u8, (i % 241) as u8, 0x80, 0xff]).collect();
+        let runs = 10;
+
+        let one_thread = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let all_threads = rayon::ThreadPoolBuilder::new().build().unwrap();
+        for (name, pool) in [("1 thread", one_thread), ("all threads", all_threads)] {
+            let started = Instant::now();
+            pool.install(|| {
+                for _ in 0..runs {
+                    std::hint::black_box(scale_image(&frame, width, height, 1280, 720, true));
+                }
+            });
+            println!("{} ({}): {:?} per frame", name, pool.current_num_threads(), started.elapsed() / runs);
+        }
+    }
+
+    #[test]
+    fn scaling_does_not_darken_transparent_edges() {
+        // Opaque red next to transparent black
+        let data = [0xff, 0, 0, 0xff, 0, 0, 0, 0];
+        let scaled = scale_image(&data, 2, 1, 4, 1, false);
+        let blended: Vec<&[u8]> = scaled.chunks(4).filter(|px| px[3] != 0 && px[3] != 0xff).collect();
+        assert!(!blended.is_empty());
+        for pixel in blended {
+            assert_eq!(&pixel[..3], &[0xff, 0, 0], "color bled into {:?}", pixel);
+        }
+    }
+
+    #[test]
+    fn scaling_from_or_to_nothing_does_not_panic() {
+        assert_eq!(scale_image(&[], 0, 0, 2, 1, false), vec![0; 8]);
+        assert!(scale_image(&[0xff; 16], 2, 2, 0, 3, false).is_empty());
+    }
+
+    #[test]
+    fn surface_damage_at_buffer_scale_2_covers_twice_the_pixels() {
+        let damage = damage_to_frame(
+            Damage::Surface(Rectangle::new((10, 5).into(), (6, 4).into())),
+            Size::from((200, 100)),
+            2,
+            Transform::Normal,
+        );
+        assert_eq!(damage, Rectangle::new((20, 10).into(), (12, 8).into()));
+        // Buffer damage is already in the frame's pixels
+        let damage = damage_to_frame(
+            Damage::Buffer(Rectangle::new((21, 11).into(), (10, 10).into())),
+            Size::from((200, 100)),
+            2,
+            Transform::Normal,
+        );
+        assert_eq!(damage, Rectangle::new((21, 11).into(), (10, 10).into()));
+    }
+
+    #[test]
+    fn damage_under_90_degree_buffer_transform() {
+        // A 100x50 frame submitted as a 50x100 buffer rotated by 90 degrees
+        let damage = damage_to_frame(
+            Damage::Buffer(Rectangle::new((0, 0).into(), (10, 20).into())),
+            Size::from((100, 50)),
+            1,
+            Transform::_90,
+        );
+        // The buffer's top-left corner is the frame's bottom-left corner
+        assert_eq!(damage, Rectangle::new((0, 40).into(), (20, 10).into()));
+    }
+}
@@ -0,0 +1,128 @@
+//! Magnifying part of the displayed frame
+//!
+//! The view is a sub-rectangle of the frame that gets scaled up to the full
+//! frame size before it goes to the terminal. Pointer positions on the
+//! terminal are mapped back through it, so clicks land where they appear.
+
+use super::transform::{crop_frame, scale_image};
+use super::FrameData;
+use crate::terminal::ZoomAction;
+use smithay::utils::{Logical, Point, Rectangle, Size};
+
+const MAX_FACTOR: u32 = 8;
+
+/// Current magnification and the centre of the view
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Zoom {
+    /// 1 shows the whole frame; doubles with every zoom step
+    factor: u32,
+    /// Centre of the view as a fraction of the frame size
+    center: (f64, f64),
+}
+
+impl Default for Zoom {
+    fn default() -> Self {
+        Self { factor: 1, center: (0.5, 0.5) }
+    }
+}
+
+impl Zoom {
+    pub fn is_zoomed(&self) -> bool {
+        self.factor > 1
+    }
+
+    /// Apply a key binding; returns whether the view changed
+    pub fn apply(&mut self, action: ZoomAction) -> bool {
+        let before = *self;
+        match action {
+            ZoomAction::In => self.factor = (self.factor * 2).min(MAX_FACTOR),
+            ZoomAction::Out => self.factor = (self.factor / 2).max(1),
+            ZoomAction::Pan { dx, dy } => {
+                let view = 1.0 / self.factor as f64;
+                self.center = (self.center.0 + dx * view, self.center.1 + dy * view);
+            }
+            ZoomAction::Reset => *self = Self::default(),
+        }
+        // Keep the view inside the frame
+        let half = 0.5 / self.factor as f64;
+        self.center = (self.center.0.clamp(half, 1.0 - half), self.center.1.clamp(half, 1.0 - half));
+        *self != before
+    }
+
+    /// Top-left corner and size of the view on a frame of `size`
+    fn view(&self, size: Size<f64, Logical>) -> (Point<f64, Logical>, Size<f64, Logical>) {
+        let factor = self.factor as f64;
+        let view = Size::from((size.w / factor, size.h / factor));
+        let origin = Point::from((self.center.0 * size.w - view.w / 2.0, self.center.1 * size.h - view.h / 2.0));
+        (origin, view)
+    }
+
+    /// Crop `frame` to the view and scale it back up to the frame size
+    pub fn magnify(&self, frame: &FrameData) -> FrameData {
+        if frame.width == 0 || frame.height == 0 {
+            return frame.clone();
+        }
+        let (origin, view) = self.view(Size::from((frame.width as f64, frame.height as f64)));
+        let x = (origin.x.round() as u32).min(frame.width - 1);
+        let y = (origin.y.round() as u32).min(frame.height - 1);
+        let width = (view.w.round() as u32).clamp(1, frame.width - x);
+        let height = (view.h.round() as u32).clamp(1, frame.height - y);
+
+        let cropped = crop_frame(frame, Rectangle::new((x as i32, y as i32).into(), (width as i32, height as i32).into()));
+
+        FrameData {
+            width: frame.width,
+            height: frame.height,
+            data: scale_image(&cropped.data, width, height, frame.width, frame.height, frame.opaque),
+            opaque: frame.opaque,
+            damage: Vec::new(),
+        }
+    }
+
+    /// Map a point on the magnified display of `size` to the unmagnified one
+    pub fn display_to_frame(&self, point: Point<f64, Logical>, size: Size<f64, Logical>) -> Point<f64, Logical> {
+        let (origin, _) = self.view(size);
+        let factor = self.factor as f64;
+        Point::from((origin.x + point.x / factor, origin.y + point.y / factor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn view_stays_inside_the_frame() {
+        let mut zoom = Zoom::default();
+        assert!(!zoom.apply(ZoomAction::Pan { dx: -1.0, dy: 0.0 }), "nothing to pan at 1x");
+
+        assert!(zoom.apply(ZoomAction::In));
+        assert!(zoom.apply(ZoomAction::Pan { dx: -10.0, dy: 10.0 }));
+        let size = Size::from((800.0, 600.0));
+        // 2x on the bottom-left quarter
+        assert_eq!(zoom.display_to_frame(Point::from((0.0, 0.0)), size), Point::from((0.0, 300.0)));
+        assert_eq!(zoom.display_to_frame(Point::from((800.0, 600.0)), size), Point::from((400.0, 600.0)));
+
+        assert!(zoom.apply(ZoomAction::Reset));
+        assert_eq!(zoom, Zoom::default());
+    }
+
+    #[test]
+    fn magnified_pixels_are_where_clicks_map_to() {
+        // 4x4 frame with one marked pixel at (3, 1)
+        let mut frame = FrameData { width: 4, height: 4, data: vec![0; 4 * 4 * 4], opaque: true, damage: Vec::new() };
+        frame.data[(4 + 3) * 4] = 0xff;
+
+        let mut zoom = Zoom::default();
+        zoom.apply(ZoomAction::In);
+        zoom.apply(ZoomAction::Pan { dx: 1.0, dy: -1.0 });
+        let shown = zoom.magnify(&frame);
+        assert_eq!((shown.width, shown.height), (4, 4));
+
+        // The marked pixel now covers the 2x2 block at (2, 2)
+        let size = Size::from((4.0, 4.0));
+        let frame_point = zoom.display_to_frame(Point::from((2.5, 2.5)), size);
+        assert_eq!((frame_point.x.floor(), frame_point.y.floor()), (3.0, 1.0));
+        assert_eq!(shown.data[(2 * 4 + 2) * 4], 0xff);
+    }
+}
@@ -0,0 +1,105 @@
+//! Optional HTTP endpoint exposing the latest frame and compositor status
+//!
+//! - `GET /frame.png` - last frame sent to the terminal, PNG encoded
+//! - `GET /status`    - JSON summary (toplevels, titles, fps, dimensions)
+
+use anyhow::{anyhow, Result};
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use termui::compositor::FrameData;
+use tiny_http::{Header, Response, Server};
+
+/// Compositor status reported by `/status`
+#[derive(Default)]
+pub struct DebugStatus {
+    pub toplevel_titles: Vec<String>,
+    pub fps: f64,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Latest state published by the frame timer for the HTTP thread
+#[derive(Default)]
+pub struct DebugSnapshot {
+    pub frame: Option<FrameData>,
+    pub status: DebugStatus,
+}
+
+pub type SharedSnapshot = Arc<Mutex<DebugSnapshot>>;
+
+/// Bind `addr` and serve requests from a background thread
+pub fn spawn(addr: &str, snapshot: SharedSnapshot) -> Result<()> {
+    let server = Server::http(addr).map_err(|e| anyhow!("Failed to bind debug HTTP on {}: {}", addr, e))?;
+    tracing::info!("Debug HTTP listening on http://{}", addr);
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match request.url() {
+                "/frame.png" => frame_response(&snapshot),
+                "/status" => status_response(&snapshot),
+                _ => Response::from_string("not found").with_status_code(404),
+            };
+            if let Err(e) = request.respond(response) {
+                tracing::debug!("Debug HTTP response failed: {:?}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn frame_response(snapshot: &SharedSnapshot) -> Response<Cursor<Vec<u8>>> {
+    let frame = snapshot.lock().unwrap().frame.clone();
+    let Some(frame) = frame else {
+        return Response::from_string("no frame yet").with_status_code(503);
+    };
+
+    match encode_png(&frame) {
+        Ok(png) => Response::from_data(png).with_header(header("Content-Type", "image/png")),
+        Err(e) => Response::from_string(format!("{}", e)).with_status_code(500),
+    }
+}
+
+fn status_response(snapshot: &SharedSnapshot) -> Response<Cursor<Vec<u8>>> {
+    let snapshot = snapshot.lock().unwrap();
+    let status = &snapshot.status;
+
+    let titles: Vec<String> = status.toplevel_titles.iter().map(|t| json_string(t)).collect();
+    let body = format!(
+        "{{\"toplevels\":{},\"titles\":[{}],\"fps\":{:.1},\"width\":{},\"height\":{}}}",
+        status.toplevel_titles.len(),
+        titles.join(","),
+        status.fps,
+        status.width,
+        status.height,
+    );
+
+    Response::from_string(body).with_header(header("Content-Type", "application/json"))
+}
+
+fn encode_png(frame: &FrameData) -> Result<Vec<u8>> {
+    let image = image::RgbaImage::from_raw(frame.width, frame.height, frame.data.clone())
+        .ok_or_else(|| anyhow!("Frame buffer size doesn't match {}x{}", frame.width, frame.height))?;
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,114 @@
+//! Keysym to keycode lookup in the keymap handed to clients
+//!
+//! The terminal reports characters and keysyms, but wl_keyboard delivers
+//! keycodes that the client turns back into keysyms through the keymap we
+//! sent it. Looking keycodes up in that same keymap keeps the two in step
+//! for any layout, not just US QWERTY.
+
+use smithay::input::keyboard::{xkb, Keycode, Keysym};
+use std::collections::HashMap;
+
+/// Shift level of a key: 0 is plain, 1 is with Shift, 2 with AltGr
+/// (ISO_Level3_Shift) and 3 with both
+type Level = u32;
+
+#[derive(Clone, Debug, Default)]
+pub struct KeycodeMap {
+    /// Keys producing each keysym, lower levels first
+    keys: HashMap<u32, Vec<(Level, Keycode)>>,
+}
+
+impl KeycodeMap {
+    /// Index the first layout of `keymap` by keysym
+    pub fn from_keymap(keymap: &xkb::Keymap) -> Self {
+        let mut keys: HashMap<u32, Vec<(Level, Keycode)>> = HashMap::new();
+        for level in 0..4 {
+            keymap.key_for_each(|keymap, keycode| {
+                for keysym in keymap.key_get_syms_by_level(keycode, 0, level) {
+                    keys.entry(keysym.raw()).or_default().push((level, keycode));
+                }
+            });
+        }
+        Self { keys }
+    }
+
+    /// The key producing `keysym`, preferably at a level selected by `shift`
+    pub fn keycode(&self, keysym: Keysym, shift: bool) -> Option<Keycode> {
+        self.find(keysym, shift).map(|(_, keycode)| keycode)
+    }
+
+    /// Whether the key `keycode()` picks produces `keysym` only with AltGr held
+    pub fn needs_altgr(&self, keysym: Keysym, shift: bool) -> bool {
+        self.find(keysym, shift).is_some_and(|(level, _)| level >= 2)
+    }
+
+    /// Whether `keysym` is only reachable with Shift; `None` if the keymap lacks it
+    pub fn needs_shift(&self, keysym: Keysym) -> Option<bool> {
+        let &(level, _) = self.keys.get(&keysym.raw())?.first()?;
+        Some(level % 2 == 1)
+    }
+
+    /// The lowest level producing `keysym` with Shift as `shift` says, or
+    /// else the lowest at all
+    fn find(&self, keysym: Keysym, shift: bool) -> Option<(Level, Keycode)> {
+        let keys = self.keys.get(&keysym.raw())?;
+        let wanted = Level::from(shift);
+        keys.iter().find(|(level, _)| level % 2 == wanted).or_else(|| keys.first()).copied()
+    }
+}
+
+/// The keymap of the XKB `layout`, e.g. "us", with the default model and
+/// options; panics if there is no such layout
+pub fn keymap_for_layout(layout: &str) -> xkb::Keymap {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    xkb::Keymap::new_from_names(&context, "", "", layout, "", None, xkb::KEYMAP_COMPILE_NO_FLAGS)
+        .expect("keymap compiles")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // evdev codes, which XKB offsets by 8
+    const KEY_Y: u32 = 21;
+    const KEY_Z: u32 = 44;
+    const KEY_7: u32 = 8;
+
+    #[test]
+    fn us_keys_sit_at_their_evdev_codes() {
+        let keycodes = KeycodeMap::from_keymap(&keymap_for_layout("us"));
+        let key = |c: char, shift| keycodes.keycode(Keysym::new(c as u32), shift).unwrap().raw();
+
+        assert_eq!(key('a', false), 30 + 8);
+        assert_eq!(key(' ', false), 57 + 8);
+        for (plain, shifted) in [('a', 'A'), ('1', '!'), ('/', '?'), ('`', '~'), ('\'', '"'), (',', '<')] {
+            assert_eq!(key(plain, false), key(shifted, true), "{:?} and {:?}", plain, shifted);
+        }
+        assert_eq!(keycodes.needs_shift(Keysym::new('?' as u32)), Some(true));
+        assert_eq!(keycodes.needs_shift(Keysym::new('/' as u32)), Some(false));
+    }
+
+    #[test]
+    fn german_layout_moves_keys() {
+        let keycodes = KeycodeMap::from_keymap(&keymap_for_layout("de"));
+        let key = |c: char, shift| keycodes.keycode(Keysym::new(c as u32), shift).unwrap().raw();
+
+        assert_eq!(key('z', false), KEY_Y + 8);
+        assert_eq!(key('y', false), KEY_Z + 8);
+        assert_eq!(key('/', true), KEY_7 + 8);
+        assert_eq!(keycodes.needs_shift(Keysym::new('/' as u32)), Some(true));
+        assert_eq!(keycodes.needs_shift(Keysym::new(0xe4)), Some(false)); // adiaeresis
+    }
+
+    #[test]
+    fn altgr_levels_are_found_too() {
+        const KEY_Q: u32 = 16;
+        let keycodes = KeycodeMap::from_keymap(&keymap_for_layout("de"));
+        let at = Keysym::new('@' as u32);
+
+        assert_eq!(keycodes.keycode(at, false).unwrap().raw(), KEY_Q + 8);
+        assert_eq!(keycodes.needs_shift(at), Some(false));
+        assert!(keycodes.needs_altgr(at, false));
+        assert!(!keycodes.needs_altgr(Keysym::new('q' as u32), false));
+    }
+}
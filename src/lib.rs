@@ -0,0 +1,27 @@
+//! Wayland compositor that hands its clients' windows over as frames
+//!
+//! [`compositor::TermuiState`] composites every commit into a
+//! [`compositor::FrameData`] and queues it in `pending_frame`, pinging
+//! `frame_ready` when set. Whoever takes the frame shows it somewhere and
+//! reports back with `frame_presented()` or `frame_discarded()`, which
+//! clients get as presentation feedback. The termui binary shows frames in
+//! a terminal with the `terminal` feature's Kitty, Sixel and iTerm2
+//! backends; without the feature only the compositor and the frame capture
+//! are built, for embedding.
+//!
+//! Without the feature, frames are taken the way `termui --headless` runs
+//! the compositor, minus the terminal:
+//!
+//! - create a calloop `EventLoop` and a wayland-server `Display`, and pass
+//!   them to `TermuiState::new()` with the output size;
+//! - accept clients on a `ListeningSocket` and insert them with a
+//!   `compositor::ClientState`, dispatching the display in the loop;
+//! - set `frame_ready` to a calloop ping inserted in the loop, and in its
+//!   callback take the frame out of `pending_frame` and call
+//!   `frame_presented()` once it's used.
+
+pub mod clock;
+pub mod compositor;
+pub mod keymap;
+pub mod stats;
+pub mod terminal;
@@ -1,69 +1,73 @@
-mod compositor;
-mod terminal;
+mod attach;
+mod cli;
+#[cfg(feature = "debug-http")]
+mod debug_http;
+#[cfg(feature = "mirror")]
+mod mirror;
+mod record;
+mod replay;
+mod script;
 
 use anyhow::{anyhow, Result};
-use compositor::{ClientState, TermuiState};
+use cli::{Options, PointerStart, RenderMode};
 use smithay::{
-    backend::input::Axis,
+    backend::input::{Axis, AxisSource},
     input::{
-        keyboard::Keycode,
-        pointer::{AxisFrame, ButtonEvent, MotionEvent},
+        keyboard::{KeyboardHandle, Keysym},
+        pointer::{AxisFrame, ButtonEvent},
     },
     reexports::{
         calloop::{
             channel::{self},
             generic::Generic,
+            ping::make_ping,
             timer::{TimeoutAction, Timer},
             EventLoop, Interest, Mode, PostAction,
         },
         wayland_server::{Display, ListeningSocket},
     },
-    utils::{Point, SERIAL_COUNTER},
+    utils::{Logical, Point, Size, SERIAL_COUNTER},
+    wayland::presentation::Refresh,
 };
 use std::{
+    cell::{Cell, RefCell},
     env,
-    process::Command,
-    sync::Arc,
-    time::Duration,
+    process::{Child, Command, ExitCode, ExitStatus},
+    rc::Rc,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
 };
-use terminal::{KittyGraphics, TerminalInput, WaylandInputEvent};
-use tracing::{error, info};
+use termui::compositor::{self, cursor::CursorMode, ClientState, ExitPolicy, FrameData, OutputIdentity, TermuiState};
+use termui::terminal::{
+    self, Backend, CellSize, Compression, FramePacer, FullRefresh, GraphicsDiagnostics, ITerm2Graphics, KittyGraphics,
+    RenderBackend, ScaleMode, SixelGraphics, TerminalInput, TextCursor, WaylandInputEvent, DEFAULT_MAX_SIZE,
+};
+use tracing::{error, info, warn};
 
-fn main() -> Result<()> {
-    // Redirect logging to file so it doesn't interfere with terminal graphics
-    let log_file = std::fs::File::create("/tmp/termui.log")
-        .map_err(|e| anyhow!("Failed to create log file: {}", e))?;
+fn main() -> Result<ExitCode> {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("termui: {}", e);
+            eprintln!();
+            Options::print_usage();
+            std::process::exit(1);
+        }
+    };
 
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::DEBUG.into()),
-        )
-        .with_writer(log_file)
-        .init();
+    let _log_guard = init_logging(&options)?;
 
-    // Get command to run from args
-    let args: Vec<String> = env::args().skip(1).collect();
+    let headless = options.headless;
+    let args = &options.command;
 
-    // Check for --headless flag (for testing)
-    let headless = args.first().map(|s| s == "--headless").unwrap_or(false);
-    let args: Vec<String> = if headless { args.into_iter().skip(1).collect() } else { args };
+    info!("Headless mode: {}", headless);
 
-    if args.is_empty() {
-        eprintln!("Usage: termui [--headless] <command> [args...]");
-        eprintln!();
-        eprintln!("Run a graphical Wayland application in the terminal using Kitty graphics protocol.");
-        eprintln!();
-        eprintln!("Options:");
-        eprintln!("  --headless  Run without terminal graphics (for testing)");
-        eprintln!();
-        eprintln!("Examples:");
-        eprintln!("  termui foot");
-        eprintln!("  termui gtk4-demo");
-        std::process::exit(1);
+    if let Some(dir) = &options.replay {
+        return replay::run(dir, &options).map(|()| ExitCode::SUCCESS);
+    }
+    if let Some(socket) = &options.attach {
+        return attach::run(socket, &options).map(|()| ExitCode::SUCCESS);
     }
-
-    info!("Headless mode: {}", headless);
 
     // Get terminal dimensions (use defaults in headless mode)
     let (term_cols, term_rows) = if headless {
@@ -74,20 +78,27 @@ fn main() -> Result<()> {
     let (pixel_width, pixel_height) = if headless {
         (800, 600)
     } else {
-        KittyGraphics::query_terminal_size_pixels()?
+        KittyGraphics::query_terminal_size_pixels(options.cell_size)?
     };
 
     // Scale factor for the virtual display (higher = larger UI elements)
-    // Use 1 for 1:1 pixel mapping, 2-4 for HiDPI-like scaling
-    let scale_factor: u32 = 4;
-    let virtual_width = pixel_width / scale_factor;
-    let virtual_height = pixel_height / scale_factor;
+    let scale_factor = options.scale;
+    let (virtual_width, virtual_height) = output_size(pixel_width, pixel_height, scale_factor);
 
     info!(
         "Terminal size: {}x{} chars, {}x{} pixels, virtual: {}x{} (scale {})",
         term_cols, term_rows, pixel_width, pixel_height, virtual_width, virtual_height, scale_factor
     );
 
+    // Connect to the host display before our own runtime dir is set up
+    #[cfg(feature = "mirror")]
+    let mirror = if options.mirror { Some(mirror::Mirror::spawn()?) } else { None };
+    // With --headless, the mirror window is the only output
+    #[cfg(feature = "mirror")]
+    let show_in_terminal = !(headless && mirror.is_some());
+    #[cfg(not(feature = "mirror"))]
+    let show_in_terminal = true;
+
     // Create event loop
     let mut event_loop: EventLoop<TermuiState> =
         EventLoop::try_new().map_err(|e| anyhow!("Failed to create event loop: {}", e))?;
@@ -96,13 +107,76 @@ fn main() -> Result<()> {
     let display: Display<TermuiState> = Display::new()
         .map_err(|e| anyhow!("Failed to create display: {}", e))?;
 
+    // Report a physical size so DPI-aware clients size their fonts sensibly;
+    // by default the virtual display is presented as a 96 DPI screen
+    let physical_size = options
+        .physical_size
+        .unwrap_or_else(|| TermuiState::default_physical_size(virtual_width, virtual_height));
+    // The output is really the terminal, so name it after the terminal program
+    let model = if headless {
+        "headless".to_string()
+    } else {
+        std::env::var("TERM_PROGRAM")
+            .or_else(|_| std::env::var("TERM"))
+            .unwrap_or_else(|_| "virtual".to_string())
+    };
+    let identity = OutputIdentity {
+        physical_size,
+        subpixel: options.subpixel,
+        make: "termui".to_string(),
+        model,
+    };
+
     // Create compositor state with virtual (scaled) dimensions
     let mut state = TermuiState::new(
         &display,
         event_loop.get_signal(),
+        event_loop.handle(),
         virtual_width,
         virtual_height,
+        options.rotate,
+        identity,
     );
+    state.exit_policy = options.exit_on;
+    state.buffer_scale = scale_factor as i32;
+    state.max_clients = options.max_clients;
+    state.clipboard_image_dir = options.clipboard_image_dir.clone();
+    // Headless, stdout is no terminal
+    state.clipboard_osc52 = options.clipboard_osc52 && !options.headless;
+    state.terminal_title = !options.no_terminal_title && !options.headless;
+    state.cursor_blink = compositor::cursor::CursorBlink::new(options.cursor_blink);
+    let title_bar = title_bar_height(pixel_height, term_rows as u32, scale_factor);
+    state.title_bar = (!options.no_decorations).then_some(title_bar);
+    state.show_cursor = options.cursor == compositor::cursor::CursorMode::Software;
+    state.resize_debounce = options.resize_debounce;
+    state.initial_pointer = options.initial_pointer.map(|start| initial_pointer(start, state.logical_size())).transpose()?;
+    // Parsed up front so mistakes are reported before the app starts
+    let script = options.script.as_deref().map(|path| script::load(path, &state.keycodes)).transpose()?;
+    state.crop_to_content = options.crop_to_content;
+    state.set_key_repeat(options.key_repeat.0, options.key_repeat.1);
+
+    // Fill uncovered areas with the terminal's own background so they blend in
+    if !headless {
+        match KittyGraphics::query_background_color(Duration::from_millis(100)) {
+            Some([r, g, b]) => {
+                info!("Terminal background: #{:02x}{:02x}{:02x}", r, g, b);
+                state.background = [r, g, b];
+            }
+            None => info!("Terminal didn't report its background color, using black"),
+        }
+    }
+    let backend = match options.backend {
+        Some(backend) => backend,
+        None if headless => Backend::Kitty,
+        None => {
+            let backend = terminal::detect_backend(Duration::from_millis(100));
+            info!("Graphics protocol: {:?}", backend);
+            backend
+        }
+    };
+
+    // Before the terminal is set up, so a kill always gets to restore it
+    watch_quit_signals(&event_loop)?;
 
     // Use XDG_RUNTIME_DIR or create our own in /tmp
     let runtime_dir = env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| {
@@ -134,6 +208,13 @@ fn main() -> Result<()> {
             move |_, socket, state| {
                 match socket.accept() {
                     Ok(Some(client_stream)) => {
+                        let live = state.live_clients.load(Ordering::Relaxed);
+                        if live >= state.max_clients {
+                            // Dropping the stream closes the connection
+                            warn!("Refusing client: already serving {} (--max-clients)", live);
+                            return Ok(PostAction::Continue);
+                        }
+
                         info!("New client connecting...");
                         let client = state
                             .display_handle
@@ -142,10 +223,12 @@ fn main() -> Result<()> {
                                 Arc::new(ClientState {
                                     compositor_state:
                                         smithay::wayland::compositor::CompositorClientState::default(),
+                                    live_clients: state.live_clients.clone(),
                                 }),
                             );
                         match client {
                             Ok(_) => {
+                                state.live_clients.fetch_add(1, Ordering::Relaxed);
                                 info!("Client connected successfully");
                                 // Flush to send globals to new client
                                 let _ = state.display_handle.flush_clients();
@@ -184,48 +267,377 @@ fn main() -> Result<()> {
     // Set up terminal input channel
     let (input_tx, input_rx) = channel::channel::<WaylandInputEvent>();
 
+    // A burst of input is handled in one dispatch, one callback per event in
+    // order; the clients are flushed once after the whole batch
+    let input_flush = Rc::new(Cell::new(false));
+    let input_handled = input_flush.clone();
     event_loop
         .handle()
-        .insert_source(input_rx, |event, _, state| {
+        .insert_source(input_rx, move |event, _, state| {
             if let channel::Event::Msg(input_event) = event {
-                tracing::debug!("Input event received: {:?}", std::mem::discriminant(&input_event));
+                tracing::trace!(
+                    target: "termui::input",
+                    "Input event received: {:?}",
+                    std::mem::discriminant(&input_event)
+                );
                 handle_input_event(state, input_event);
-                // Flush display to ensure events are sent to client immediately
-                if let Err(e) = state.display_handle.flush_clients() {
-                    tracing::error!("Failed to flush display: {:?}", e);
-                }
+                state.last_activity = Instant::now();
+                input_handled.set(true);
             }
         })
         .map_err(|e| anyhow!("Failed to add input channel to event loop: {:?}", e))?;
 
-    // Frame timer for rendering (target ~30 fps)
-    let frame_timer = Timer::from_duration(Duration::from_millis(33));
+    // Tell the user where to connect their clients: on stderr, since
+    // stdout carries the graphics; redirect it to keep these around
+    if options.no_spawn || options.print_socket {
+        eprintln!("WAYLAND_DISPLAY={}", socket_name);
+        eprintln!("XDG_RUNTIME_DIR={}", runtime_dir);
+    }
+    // Start the client, or in --no-spawn mode wait for the user's
+    let placeholder_text = if options.no_spawn {
+        info!("Display ready, waiting for clients");
+        format!("Waiting for clients on {}...", socket_name)
+    } else {
+        info!("Display ready, spawning client...");
+        let mut child = spawn_client(args, &socket_name, &runtime_dir)?;
+        info!("Spawned child process");
+
+        // Watch for the command exiting, for --exit-on child-exit and the exit status
+        event_loop
+            .handle()
+            .insert_source(Timer::from_duration(CHILD_POLL_INTERVAL), move |_, _, state| {
+                if state.child_status.is_none() {
+                    match child.try_wait() {
+                        Ok(Some(status)) => {
+                            info!("Child process exited: {}", status);
+                            state.child_status = Some(status);
+                            state.exit_on(ExitPolicy::ChildExit);
+                        }
+                        Ok(None) => return TimeoutAction::ToDuration(CHILD_POLL_INTERVAL),
+                        Err(e) => {
+                            warn!("Failed to check on child process: {}", e);
+                            return TimeoutAction::Drop;
+                        }
+                    }
+                }
+                // A command that failed before opening a window never closes
+                // one either; done once no client is left that still might.
+                // Clients it started may open windows after it exits.
+                if !state.toplevels.is_empty() {
+                    return TimeoutAction::Drop;
+                }
+                if state.live_clients.load(Ordering::Relaxed) == 0 {
+                    state.exit_on(ExitPolicy::LastWindow);
+                    return TimeoutAction::Drop;
+                }
+                TimeoutAction::ToDuration(CHILD_POLL_INTERVAL)
+            })
+            .map_err(|e| anyhow!("Failed to add child watcher to event loop: {:?}", e))?;
+        format!("Loading {}...", args[0])
+    };
+
+    // Optionally hold off drawing until the client has produced a frame,
+    // so the user doesn't see an empty alternate screen flash
+    if let Some(timeout) = options.wait_first_frame {
+        wait_for_first_frame(&mut event_loop, &mut state, timeout)?;
+    }
+
+    // Show a loading frame until the client produces real content
+    {
+        let mut pending = state.pending_frame.lock().unwrap();
+        if pending.is_none() {
+            *pending = Some(FrameData::placeholder(
+                virtual_width,
+                virtual_height,
+                &placeholder_text,
+                state.background,
+            ));
+        }
+    }
+
+    let text_cursor = (options.cursor == CursorMode::Text).then(TextCursor::default);
+    // Acknowledgements and other responses come back through the terminal's
+    // input, so there is nothing to watch for without one
+    let settings = RendererSettings {
+        full_refresh: options.full_refresh,
+        integer_scale: options.integer_scale,
+        compression: options.compression,
+        scale_mode: options.scale_quality,
+        text_cursor: text_cursor.clone(),
+        ack_pacing: options.ack_pacing && show_in_terminal && !headless,
+        graphics_diagnostics: options.graphics_diagnostics && show_in_terminal && !headless,
+        max_frames_behind: options.max_frames_behind,
+    };
+    settings.track_responses(&mut state, backend);
+    if options.ack_pacing && state.frame_pacer.is_none() {
+        info!("--ack-pacing and --max-frames-behind have no effect without a Kitty graphics terminal");
+    }
+    // Kitty graphics may be switched to later with --backend-key
+    let responses_wanted = (backend == Backend::Kitty || options.backend_key.is_some())
+        && (settings.ack_pacing || settings.graphics_diagnostics);
+
+    let mut current_backend = backend;
+    let mut renderer = settings.create(backend);
+    if options.backend_key.is_some() && show_in_terminal {
+        state.set_title_note(Some(backend.name()));
+    }
+
+    #[cfg(feature = "debug-http")]
+    let debug_snapshot = match &options.debug_http {
+        Some(addr) => {
+            let snapshot = debug_http::SharedSnapshot::default();
+            debug_http::spawn(addr, snapshot.clone())?;
+            Some(snapshot)
+        }
+        None => None,
+    };
+    #[cfg(feature = "debug-http")]
+    let mut fps_window = (Instant::now(), 0u32);
+    let record = |dir| record::Recorder::spawn(dir, options.record_fps);
+    let mut recorder = options.record.as_deref().map(record).transpose()?;
+    // Damage is relative to the unmagnified frame, so it doesn't apply
+    // while zoomed or to the first frame after
+    let mut was_zoomed = false;
+
+    // Presentation feedback: frames go out on a fixed beat with the timer,
+    // and no faster than it with on-damage
+    let render_mode = options.render_mode;
+    let refresh = match render_mode {
+        RenderMode::Timer => Refresh::fixed(FRAME_INTERVAL),
+        RenderMode::OnDamage => Refresh::variable(FRAME_INTERVAL),
+    };
+
+    // Send the pending frame to the terminal (and the other outputs)
+    let present = Rc::new(RefCell::new(move |state: &mut TermuiState| {
+        // Check for pending frame and render (leaving it queued while the terminal is behind)
+        // Taken even with nowhere to show it, so the queue doesn't go stale
+        let frame = if state.terminal_behind() {
+            None
+        } else {
+            state.pending_frame.lock().unwrap().take()
+        };
+        // Frames that failed to reach the terminal weren't presented
+        let mut discarded = false;
+        if let Some(frame) = &frame {
+            if show_in_terminal && std::mem::take(&mut state.cycle_backend) {
+                // Off the screen with the old backend's frames, and the
+                // whole frame with the new one
+                if let Err(e) = renderer.leave_terminal() {
+                    warn!("Failed to clear {} from the terminal: {:?}", current_backend.name(), e);
+                }
+                current_backend = current_backend.next();
+                info!("Switching to {}", current_backend.name());
+                renderer = settings.create(current_backend);
+                settings.track_responses(state, current_backend);
+                was_zoomed = true;
+                state.set_title_note(Some(current_backend.name()));
+            }
+            if show_in_terminal {
+                let fallback = state.graphics_diagnostics.as_mut().and_then(GraphicsDiagnostics::take_fallback);
+                if let Some(fallback) = fallback {
+                    renderer.apply_fallback(fallback);
+                }
+                let zoomed = state.zoom.is_zoomed().then(|| state.zoom.magnify(frame));
+                let shown = zoomed.as_ref().unwrap_or(frame);
+                let damage = if was_zoomed { &[][..] } else { &shown.damage[..] };
+                was_zoomed = zoomed.is_some();
+                match renderer.display_frame(shown.width, shown.height, &shown.data, shown.opaque, damage) {
+                    Ok(None) => state.stats.borrow_mut().unchanged += 1,
+                    Ok(Some(image_id)) => {
+                        if let Some(pacer) = &mut state.frame_pacer {
+                            pacer.sent(image_id);
+                        }
+                        let timings = renderer.last_timings();
+                        let mut stats = state.stats.borrow_mut();
+                        stats.transmitted += 1;
+                        stats.scale_time += timings.scale;
+                        stats.encode_time += timings.encode;
+                    }
+                    Err(e) => {
+                        error!("Failed to render frame: {:?}", e);
+                        discarded = true;
+                    }
+                }
+            }
+            #[cfg(feature = "mirror")]
+            if let Some(mirror) = &mirror {
+                mirror.show(frame);
+            }
+        }
+
+        if let (Some(frame), Some(recorder)) = (&frame, &mut recorder) {
+            recorder.record(frame);
+        }
+        if frame.is_some() {
+            if discarded {
+                state.frame_discarded();
+            } else {
+                state.frame_presented(refresh);
+            }
+        }
 
-    let mut kitty = KittyGraphics::new();
+        #[cfg(feature = "debug-http")]
+        let frame = match &debug_snapshot {
+            Some(snapshot) => publish_debug_snapshot(state, snapshot, frame, &mut fps_window),
+            None => frame,
+        };
+        if let Some(frame) = frame {
+            state.recycle_frame(frame);
+        }
+    }));
 
+    // Frame timer (~30 fps): housekeeping, and rendering in timer mode
+    let timer_present = present.clone();
     event_loop
         .handle()
-        .insert_source(frame_timer, move |_, _, state| {
-            // Check for pending frame and render
-            if let Some(frame) = state.pending_frame.lock().unwrap().take() {
-                if let Err(e) = kitty.display_frame(frame.width, frame.height, &frame.data) {
-                    error!("Failed to render frame: {:?}", e);
+        .insert_source(Timer::from_duration(FRAME_INTERVAL), move |_, _, state| {
+            if state.cursor_blink.tick(Instant::now()) {
+                state.redraw();
+            }
+
+            // Give up on acknowledgements that are overdue
+            if let Some(pacer) = &mut state.frame_pacer {
+                let expired = pacer.expire();
+                if expired > 0 {
+                    tracing::debug!("{} frame(s) went unacknowledged", expired);
+                    state.catch_up();
+                    state.frame_queued();
                 }
             }
-            TimeoutAction::ToDuration(Duration::from_millis(33))
+
+            if render_mode == RenderMode::Timer {
+                (timer_present.borrow_mut())(state);
+            }
+            TimeoutAction::ToDuration(FRAME_INTERVAL)
         })
         .map_err(|e| anyhow!("Failed to add frame timer to event loop: {:?}", e))?;
 
+    // In on-damage mode, frames go out as soon as they are queued, but no
+    // sooner than FRAME_INTERVAL after the previous one
+    if render_mode == RenderMode::OnDamage {
+        let (ping, frame_ready) = make_ping().map_err(|e| anyhow!("Failed to create frame wakeup: {}", e))?;
+        let last_sent: Rc<Cell<Option<Instant>>> = Rc::new(Cell::new(None));
+        let deferred = Rc::new(Cell::new(false));
+        event_loop
+            .handle()
+            .insert_source(frame_ready, move |_, _, state| {
+                if deferred.get() {
+                    return;
+                }
+                let wait = last_sent.get().map_or(Duration::ZERO, |sent| FRAME_INTERVAL.saturating_sub(sent.elapsed()));
+                if wait.is_zero() {
+                    last_sent.set(Some(Instant::now()));
+                    (present.borrow_mut())(state);
+                    return;
+                }
+
+                deferred.set(true);
+                let (present, last_sent, deferred) = (present.clone(), last_sent.clone(), deferred.clone());
+                let inserted = state.loop_handle.insert_source(Timer::from_duration(wait), move |_, _, state| {
+                    deferred.set(false);
+                    last_sent.set(Some(Instant::now()));
+                    (present.borrow_mut())(state);
+                    TimeoutAction::Drop
+                });
+                if let Err(e) = inserted {
+                    error!("Failed to schedule frame: {:?}", e);
+                }
+            })
+            .map_err(|e| anyhow!("Failed to add frame wakeup to event loop: {:?}", e))?;
+        // Picks up the placeholder queued above
+        ping.ping();
+        state.frame_ready = Some(ping);
+    }
+
+    // Feed the script's input through the terminal's input channel, starting
+    // once the first window is up
+    if let Some(steps) = script {
+        let mut steps = steps.into_iter();
+        let script_tx = input_tx.clone();
+        let mut started = false;
+        event_loop
+            .handle()
+            .insert_source(Timer::from_duration(SCRIPT_START_POLL), move |_, _, state| {
+                if !started {
+                    if state.toplevels.is_empty() {
+                        return TimeoutAction::ToDuration(SCRIPT_START_POLL);
+                    }
+                    info!("Running input script");
+                    started = true;
+                }
+                for step in steps.by_ref() {
+                    match step {
+                        script::Step::Input(event) => {
+                            let _ = script_tx.send(event.with_time(state.clock.now_millis()));
+                        }
+                        script::Step::Wait(duration) => return TimeoutAction::ToDuration(duration),
+                        script::Step::Exit => {
+                            info!("Exiting: end of input script");
+                            state.running = false;
+                            state.loop_signal.stop();
+                            return TimeoutAction::Drop;
+                        }
+                    }
+                }
+                info!("Input script finished");
+                TimeoutAction::Drop
+            })
+            .map_err(|e| anyhow!("Failed to add script timer to event loop: {:?}", e))?;
+    }
+
+    // Exit once neither the terminal nor the clients did anything for a while
+    if let Some(idle_exit) = options.idle_exit {
+        event_loop
+            .handle()
+            .insert_source(Timer::from_duration(idle_exit), move |_, _, state| {
+                match state.check_idle(idle_exit) {
+                    Some(wait) => TimeoutAction::ToDuration(wait),
+                    None => TimeoutAction::Drop,
+                }
+            })
+            .map_err(|e| anyhow!("Failed to add idle timer to event loop: {:?}", e))?;
+    }
+
+    if options.stats {
+        event_loop
+            .handle()
+            .insert_source(Timer::from_duration(STATS_INTERVAL), |_, _, state| {
+                let in_flight = state.frame_pacer.as_ref().map(FramePacer::in_flight);
+                state.stats.borrow_mut().report(state.toplevels.len(), in_flight);
+                TimeoutAction::ToDuration(STATS_INTERVAL)
+            })
+            .map_err(|e| anyhow!("Failed to add stats timer to event loop: {:?}", e))?;
+    }
+
     // Set up terminal (skip in headless mode)
-    let kitty_setup = KittyGraphics::new();
-    if !headless {
+    let mut kitty_setup = KittyGraphics::new();
+    kitty_setup.set_save_title(state.terminal_title);
+    kitty_setup.set_sixel_display_mode(backend == Backend::Sixel || options.backend_key.is_some());
+    let enhanced_keyboard = if !headless {
         kitty_setup.setup_terminal()?;
-        TerminalInput::enable_mouse_capture()?;
+        TerminalInput::enable_mouse_capture()?
+    } else {
+        false
+    };
+    {
+        state.key_releases = enhanced_keyboard;
     }
 
     // Spawn input handling thread (skip in headless mode)
     let _input_thread = if !headless {
         let input_tx = input_tx.clone();
+        let close_key = options.close_key;
+        let backend_key = options.backend_key;
+        let zoom_keys = options.zoom_keys;
+        let scroll_speed = options.scroll_speed;
+        // Resizes are sized from the cells measured now, when the terminal
+        // only reports its pixel size when asked
+        let cell_size = if options.cell_size.forced {
+            options.cell_size
+        } else {
+            CellSize::measured((pixel_width, pixel_height), term_cols, term_rows)
+        };
+        let keycodes = state.keycodes.clone();
         Some(std::thread::spawn(move || {
             // Use virtual dimensions for input scaling
             let mut term_input = TerminalInput::new(
@@ -234,33 +646,39 @@ fn main() -> Result<()> {
                 virtual_width,
                 virtual_height,
             );
+            term_input.set_reports_lock_state(enhanced_keyboard);
+            term_input.set_close_chord(Some(close_key));
+            term_input.set_backend_chord(backend_key);
+            term_input.set_zoom_keys(zoom_keys);
+            term_input.set_scroll_speed(scroll_speed);
+            term_input.set_cell_size(cell_size);
+            term_input.set_text_cursor(text_cursor);
+            term_input.set_graphics_responses(responses_wanted);
+            term_input.set_keycodes(keycodes);
 
-            loop {
+            'input: loop {
                 match TerminalInput::poll_event(Duration::from_millis(10)) {
                     Ok(Some(event)) => {
-                        if let Some(input_event) = term_input.translate_event(event) {
-                            // Update dimensions on resize
+                        for input_event in term_input.translate_event(event) {
+                            // Update dimensions on resize; pointer positions map
+                            // to the output, like at startup
                             if let WaylandInputEvent::Resize { width, height } = &input_event {
                                 let (cols, rows) =
                                     KittyGraphics::query_terminal_size_chars().unwrap_or((80, 24));
-                                term_input.update_dimensions(
-                                    cols as u32,
-                                    rows as u32,
-                                    *width,
-                                    *height,
-                                );
+                                let (width, height) = output_size(*width, *height, scale_factor);
+                                term_input.update_dimensions(cols as u32, rows as u32, width, height);
                             }
 
                             let is_quit = matches!(input_event, WaylandInputEvent::Quit);
                             if input_tx.send(input_event).is_err() || is_quit {
-                                break;
+                                break 'input;
                             }
                         }
                     }
                     Ok(None) => {}
                     Err(e) => {
                         error!("Input error: {:?}", e);
-                        break;
+                        break 'input;
                     }
                 }
             }
@@ -269,14 +687,75 @@ fn main() -> Result<()> {
         None
     };
 
-    // Ensure display is ready before spawning client
-    info!("Display ready, spawning client...");
+    // Run the event loop
+    while state.running {
+        event_loop
+            .dispatch(Some(Duration::from_millis(16)), &mut state)
+            .map_err(|e| anyhow!("Event loop error: {}", e))?;
+        // Send the events input produced to the clients right away
+        if input_flush.replace(false) {
+            if let Err(e) = state.display_handle.flush_clients() {
+                tracing::error!("Failed to flush display: {:?}", e);
+            }
+        }
+    }
+
+    // Cleanup
+    info!("Shutting down...");
+    if !headless {
+        TerminalInput::disable_mouse_capture()?;
+        kitty_setup.restore_terminal()?;
+    }
+
+    Ok(state.child_status.map_or(ExitCode::SUCCESS, exit_code))
+}
+
+/// The exit code to pass on for a command that ended with `status`: its
+/// own, or 128 plus the signal that killed it, like shells report
+fn exit_code(status: ExitStatus) -> ExitCode {
+    use std::os::unix::process::ExitStatusExt;
+
+    let code = status.code().or_else(|| status.signal().map(|signal| 128 + signal)).unwrap_or(1);
+    ExitCode::from(code as u8)
+}
+
+/// End the session on SIGTERM and SIGINT the way the quit keys do, through
+/// the cleanup that restores the terminal
+///
+/// The terminal is in raw mode, so Ctrl+C arrives as a key, not a SIGINT;
+/// these come from `kill` and the like.
+fn watch_quit_signals(event_loop: &EventLoop<TermuiState>) -> Result<()> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+
+    let (reader, writer) =
+        std::os::unix::net::UnixStream::pair().map_err(|e| anyhow!("Failed to create signal pipe: {}", e))?;
+    reader
+        .set_nonblocking(true)
+        .map_err(|e| anyhow!("Failed to make signal pipe non-blocking: {}", e))?;
+    for signal in [SIGTERM, SIGINT] {
+        let writer = writer.try_clone().map_err(|e| anyhow!("Failed to clone signal pipe: {}", e))?;
+        signal_hook::low_level::pipe::register(signal, writer)
+            .map_err(|e| anyhow!("Failed to watch signal {}: {}", signal, e))?;
+    }
+
+    event_loop
+        .handle()
+        .insert_source(Generic::new(reader, Interest::READ, Mode::Level), |_, _, state| {
+            info!("Caught a termination signal, shutting down");
+            state.running = false;
+            state.loop_signal.stop();
+            Ok(PostAction::Remove)
+        })
+        .map_err(|e| anyhow!("Failed to add signal watcher to event loop: {:?}", e))?;
+    Ok(())
+}
 
-    // Spawn the child process
-    let _child = Command::new(&args[0])
+/// Run the client command against our socket, with the environment set up for Wayland
+fn spawn_client(args: &[String], socket_name: &str, runtime_dir: &str) -> Result<Child> {
+    Command::new(&args[0])
         .args(&args[1..])
-        .env("WAYLAND_DISPLAY", &socket_name)
-        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .env("WAYLAND_DISPLAY", socket_name)
+        .env("XDG_RUNTIME_DIR", runtime_dir)
         .env("XDG_SESSION_TYPE", "wayland")
         .env("XDG_CURRENT_DESKTOP", "termui")
         .env("GDK_BACKEND", "wayland")
@@ -284,33 +763,109 @@ fn main() -> Result<()> {
         .env("SDL_VIDEODRIVER", "wayland")
         .env("MOZ_ENABLE_WAYLAND", "1")
         .env("_JAVA_AWT_WM_NONREPARENTING", "1")
-        // Force software rendering (we only support wl_shm)
+        // Force software rendering: GPU drivers draw into tiled buffers only
+        // they can read, where software renderers use shm or linear dmabufs
         .env("LIBGL_ALWAYS_SOFTWARE", "1")
         .env("WLR_RENDERER", "pixman")
-        .env("GALLIUM_DRIVER", "llvmpipe")
-        .env("__GLX_VENDOR_LIBRARY_NAME", "mesa")
-        .env("MESA_LOADER_DRIVER_OVERRIDE", "llvmpipe")
         // Disable things that might cause issues
         .env_remove("DISPLAY")
         .spawn()
-        .map_err(|e| anyhow!("Failed to spawn {}: {}", args[0], e))?;
+        .map_err(|e| anyhow!("Failed to spawn {}: {}", args[0], e))
+}
 
-    info!("Spawned child process");
+/// Hand the latest frame and status to the debug HTTP thread, returning
+/// the frame it replaces
+#[cfg(feature = "debug-http")]
+fn publish_debug_snapshot(
+    state: &TermuiState,
+    snapshot: &debug_http::SharedSnapshot,
+    frame: Option<FrameData>,
+    fps_window: &mut (Instant, u32),
+) -> Option<FrameData> {
+    let mut snapshot = snapshot.lock().unwrap();
 
-    // Run the event loop
-    while state.running {
-        event_loop
-            .dispatch(Some(Duration::from_millis(16)), &mut state)
-            .map_err(|e| anyhow!("Event loop error: {}", e))?;
+    let replaced = if frame.is_some() {
+        fps_window.1 += 1;
+        std::mem::replace(&mut snapshot.frame, frame)
+    } else {
+        None
+    };
+    let elapsed = fps_window.0.elapsed();
+    if elapsed >= Duration::from_secs(1) {
+        snapshot.status.fps = fps_window.1 as f64 / elapsed.as_secs_f64();
+        *fps_window = (Instant::now(), 0);
     }
 
-    // Cleanup
-    info!("Shutting down...");
-    if !headless {
-        TerminalInput::disable_mouse_capture()?;
-        kitty_setup.restore_terminal()?;
+    snapshot.status.toplevel_titles = state.toplevel_titles();
+    snapshot.status.width = state.term_width;
+    snapshot.status.height = state.term_height;
+    replaced
+}
+
+/// Keeps trace output alive; must outlive the event loop so it gets flushed
+struct LogGuard {
+    #[cfg(feature = "profile")]
+    _chrome: Option<tracing_chrome::FlushGuard>,
+}
+
+fn init_logging(options: &Options) -> Result<LogGuard> {
+    use tracing_subscriber::{fmt::format::FmtSpan, prelude::*};
+
+    // Redirect logging to file so it doesn't interfere with terminal graphics
+    let log_file = std::fs::File::create("/tmp/termui.log")
+        .map_err(|e| anyhow!("Failed to create log file: {}", e))?;
+
+    // In profile mode, log each span's busy/idle time when it closes
+    let span_events = if options.profile { FmtSpan::CLOSE } else { FmtSpan::NONE };
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_writer(log_file)
+        .with_span_events(span_events);
+    let filter = tracing_subscriber::EnvFilter::from_default_env()
+        .add_directive(tracing::Level::DEBUG.into());
+    let registry = tracing_subscriber::registry().with(filter).with(fmt_layer);
+
+    #[cfg(feature = "profile")]
+    {
+        let (chrome_layer, guard) = match &options.profile_trace {
+            Some(path) => {
+                let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+                    .file(path)
+                    .include_args(true)
+                    .build();
+                (Some(layer), Some(guard))
+            }
+            None => (None, None),
+        };
+        registry.with(chrome_layer).init();
+        Ok(LogGuard { _chrome: guard })
+    }
+
+    #[cfg(not(feature = "profile"))]
+    {
+        registry.init();
+        Ok(LogGuard {})
+    }
+}
+
+fn wait_for_first_frame(
+    event_loop: &mut EventLoop<TermuiState>,
+    state: &mut TermuiState,
+    timeout: Duration,
+) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+
+    while state.running && state.pending_frame.lock().unwrap().is_none() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            info!("No frame after {:?}, showing terminal anyway", timeout);
+            return Ok(());
+        }
+        event_loop
+            .dispatch(Some(remaining.min(Duration::from_millis(16))), state)
+            .map_err(|e| anyhow!("Event loop error: {}", e))?;
     }
 
+    info!("First frame ready");
     Ok(())
 }
 
@@ -319,22 +874,14 @@ fn handle_input_event(state: &mut TermuiState, event: WaylandInputEvent) {
 
     match event {
         WaylandInputEvent::PointerMotion { x, y, time } => {
-            state.pointer_location = Point::from((x, y));
-
-            let pointer = state.seat.get_pointer().unwrap();
+            let location = state.display_to_logical(Point::from((x, y)));
+            state.move_pointer(location, time);
 
-            if let Some((surface, offset)) = state.surface_under_pointer() {
-                pointer.motion(
-                    state,
-                    Some((surface, offset)),
-                    &MotionEvent {
-                        location: state.pointer_location,
-                        serial,
-                        time,
-                    },
-                );
+            // The software cursor moved, and shows steadily while moving
+            let unblinked = state.cursor_blink.reset(Instant::now());
+            if state.show_cursor || unblinked {
+                state.redraw();
             }
-            pointer.frame(state);
         }
 
         WaylandInputEvent::PointerButton { button, state: btn_state, time } => {
@@ -351,55 +898,112 @@ fn handle_input_event(state: &mut TermuiState, event: WaylandInputEvent) {
             pointer.frame(state);
         }
 
-        WaylandInputEvent::PointerAxis { horizontal, vertical, time } => {
+        WaylandInputEvent::PointerAxis { horizontal, vertical, steps, time } => {
             let pointer = state.seat.get_pointer().unwrap();
 
-            let mut frame = AxisFrame::new(time);
+            // Wheel notches, for clients that scroll a line or an item per
+            // notch, and the distance for those that scroll smoothly
+            let mut frame = AxisFrame::new(time).source(AxisSource::Wheel);
             if vertical != 0.0 {
-                frame = frame.value(Axis::Vertical, vertical);
+                frame = frame.value(Axis::Vertical, vertical).v120(Axis::Vertical, steps * 120);
             }
             if horizontal != 0.0 {
-                frame = frame.value(Axis::Horizontal, horizontal);
+                frame = frame.value(Axis::Horizontal, horizontal).v120(Axis::Horizontal, steps * 120);
             }
 
             pointer.axis(state, frame);
             pointer.frame(state);
         }
 
-        WaylandInputEvent::KeyboardKey { keysym, state: key_state, time } => {
+        WaylandInputEvent::KeyboardKey { keysym, state: key_state, locks, time } => {
             let keyboard = state.seat.get_keyboard().unwrap();
 
-            // Convert keysym to keycode
-            let keycode = keysym_to_keycode(keysym);
+            // Bring xkb's lock state in line with the host keyboard first, so the
+            // key below is interpreted with the right Caps/Num Lock
+            if let Some(locks) = locks {
+                if !is_lock_keysym(keysym) {
+                    state.sync_locks(locks, time);
+                }
+            }
 
             let pressed = matches!(key_state, terminal::KeyState::Pressed);
-            tracing::info!(
+            // Look the key up in the keymap the client has, so it reads back the same keysym
+            let modifiers = keyboard.modifier_state();
+            let Some(keycode) = state.keycodes.keycode(keysym, modifiers.shift) else {
+                tracing::debug!("No key for keysym 0x{:x} in the keymap", keysym.raw());
+                return;
+            };
+            // Terminals report the character, not AltGr: hold it around keys
+            // whose keysym sits on an AltGr level of the keymap
+            let altgr = (pressed
+                && !modifiers.iso_level3_shift
+                && state.keycodes.needs_altgr(keysym, modifiers.shift))
+            .then(|| state.keycodes.keycode(Keysym::ISO_Level3_Shift, false))
+            .flatten();
+            // Keystrokes may be passwords: only logged when asked for with
+            // RUST_LOG=termui::input=trace
+            tracing::trace!(
+                target: "termui::input",
                 "Key: keysym=0x{:x} ({}) -> keycode={}, state={}",
                 keysym.raw(),
                 char::from_u32(keysym.raw()).unwrap_or('?'),
                 keycode.raw(),
                 if pressed { "PRESS" } else { "RELEASE" }
             );
-            let key_state = if pressed {
-                smithay::backend::input::KeyState::Pressed
+            if pressed {
+                state.send_key(keycode, altgr, smithay::backend::input::KeyState::Pressed, time);
+                // A key pressed takes over repeating from the one before
+                state.start_key_repeat(keycode, altgr);
             } else {
-                smithay::backend::input::KeyState::Released
-            };
+                state.stop_key_repeat(Some(keycode));
+                state.send_key(keycode, None, smithay::backend::input::KeyState::Released, time);
+            }
 
-            keyboard.input::<(), _>(
-                state,
-                keycode,
-                key_state,
-                serial,
-                time,
-                |_, _, _| {
-                    smithay::input::keyboard::FilterResult::Forward
-                },
-            );
+            update_lock_leds(state, &keyboard);
         }
 
         WaylandInputEvent::Resize { width, height } => {
-            state.resize_output(width, height);
+            // The buffer scale is --scale
+            let (width, height) = output_size(width, height, state.buffer_scale as u32);
+            state.request_resize(width, height);
+        }
+
+        WaylandInputEvent::CloseWindow => {
+            // Like a window manager's close action: the client decides whether to quit
+            if let Some(toplevel) = state.focused_toplevel() {
+                info!("Asking focused window to close");
+                toplevel.send_close();
+            }
+        }
+
+        WaylandInputEvent::CycleBackend => {
+            // Switched before the next frame, which the redraw brings
+            state.cycle_backend = true;
+            state.redraw();
+        }
+
+        WaylandInputEvent::Zoom(action) => {
+            if state.zoom.apply(action) {
+                tracing::debug!("Zoom: {:?}", state.zoom);
+                state.redraw();
+            }
+        }
+
+        WaylandInputEvent::Paste(text) => compositor::clipboard::set_selection(state, text),
+
+        WaylandInputEvent::GraphicsResponse { image_id, message } => {
+            if message != "OK" {
+                warn!("Terminal rejected image {}: {}", image_id, message);
+            }
+            if let Some(diagnostics) = &mut state.graphics_diagnostics {
+                diagnostics.record(image_id, &message);
+            }
+            if let Some(pacer) = &mut state.frame_pacer {
+                pacer.acknowledge(image_id);
+            }
+            state.catch_up();
+            // A frame held back for the acknowledgement can go out now
+            state.frame_queued();
         }
 
         WaylandInputEvent::Quit => {
@@ -409,98 +1013,178 @@ fn handle_input_event(state: &mut TermuiState, event: WaylandInputEvent) {
     }
 }
 
-/// Convert keysym to XKB keycode (evdev + 8 offset)
-/// XKB keycodes are Linux evdev keycodes + 8
-fn keysym_to_keycode(keysym: smithay::input::keyboard::Keysym) -> Keycode {
-    let raw = keysym.raw();
-
-    // evdev keycodes - we'll add 8 at the end for XKB
-    let evdev_code = match raw {
-        // Function keys
-        0xff1b => 1,         // Escape -> KEY_ESC
-        0xff0d => 28,        // Return -> KEY_ENTER
-        0xff09 => 15,        // Tab -> KEY_TAB
-        0xff08 => 14,        // BackSpace -> KEY_BACKSPACE
-        0x20 => 57,          // Space -> KEY_SPACE
-
-        // Arrow keys
-        0xff51 => 105,       // Left -> KEY_LEFT
-        0xff53 => 106,       // Right -> KEY_RIGHT
-        0xff52 => 103,       // Up -> KEY_UP
-        0xff54 => 108,       // Down -> KEY_DOWN
-
-        // Navigation
-        0xff50 => 102,       // Home -> KEY_HOME
-        0xff57 => 107,       // End -> KEY_END
-        0xff55 => 104,       // Page_Up -> KEY_PAGEUP
-        0xff56 => 109,       // Page_Down -> KEY_PAGEDOWN
-        0xff63 => 110,       // Insert -> KEY_INSERT
-        0xffff => 111,       // Delete -> KEY_DELETE
-
-        // Letters (US QWERTY layout keycodes)
-        c if c < 128 => {
-            match c as u8 as char {
-                // Number row
-                '1' | '!' => 2,
-                '2' | '@' => 3,
-                '3' | '#' => 4,
-                '4' | '$' => 5,
-                '5' | '%' => 6,
-                '6' | '^' => 7,
-                '7' | '&' => 8,
-                '8' | '*' => 9,
-                '9' | '(' => 10,
-                '0' | ')' => 11,
-                '-' | '_' => 12,
-                '=' | '+' => 13,
-
-                // Top row: QWERTYUIOP
-                'q' | 'Q' => 16,
-                'w' | 'W' => 17,
-                'e' | 'E' => 18,
-                'r' | 'R' => 19,
-                't' | 'T' => 20,
-                'y' | 'Y' => 21,
-                'u' | 'U' => 22,
-                'i' | 'I' => 23,
-                'o' | 'O' => 24,
-                'p' | 'P' => 25,
-                '[' | '{' => 26,
-                ']' | '}' => 27,
-
-                // Home row: ASDFGHJKL
-                'a' | 'A' => 30,
-                's' | 'S' => 31,
-                'd' | 'D' => 32,
-                'f' | 'F' => 33,
-                'g' | 'G' => 34,
-                'h' | 'H' => 35,
-                'j' | 'J' => 36,
-                'k' | 'K' => 37,
-                'l' | 'L' => 38,
-                ';' | ':' => 39,
-                '\'' | '"' => 40,
-                '`' | '~' => 41,
-                '\\' | '|' => 43,
-
-                // Bottom row: ZXCVBNM
-                'z' | 'Z' => 44,
-                'x' | 'X' => 45,
-                'c' | 'C' => 46,
-                'v' | 'V' => 47,
-                'b' | 'B' => 48,
-                'n' | 'N' => 49,
-                'm' | 'M' => 50,
-                ',' | '<' => 51,
-                '.' | '>' => 52,
-                '/' | '?' => 53,
-
-                _ => 0,
+/// Smallest output we present to clients, however small the terminal
+const MIN_OUTPUT_SIZE: (u32, u32) = (64, 48);
+
+/// Size of the output for a terminal of `pixel_width`x`pixel_height`, with
+/// each output pixel shown as `scale`x`scale` terminal pixels
+fn output_size(pixel_width: u32, pixel_height: u32, scale: u32) -> (u32, u32) {
+    let scale = scale.max(1);
+    at_least_min_output_size(pixel_width / scale, pixel_height / scale)
+}
+
+/// Grow an output size to at least MIN_OUTPUT_SIZE
+///
+/// A terminal of a few cells would otherwise give a zero-sized output, which
+/// clients can't draw into. The terminal then shows only part of the frame.
+fn at_least_min_output_size(width: u32, height: u32) -> (u32, u32) {
+    let size = (width.max(MIN_OUTPUT_SIZE.0), height.max(MIN_OUTPUT_SIZE.1));
+    if size != (width, height) {
+        warn!("Terminal is too small for a {}x{} output, using {}x{}", width, height, size.0, size.1);
+    }
+    size
+}
+
+/// Height of the title bars drawn over windows, in output coordinates: a
+/// terminal cell's, rounded up so the title fits in it
+fn title_bar_height(pixel_height: u32, rows: u32, scale: u32) -> i32 {
+    let cell_height = pixel_height / rows.max(1);
+    cell_height.div_ceil(scale.max(1)).max(1) as i32
+}
+
+/// Output coordinates for `--initial-pointer`, which must lie on the output
+fn initial_pointer(start: PointerStart, size: Size<i32, Logical>) -> Result<Point<f64, Logical>> {
+    let size = size.to_f64();
+    let (x, y) = match start {
+        PointerStart::Center => (size.w / 2.0, size.h / 2.0),
+        PointerStart::At(x, y) => (x, y),
+    };
+    if x >= size.w || y >= size.h {
+        anyhow::bail!("--initial-pointer {},{} is outside the {}x{} output", x, y, size.w, size.h);
+    }
+    Ok(Point::from((x, y)))
+}
+
+/// How the graphics backends are set up, kept for setting up the next one
+/// `--backend-key` switches to
+struct RendererSettings {
+    full_refresh: Option<FullRefresh>,
+    integer_scale: bool,
+    compression: Compression,
+    scale_mode: ScaleMode,
+    text_cursor: Option<TextCursor>,
+    /// `--ack-pacing`, with a terminal to answer
+    ack_pacing: bool,
+    /// `--graphics-diagnostics`, with a terminal to answer
+    graphics_diagnostics: bool,
+    max_frames_behind: usize,
+}
+
+impl RendererSettings {
+    /// A fresh `backend`, with nothing on screen yet
+    fn create(&self, backend: Backend) -> Box<dyn RenderBackend> {
+        match backend {
+            Backend::Kitty => {
+                let mut kitty = KittyGraphics::new();
+                kitty.set_full_refresh(self.full_refresh);
+                kitty.set_integer_scale(self.integer_scale);
+                kitty.set_compression(self.compression);
+                kitty.set_scale_mode(self.scale_mode);
+                kitty.set_text_cursor(self.text_cursor.clone());
+                kitty.set_request_acks(self.ack_pacing || self.graphics_diagnostics);
+                Box::new(kitty)
+            }
+            Backend::Sixel => {
+                let mut sixel = SixelGraphics::new();
+                sixel.set_text_cursor(self.text_cursor.clone());
+                Box::new(sixel)
+            }
+            Backend::ITerm2 => {
+                let mut iterm2 = ITerm2Graphics::new();
+                iterm2.set_scale_mode(self.scale_mode);
+                iterm2.set_text_cursor(self.text_cursor.clone());
+                Box::new(iterm2)
             }
         }
-        _ => 0,
-    };
+    }
+
+    /// Pace and check frames by the terminal's responses, as far as they're
+    /// wanted; only Kitty graphics are answered
+    fn track_responses(&self, state: &mut TermuiState, backend: Backend) {
+        let answered = backend == Backend::Kitty;
+        state.frame_pacer = (self.ack_pacing && answered)
+            .then(|| FramePacer::new(self.max_frames_behind, ACK_TIMEOUT));
+        let compressed = self.compression != Compression::None;
+        state.graphics_diagnostics = (self.graphics_diagnostics && answered)
+            .then(|| GraphicsDiagnostics::new(compressed, DEFAULT_MAX_SIZE));
+    }
+}
+
+/// Interval of the frame timer, and the shortest gap between frames with
+/// `--render-mode on-damage`
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/// How long --ack-pacing waits for the terminal before sending the next frame anyway
+const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How often to check whether the spawned command has exited
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often --script checks whether the first window is up
+const SCRIPT_START_POLL: Duration = Duration::from_millis(50);
+
+/// How often --stats reports
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+fn is_lock_keysym(keysym: Keysym) -> bool {
+    matches!(keysym, Keysym::Caps_Lock | Keysym::Num_Lock | Keysym::Scroll_Lock)
+}
 
-    // XKB keycode = evdev keycode + 8
-    Keycode::new(evdev_code + 8)
+/// Record the keymap's lock LEDs after a key went through xkb
+fn update_lock_leds(state: &mut TermuiState, keyboard: &KeyboardHandle<TermuiState>) {
+    let leds = keyboard.led_state();
+    if leds != state.lock_leds {
+        tracing::debug!(
+            "Lock state: caps={:?} num={:?} scroll={:?}",
+            leds.caps, leds.num, leds.scroll
+        );
+        state.lock_leds = leds;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyCode;
+    use termui::keymap::{keymap_for_layout, KeycodeMap};
+
+    #[test]
+    fn exit_status_is_passed_on() {
+        use std::os::unix::process::ExitStatusExt;
+
+        assert_eq!(exit_code(ExitStatus::from_raw(3 << 8)), ExitCode::from(3));
+        // Killed by SIGKILL
+        assert_eq!(exit_code(ExitStatus::from_raw(9)), ExitCode::from(137));
+    }
+
+    #[test]
+    fn one_cell_terminal_gets_the_minimum_output() {
+        // One cell at the fallback cell size, divided by the scale factor
+        assert_eq!(at_least_min_output_size(10 / 4, 20 / 4), MIN_OUTPUT_SIZE);
+        assert_eq!(at_least_min_output_size(0, 600), (MIN_OUTPUT_SIZE.0, 600));
+        assert_eq!(at_least_min_output_size(800, 600), (800, 600));
+    }
+
+    #[test]
+    fn resized_outputs_are_the_terminal_pixels_over_the_scale() {
+        assert_eq!(output_size(1920, 1080, 4), (480, 270));
+        assert_eq!(output_size(3840, 2160, 4), (960, 540));
+        assert_eq!(output_size(1920, 1080, 1), (1920, 1080));
+    }
+
+    #[test]
+    fn small_terminals_are_clamped_after_scaling() {
+        // 200 terminal pixels are large enough, but not at 50 output pixels
+        assert_eq!(output_size(200, 1080, 4), (MIN_OUTPUT_SIZE.0, 270));
+        assert_eq!(output_size(240, 160, 4), MIN_OUTPUT_SIZE);
+    }
+
+    #[test]
+    fn printable_ascii_round_trips_to_a_keycode() {
+        let keycodes = KeycodeMap::from_keymap(&keymap_for_layout("us"));
+        for c in ' '..='~' {
+            let keysym = TerminalInput::keycode_to_keysym(KeyCode::Char(c)).unwrap();
+            assert!(keycodes.keycode(keysym, false).is_some(), "{:?} maps to no keycode", c);
+        }
+    }
 }
@@ -0,0 +1,273 @@
+//! Mirror the frames sent to the terminal into a window on the host Wayland
+//! desktop, as a development aid
+//!
+//! The window runs on its own thread with its own wayland-client connection
+//! (to the host compositor, not ours) and always shows the newest frame.
+
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::os::unix::io::{AsFd, AsRawFd};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use termui::compositor::FrameData;
+use wayland_client::{
+    delegate_noop,
+    protocol::{wl_buffer, wl_compositor, wl_registry, wl_shm, wl_shm_pool, wl_surface},
+    backend::WaylandError,
+    Connection, Dispatch, QueueHandle,
+};
+use wayland_protocols::xdg::shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base};
+
+/// Handle for sending frames to the mirror window
+pub struct Mirror {
+    frames: Sender<FrameData>,
+}
+
+impl Mirror {
+    /// Connect to the host display (`WAYLAND_DISPLAY`) and open the window
+    pub fn spawn() -> Result<Self> {
+        let conn = Connection::connect_to_env()
+            .map_err(|e| anyhow!("Failed to connect to host Wayland display for --mirror: {}", e))?;
+        let (frames, rx) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            if let Err(e) = run(conn, rx) {
+                tracing::error!("Mirror window failed: {:?}", e);
+            }
+        });
+
+        Ok(Self { frames })
+    }
+
+    /// Queue a frame for the window; dropped silently once the window is closed
+    pub fn show(&self, frame: &FrameData) {
+        let _ = self.frames.send(frame.clone());
+    }
+}
+
+#[derive(Default)]
+struct MirrorState {
+    running: bool,
+    configured: bool,
+    /// The host compositor still holds our buffer
+    buffer_busy: bool,
+    compositor: Option<wl_compositor::WlCompositor>,
+    shm: Option<wl_shm::WlShm>,
+    xdg_wm_base: Option<xdg_wm_base::XdgWmBase>,
+}
+
+fn run(conn: Connection, frames: Receiver<FrameData>) -> Result<()> {
+    let mut event_queue = conn.new_event_queue();
+    let qh = event_queue.handle();
+    let _registry = conn.display().get_registry(&qh, ());
+
+    let mut state = MirrorState { running: true, ..Default::default() };
+    event_queue.roundtrip(&mut state)?;
+
+    let compositor = state.compositor.clone().ok_or_else(|| anyhow!("Host has no wl_compositor"))?;
+    let shm = state.shm.clone().ok_or_else(|| anyhow!("Host has no wl_shm"))?;
+    let xdg_wm_base = state.xdg_wm_base.clone().ok_or_else(|| anyhow!("Host has no xdg_wm_base"))?;
+
+    let surface = compositor.create_surface(&qh, ());
+    let xdg_surface = xdg_wm_base.get_xdg_surface(&surface, &qh, ());
+    let xdg_toplevel = xdg_surface.get_toplevel(&qh, ());
+    xdg_toplevel.set_title("termui mirror".to_string());
+    xdg_toplevel.set_app_id("termui-mirror".to_string());
+    surface.commit();
+
+    let mut pending: Option<FrameData> = None;
+    let mut buffer: Option<ShmBuffer> = None;
+
+    while state.running {
+        match frames.recv_timeout(Duration::from_millis(16)) {
+            Ok(frame) => pending = Some(frame),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        // Only the newest frame matters
+        while let Ok(frame) = frames.try_recv() {
+            pending = Some(frame);
+        }
+
+        if state.configured && !state.buffer_busy {
+            if let Some(frame) = pending.take().filter(|f| f.width > 0 && f.height > 0) {
+                if buffer.as_ref().is_none_or(|b| (b.width, b.height) != (frame.width, frame.height)) {
+                    buffer = Some(ShmBuffer::new(&shm, frame.width, frame.height, &qh)?);
+                }
+                let buffer = buffer.as_mut().unwrap();
+                buffer.write_rgba(&frame.data);
+
+                surface.attach(Some(&buffer.buffer), 0, 0);
+                surface.damage_buffer(0, 0, frame.width as i32, frame.height as i32);
+                surface.commit();
+                state.buffer_busy = true;
+            }
+        }
+
+        conn.flush()?;
+        if let Some(guard) = event_queue.prepare_read() {
+            match guard.read() {
+                Ok(_) => {}
+                Err(WaylandError::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        event_queue.dispatch_pending(&mut state)?;
+    }
+
+    tracing::info!("Mirror window closed");
+    Ok(())
+}
+
+/// A single XRGB8888 wl_shm buffer backed by a memfd
+struct ShmBuffer {
+    width: u32,
+    height: u32,
+    pool: wl_shm_pool::WlShmPool,
+    buffer: wl_buffer::WlBuffer,
+    ptr: *mut u8,
+    len: usize,
+    _file: File,
+}
+
+impl ShmBuffer {
+    fn new(shm: &wl_shm::WlShm, width: u32, height: u32, qh: &QueueHandle<MirrorState>) -> Result<Self> {
+        let stride = width * 4;
+        let len = (stride * height) as usize;
+
+        let file = File::from(rustix::fs::memfd_create("termui-mirror", rustix::fs::MemfdFlags::CLOEXEC)?);
+        rustix::fs::ftruncate(&file, len as u64)?;
+
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(anyhow!("mmap of mirror buffer failed"));
+        }
+
+        let pool = shm.create_pool(file.as_fd(), len as i32, qh, ());
+        let buffer = pool.create_buffer(
+            0,
+            width as i32,
+            height as i32,
+            stride as i32,
+            wl_shm::Format::Xrgb8888,
+            qh,
+            (),
+        );
+
+        Ok(Self { width, height, pool, buffer, ptr: ptr as *mut u8, len, _file: file })
+    }
+
+    /// Copy an RGBA frame in, converting to XRGB8888 (BGRX in memory)
+    fn write_rgba(&mut self, rgba: &[u8]) {
+        let pixels = unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) };
+        for (dst, src) in pixels.chunks_exact_mut(4).zip(rgba.chunks_exact(4)) {
+            dst.copy_from_slice(&[src[2], src[1], src[0], 0xff]);
+        }
+    }
+}
+
+impl Drop for ShmBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        self.pool.destroy();
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for MirrorState {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global { name, interface, .. } = event {
+            match interface.as_str() {
+                "wl_compositor" => state.compositor = Some(registry.bind(name, 4, qh, ())),
+                "wl_shm" => state.shm = Some(registry.bind(name, 1, qh, ())),
+                "xdg_wm_base" => state.xdg_wm_base = Some(registry.bind(name, 1, qh, ())),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_buffer::WlBuffer, ()> for MirrorState {
+    fn event(
+        state: &mut Self,
+        _: &wl_buffer::WlBuffer,
+        event: wl_buffer::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            state.buffer_busy = false;
+        }
+    }
+}
+
+impl Dispatch<xdg_wm_base::XdgWmBase, ()> for MirrorState {
+    fn event(
+        _: &mut Self,
+        xdg_wm_base: &xdg_wm_base::XdgWmBase,
+        event: xdg_wm_base::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            xdg_wm_base.pong(serial);
+        }
+    }
+}
+
+impl Dispatch<xdg_surface::XdgSurface, ()> for MirrorState {
+    fn event(
+        state: &mut Self,
+        xdg_surface: &xdg_surface::XdgSurface,
+        event: xdg_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            xdg_surface.ack_configure(serial);
+            state.configured = true;
+        }
+    }
+}
+
+impl Dispatch<xdg_toplevel::XdgToplevel, ()> for MirrorState {
+    fn event(
+        state: &mut Self,
+        _: &xdg_toplevel::XdgToplevel,
+        event: xdg_toplevel::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // The window always takes the frame's size, so only closing matters
+        if let xdg_toplevel::Event::Close = event {
+            state.running = false;
+        }
+    }
+}
+
+delegate_noop!(MirrorState: ignore wl_compositor::WlCompositor);
+delegate_noop!(MirrorState: ignore wl_surface::WlSurface);
+delegate_noop!(MirrorState: ignore wl_shm::WlShm);
+delegate_noop!(MirrorState: ignore wl_shm_pool::WlShmPool);
@@ -0,0 +1,102 @@
+//! Record the frames sent to the terminal (`--record`), for bug reports
+//!
+//! Frames are saved as numbered PNG files, the layout `--replay` plays
+//! back, with `timestamps` listing when each was shown. PNG encoding runs
+//! on a writer thread fed through a bounded queue, so a slow disk drops
+//! frames rather than holding up the frame timer.
+
+use anyhow::{anyhow, Result};
+use image::ExtendedColorType;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use termui::compositor::FrameData;
+
+/// Frames waiting for the writer thread before more are dropped
+const QUEUE_LENGTH: usize = 8;
+
+/// Handle for sending frames to the writer thread
+pub struct Recorder {
+    frames: Option<SyncSender<(FrameData, Duration)>>,
+    writer: Option<JoinHandle<()>>,
+    started: Instant,
+    /// Shortest time between recorded frames, from `--record-fps`
+    interval: Duration,
+    last_recorded: Option<Instant>,
+    /// Frames the writer thread couldn't keep up with
+    dropped: u64,
+}
+
+impl Recorder {
+    /// Record into `dir`, created if needed, at most `fps` frames a second
+    pub fn spawn(dir: &Path, fps: u32) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Failed to create recording directory {}: {}", dir.display(), e))?;
+        let timestamps = File::create(dir.join("timestamps"))
+            .map_err(|e| anyhow!("Failed to start recording in {}: {}", dir.display(), e))?;
+        let (frames, rx) = mpsc::sync_channel(QUEUE_LENGTH);
+        let dir = dir.to_path_buf();
+        let writer = std::thread::spawn(move || {
+            if let Err(e) = write_frames(&dir, BufWriter::new(timestamps), rx) {
+                tracing::error!("Recording stopped: {:?}", e);
+            }
+        });
+
+        Ok(Self {
+            frames: Some(frames),
+            writer: Some(writer),
+            started: Instant::now(),
+            interval: Duration::from_secs(1) / fps.max(1),
+            last_recorded: None,
+            dropped: 0,
+        })
+    }
+
+    /// Queue `frame`, just shown, unless one was recorded too recently
+    pub fn record(&mut self, frame: &FrameData) {
+        let now = Instant::now();
+        if self.last_recorded.is_some_and(|last| now - last < self.interval) {
+            return;
+        }
+        let Some(frames) = &self.frames else {
+            return;
+        };
+        match frames.try_send((frame.clone(), now - self.started)) {
+            Ok(()) => self.last_recorded = Some(now),
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                tracing::debug!("Recording fell behind, {} frame(s) dropped so far", self.dropped);
+            }
+            // The writer gave up, and said why
+            Err(TrySendError::Disconnected(_)) => self.frames = None,
+        }
+    }
+}
+
+impl Drop for Recorder {
+    /// Wait for the queued frames to be written
+    fn drop(&mut self) {
+        self.frames = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        if self.dropped > 0 {
+            tracing::warn!("The recording is missing {} frame(s) the disk couldn't keep up with", self.dropped);
+        }
+    }
+}
+
+/// Save frames as `1.png`, `2.png`, ... with a line for each in `timestamps`
+fn write_frames(dir: &Path, mut timestamps: impl Write, frames: Receiver<(FrameData, Duration)>) -> Result<()> {
+    for (number, (frame, time)) in (1u64..).zip(frames) {
+        let path: PathBuf = dir.join(format!("{}.png", number));
+        image::save_buffer(&path, &frame.data, frame.width, frame.height, ExtendedColorType::Rgba8)
+            .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+        writeln!(timestamps, "{}.png {:.3}", number, time.as_secs_f64())?;
+        timestamps.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+//! Replay a directory of numbered PNG frames through the graphics backend,
+//! without starting a compositor
+
+use crate::cli::Options;
+use anyhow::{anyhow, bail, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use termui::terminal::{KittyGraphics, RenderBackend, TerminalInput, WaylandInputEvent};
+use tracing::info;
+
+/// Play back the frames in `dir` at the configured rate, looping if requested
+pub fn run(dir: &Path, options: &Options) -> Result<()> {
+    let (fps, looping, headless) = (options.replay_fps, options.replay_loop, options.headless);
+    let frames = frame_paths(dir)?;
+    if frames.is_empty() {
+        bail!("No PNG frames found in {}", dir.display());
+    }
+    info!("Replaying {} frames from {} at {} fps", frames.len(), dir.display(), fps);
+
+    let mut kitty = KittyGraphics::new();
+    kitty.set_full_refresh(options.full_refresh);
+    kitty.set_compression(options.compression);
+    if !headless {
+        kitty.setup_terminal()?;
+        TerminalInput::enable_mouse_capture()?;
+    }
+
+    let result = play(&mut kitty, &frames, fps, looping, headless);
+
+    if !headless {
+        TerminalInput::disable_mouse_capture()?;
+        kitty.restore_terminal()?;
+    }
+
+    result
+}
+
+fn play(kitty: &mut KittyGraphics, frames: &[PathBuf], fps: u32, looping: bool, headless: bool) -> Result<()> {
+    let frame_interval = Duration::from_secs(1) / fps.max(1);
+    // Only used to recognize the quit chords
+    let mut term_input = TerminalInput::new(1, 1, 1, 1);
+
+    loop {
+        for path in frames {
+            let started = Instant::now();
+
+            let image = image::open(path)
+                .map_err(|e| anyhow!("Failed to read frame {}: {}", path.display(), e))?;
+            let opaque = !image.color().has_alpha();
+            let image = image.to_rgba8();
+            kitty.display_frame(image.width(), image.height(), image.as_raw(), opaque, &[])?;
+
+            // Wait out the rest of the frame interval, watching for Ctrl+C/Ctrl+Q
+            loop {
+                let remaining = frame_interval.saturating_sub(started.elapsed());
+                if remaining.is_zero() {
+                    break;
+                }
+                if headless {
+                    std::thread::sleep(remaining);
+                    break;
+                }
+                if let Some(event) = TerminalInput::poll_event(remaining)? {
+                    let events = term_input.translate_event(event);
+                    if events.iter().any(|e| matches!(e, WaylandInputEvent::Quit)) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        if !looping {
+            return Ok(());
+        }
+    }
+}
+
+/// PNG files in `dir`, ordered by the number in their name
+fn frame_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| anyhow!("Failed to read replay directory {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")))
+        .collect();
+
+    // Shorter names first so unpadded numbers sort numerically (2 before 10)
+    paths.sort_by(|a, b| {
+        let (a, b) = (a.file_name().unwrap(), b.file_name().unwrap());
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    });
+    Ok(paths)
+}
@@ -0,0 +1,207 @@
+//! Input scripts for --script: a list of commands injected into the app as
+//! if they came from the terminal
+//!
+//! One command per line; blank lines and lines starting with `#` are skipped:
+//!
+//! - `move X Y` - move the pointer to X,Y in display pixels, as the mouse reports them
+//! - `click [left|right|middle]` - press and release a button (default left)
+//! - `type "text"` - type each character (`\"` and `\\` escape)
+//! - `key <chord>` - press a key by its XKB name with modifiers, e.g. `Enter` or `ctrl+shift+Tab`
+//! - `wait <ms>` - pause before the next command
+//! - `exit` - end the session
+
+use anyhow::{anyhow, Result};
+use smithay::backend::input::ButtonState;
+use smithay::input::keyboard::{xkb, Keysym};
+use std::path::Path;
+use std::time::Duration;
+use termui::keymap::KeycodeMap;
+use termui::terminal::{KeyState, WaylandInputEvent};
+
+/// Linux input button codes
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+const BTN_MIDDLE: u32 = 0x112;
+
+pub enum Step {
+    /// Event to inject; its timestamp is filled in when it is sent
+    Input(WaylandInputEvent),
+    Wait(Duration),
+    Exit,
+}
+
+/// Read and parse the script at `path`
+///
+/// `keycodes` decides which characters need Shift held to be typed.
+pub fn load(path: &Path, keycodes: &KeycodeMap) -> Result<Vec<Step>> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read script {}: {}", path.display(), e))?;
+    parse(&source, keycodes).map_err(|(line, e)| anyhow!("{}:{}: {}", path.display(), line, e))
+}
+
+/// Parse a script; errors carry the 1-based line they were found on
+fn parse(source: &str, keycodes: &KeycodeMap) -> Result<Vec<Step>, (usize, String)> {
+    let mut steps = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        parse_command(line, keycodes, &mut steps).map_err(|e| (index + 1, e))?;
+    }
+    Ok(steps)
+}
+
+fn parse_command(line: &str, keycodes: &KeycodeMap, steps: &mut Vec<Step>) -> Result<(), String> {
+    let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let args: Vec<&str> = rest.split_whitespace().collect();
+
+    match (command, args.as_slice()) {
+        ("move", [x, y]) => {
+            let coordinate = |s: &str| s.parse::<f64>().map_err(|_| format!("invalid coordinate {:?}", s));
+            steps.push(Step::Input(WaylandInputEvent::PointerMotion { x: coordinate(x)?, y: coordinate(y)?, time: 0 }));
+        }
+        ("click", [] | [_]) => {
+            let button = match args.first().copied().unwrap_or("left") {
+                "left" => BTN_LEFT,
+                "right" => BTN_RIGHT,
+                "middle" => BTN_MIDDLE,
+                other => return Err(format!("unknown button {:?}", other)),
+            };
+            for state in [ButtonState::Pressed, ButtonState::Released] {
+                steps.push(Step::Input(WaylandInputEvent::PointerButton { button, state, time: 0 }));
+            }
+        }
+        ("type", _) => {
+            for c in parse_quoted(rest)?.chars() {
+                let keysym = Keysym::from_char(c);
+                let shift = keycodes.needs_shift(keysym).unwrap_or(false);
+                push_keys(steps, &[], keysym, shift);
+            }
+        }
+        ("key", [chord]) => {
+            let mut parts: Vec<&str> = chord.split('+').collect();
+            let name = parts.pop().unwrap_or_default();
+            let keysym = xkb::keysym_from_name(name, xkb::KEYSYM_CASE_INSENSITIVE);
+            if keysym.raw() == 0 {
+                return Err(format!("unknown key {:?}", name));
+            }
+            let modifiers = parts
+                .iter()
+                .map(|part| match part.to_ascii_lowercase().as_str() {
+                    "ctrl" | "control" => Ok(Keysym::Control_L),
+                    "alt" => Ok(Keysym::Alt_L),
+                    "shift" => Ok(Keysym::Shift_L),
+                    "super" => Ok(Keysym::Super_L),
+                    _ => Err(format!("unknown modifier {:?}", part)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            push_keys(steps, &modifiers, keysym, false);
+        }
+        ("wait", [ms]) => {
+            let ms: u64 = ms.parse().map_err(|_| format!("invalid duration {:?}", ms))?;
+            steps.push(Step::Wait(Duration::from_millis(ms)));
+        }
+        ("exit", []) => steps.push(Step::Exit),
+        ("move" | "click" | "key" | "wait" | "exit", _) => {
+            return Err(format!("wrong arguments for {}", command));
+        }
+        _ => return Err(format!("unknown command {:?}", command)),
+    }
+    Ok(())
+}
+
+/// Press and release `keysym` with `modifiers` (and Shift, if `shift`) held around it
+fn push_keys(steps: &mut Vec<Step>, modifiers: &[Keysym], keysym: Keysym, shift: bool) {
+    let mut held = modifiers.to_vec();
+    if shift && !held.contains(&Keysym::Shift_L) {
+        held.push(Keysym::Shift_L);
+    }
+    let key = |keysym, state| Step::Input(WaylandInputEvent::KeyboardKey { keysym, state, locks: None, time: 0 });
+
+    steps.extend(held.iter().map(|&k| key(k, KeyState::Pressed)));
+    steps.push(key(keysym, KeyState::Pressed));
+    steps.push(key(keysym, KeyState::Released));
+    steps.extend(held.iter().rev().map(|&k| key(k, KeyState::Released)));
+}
+
+/// The contents of a double-quoted string
+fn parse_quoted(s: &str) -> Result<String, String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .filter(|_| s.len() >= 2)
+        .ok_or_else(|| "expected text in double quotes".to_string())?;
+
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ ('"' | '\\')) => text.push(escaped),
+                _ => return Err("only \\\" and \\\\ can be escaped".to_string()),
+            },
+            '"' => return Err("unescaped quote inside text".to_string()),
+            c => text.push(c),
+        }
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(steps: &[Step]) -> Vec<(u32, bool)> {
+        steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::Input(WaylandInputEvent::KeyboardKey { keysym, state, .. }) => {
+                    Some((keysym.raw(), matches!(state, KeyState::Pressed)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn uppercase_is_typed_with_shift() {
+        let keymap = termui::keymap::keymap_for_layout("us");
+        let steps = parse("type \"a\\\"B\"", &KeycodeMap::from_keymap(&keymap)).unwrap();
+        let shift = Keysym::Shift_L.raw();
+        assert_eq!(
+            keys(&steps),
+            vec![
+                ('a' as u32, true),
+                ('a' as u32, false),
+                (shift, true),
+                ('"' as u32, true),
+                ('"' as u32, false),
+                (shift, false),
+                (shift, true),
+                ('B' as u32, true),
+                ('B' as u32, false),
+                (shift, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn chords_hold_their_modifiers() {
+        let steps = parse("key ctrl+Return\nwait 50\nexit", &KeycodeMap::default()).unwrap();
+        let ctrl = Keysym::Control_L.raw();
+        let enter = Keysym::Return.raw();
+        assert_eq!(keys(&steps), vec![(ctrl, true), (enter, true), (enter, false), (ctrl, false)]);
+        assert!(matches!(steps[4], Step::Wait(d) if d == Duration::from_millis(50)));
+        assert!(matches!(steps[5], Step::Exit));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let source = "# setup\nmove 10 20\n\nclick sideways\n";
+        assert_eq!(parse(source, &KeycodeMap::default()).err(), Some((4, "unknown button \"sideways\"".into())));
+        assert!(parse("type hello", &KeycodeMap::default()).is_err());
+        assert!(parse("key Nonsense", &KeycodeMap::default()).is_err());
+    }
+}
@@ -0,0 +1,50 @@
+//! Runtime frame statistics, reported periodically with `--stats`
+
+use std::time::Duration;
+
+/// Counters for the frame pipeline since the last report
+#[derive(Default)]
+pub struct FrameStats {
+    /// Frames read from client buffers
+    pub captured: u64,
+    /// Frames written to the terminal
+    pub transmitted: u64,
+    /// Frames never transmitted: replaced by a newer one first, or not
+    /// captured at all while the terminal was behind
+    pub skipped: u64,
+    /// Frames not transmitted because the terminal shows them already
+    pub unchanged: u64,
+    pub capture_time: Duration,
+    pub scale_time: Duration,
+    pub encode_time: Duration,
+}
+
+impl FrameStats {
+    /// Log the counters gathered since the last report and start over
+    ///
+    /// `in_flight` is the number of frames awaiting acknowledgement, when
+    /// frames are paced.
+    pub fn report(&mut self, toplevels: usize, in_flight: Option<usize>) {
+        let awaiting = in_flight.map(|n| format!(", {} awaiting ack", n)).unwrap_or_default();
+        tracing::info!(
+            "Stats: {} captured, {} transmitted, {} skipped, {} unchanged{}; avg capture {}, scale {}, encode {}; {} toplevel(s)",
+            self.captured,
+            self.transmitted,
+            self.skipped,
+            self.unchanged,
+            awaiting,
+            average(self.capture_time, self.captured),
+            average(self.scale_time, self.transmitted),
+            average(self.encode_time, self.transmitted),
+            toplevels,
+        );
+        *self = Self::default();
+    }
+}
+
+fn average(total: Duration, count: u64) -> String {
+    if count == 0 {
+        return "-".to_string();
+    }
+    format!("{:.2}ms", total.as_secs_f64() * 1000.0 / count as f64)
+}
@@ -0,0 +1,200 @@
+//! The graphics protocols frames can be sent to the terminal with
+
+use super::kitty::{frame_hash, query_terminal, TextCursor};
+use super::{Fallback, FrameTimings};
+use anyhow::Result;
+use smithay::utils::{Buffer, Rectangle};
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Graphics protocol to send frames with (`--backend`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Kitty,
+    Sixel,
+    /// iTerm2 inline images; never detected, only chosen
+    ITerm2,
+}
+
+impl Backend {
+    /// The protocol `--backend-key` switches to: Kitty, iTerm2, Sixel and around
+    pub fn next(self) -> Self {
+        match self {
+            Backend::Kitty => Backend::ITerm2,
+            Backend::ITerm2 => Backend::Sixel,
+            Backend::Sixel => Backend::Kitty,
+        }
+    }
+
+    /// Name for the terminal's title
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Kitty => "Kitty graphics",
+            Backend::Sixel => "Sixel",
+            Backend::ITerm2 => "iTerm2 images",
+        }
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kitty" => Ok(Backend::Kitty),
+            "sixel" => Ok(Backend::Sixel),
+            "iterm2" => Ok(Backend::ITerm2),
+            _ => Err(format!("expected kitty, sixel or iterm2, got {:?}", s)),
+        }
+    }
+}
+
+/// Something that shows frames in the terminal
+pub trait RenderBackend {
+    /// Show a frame of RGBA pixels; `opaque` if its alpha can be ignored,
+    /// and `damage` the parts that changed since the last one (empty: all)
+    ///
+    /// Returns the id the terminal's acknowledgement will refer to, or
+    /// `None` if the frame is already on screen and nothing was sent.
+    fn display_frame(
+        &mut self,
+        width: u32,
+        height: u32,
+        rgba_data: &[u8],
+        opaque: bool,
+        damage: &[Rectangle<i32, Buffer>],
+    ) -> Result<Option<u32>>;
+
+    /// Timings of the most recently displayed frame
+    fn last_timings(&self) -> FrameTimings;
+
+    /// Send frames the more conservative way `fallback` calls for, after
+    /// the terminal kept rejecting them
+    fn apply_fallback(&mut self, _fallback: Fallback) {}
+
+    /// Take this backend's images off the screen and undo the terminal
+    /// modes it set, so another backend can take over; the frame on screen
+    /// is forgotten, and the next one sent in full
+    fn leave_terminal(&mut self) -> Result<()>;
+}
+
+/// Output of the backends that send every frame whole, for lack of image
+/// ids to draw damage over: frames already on screen are skipped, and the
+/// ones sent are numbered in place of ids
+pub(super) struct WholeFrameOutput {
+    /// Hash of the frame on screen, to skip resending it
+    last_hash: Option<u64>,
+    /// Frames sent so far
+    frames_sent: u32,
+    /// Where to leave the terminal's cursor after each frame, if it shows the pointer
+    pub text_cursor: Option<TextCursor>,
+    pub last_timings: FrameTimings,
+    /// Where frames are written; stdout except in tests
+    output: Box<dyn Write>,
+}
+
+impl WholeFrameOutput {
+    pub fn new() -> Self {
+        Self {
+            last_hash: None,
+            frames_sent: 0,
+            text_cursor: None,
+            last_timings: FrameTimings::default(),
+            output: Box::new(io::stdout()),
+        }
+    }
+
+    /// Write frames to `output` instead of stdout
+    #[cfg(test)]
+    pub fn with_output(output: impl Write + 'static) -> Self {
+        Self { output: Box::new(output), ..Self::new() }
+    }
+
+    /// Send the frame `encode` turns into terminal output, unless it is
+    /// already on screen; returns its number, as `display_frame` does
+    pub fn send(
+        &mut self,
+        width: u32,
+        height: u32,
+        rgba_data: &[u8],
+        opaque: bool,
+        encode: impl FnOnce() -> Result<(Vec<u8>, FrameTimings)>,
+    ) -> Result<Option<u32>> {
+        if width == 0 || height == 0 {
+            anyhow::bail!("Not displaying an empty {}x{} frame", width, height);
+        }
+        let hash = frame_hash(width, height, rgba_data, opaque);
+        if self.last_hash == Some(hash) {
+            tracing::trace!("Frame unchanged, not sending it");
+            return Ok(None);
+        }
+
+        let (encoded, timings) = encode()?;
+        let mut stdout = io::BufWriter::with_capacity(encoded.len() + 64, &mut self.output);
+        stdout.write_all(&encoded)?;
+        if let Some(cursor) = &self.text_cursor {
+            write!(stdout, "{}", cursor.escape())?;
+        }
+        stdout.flush()?;
+        drop(stdout);
+
+        self.last_hash = Some(hash);
+        self.frames_sent = self.frames_sent.wrapping_add(1);
+        self.last_timings = timings;
+        Ok(Some(self.frames_sent))
+    }
+
+    /// Write `sequence` and clear the screen, the images sent with it, and
+    /// forget the frame that was on it
+    pub fn clear(&mut self, sequence: &str) -> Result<()> {
+        write!(self.output, "{}\x1b[2J", sequence)?;
+        self.output.flush()?;
+        self.last_hash = None;
+        Ok(())
+    }
+}
+
+/// A Kitty graphics query for a 1x1 image, then primary device attributes
+const DETECT_QUERY: &str = "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[c";
+
+/// Ask the terminal which graphics protocol it supports
+///
+/// Kitty graphics if it answers the graphics query, else Sixel if its
+/// device attributes list it (4). Terminals that answer neither in time
+/// get Kitty graphics, as before there was a choice.
+pub fn detect_backend(timeout: Duration) -> Backend {
+    query_terminal(DETECT_QUERY, timeout, parse_capabilities).unwrap_or(Backend::Kitty)
+}
+
+/// Pick the protocol from the replies to `DETECT_QUERY`, once the device
+/// attributes are in; every terminal sends those, after any graphics reply
+fn parse_capabilities(data: &[u8]) -> Option<Backend> {
+    let start = data.windows(3).position(|w| w == b"\x1b[?")? + 3;
+    let end = start + data[start..].iter().position(|&b| b == b'c')?;
+    if data[..start].windows(7).any(|w| w == b"\x1b_Gi=31") {
+        return Some(Backend::Kitty);
+    }
+    let attributes = std::str::from_utf8(&data[start..end]).ok()?;
+    Some(if attributes.split(';').any(|attribute| attribute == "4") { Backend::Sixel } else { Backend::Kitty })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends_cycle_through_all_three() {
+        let cycle: Vec<_> = std::iter::successors(Some(Backend::Kitty), |b| Some(b.next())).take(4).collect();
+        assert_eq!(cycle, [Backend::Kitty, Backend::ITerm2, Backend::Sixel, Backend::Kitty]);
+    }
+
+    #[test]
+    fn capabilities_come_from_the_replies() {
+        assert_eq!(parse_capabilities(b"\x1b_Gi=31;OK\x1b\\\x1b[?62;22c"), Some(Backend::Kitty));
+        assert_eq!(parse_capabilities(b"\x1b[?63;1;2;4;6;9c"), Some(Backend::Sixel));
+        assert_eq!(parse_capabilities(b"\x1b[?62;22c"), Some(Backend::Kitty));
+        // Still waiting for the device attributes
+        assert_eq!(parse_capabilities(b"\x1b_Gi=31;OK\x1b\\"), None);
+        assert_eq!(parse_capabilities(b"\x1b[?63;4"), None);
+    }
+}
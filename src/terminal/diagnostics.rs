@@ -0,0 +1,113 @@
+//! Fallbacks for terminals that reject the frames sent to them
+//!
+//! With `--graphics-diagnostics` the terminal answers every frame. A few
+//! errors in a row mean something about how frames are sent doesn't suit
+//! it, so later frames are sent more conservatively: uncompressed first,
+//! then at ever smaller sizes.
+
+/// Errors in a row before falling back
+const ERRORS_BEFORE_FALLBACK: u32 = 3;
+/// Frames aren't shrunk below this size
+const MIN_SIZE: (u32, u32) = (320, 180);
+
+/// A more conservative way of sending frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fallback {
+    /// Send frames without compression
+    Uncompressed,
+    /// Scale frames down to fit this size
+    MaxSize(u32, u32),
+}
+
+pub struct GraphicsDiagnostics {
+    /// Errors since the terminal last accepted a frame, or since the last fallback
+    consecutive_errors: u32,
+    /// Whether frames are still sent compressed
+    compressed: bool,
+    /// Largest frame size still sent
+    max_size: (u32, u32),
+    /// Fallback decided on but not yet applied
+    pending: Option<Fallback>,
+}
+
+impl GraphicsDiagnostics {
+    pub fn new(compressed: bool, max_size: (u32, u32)) -> Self {
+        Self {
+            consecutive_errors: 0,
+            compressed,
+            max_size,
+            pending: None,
+        }
+    }
+
+    /// Handle the terminal's response `message` for `image_id`
+    pub fn record(&mut self, image_id: u32, message: &str) {
+        if message == "OK" {
+            tracing::debug!("Terminal accepted image {}", image_id);
+            self.consecutive_errors = 0;
+            return;
+        }
+        self.consecutive_errors += 1;
+        if self.consecutive_errors < ERRORS_BEFORE_FALLBACK {
+            return;
+        }
+        self.consecutive_errors = 0;
+        self.pending = self.next_fallback();
+        if self.pending.is_none() {
+            tracing::warn!("Terminal keeps rejecting frames, with nothing left to fall back to");
+        }
+    }
+
+    fn next_fallback(&mut self) -> Option<Fallback> {
+        if self.compressed {
+            self.compressed = false;
+            return Some(Fallback::Uncompressed);
+        }
+        let (width, height) = (self.max_size.0 / 2, self.max_size.1 / 2);
+        if width < MIN_SIZE.0 || height < MIN_SIZE.1 {
+            return None;
+        }
+        self.max_size = (width, height);
+        Some(Fallback::MaxSize(width, height))
+    }
+
+    /// The fallback to apply before sending the next frame, if any
+    pub fn take_fallback(&mut self) -> Option<Fallback> {
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(diagnostics: &mut GraphicsDiagnostics, count: u32) {
+        for image_id in 0..count {
+            diagnostics.record(image_id, "EINVAL:bad image");
+        }
+    }
+
+    #[test]
+    fn repeated_errors_fall_back_step_by_step() {
+        let mut diagnostics = GraphicsDiagnostics::new(true, (1920, 1080));
+        errors(&mut diagnostics, 2);
+        assert_eq!(diagnostics.take_fallback(), None);
+        errors(&mut diagnostics, 1);
+        assert_eq!(diagnostics.take_fallback(), Some(Fallback::Uncompressed));
+        errors(&mut diagnostics, 3);
+        assert_eq!(diagnostics.take_fallback(), Some(Fallback::MaxSize(960, 540)));
+        errors(&mut diagnostics, 3);
+        assert_eq!(diagnostics.take_fallback(), Some(Fallback::MaxSize(480, 270)));
+        errors(&mut diagnostics, 3);
+        assert_eq!(diagnostics.take_fallback(), None);
+    }
+
+    #[test]
+    fn an_accepted_frame_resets_the_count() {
+        let mut diagnostics = GraphicsDiagnostics::new(false, (1920, 1080));
+        errors(&mut diagnostics, 2);
+        diagnostics.record(7, "OK");
+        errors(&mut diagnostics, 2);
+        assert_eq!(diagnostics.take_fallback(), None);
+    }
+}
@@ -0,0 +1,88 @@
+//! Input events handed from the terminal frontend to the compositor
+
+use smithay::{backend::input::ButtonState, input::keyboard::Keysym};
+
+/// Input event types for the compositor
+pub enum WaylandInputEvent {
+    PointerMotion {
+        x: f64,
+        y: f64,
+        time: u32,
+    },
+    PointerButton {
+        button: u32,
+        state: ButtonState,
+        time: u32,
+    },
+    PointerAxis {
+        horizontal: f64,
+        vertical: f64,
+        /// Wheel notches along the axis that moved, negative up or left
+        steps: i32,
+        time: u32,
+    },
+    KeyboardKey {
+        keysym: Keysym,
+        state: KeyState,
+        /// Lock state reported by the terminal alongside the key, if known
+        locks: Option<LockState>,
+        time: u32,
+    },
+    /// The terminal's new size in pixels
+    Resize {
+        width: u32,
+        height: u32,
+    },
+    /// Ask the focused window to close
+    CloseWindow,
+    /// Change the zoom into the displayed frame
+    Zoom(ZoomAction),
+    /// Switch to the next graphics protocol
+    CycleBackend,
+    /// Text pasted into the terminal (bracketed paste)
+    Paste(String),
+    /// The terminal answered a graphics command (`message` is "OK" or an error)
+    GraphicsResponse {
+        image_id: u32,
+        message: String,
+    },
+    Quit,
+}
+
+impl WaylandInputEvent {
+    /// The event with its timestamp set to `time`, for events that carry one
+    pub fn with_time(mut self, time: u32) -> Self {
+        match &mut self {
+            WaylandInputEvent::PointerMotion { time: t, .. }
+            | WaylandInputEvent::PointerButton { time: t, .. }
+            | WaylandInputEvent::PointerAxis { time: t, .. }
+            | WaylandInputEvent::KeyboardKey { time: t, .. } => *t = time,
+            _ => {}
+        }
+        self
+    }
+}
+
+/// Zoom key bindings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ZoomAction {
+    In,
+    Out,
+    /// Move the view by a fraction of its own size
+    Pan { dx: f64, dy: f64 },
+    /// Back to the whole frame
+    Reset,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum KeyState {
+    Pressed,
+    Released,
+}
+
+/// Caps/Num Lock state of the host keyboard
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LockState {
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
@@ -1,19 +1,225 @@
 use anyhow::Result;
 use crossterm::event::{
-    self, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    self, Event, KeyCode, KeyEvent, KeyEventState, KeyModifiers, MediaKeyCode, ModifierKeyCode,
+    MouseButton, MouseEvent, MouseEventKind,
 };
 use smithay::{
     backend::input::ButtonState,
     input::keyboard::Keysym,
 };
+use std::sync::Arc;
 use std::time::Duration;
 
+use super::{CellSize, KeyState, LockState, TextCursor, WaylandInputEvent, ZoomAction};
+use crate::clock::{Clock, SystemClock};
+use crate::keymap::KeycodeMap;
+
 /// Terminal input handler
 pub struct TerminalInput {
     term_width: u32,
     term_height: u32,
     pixel_width: u32,
     pixel_height: u32,
+    /// Whether key events carry the terminal's lock state (Kitty keyboard protocol)
+    reports_lock_state: bool,
+    /// Chord that asks the focused window to close
+    close_chord: Option<KeyChord>,
+    /// Modifiers of the zoom key bindings
+    zoom_keys: Option<ZoomKeys>,
+    /// Chord that switches to the next graphics protocol
+    backend_chord: Option<KeyChord>,
+    /// Modifiers the terminal reported as physically held (Kitty keyboard protocol)
+    held_modifiers: KeyModifiers,
+    /// Pick Kitty graphics responses out of the key stream
+    graphics_responses: bool,
+    /// Progress through a graphics response being received as key events
+    response: ResponseState,
+    /// Source of event timestamps
+    clock: Arc<dyn Clock>,
+    /// The clients' keymap, deciding which characters need Shift
+    keycodes: KeycodeMap,
+    /// Terminal cursor following the mouse, for `--cursor text`
+    text_cursor: Option<TextCursor>,
+    /// Multipliers of how far a wheel notch scrolls
+    scroll_speed: ScrollSpeed,
+    /// Mouse buttons pressed, as far as the events passed on go
+    pressed_buttons: Vec<u32>,
+    /// Cell size to size the terminal from when it won't say
+    cell_size: CellSize,
+}
+
+/// Distance a wheel notch scrolls at speed 1, in surface coordinates; the
+/// same as a mouse wheel click under libinput
+const SCROLL_STEP: f64 = 15.0;
+
+/// How far a wheel notch scrolls, relative to `SCROLL_STEP` (`--scroll-speed`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScrollSpeed {
+    pub vertical: f64,
+    pub horizontal: f64,
+}
+
+impl Default for ScrollSpeed {
+    fn default() -> Self {
+        ScrollSpeed { vertical: 1.0, horizontal: 1.0 }
+    }
+}
+
+impl std::str::FromStr for ScrollSpeed {
+    type Err = String;
+
+    /// Parses one multiplier for both axes, or `vertical,horizontal`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| value.parse().ok().filter(|&speed: &f64| speed.is_finite() && speed > 0.0);
+        let speed = match s.split_once(',') {
+            Some((vertical, horizontal)) => parse(vertical).zip(parse(horizontal)),
+            None => parse(s).map(|speed| (speed, speed)),
+        };
+        speed
+            .map(|(vertical, horizontal)| ScrollSpeed { vertical, horizontal })
+            .ok_or_else(|| format!("expected a positive multiplier or VERTICAL,HORIZONTAL, got {:?}", s))
+    }
+}
+
+/// Crossterm has no notion of APC sequences, so a graphics response
+/// `ESC _ G <keys> ; <message> ESC \` arrives as Alt+`_`, plain characters and
+/// finally Alt+`\`
+#[derive(Default)]
+enum ResponseState {
+    #[default]
+    Idle,
+    /// Saw Alt+`_`; held back until we know whether a response follows
+    Introducer(KeyEvent),
+    /// Inside a response, collecting its text
+    Body(String),
+}
+
+/// Longest response we collect before deciding it was something else
+const MAX_RESPONSE_LEN: usize = 512;
+
+const KEY_SHIFT_L: u32 = 0xffe1;
+const KEY_CONTROL_L: u32 = 0xffe3;
+const KEY_ALT_L: u32 = 0xffe9;
+const KEY_SUPER_L: u32 = 0xffeb;
+
+/// Modifiers we synthesize key events for, outermost first
+const SYNTHETIC_MODIFIERS: [(KeyModifiers, u32); 4] = [
+    (KeyModifiers::SUPER, KEY_SUPER_L),
+    (KeyModifiers::CONTROL, KEY_CONTROL_L),
+    (KeyModifiers::ALT, KEY_ALT_L),
+    (KeyModifiers::SHIFT, KEY_SHIFT_L),
+];
+
+/// Whether typing `c` (as `keysym`) needs Shift held
+///
+/// Symbols missing from the keymap are placed as on a US layout.
+fn needs_shift(c: char, keysym: Keysym, caps_lock: bool, keycodes: &KeycodeMap) -> bool {
+    if c.is_ascii_alphabetic() {
+        // Caps Lock inverts the case Shift produces for letters
+        c.is_ascii_uppercase() != caps_lock
+    } else {
+        keycodes.needs_shift(keysym).unwrap_or_else(|| "~!@#$%^&*()_+{}|:\"<>?".contains(c))
+    }
+}
+
+/// A key plus the modifiers that must be held with it, e.g. `ctrl+alt+w`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyChord {
+    pub modifiers: KeyModifiers,
+    pub code: KeyCode,
+}
+
+impl KeyChord {
+    /// Whether a key press is this chord; letters match regardless of case
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        let code = match event.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        event.kind == event::KeyEventKind::Press && code == self.code && event.modifiers == self.modifiers
+    }
+}
+
+/// Modifiers that turn `=`/`+`, `-`, `0` and the arrow keys into zoom controls
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ZoomKeys {
+    pub modifiers: KeyModifiers,
+}
+
+/// How far one press of an arrow key pans, as a fraction of the view
+const PAN_STEP: f64 = 0.25;
+
+impl ZoomKeys {
+    /// The zoom action a key press stands for, if any
+    pub fn action(&self, event: &KeyEvent) -> Option<ZoomAction> {
+        if event.kind != event::KeyEventKind::Press || event.modifiers != self.modifiers {
+            return None;
+        }
+        Some(match event.code {
+            KeyCode::Char('=') | KeyCode::Char('+') => ZoomAction::In,
+            KeyCode::Char('-') => ZoomAction::Out,
+            KeyCode::Char('0') => ZoomAction::Reset,
+            KeyCode::Left => ZoomAction::Pan { dx: -PAN_STEP, dy: 0.0 },
+            KeyCode::Right => ZoomAction::Pan { dx: PAN_STEP, dy: 0.0 },
+            KeyCode::Up => ZoomAction::Pan { dx: 0.0, dy: -PAN_STEP },
+            KeyCode::Down => ZoomAction::Pan { dx: 0.0, dy: PAN_STEP },
+            _ => return None,
+        })
+    }
+}
+
+impl std::str::FromStr for ZoomKeys {
+    type Err = String;
+
+    /// Parses `+`-separated modifiers, e.g. `ctrl+alt`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chord: KeyChord = format!("{}+x", s).parse()?;
+        if chord.modifiers.is_empty() {
+            return Err("zoom keys need at least one modifier".into());
+        }
+        Ok(ZoomKeys { modifiers: chord.modifiers })
+    }
+}
+
+impl std::str::FromStr for KeyChord {
+    type Err = String;
+
+    /// Parses `+`-separated modifiers followed by a key, e.g. `ctrl+alt+w` or `super+f4`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let mut parts: Vec<&str> = lower.split('+').collect();
+        let key = parts.pop().filter(|k| !k.is_empty()).ok_or_else(|| format!("missing key in {:?}", s))?;
+
+        let mut modifiers = KeyModifiers::NONE;
+        for part in parts {
+            modifiers |= match part {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                "super" => KeyModifiers::SUPER,
+                _ => return Err(format!("unknown modifier {:?}", part)),
+            };
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match key {
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "enter" | "return" => KeyCode::Enter,
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "space" => KeyCode::Char(' '),
+                _ => match key.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=35) => KeyCode::F(n),
+                    _ => return Err(format!("unknown key {:?}", key)),
+                },
+            },
+        };
+
+        Ok(KeyChord { modifiers, code })
+    }
 }
 
 impl TerminalInput {
@@ -23,9 +229,76 @@ impl TerminalInput {
             term_height,
             pixel_width,
             pixel_height,
+            reports_lock_state: false,
+            close_chord: None,
+            zoom_keys: None,
+            backend_chord: None,
+            held_modifiers: KeyModifiers::NONE,
+            graphics_responses: false,
+            response: ResponseState::Idle,
+            clock: Arc::new(SystemClock),
+            keycodes: KeycodeMap::default(),
+            text_cursor: None,
+            scroll_speed: ScrollSpeed::default(),
+            pressed_buttons: Vec::new(),
+            cell_size: CellSize::default(),
         }
     }
 
+    /// Decide Shift for characters by the keymap clients were given
+    pub fn set_keycodes(&mut self, keycodes: KeycodeMap) {
+        self.keycodes = keycodes;
+    }
+
+    /// Take event timestamps from `clock` instead of the system clock
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Expect Kitty graphics responses (`q=0`) interleaved with key input
+    pub fn set_graphics_responses(&mut self, enabled: bool) {
+        self.graphics_responses = enabled;
+    }
+
+    /// Turn presses of `chord` into close requests for the focused window
+    pub fn set_close_chord(&mut self, chord: Option<KeyChord>) {
+        self.close_chord = chord;
+    }
+
+    /// Turn the zoom bindings with these modifiers into zoom requests
+    pub fn set_zoom_keys(&mut self, keys: Option<ZoomKeys>) {
+        self.zoom_keys = keys;
+    }
+
+    /// Turn presses of `chord` into switches to the next graphics protocol
+    pub fn set_backend_chord(&mut self, chord: Option<KeyChord>) {
+        self.backend_chord = chord;
+    }
+
+    /// Scroll this much further (or less far) per wheel notch
+    pub fn set_scroll_speed(&mut self, speed: ScrollSpeed) {
+        self.scroll_speed = speed;
+    }
+
+    /// Size the terminal from `cell` on resizes if it doesn't report its
+    /// pixel size, or always if `cell` is forced
+    pub fn set_cell_size(&mut self, cell: CellSize) {
+        self.cell_size = cell;
+    }
+
+    /// Move the terminal's cursor to the cell under the mouse as it moves
+    pub fn set_text_cursor(&mut self, cursor: Option<TextCursor>) {
+        self.text_cursor = cursor;
+    }
+
+    /// Trust the Caps/Num Lock state reported with key events
+    ///
+    /// Only meaningful when the Kitty keyboard protocol is active, since
+    /// legacy key encodings never include lock state.
+    pub fn set_reports_lock_state(&mut self, enabled: bool) {
+        self.reports_lock_state = enabled;
+    }
+
     pub fn update_dimensions(
         &mut self,
         term_width: u32,
@@ -40,7 +313,9 @@ impl TerminalInput {
     }
 
     /// Enable mouse capture in terminal
-    pub fn enable_mouse_capture() -> Result<()> {
+    ///
+    /// Returns whether the enhanced (Kitty) keyboard protocol was enabled.
+    pub fn enable_mouse_capture() -> Result<bool> {
         use crossterm::execute;
         use crossterm::event::{
             EnableMouseCapture, EnableBracketedPaste,
@@ -69,7 +344,7 @@ impl TerminalInput {
             )?;
         }
 
-        Ok(())
+        Ok(supports_enhanced)
     }
 
     /// Disable mouse capture
@@ -140,23 +415,71 @@ impl TerminalInput {
             KeyCode::PageDown => Keysym::new(0xff56),     // XKB_KEY_Page_Down
             KeyCode::Insert => Keysym::new(0xff63),       // XKB_KEY_Insert
             KeyCode::Delete => Keysym::new(0xffff),       // XKB_KEY_Delete
-            KeyCode::F(1) => Keysym::new(0xffbe),         // XKB_KEY_F1
-            KeyCode::F(2) => Keysym::new(0xffbf),
-            KeyCode::F(3) => Keysym::new(0xffc0),
-            KeyCode::F(4) => Keysym::new(0xffc1),
-            KeyCode::F(5) => Keysym::new(0xffc2),
-            KeyCode::F(6) => Keysym::new(0xffc3),
-            KeyCode::F(7) => Keysym::new(0xffc4),
-            KeyCode::F(8) => Keysym::new(0xffc5),
-            KeyCode::F(9) => Keysym::new(0xffc6),
-            KeyCode::F(10) => Keysym::new(0xffc7),
-            KeyCode::F(11) => Keysym::new(0xffc8),
-            KeyCode::F(12) => Keysym::new(0xffc9),
-            KeyCode::F(n) => Keysym::new(0xffbe + (n as u32 - 1)),
+            KeyCode::CapsLock => Keysym::new(0xffe5),     // XKB_KEY_Caps_Lock
+            KeyCode::NumLock => Keysym::new(0xff7f),      // XKB_KEY_Num_Lock
+            KeyCode::ScrollLock => Keysym::new(0xff14),   // XKB_KEY_Scroll_Lock
+            KeyCode::PrintScreen => Keysym::new(0xff61),  // XKB_KEY_Print
+            KeyCode::Pause => Keysym::new(0xff13),        // XKB_KEY_Pause
+            KeyCode::Menu => Keysym::new(0xff67),         // XKB_KEY_Menu
+            KeyCode::KeypadBegin => Keysym::new(0xff9d),  // XKB_KEY_KP_Begin
+            KeyCode::Media(media) => Keysym::new(match media {
+                MediaKeyCode::PlayPause | MediaKeyCode::Play => 0x1008ff14, // XKB_KEY_XF86AudioPlay
+                MediaKeyCode::Pause => 0x1008ff31,          // XKB_KEY_XF86AudioPause
+                MediaKeyCode::Stop => 0x1008ff15,           // XKB_KEY_XF86AudioStop
+                MediaKeyCode::TrackPrevious => 0x1008ff16,  // XKB_KEY_XF86AudioPrev
+                MediaKeyCode::TrackNext => 0x1008ff17,      // XKB_KEY_XF86AudioNext
+                MediaKeyCode::Rewind => 0x1008ff3e,         // XKB_KEY_XF86AudioRewind
+                MediaKeyCode::FastForward => 0x1008ff97,    // XKB_KEY_XF86AudioForward
+                MediaKeyCode::LowerVolume => 0x1008ff11,    // XKB_KEY_XF86AudioLowerVolume
+                MediaKeyCode::RaiseVolume => 0x1008ff13,    // XKB_KEY_XF86AudioRaiseVolume
+                MediaKeyCode::MuteVolume => 0x1008ff12,     // XKB_KEY_XF86AudioMute
+                _ => return None,
+            }),
+            KeyCode::Modifier(modifier) => Keysym::new(match modifier {
+                ModifierKeyCode::LeftShift => KEY_SHIFT_L,  // XKB_KEY_Shift_L
+                ModifierKeyCode::RightShift => 0xffe2,      // XKB_KEY_Shift_R
+                ModifierKeyCode::LeftControl => 0xffe3,     // XKB_KEY_Control_L
+                ModifierKeyCode::RightControl => 0xffe4,    // XKB_KEY_Control_R
+                ModifierKeyCode::LeftAlt => 0xffe9,         // XKB_KEY_Alt_L
+                ModifierKeyCode::RightAlt => 0xffea,        // XKB_KEY_Alt_R
+                ModifierKeyCode::LeftSuper => 0xffeb,       // XKB_KEY_Super_L
+                ModifierKeyCode::RightSuper => 0xffec,      // XKB_KEY_Super_R
+                _ => return None,
+            }),
+            // XKB_KEY_F1 to XKB_KEY_F35 are contiguous
+            KeyCode::F(n @ 1..=35) => Keysym::new(0xffbe + (n as u32 - 1)),
             _ => return None,
         })
     }
 
+    /// Keysym for a key on the numeric keypad, which crossterm reports as
+    /// the main-block key it matches plus `KeyEventState::KEYPAD`
+    fn keypad_keycode_to_keysym(key: KeyCode) -> Option<Keysym> {
+        Some(Keysym::new(match key {
+            KeyCode::Char(c @ '0'..='9') => 0xffb0 + (c as u32 - '0' as u32), // XKB_KEY_KP_0..KP_9
+            KeyCode::Char('*') => 0xffaa,  // XKB_KEY_KP_Multiply
+            KeyCode::Char('+') => 0xffab,  // XKB_KEY_KP_Add
+            KeyCode::Char(',') => 0xffac,  // XKB_KEY_KP_Separator
+            KeyCode::Char('-') => 0xffad,  // XKB_KEY_KP_Subtract
+            KeyCode::Char('.') => 0xffae,  // XKB_KEY_KP_Decimal
+            KeyCode::Char('/') => 0xffaf,  // XKB_KEY_KP_Divide
+            KeyCode::Char('=') => 0xffbd,  // XKB_KEY_KP_Equal
+            KeyCode::Enter => 0xff8d,      // XKB_KEY_KP_Enter
+            // With Num Lock off
+            KeyCode::Home => 0xff95,       // XKB_KEY_KP_Home
+            KeyCode::Left => 0xff96,       // XKB_KEY_KP_Left
+            KeyCode::Up => 0xff97,         // XKB_KEY_KP_Up
+            KeyCode::Right => 0xff98,      // XKB_KEY_KP_Right
+            KeyCode::Down => 0xff99,       // XKB_KEY_KP_Down
+            KeyCode::PageUp => 0xff9a,     // XKB_KEY_KP_Prior
+            KeyCode::PageDown => 0xff9b,   // XKB_KEY_KP_Next
+            KeyCode::End => 0xff9c,        // XKB_KEY_KP_End
+            KeyCode::Insert => 0xff9e,     // XKB_KEY_KP_Insert
+            KeyCode::Delete => 0xff9f,     // XKB_KEY_KP_Delete
+            _ => return None,
+        }))
+    }
+
     /// Convert mouse button to Wayland button code
     pub fn mouse_button_to_code(button: MouseButton) -> u32 {
         match button {
@@ -167,50 +490,173 @@ impl TerminalInput {
     }
 }
 
-/// Input event types for the compositor
-pub enum WaylandInputEvent {
-    PointerMotion {
-        x: f64,
-        y: f64,
-        time: u32,
-    },
-    PointerButton {
-        button: u32,
-        state: ButtonState,
-        time: u32,
-    },
-    PointerAxis {
-        horizontal: f64,
-        vertical: f64,
-        time: u32,
-    },
-    KeyboardKey {
-        keysym: Keysym,
-        state: KeyState,
-        time: u32,
-    },
-    Resize {
-        width: u32,
-        height: u32,
-    },
-    Quit,
-}
-
-#[derive(Clone, Copy, Debug)]
-pub enum KeyState {
-    Pressed,
-    Released,
+/// Parse the `i=<id>[,...];<message>` part of a graphics response
+fn parse_graphics_response(body: &str) -> Option<WaylandInputEvent> {
+    let (keys, message) = body.split_once(';')?;
+    let image_id = keys
+        .split(',')
+        .find_map(|kv| kv.strip_prefix("i="))
+        .and_then(|id| id.parse().ok())?;
+    Some(WaylandInputEvent::GraphicsResponse { image_id, message: message.to_string() })
 }
 
 impl TerminalInput {
-    /// Convert a crossterm event to a Wayland input event
-    pub fn translate_event(&self, event: Event) -> Option<WaylandInputEvent> {
-        let time = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u32;
+    /// Convert a crossterm event to Wayland input events
+    ///
+    /// Terminals deliver modified keys without the modifier key events
+    /// themselves, so key presses are wrapped in synthetic Super_L,
+    /// Control_L, Alt_L and Shift_L presses/releases as needed, skipping any
+    /// modifier the terminal reported as physically held already.
+    pub fn translate_event(&mut self, event: Event) -> Vec<WaylandInputEvent> {
+        if self.graphics_responses {
+            if let Event::Key(key) = &event {
+                if let Some(events) = self.filter_graphics_response(*key) {
+                    return events;
+                }
+            }
+        }
+        self.translate_with_modifiers(event)
+    }
+
+    /// Consume key events that belong to a graphics response
+    ///
+    /// Returns the events to emit instead, or `None` if `key` is ordinary input.
+    fn filter_graphics_response(&mut self, key: KeyEvent) -> Option<Vec<WaylandInputEvent>> {
+        let is_char = |c: char| key.code == KeyCode::Char(c);
+
+        match std::mem::take(&mut self.response) {
+            ResponseState::Idle if is_char('_') && key.modifiers == KeyModifiers::ALT => {
+                self.response = ResponseState::Introducer(key);
+                Some(Vec::new())
+            }
+            ResponseState::Idle => None,
+            ResponseState::Introducer(_) if is_char('G') => {
+                self.response = ResponseState::Body(String::new());
+                Some(Vec::new())
+            }
+            ResponseState::Introducer(held) => {
+                // A real Alt+_ after all; replay it before this key
+                let mut events = self.translate_with_modifiers(Event::Key(held));
+                events.extend(self.translate_event(Event::Key(key)));
+                Some(events)
+            }
+            ResponseState::Body(body) if is_char('\\') && key.modifiers == KeyModifiers::ALT => {
+                Some(parse_graphics_response(&body).into_iter().collect())
+            }
+            ResponseState::Body(mut body) => {
+                if let KeyCode::Char(c) = key.code {
+                    body.push(c);
+                }
+                if body.len() <= MAX_RESPONSE_LEN {
+                    self.response = ResponseState::Body(body);
+                } else {
+                    tracing::debug!("Discarding overlong graphics response");
+                }
+                Some(Vec::new())
+            }
+        }
+    }
+
+    fn translate_with_modifiers(&mut self, event: Event) -> Vec<WaylandInputEvent> {
+        let key = match &event {
+            Event::Key(key) => Some(*key),
+            _ => None,
+        };
+        let dragged = match event {
+            Event::Mouse(MouseEvent { kind: MouseEventKind::Drag(button), .. }) => Some(button),
+            _ => None,
+        };
+        let Some(translated) = self.translate_single(event) else {
+            return Vec::new();
+        };
+
+        if let WaylandInputEvent::PointerButton { button, state, .. } = &translated {
+            self.pressed_buttons.retain(|pressed| pressed != button);
+            if *state == ButtonState::Pressed {
+                self.pressed_buttons.push(*button);
+            }
+        }
+        // crossterm can lose a press under load; without it the client
+        // wouldn't know the drag is one, so it is pressed where the
+        // pointer was, before moving on
+        if let (Some(button), WaylandInputEvent::PointerMotion { time, .. }) = (dragged, &translated) {
+            let button = Self::mouse_button_to_code(button);
+            if !self.pressed_buttons.contains(&button) {
+                self.pressed_buttons.push(button);
+                let press = WaylandInputEvent::PointerButton { button, state: ButtonState::Pressed, time: *time };
+                return vec![press, translated];
+            }
+        }
+
+        let (Some(key), WaylandInputEvent::KeyboardKey { keysym, state, locks, time }) = (key, &translated) else {
+            return vec![translated];
+        };
+        let (keysym, pressed, locks, time) = (*keysym, matches!(state, KeyState::Pressed), *locks, *time);
+
+        if let KeyCode::Modifier(modifier) = key.code {
+            let held = match modifier {
+                ModifierKeyCode::LeftShift | ModifierKeyCode::RightShift => KeyModifiers::SHIFT,
+                ModifierKeyCode::LeftControl | ModifierKeyCode::RightControl => KeyModifiers::CONTROL,
+                ModifierKeyCode::LeftAlt | ModifierKeyCode::RightAlt => KeyModifiers::ALT,
+                ModifierKeyCode::LeftSuper | ModifierKeyCode::RightSuper => KeyModifiers::SUPER,
+                _ => KeyModifiers::NONE,
+            };
+            self.held_modifiers.set(held, pressed);
+            return vec![translated];
+        }
+        if !pressed {
+            return vec![translated];
+        }
+
+        // Shift for characters comes from the character itself, since legacy
+        // encodings don't reliably report it
+        let synthesized = KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SUPER | KeyModifiers::SHIFT;
+        let mut wanted = key.modifiers & synthesized;
+        if key.state.contains(KeyEventState::KEYPAD) {
+            // Keypad digits are the Num Lock level, which Shift also
+            // reaches when the client's Num Lock is off
+            let num_lock = locks.is_some_and(|l| l.num_lock);
+            wanted.set(KeyModifiers::SHIFT, self.keycodes.needs_shift(keysym) == Some(true) && !num_lock);
+        } else if let KeyCode::Char(c) = key.code {
+            let caps_lock = locks.is_some_and(|l| l.caps_lock);
+            wanted.set(KeyModifiers::SHIFT, needs_shift(c, keysym, caps_lock, &self.keycodes));
+        }
+        let missing: Vec<u32> = SYNTHETIC_MODIFIERS
+            .iter()
+            .filter(|(modifier, _)| wanted.contains(*modifier) && !self.held_modifiers.contains(*modifier))
+            .map(|&(_, keysym)| keysym)
+            .collect();
+
+        let modifier_key = |keysym, state| WaylandInputEvent::KeyboardKey {
+            keysym: Keysym::new(keysym),
+            state,
+            locks: None,
+            time,
+        };
+        let mut events: Vec<_> = missing.iter().map(|&k| modifier_key(k, KeyState::Pressed)).collect();
+        events.push(translated);
+        events.extend(missing.iter().rev().map(|&k| modifier_key(k, KeyState::Released)));
+        events
+    }
+
+    fn translate_single(&self, event: Event) -> Option<WaylandInputEvent> {
+        let time = self.clock.now_millis();
+
+        if let Event::Key(key) = &event {
+            if let Some(action) = self.zoom_keys.and_then(|keys| keys.action(key)) {
+                return Some(WaylandInputEvent::Zoom(action));
+            }
+        }
 
         match event {
+            Event::Key(key) if self.close_chord.is_some_and(|chord| chord.matches(&key)) => {
+                Some(WaylandInputEvent::CloseWindow)
+            }
+
+            Event::Key(key) if self.backend_chord.is_some_and(|chord| chord.matches(&key)) => {
+                Some(WaylandInputEvent::CycleBackend)
+            }
+
             Event::Key(KeyEvent {
                 code: KeyCode::Char('c'),
                 modifiers,
@@ -227,22 +673,45 @@ impl TerminalInput {
                 Some(WaylandInputEvent::Quit)
             }
 
-            Event::Key(KeyEvent { code, kind, .. }) => {
-                let keysym = Self::keycode_to_keysym(code)?;
+            Event::Key(KeyEvent { code, kind, state: key_event_state, .. }) => {
+                let keypad = key_event_state.contains(KeyEventState::KEYPAD);
+                let keysym = keypad
+                    .then(|| Self::keypad_keycode_to_keysym(code))
+                    .flatten()
+                    .or_else(|| Self::keycode_to_keysym(code))?;
                 let state = match kind {
                     event::KeyEventKind::Press | event::KeyEventKind::Repeat => KeyState::Pressed,
                     event::KeyEventKind::Release => KeyState::Released,
                 };
-                Some(WaylandInputEvent::KeyboardKey { keysym, state, time })
+                let locks = self.reports_lock_state.then(|| LockState {
+                    caps_lock: key_event_state.contains(KeyEventState::CAPS_LOCK),
+                    num_lock: key_event_state.contains(KeyEventState::NUM_LOCK),
+                });
+                Some(WaylandInputEvent::KeyboardKey { keysym, state, locks, time })
             }
 
             Event::Mouse(MouseEvent {
                 kind,
                 column,
                 row,
-                modifiers: _,
+                modifiers,
             }) => {
                 let (x, y) = self.cell_to_pixel(column, row);
+                if let Some(cursor) = &self.text_cursor {
+                    cursor.set(column, row);
+                    if let Err(e) = cursor.show() {
+                        tracing::debug!("Failed to move the text cursor: {}", e);
+                    }
+                }
+
+                // Shift turns the wheel sideways, as in browsers; many
+                // terminals have no horizontal wheel events of their own
+                let sideways = modifiers.contains(KeyModifiers::SHIFT);
+                let kind = match kind {
+                    MouseEventKind::ScrollUp if sideways => MouseEventKind::ScrollLeft,
+                    MouseEventKind::ScrollDown if sideways => MouseEventKind::ScrollRight,
+                    kind => kind,
+                };
 
                 match kind {
                     MouseEventKind::Moved | MouseEventKind::Drag(_) => {
@@ -265,28 +734,32 @@ impl TerminalInput {
                     MouseEventKind::ScrollDown => {
                         Some(WaylandInputEvent::PointerAxis {
                             horizontal: 0.0,
-                            vertical: 15.0,
+                            vertical: SCROLL_STEP * self.scroll_speed.vertical,
+                            steps: 1,
                             time,
                         })
                     }
                     MouseEventKind::ScrollUp => {
                         Some(WaylandInputEvent::PointerAxis {
                             horizontal: 0.0,
-                            vertical: -15.0,
+                            vertical: -SCROLL_STEP * self.scroll_speed.vertical,
+                            steps: -1,
                             time,
                         })
                     }
                     MouseEventKind::ScrollLeft => {
                         Some(WaylandInputEvent::PointerAxis {
-                            horizontal: -15.0,
+                            horizontal: -SCROLL_STEP * self.scroll_speed.horizontal,
                             vertical: 0.0,
+                            steps: -1,
                             time,
                         })
                     }
                     MouseEventKind::ScrollRight => {
                         Some(WaylandInputEvent::PointerAxis {
-                            horizontal: 15.0,
+                            horizontal: SCROLL_STEP * self.scroll_speed.horizontal,
                             vertical: 0.0,
+                            steps: 1,
                             time,
                         })
                     }
@@ -294,19 +767,362 @@ impl TerminalInput {
             }
 
             Event::Resize(cols, rows) => {
-                // Recalculate pixel dimensions
+                // Not queried with CSI 14 t: its reply would have to be
+                // picked out of the input read here
                 let (pixel_width, pixel_height) =
-                    super::KittyGraphics::query_terminal_size_pixels().unwrap_or((
-                        cols as u32 * 10,
-                        rows as u32 * 20,
-                    ));
+                    super::KittyGraphics::terminal_size_pixels(self.cell_size, cols, rows);
                 Some(WaylandInputEvent::Resize {
                     width: pixel_width,
                     height: pixel_height,
                 })
             }
 
+            Event::Paste(text) => Some(WaylandInputEvent::Paste(text)),
+
             _ => None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(c: char) -> Event {
+        let shifted = needs_shift(c, Keysym::new(c as u32), false, &KeycodeMap::default());
+        let modifiers = if shifted { KeyModifiers::SHIFT } else { KeyModifiers::NONE };
+        Event::Key(KeyEvent::new(KeyCode::Char(c), modifiers))
+    }
+
+    fn keys(events: Vec<WaylandInputEvent>) -> Vec<(u32, bool)> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                WaylandInputEvent::KeyboardKey { keysym, state, .. } => {
+                    (keysym.raw(), matches!(state, KeyState::Pressed))
+                }
+                _ => panic!("expected only key events"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shifted_characters_are_wrapped_in_shift() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        let typed: Vec<_> = "Hello!".chars().flat_map(|c| keys(input.translate_event(press(c)))).collect();
+
+        let shift = KEY_SHIFT_L;
+        assert_eq!(
+            typed,
+            vec![
+                (shift, true), ('H' as u32, true), (shift, false),
+                ('e' as u32, true),
+                ('l' as u32, true),
+                ('l' as u32, true),
+                ('o' as u32, true),
+                (shift, true), ('!' as u32, true), (shift, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn alt_and_super_are_synthesized_around_the_key() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        let key = Event::Key(KeyEvent::new(KeyCode::Char('f'), KeyModifiers::ALT | KeyModifiers::SUPER));
+
+        assert_eq!(
+            keys(input.translate_event(key)),
+            vec![
+                (KEY_SUPER_L, true), (KEY_ALT_L, true),
+                ('f' as u32, true),
+                (KEY_ALT_L, false), (KEY_SUPER_L, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn control_is_held_around_the_key() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        let save = Event::Key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        assert_eq!(
+            keys(input.translate_event(save)),
+            vec![(KEY_CONTROL_L, true), ('s' as u32, true), (KEY_CONTROL_L, false)]
+        );
+
+        // Ctrl+Shift+T, e.g. to reopen a closed tab
+        let reopen = Event::Key(KeyEvent::new(KeyCode::Char('T'), KeyModifiers::CONTROL | KeyModifiers::SHIFT));
+        assert_eq!(
+            keys(input.translate_event(reopen)),
+            vec![
+                (KEY_CONTROL_L, true), (KEY_SHIFT_L, true),
+                ('T' as u32, true),
+                (KEY_SHIFT_L, false), (KEY_CONTROL_L, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn pasted_text_is_passed_on_whole() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+
+        let events = input.translate_event(Event::Paste("two\nlines".into()));
+        assert!(matches!(events.as_slice(), [WaylandInputEvent::Paste(text)] if text == "two\nlines"));
+    }
+
+    #[test]
+    fn quit_chord_is_not_forwarded() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        let key = Event::Key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL | KeyModifiers::ALT));
+
+        let events = input.translate_event(key);
+        assert!(matches!(events.as_slice(), [WaylandInputEvent::Quit]));
+    }
+
+    #[test]
+    fn held_shift_is_not_pressed_again() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        let shift = KeyCode::Modifier(ModifierKeyCode::LeftShift);
+        input.translate_event(Event::Key(KeyEvent::new(shift, KeyModifiers::SHIFT)));
+
+        assert_eq!(keys(input.translate_event(press('A'))), vec![('A' as u32, true)]);
+    }
+
+    #[test]
+    fn graphics_response_is_picked_out_of_key_input() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        input.set_graphics_responses(true);
+
+        // How crossterm decodes "\x1b_Gi=5;OK\x1b\\"
+        let alt = |c| Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::ALT));
+        let mut events = input.translate_event(alt('_'));
+        for c in "Gi=5;OK".chars() {
+            events.extend(input.translate_event(press(c)));
+        }
+        events.extend(input.translate_event(alt('\\')));
+
+        match events.as_slice() {
+            [WaylandInputEvent::GraphicsResponse { image_id: 5, message }] => assert_eq!(message, "OK"),
+            _ => panic!("expected a single graphics response"),
+        }
+
+        // A genuine Alt+_ is passed on once the next key shows it isn't a response
+        let events = [alt('_'), press('x')].into_iter().flat_map(|e| input.translate_event(e));
+        assert_eq!(
+            keys(events.collect()),
+            vec![
+                (KEY_ALT_L, true), (KEY_SHIFT_L, true), ('_' as u32, true), (KEY_SHIFT_L, false), (KEY_ALT_L, false),
+                ('x' as u32, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn event_times_come_from_the_clock() {
+        let clock = crate::clock::ManualClock::default();
+        clock.advance(Duration::from_millis(1500));
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        input.set_clock(Arc::new(clock.clone()));
+
+        let times = |events: Vec<WaylandInputEvent>| -> Vec<u32> {
+            events
+                .into_iter()
+                .map(|event| match event {
+                    WaylandInputEvent::KeyboardKey { time, .. } => time,
+                    _ => panic!("expected only key events"),
+                })
+                .collect()
+        };
+        assert_eq!(times(input.translate_event(press('a'))), vec![1500]);
+
+        clock.advance(Duration::from_millis(16));
+        assert_eq!(times(input.translate_event(press('b'))), vec![1516]);
+    }
+
+    #[test]
+    fn shift_follows_the_clients_keymap() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        let keymap = crate::keymap::keymap_for_layout("de");
+        input.set_keycodes(KeycodeMap::from_keymap(&keymap));
+        let typed = |input: &mut TerminalInput, c| {
+            keys(input.translate_event(Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))))
+        };
+
+        // On a German layout '/' is Shift+7 and '<' has a key of its own
+        let shift = KEY_SHIFT_L;
+        assert_eq!(typed(&mut input, '/'), vec![(shift, true), ('/' as u32, true), (shift, false)]);
+        assert_eq!(typed(&mut input, '<'), vec![('<' as u32, true)]);
+        assert_eq!(typed(&mut input, 'z'), vec![('z' as u32, true)]);
+    }
+
+    #[test]
+    fn function_keys_go_up_to_f35() {
+        let expected = [
+            (1, 0xffbe), (2, 0xffbf), (3, 0xffc0), (4, 0xffc1), (5, 0xffc2), (6, 0xffc3),
+            (7, 0xffc4), (8, 0xffc5), (9, 0xffc6), (10, 0xffc7), (11, 0xffc8), (12, 0xffc9),
+            (13, 0xffca), (14, 0xffcb), (15, 0xffcc), (16, 0xffcd), (17, 0xffce), (18, 0xffcf),
+            (19, 0xffd0), (20, 0xffd1), (21, 0xffd2), (22, 0xffd3), (23, 0xffd4), (24, 0xffd5),
+        ];
+        for (n, keysym) in expected {
+            assert_eq!(TerminalInput::keycode_to_keysym(KeyCode::F(n)), Some(Keysym::new(keysym)), "F{}", n);
+        }
+        assert_eq!(TerminalInput::keycode_to_keysym(KeyCode::F(35)), Some(Keysym::new(0xffe0)));
+        assert_eq!(TerminalInput::keycode_to_keysym(KeyCode::F(36)), None);
+        assert_eq!(TerminalInput::keycode_to_keysym(KeyCode::F(0)), None);
+    }
+
+    #[test]
+    fn named_keys_have_a_key_in_the_default_keymap() {
+        // Keycodes come from the keymap, so a keysym sent here that it lacks
+        // would be dropped on the way to the client
+        let keycodes = KeycodeMap::from_keymap(&crate::keymap::keymap_for_layout("us"));
+        let mut named = vec![
+            KeyCode::Enter, KeyCode::Tab, KeyCode::Backspace, KeyCode::Esc, KeyCode::Left, KeyCode::Right,
+            KeyCode::Up, KeyCode::Down, KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown,
+            KeyCode::Insert, KeyCode::Delete, KeyCode::CapsLock, KeyCode::NumLock, KeyCode::ScrollLock,
+            KeyCode::PrintScreen, KeyCode::Pause, KeyCode::Menu, KeyCode::KeypadBegin,
+        ];
+        // evdev keymaps put XF86 keysyms on the F13 and up keys
+        named.extend((1..=12).map(KeyCode::F));
+        named.extend(
+            [
+                MediaKeyCode::PlayPause, MediaKeyCode::Pause, MediaKeyCode::Stop, MediaKeyCode::TrackPrevious,
+                MediaKeyCode::TrackNext, MediaKeyCode::Rewind, MediaKeyCode::FastForward,
+                MediaKeyCode::LowerVolume, MediaKeyCode::RaiseVolume, MediaKeyCode::MuteVolume,
+            ]
+            .map(KeyCode::Media),
+        );
+        named.extend(
+            [
+                ModifierKeyCode::LeftShift, ModifierKeyCode::RightShift, ModifierKeyCode::LeftControl,
+                ModifierKeyCode::RightControl, ModifierKeyCode::LeftAlt, ModifierKeyCode::RightAlt,
+                ModifierKeyCode::LeftSuper, ModifierKeyCode::RightSuper,
+            ]
+            .map(KeyCode::Modifier),
+        );
+        for code in named {
+            let keysym = TerminalInput::keycode_to_keysym(code).unwrap();
+            assert!(keycodes.keycode(keysym, false).is_some(), "no key for {:?}", code);
+        }
+        for c in "0123456789*+-./=".chars() {
+            let keysym = TerminalInput::keypad_keycode_to_keysym(KeyCode::Char(c)).unwrap();
+            assert!(keycodes.keycode(keysym, false).is_some(), "no keypad key for {:?}", c);
+        }
+    }
+
+    #[test]
+    fn keypad_keys_send_keypad_keysyms() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        input.set_keycodes(KeycodeMap::from_keymap(&crate::keymap::keymap_for_layout("us")));
+        let keypad = |input: &mut TerminalInput, code, state| {
+            let event = KeyEvent::new_with_kind_and_state(code, KeyModifiers::NONE, event::KeyEventKind::Press, state);
+            keys(input.translate_event(Event::Key(event)))
+        };
+
+        // KP_1 is the key's Num Lock level, so Shift reaches it for a
+        // client with Num Lock off
+        let shift = KEY_SHIFT_L;
+        let kp_1 = 0xffb1;
+        assert_eq!(
+            keypad(&mut input, KeyCode::Char('1'), KeyEventState::KEYPAD),
+            vec![(shift, true), (kp_1, true), (shift, false)]
+        );
+        assert_eq!(keypad(&mut input, KeyCode::Enter, KeyEventState::KEYPAD), vec![(0xff8d, true)]);
+        assert_eq!(keypad(&mut input, KeyCode::Left, KeyEventState::KEYPAD), vec![(0xff96, true)]);
+        assert_eq!(keypad(&mut input, KeyCode::Char('1'), KeyEventState::NONE), vec![('1' as u32, true)]);
+
+        input.set_reports_lock_state(true);
+        let num_lock = KeyEventState::KEYPAD | KeyEventState::NUM_LOCK;
+        assert_eq!(keypad(&mut input, KeyCode::Char('1'), num_lock), vec![(kp_1, true)]);
+
+        assert_eq!(keypad(&mut input, KeyCode::PrintScreen, KeyEventState::NONE), vec![(0xff61, true)]);
+        assert_eq!(keypad(&mut input, KeyCode::Menu, KeyEventState::NONE), vec![(0xff67, true)]);
+    }
+
+    #[test]
+    fn resizes_are_sized_from_a_forced_cell_size() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        input.set_cell_size(CellSize { width: 8, height: 16, forced: true });
+        match input.translate_event(Event::Resize(100, 50)).as_slice() {
+            [WaylandInputEvent::Resize { width: 800, height: 800 }] => {}
+            _ => panic!("expected an 800x800 resize"),
+        }
+    }
+
+    #[test]
+    fn scrolling_goes_at_the_configured_speed() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        input.set_scroll_speed("2,0.5".parse().unwrap());
+        let wheel = |input: &mut TerminalInput, kind, modifiers| {
+            let event = MouseEvent { kind, column: 0, row: 0, modifiers };
+            match input.translate_event(Event::Mouse(event)).as_slice() {
+                &[WaylandInputEvent::PointerAxis { horizontal, vertical, steps, .. }] => (horizontal, vertical, steps),
+                _ => panic!("expected a single axis event"),
+            }
+        };
+        // Each event is one notch, however far it scrolls
+        let none = KeyModifiers::NONE;
+        assert_eq!(wheel(&mut input, MouseEventKind::ScrollUp, none), (0.0, -30.0, -1));
+        assert_eq!(wheel(&mut input, MouseEventKind::ScrollRight, none), (7.5, 0.0, 1));
+        // Shift+wheel scrolls sideways, down to the right
+        assert_eq!(wheel(&mut input, MouseEventKind::ScrollDown, KeyModifiers::SHIFT), (7.5, 0.0, 1));
+        assert_eq!(wheel(&mut input, MouseEventKind::ScrollUp, KeyModifiers::SHIFT), (-7.5, 0.0, -1));
+
+        assert_eq!("1.5".parse(), Ok(ScrollSpeed { vertical: 1.5, horizontal: 1.5 }));
+        assert!("0".parse::<ScrollSpeed>().is_err());
+        assert!("1,fast".parse::<ScrollSpeed>().is_err());
+    }
+
+    #[test]
+    fn drags_press_the_button_if_the_press_got_lost() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        let mouse = |input: &mut TerminalInput, kind| {
+            let event = MouseEvent { kind, column: 1, row: 1, modifiers: KeyModifiers::NONE };
+            input
+                .translate_event(Event::Mouse(event))
+                .into_iter()
+                .map(|event| match event {
+                    WaylandInputEvent::PointerButton { button, state, .. } => Some((button, state)),
+                    WaylandInputEvent::PointerMotion { .. } => None,
+                    _ => panic!("expected only pointer events"),
+                })
+                .collect::<Vec<_>>()
+        };
+        let (left, right) = (MouseButton::Left, MouseButton::Right);
+        let btn_left = TerminalInput::mouse_button_to_code(left);
+
+        // No Down before the drag
+        assert_eq!(mouse(&mut input, MouseEventKind::Drag(left)), vec![Some((btn_left, ButtonState::Pressed)), None]);
+        assert_eq!(mouse(&mut input, MouseEventKind::Drag(left)), vec![None]);
+        assert_eq!(mouse(&mut input, MouseEventKind::Up(left)), vec![Some((btn_left, ButtonState::Released))]);
+
+        // With one, it isn't pressed twice
+        mouse(&mut input, MouseEventKind::Down(right));
+        assert_eq!(mouse(&mut input, MouseEventKind::Drag(right)), vec![None]);
+    }
+
+    #[test]
+    fn zoom_keys_need_their_modifiers() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        input.set_zoom_keys(Some("ctrl+alt".parse().unwrap()));
+
+        let ctrl_alt = KeyModifiers::CONTROL | KeyModifiers::ALT;
+        let events = input.translate_event(Event::Key(KeyEvent::new(KeyCode::Char('='), ctrl_alt)));
+        assert!(matches!(events.as_slice(), [WaylandInputEvent::Zoom(ZoomAction::In)]));
+        let events = input.translate_event(Event::Key(KeyEvent::new(KeyCode::Left, ctrl_alt)));
+        assert!(matches!(events.as_slice(), [WaylandInputEvent::Zoom(ZoomAction::Pan { .. })]));
+
+        assert_eq!(keys(input.translate_event(press('='))), vec![('=' as u32, true)]);
+    }
+
+    #[test]
+    fn the_backend_chord_cycles_backends() {
+        let mut input = TerminalInput::new(80, 24, 800, 600);
+        input.set_backend_chord(Some("ctrl+alt+b".parse().unwrap()));
+
+        let ctrl_alt = KeyModifiers::CONTROL | KeyModifiers::ALT;
+        let events = input.translate_event(Event::Key(KeyEvent::new(KeyCode::Char('b'), ctrl_alt)));
+        assert!(matches!(events.as_slice(), [WaylandInputEvent::CycleBackend]));
+
+        assert_eq!(keys(input.translate_event(press('b'))), vec![('b' as u32, true)]);
+    }
+}
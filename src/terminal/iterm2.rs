@@ -0,0 +1,164 @@
+//! iTerm2 inline images, for iTerm2 and WezTerm
+//!
+//! Each frame is sent whole as a PNG file (OSC 1337). Encoding a PNG costs
+//! far more than Kitty's raw pixels: the ignored `png_encoding_cost` test
+//! measures both on a 1920x1080 frame. Compression is kept at its fastest
+//! and filtering off, since the link usually matters less than the frame rate.
+
+use super::backend::WholeFrameOutput;
+use super::kitty::{ScaleMode, TextCursor, DEFAULT_MAX_SIZE};
+use super::{FrameTimings, RenderBackend};
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{ExtendedColorType, ImageEncoder};
+use smithay::utils::{Buffer, Rectangle};
+use std::time::Instant;
+
+pub struct ITerm2Graphics {
+    output: WholeFrameOutput,
+    /// How frames too large to send are resized
+    scale_mode: ScaleMode,
+}
+
+impl ITerm2Graphics {
+    pub fn new() -> Self {
+        Self { output: WholeFrameOutput::new(), scale_mode: ScaleMode::Bilinear }
+    }
+
+    /// Write frames to `output` instead of stdout
+    #[cfg(test)]
+    fn with_output(output: impl std::io::Write + 'static) -> Self {
+        Self { output: WholeFrameOutput::with_output(output), ..Self::new() }
+    }
+
+    /// Resize frames that don't fit with `scale_mode`
+    pub fn set_scale_mode(&mut self, scale_mode: ScaleMode) {
+        self.scale_mode = scale_mode;
+    }
+
+    /// Return the terminal's cursor to the pointer's cell after every frame
+    pub fn set_text_cursor(&mut self, cursor: Option<TextCursor>) {
+        self.output.text_cursor = cursor;
+    }
+}
+
+impl Default for ITerm2Graphics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderBackend for ITerm2Graphics {
+    #[tracing::instrument(level = "debug", skip(self, rgba_data, _damage))]
+    fn display_frame(
+        &mut self,
+        width: u32,
+        height: u32,
+        rgba_data: &[u8],
+        opaque: bool,
+        _damage: &[Rectangle<i32, Buffer>],
+    ) -> Result<Option<u32>> {
+        let scale_mode = self.scale_mode;
+        self.output.send(width, height, rgba_data, opaque, || {
+            let started = Instant::now();
+            // Scale down large images like the Kitty path does
+            let (max_width, max_height) = DEFAULT_MAX_SIZE;
+            let (scaled_data, scaled_width, scaled_height) = if width > max_width || height > max_height {
+                let scale = f32::min(max_width as f32 / width as f32, max_height as f32 / height as f32);
+                let new_width = ((width as f32 * scale) as u32).max(1);
+                let new_height = ((height as f32 * scale) as u32).max(1);
+                let mut scaled = Vec::new();
+                scale_mode.resize(rgba_data, (width, height), (new_width, new_height), opaque, &mut scaled);
+                (scaled, new_width, new_height)
+            } else {
+                (rgba_data.to_vec(), width, height)
+            };
+            let scaled = Instant::now();
+
+            let png = encode_png(&scaled_data, scaled_width, scaled_height, opaque)?;
+            // The image spans the whole text area from its top-left corner, and
+            // leaving the cursor there keeps the screen from scrolling
+            let encoded = format!(
+                "\x1b[H\x1b]1337;File=inline=1;size={};width=100%;height=100%;\
+                 preserveAspectRatio=0;doNotMoveCursor=1:{}\x07",
+                png.len(),
+                BASE64.encode(&png),
+            );
+            Ok((encoded.into_bytes(), FrameTimings { scale: scaled - started, encode: scaled.elapsed() }))
+        })
+    }
+
+    fn last_timings(&self) -> FrameTimings {
+        self.output.last_timings
+    }
+
+    fn leave_terminal(&mut self) -> Result<()> {
+        // Inline images are part of the text, and go with it
+        self.output.clear("")
+    }
+}
+
+/// `rgba` as a PNG, dropping the alpha channel of an `opaque` frame
+fn encode_png(rgba: &[u8], width: u32, height: u32, opaque: bool) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    let encoder = PngEncoder::new_with_quality(&mut png, CompressionType::Fast, FilterType::NoFilter);
+    let result = if opaque {
+        let rgb: Vec<u8> = rgba.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+        encoder.write_image(&rgb, width, height, ExtendedColorType::Rgb8)
+    } else {
+        encoder.write_image(rgba, width, height, ExtendedColorType::Rgba8)
+    };
+    result.map_err(|e| anyhow!("Failed to encode frame as PNG: {}", e))?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use crate::terminal::KittyGraphics;
+
+    #[test]
+    fn frames_are_sent_as_inline_pngs() {
+        let frame = [0x20u8, 0x40, 0x60, 0xff].repeat(4);
+        let png = encode_png(&frame, 2, 2, true).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(decoded.into_raw(), frame);
+
+        let mut iterm2 = ITerm2Graphics::with_output(io::sink());
+        assert_eq!(iterm2.display_frame(2, 2, &frame, true, &[]).unwrap(), Some(1));
+        assert_eq!(iterm2.display_frame(2, 2, &frame, true, &[]).unwrap(), None);
+    }
+
+    /// Encoding time of a 1920x1080 frame, as PNG and as Kitty's raw pixels
+    ///
+    /// `cargo test --release png_encoding_cost -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn png_encoding_cost() {
+        let (width, height) = (1920, 1080);
+        // Flat areas with some detail, like an application window
+        let frame: Vec<u8> = (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = (i % width, i / width);
+                let shade = if (x / 64 + y / 32) % 2 == 0 { 0xf0 } else { (x ^ y) as u8 };
+                [shade, shade, 0xe0, 0xff]
+            })
+            .collect();
+        let runs = 10;
+
+        let mut iterm2 = ITerm2Graphics::with_output(io::sink());
+        let mut kitty = KittyGraphics::with_output(io::sink());
+        for (name, backend) in [("iTerm2 PNG", &mut iterm2 as &mut dyn RenderBackend), ("Kitty raw", &mut kitty)] {
+            let started = Instant::now();
+            for run in 0..runs {
+                let mut frame = frame.clone();
+                // A different frame every time, so none is skipped as unchanged
+                frame[0] = run as u8;
+                backend.display_frame(width, height, &frame, true, &[]).unwrap();
+            }
+            println!("{}: {:?} per frame", name, started.elapsed() / runs);
+        }
+    }
+}
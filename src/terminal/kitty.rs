@@ -1,14 +1,362 @@
 use anyhow::Result;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use super::{Fallback, RenderBackend};
+use crate::compositor::transform::{scale_image_into, scale_image_nearest_into, scale_nearest_into};
+use flate2::write::ZlibEncoder;
+use smithay::utils::{Buffer, Rectangle};
+use std::borrow::Cow;
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const CHUNK_SIZE: usize = 4096;
+/// Frames larger than this are scaled down before sending, unless set otherwise
+pub const DEFAULT_MAX_SIZE: (u32, u32) = (1920, 1080);
+/// Raw bytes encoded into one chunk; a whole number of base64 groups, so
+/// chunks encode independently
+const RAW_CHUNK_SIZE: usize = CHUNK_SIZE / 4 * 3;
+
+/// How often to force a full delete + retransmit of the image
+///
+/// Some terminals accumulate artifacts when images are only ever replaced;
+/// periodically wiping all image data clears them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FullRefresh {
+    /// Every N displayed frames
+    Frames(u32),
+    /// Whenever this much time has passed since the last full refresh
+    Interval(Duration),
+}
+
+impl std::str::FromStr for FullRefresh {
+    type Err = String;
+
+    /// Parses `N` (frames), `Ns` (seconds) or `Nms` (milliseconds)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected N, Ns or Nms, got {:?}", s);
+        let refresh = if let Some(ms) = s.strip_suffix("ms") {
+            FullRefresh::Interval(Duration::from_millis(ms.parse().map_err(|_| invalid())?))
+        } else if let Some(secs) = s.strip_suffix('s') {
+            FullRefresh::Interval(Duration::from_secs(secs.parse().map_err(|_| invalid())?))
+        } else {
+            FullRefresh::Frames(s.parse().map_err(|_| invalid())?)
+        };
+
+        match refresh {
+            FullRefresh::Frames(0) => Err("frame count must be at least 1".into()),
+            FullRefresh::Interval(d) if d.is_zero() => Err("interval must be non-zero".into()),
+            refresh => Ok(refresh),
+        }
+    }
+}
+
+/// How frame data is compressed for transmission
+///
+/// Kitty's `o=z` is zlib (RFC 1950) deflate; other compression formats
+/// aren't part of the protocol.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Raw pixels, for terminals that mis-render compressed data
+    #[default]
+    None,
+    /// zlib at its fastest level, usually several times smaller
+    Zlib,
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zlib" => Ok(Compression::Zlib),
+            _ => Err(format!("expected none or zlib, got {:?}", s)),
+        }
+    }
+}
+
+/// How frames are resized to fit the terminal (`--scale-quality`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// Smooth, interpolating between the four nearest pixels
+    #[default]
+    Bilinear,
+    /// The nearest pixel, much faster and sharp for pixel art
+    Nearest,
+}
+
+impl std::str::FromStr for ScaleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bilinear" => Ok(ScaleMode::Bilinear),
+            "nearest" => Ok(ScaleMode::Nearest),
+            _ => Err(format!("expected bilinear or nearest, got {:?}", s)),
+        }
+    }
+}
+
+impl ScaleMode {
+    /// Resize a frame of `from` pixels to `to` into `result`
+    pub(super) fn resize(self, rgba_data: &[u8], from: (u32, u32), to: (u32, u32), opaque: bool, result: &mut Vec<u8>) {
+        match self {
+            ScaleMode::Bilinear => scale_image_into(rgba_data, from.0, from.1, to.0, to.1, opaque, result),
+            ScaleMode::Nearest => scale_image_nearest_into(rgba_data, from.0, from.1, to.0, to.1, result),
+        }
+    }
+}
+
+/// Size of a terminal cell in pixels, for sizing the terminal when it
+/// doesn't report its pixel size (`--cell-size`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellSize {
+    pub width: u32,
+    pub height: u32,
+    /// Size the terminal from this even when it does report a pixel size,
+    /// for terminals whose report is wrong (`--force-cell-size`)
+    pub forced: bool,
+}
+
+impl Default for CellSize {
+    /// A typical cell of a monospace font at common sizes
+    fn default() -> Self {
+        Self { width: 10, height: 20, forced: false }
+    }
+}
+
+impl CellSize {
+    /// Pixel size of `cols`x`rows` of these cells
+    pub fn pixels(&self, cols: u16, rows: u16) -> (u32, u32) {
+        (u32::from(cols) * self.width, u32::from(rows) * self.height)
+    }
+
+    /// The cells `pixels` of `cols`x`rows` are made of, at least a pixel
+    /// each, to size the terminal from once it has been resized
+    pub fn measured(pixels: (u32, u32), cols: u16, rows: u16) -> Self {
+        let per_cell = |pixels: u32, cells: u16| (pixels / u32::from(cells).max(1)).max(1);
+        Self { width: per_cell(pixels.0, cols), height: per_cell(pixels.1, rows), forced: false }
+    }
+}
+
+/// Cheap fingerprint of a frame, to recognize one that is already shown
+pub(super) fn frame_hash(width: u32, height: u32, rgba: &[u8], opaque: bool) -> u64 {
+    const K: u64 = 0x517c_c1b7_2722_0a95;
+    let mix = |hash: u64, word: u64| (hash.rotate_left(5) ^ word).wrapping_mul(K);
+
+    let mut hash = mix(mix(0, (u64::from(width) << 32) | u64::from(height)), u64::from(opaque));
+    let mut words = rgba.chunks_exact(8);
+    for word in &mut words {
+        hash = mix(hash, u64::from_le_bytes(word.try_into().unwrap()));
+    }
+    words.remainder().iter().fold(hash, |hash, &byte| mix(hash, u64::from(byte)))
+}
+
+/// `damage` clipped to a `width`x`height` frame, if it is worth sending on
+/// its own: something, but less than the whole frame
+fn damaged_regions(damage: &[Rectangle<i32, Buffer>], width: u32, height: u32) -> Option<Vec<Rectangle<i32, Buffer>>> {
+    let frame = Rectangle::from_size((width as i32, height as i32).into());
+    let regions: Vec<_> = damage.iter().filter_map(|rect| rect.intersection(frame)).collect();
+    let area: i64 = regions.iter().map(|rect| i64::from(rect.size.w) * i64::from(rect.size.h)).sum();
+    (!regions.is_empty() && area < i64::from(width) * i64::from(height)).then_some(regions)
+}
+
+/// Write a graphics command with base64 `encoded` data, split into chunks
+/// (m=1 while more follow)
+fn write_chunked(out: &mut impl Write, control: &str, encoded: &str) -> io::Result<()> {
+    let count = encoded.len().div_ceil(CHUNK_SIZE);
+    for (i, chunk) in encoded.as_bytes().chunks(CHUNK_SIZE).enumerate() {
+        let more = u8::from(i + 1 < count);
+        if i == 0 {
+            write!(out, "\x1b_G{},m={};", control, more)?;
+        } else {
+            write!(out, "\x1b_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+    }
+    Ok(())
+}
+
+/// `data` compressed as `compression` asks, along with the control key announcing it
+fn compress(data: &[u8], compression: Compression) -> io::Result<(Cow<'_, [u8]>, &'static str)> {
+    match compression {
+        Compression::None => Ok((Cow::Borrowed(data), "")),
+        Compression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::with_capacity(data.len() / 4), flate2::Compression::fast());
+            encoder.write_all(data)?;
+            Ok((Cow::Owned(encoder.finish()?), ",o=z"))
+        }
+    }
+}
+
+/// The terminal cell under the pointer, for `--cursor text`
+///
+/// Shared between the input thread, which moves the terminal's cursor as the
+/// mouse moves, and the frame output, which puts it back after each frame.
+#[derive(Clone, Debug, Default)]
+pub struct TextCursor(Arc<AtomicU32>);
+
+impl TextCursor {
+    pub fn set(&self, col: u16, row: u16) {
+        self.0.store((col as u32) << 16 | row as u32, Ordering::Relaxed);
+    }
+
+    /// Escape sequence moving the terminal's cursor to the cell and showing it
+    pub(super) fn escape(&self) -> String {
+        let cell = self.0.load(Ordering::Relaxed);
+        format!("\x1b[{};{}H\x1b[?25h", (cell & 0xffff) + 1, (cell >> 16) + 1)
+    }
+
+    /// Move the terminal's cursor to the cell now
+    pub fn show(&self) -> Result<()> {
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{}", self.escape())?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Time spent on each step of the last `display_frame`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTimings {
+    pub scale: Duration,
+    /// Base64 encoding and writing the escape sequences
+    pub encode: Duration,
+}
 
 /// Kitty graphics protocol implementation
 pub struct KittyGraphics {
     image_id: u32,
     last_width: u32,
     last_height: u32,
+    full_refresh: Option<FullRefresh>,
+    frames_since_refresh: u32,
+    last_refresh: Instant,
+    /// Ask the terminal to answer each transmitted frame (q=0 instead of q=2)
+    request_acks: bool,
+    last_timings: FrameTimings,
+    encoder: ChunkEncoder,
+    /// Show frames at the largest whole-number scale that fits, centered
+    integer_scale: bool,
+    /// Where to leave the terminal's cursor after each frame, if it shows the pointer
+    text_cursor: Option<TextCursor>,
+    /// Applied to the pixel data before base64 encoding
+    compression: Compression,
+    /// The frame on screen, to skip resending it or send only what changed
+    last_shown: Option<Shown>,
+    /// Where frames are written; stdout except in tests
+    output: Box<dyn Write>,
+    /// Save the terminal's title on setup and restore it on exit, since
+    /// windows set it in between
+    save_title: bool,
+    /// Frames are scaled down to fit this size
+    max_size: (u32, u32),
+    /// Leave Sixel display mode, which `SixelGraphics` enters, on exit
+    sixel_display_mode: bool,
+    /// How frames are resized when they don't fit
+    scale_mode: ScaleMode,
+    /// The last frame as scaled and as packed to RGB, kept so the next one
+    /// can reuse their memory
+    scaled: Vec<u8>,
+    packed: Vec<u8>,
+    /// Escape sequences of the frame being sent, written out at once and
+    /// kept for the next frame's
+    frame_out: Vec<u8>,
+    /// Pixels of a damaged region and their base64, reused region to region
+    region: Vec<u8>,
+    region_encoded: String,
+}
+
+/// Where the image goes: a cell plus a pixel offset within it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Placement {
+    col: u32,
+    row: u32,
+    x: u32,
+    y: u32,
+}
+
+/// The frame last sent to the terminal
+#[derive(Clone, Copy, Debug)]
+struct Shown {
+    /// `frame_hash()` of the frame as passed in, before scaling
+    hash: u64,
+    placement: Placement,
+    image_id: u32,
+}
+
+/// Terminal size from TIOCGWINSZ: (columns, rows, width px, height px)
+fn window_size() -> Option<(u32, u32, u32, u32)> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(io::stdout().as_raw_fd(), libc::TIOCGWINSZ, &mut winsize) };
+    let size = (winsize.ws_col as u32, winsize.ws_row as u32, winsize.ws_xpixel as u32, winsize.ws_ypixel as u32);
+    (result == 0 && size.0 > 0 && size.1 > 0 && size.2 > 0 && size.3 > 0).then_some(size)
+}
+
+/// Scale and placement of a `width`x`height` frame centered in a terminal
+///
+/// The scale is the largest whole number at which the frame fits; when not
+/// even 1x fits, it is the fractional factor that fits the frame instead.
+fn letterbox(width: u32, height: u32, terminal: (u32, u32, u32, u32)) -> (f32, Placement) {
+    let (cols, rows, term_width, term_height) = terminal;
+    let fit = f32::min(term_width as f32 / width as f32, term_height as f32 / height as f32);
+    let scale = if fit >= 1.0 { fit.floor() } else { fit };
+    let shown_width = (width as f32 * scale) as u32;
+    let shown_height = (height as f32 * scale) as u32;
+
+    let offset_x = term_width.saturating_sub(shown_width) / 2;
+    let offset_y = term_height.saturating_sub(shown_height) / 2;
+    let (cell_width, cell_height) = ((term_width / cols).max(1), (term_height / rows).max(1));
+    let placement = Placement {
+        col: offset_x / cell_width,
+        row: offset_y / cell_height,
+        x: offset_x % cell_width,
+        y: offset_y % cell_height,
+    };
+    (scale, placement)
+}
+
+/// Base64 encoder that keeps the previous frame to reuse the encoding of
+/// chunks that haven't changed
+///
+/// Only one frame is kept, so memory stays bounded by the frame size.
+#[derive(Default)]
+struct ChunkEncoder {
+    data: Vec<u8>,
+    encoded: String,
+}
+
+impl ChunkEncoder {
+    /// Base64 of `data`, re-encoding only the chunks that differ from the previous frame
+    fn encode(&mut self, data: &[u8]) -> &str {
+        // Chunks only line up with the previous frame's when the size matches
+        if data.len() != self.data.len() {
+            self.data = data.to_vec();
+            self.encoded = BASE64.encode(data);
+            return &self.encoded;
+        }
+
+        let mut changed = 0;
+        let mut buf = [0u8; CHUNK_SIZE];
+        for (i, chunk) in data.chunks(RAW_CHUNK_SIZE).enumerate() {
+            let start = i * RAW_CHUNK_SIZE;
+            let previous = &mut self.data[start..start + chunk.len()];
+            if previous != chunk {
+                previous.copy_from_slice(chunk);
+                // Equally long input encodes to equally long output, so this replaces in place
+                let len = BASE64.encode_slice(chunk, &mut buf).expect("chunk fits its encoding");
+                let encoded = std::str::from_utf8(&buf[..len]).expect("base64 is ASCII");
+                self.encoded.replace_range(i * CHUNK_SIZE..i * CHUNK_SIZE + len, encoded);
+                changed += 1;
+            }
+        }
+        tracing::trace!("Re-encoded {} of {} chunks", changed, data.len().div_ceil(RAW_CHUNK_SIZE));
+        &self.encoded
+    }
 }
 
 impl KittyGraphics {
@@ -17,7 +365,101 @@ impl KittyGraphics {
             image_id: 1,
             last_width: 0,
             last_height: 0,
+            full_refresh: None,
+            frames_since_refresh: 0,
+            last_refresh: Instant::now(),
+            request_acks: false,
+            last_timings: FrameTimings::default(),
+            encoder: ChunkEncoder::default(),
+            integer_scale: false,
+            text_cursor: None,
+            compression: Compression::None,
+            last_shown: None,
+            output: Box::new(io::stdout()),
+            save_title: false,
+            max_size: DEFAULT_MAX_SIZE,
+            sixel_display_mode: false,
+            scale_mode: ScaleMode::Bilinear,
+            scaled: Vec::new(),
+            packed: Vec::new(),
+            frame_out: Vec::new(),
+            region: Vec::new(),
+            region_encoded: String::new(),
+        }
+    }
+
+    /// Write frames to `output` instead of stdout
+    #[cfg(test)]
+    pub(super) fn with_output(output: impl Write + 'static) -> Self {
+        Self { output: Box::new(output), ..Self::new() }
+    }
+
+    /// Content hash of the frame last sent, if any
+    pub fn last_hash(&self) -> Option<u64> {
+        self.last_shown.map(|shown| shown.hash)
+    }
+
+    /// Have the terminal acknowledge every frame, for frame pacing
+    ///
+    /// The responses arrive on stdin and must be picked out of the input
+    /// stream (see `TerminalInput::set_graphics_responses`).
+    pub fn set_request_acks(&mut self, enabled: bool) {
+        self.request_acks = enabled;
+    }
+
+    /// Compress frames before sending them; can be switched at any time
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Resize frames that don't fit with `scale_mode`
+    pub fn set_scale_mode(&mut self, scale_mode: ScaleMode) {
+        self.scale_mode = scale_mode;
+    }
+
+    /// Center frames at the largest whole-number scale that fits the terminal,
+    /// with nearest-neighbor scaling so every pixel becomes an NxN block
+    pub fn set_integer_scale(&mut self, enabled: bool) {
+        self.integer_scale = enabled;
+    }
+
+    /// Push the terminal's title on its title stack in `setup_terminal()`
+    /// and pop it back in `restore_terminal()`
+    pub fn set_save_title(&mut self, enabled: bool) {
+        self.save_title = enabled;
+    }
+
+    /// Reset Sixel display mode (DECSDM) in `restore_terminal()`, for the
+    /// Sixel backend, which sets it with every frame
+    pub fn set_sixel_display_mode(&mut self, enabled: bool) {
+        self.sixel_display_mode = enabled;
+    }
+
+    /// Return the terminal's cursor to the pointer's cell after every frame
+    pub fn set_text_cursor(&mut self, cursor: Option<TextCursor>) {
+        self.text_cursor = cursor;
+    }
+
+    /// Enable (or disable with `None`) periodic anti-ghosting full refreshes
+    pub fn set_full_refresh(&mut self, full_refresh: Option<FullRefresh>) {
+        self.full_refresh = full_refresh;
+    }
+
+    /// Whether the next frame must wipe all image data and be sent in full
+    ///
+    /// Counts the frame towards the refresh interval; resets when it fires.
+    fn full_refresh_due(&mut self) -> bool {
+        self.frames_since_refresh += 1;
+        let due = match self.full_refresh {
+            None => false,
+            Some(FullRefresh::Frames(n)) => self.frames_since_refresh >= n,
+            Some(FullRefresh::Interval(interval)) => self.last_refresh.elapsed() >= interval,
+        };
+        if due {
+            self.frames_since_refresh = 0;
+            self.last_refresh = Instant::now();
         }
+        due
     }
 
     /// Clear the screen and prepare for graphics
@@ -32,6 +474,10 @@ impl KittyGraphics {
         write!(stdout, "\x1b[2J")?;
         // Move cursor to top-left
         write!(stdout, "\x1b[H")?;
+        // Save the window title (XTWINOPS); reading it back is disabled in most terminals
+        if self.save_title {
+            write!(stdout, "\x1b[22;0t")?;
+        }
 
         stdout.flush()?;
         Ok(())
@@ -47,115 +493,290 @@ impl KittyGraphics {
         write!(stdout, "\x1b[?25h")?;
         // Leave alternate screen buffer
         write!(stdout, "\x1b[?1049l")?;
+        // Let Sixel images scroll with the text again
+        if self.sixel_display_mode {
+            write!(stdout, "\x1b[?80l")?;
+        }
+        // Restore the window title saved in setup_terminal()
+        if self.save_title {
+            write!(stdout, "\x1b[23;0t")?;
+        }
 
         stdout.flush()?;
         Ok(())
     }
 
+    /// Get terminal size in pixels, estimated from `cell` if the terminal
+    /// doesn't say or `cell` is forced
+    ///
+    /// The terminal may be asked with `CSI 14 t` and its reply read from
+    /// stdin, so this is for startup, before the input thread reads stdin.
+    pub fn query_terminal_size_pixels(cell: CellSize) -> Result<(u32, u32)> {
+        if cell.forced {
+            let (cols, rows) = crossterm::terminal::size()?;
+            return Ok(cell.pixels(cols, rows));
+        }
+        if let Some(size) = Self::window_size_pixels() {
+            return Ok(size);
+        }
+
+        // Some terminals leave the ioctl pixel fields at zero but answer
+        // the "report text area size in pixels" query
+        if let Some(size) = Self::query_text_area_pixels(Duration::from_millis(100)) {
+            tracing::info!("Terminal reported text area size via CSI 14 t: {}x{}", size.0, size.1);
+            return Ok(size);
+        }
+
+        // Fallback: estimate based on cell size
+        let (cols, rows) = crossterm::terminal::size()?;
+        tracing::info!("Terminal didn't report its pixel size; assuming {}x{} cells", cell.width, cell.height);
+        Ok(cell.pixels(cols, rows))
+    }
+
+    /// Terminal size in pixels at `cols`x`rows`, without asking the terminal:
+    /// the pixel size the kernel has for it, or else estimated from `cell`
+    pub fn terminal_size_pixels(cell: CellSize, cols: u16, rows: u16) -> (u32, u32) {
+        match Self::window_size_pixels() {
+            Some(size) if !cell.forced => size,
+            _ => cell.pixels(cols, rows),
+        }
+    }
+
+    /// Pixel size from TIOCGWINSZ, if the terminal fills it in
+    fn window_size_pixels() -> Option<(u32, u32)> {
+        use std::os::unix::io::AsRawFd;
+
+        let stdout = io::stdout();
+        let fd = stdout.as_raw_fd();
+
+        let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+        let result = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) };
+
+        (result == 0 && winsize.ws_xpixel > 0 && winsize.ws_ypixel > 0)
+            .then(|| (u32::from(winsize.ws_xpixel), u32::from(winsize.ws_ypixel)))
+    }
+
+    /// Send `CSI 14 t` and wait for the `CSI 4 ; height ; width t` reply
+    fn query_text_area_pixels(timeout: Duration) -> Option<(u32, u32)> {
+        query_terminal("\x1b[14t", timeout, parse_text_area_report)
+    }
+
+    /// Ask the terminal for its background color (OSC 11), as RGB
+    ///
+    /// Returns `None` if the terminal doesn't answer within `timeout`.
+    pub fn query_background_color(timeout: Duration) -> Option<[u8; 3]> {
+        query_terminal("\x1b]11;?\x1b\\", timeout, parse_background_report)
+    }
+
+    /// Get terminal size in characters
+    pub fn query_terminal_size_chars() -> Result<(u16, u16)> {
+        Ok(crossterm::terminal::size()?)
+    }
+}
+
+impl RenderBackend for KittyGraphics {
     /// Display RGBA image data at the current cursor position
-    pub fn display_frame(&mut self, width: u32, height: u32, rgba_data: &[u8]) -> Result<()> {
+    ///
+    /// An `opaque` frame is sent as RGB (f=24), a quarter smaller than RGBA.
+    /// Returns the image id the frame was sent under, which is what the
+    /// terminal's acknowledgement refers to, or `None` if the frame is
+    /// already on screen and nothing was sent.
+    #[tracing::instrument(level = "debug", skip(self, rgba_data))]
+    fn display_frame(
+        &mut self,
+        width: u32,
+        height: u32,
+        rgba_data: &[u8],
+        opaque: bool,
+        damage: &[Rectangle<i32, Buffer>],
+    ) -> Result<Option<u32>> {
         tracing::trace!("Displaying frame: {}x{}, {} bytes", width, height, rgba_data.len());
+        if width == 0 || height == 0 {
+            anyhow::bail!("Not displaying an empty {}x{} frame", width, height);
+        }
+
+        let started = Instant::now();
+        let letterboxed = if self.integer_scale { window_size() } else { None }
+            .map(|terminal| letterbox(width, height, terminal));
+        let placement = letterboxed.map(|(_, placement)| placement).unwrap_or_default();
+
+        let hash = frame_hash(width, height, rgba_data, opaque);
+        if self.last_shown.is_some_and(|shown| (shown.hash, shown.placement) == (hash, placement)) {
+            tracing::trace!("Frame unchanged, not sending it");
+            return Ok(None);
+        }
 
-        // Scale down large images to fit terminal better
-        let (scaled_data, scaled_width, scaled_height) = if width > 1920 || height > 1080 {
-            let scale = f32::min(1920.0 / width as f32, 1080.0 / height as f32);
+        // Scaled and packed into the previous frame's buffers, which are
+        // usually the right size already
+        let mut scaled_buffer = std::mem::take(&mut self.scaled);
+        let resized = if let Some((scale, _)) = letterboxed {
+            let new_width = ((width as f32 * scale) as u32).max(1);
+            let new_height = ((height as f32 * scale) as u32).max(1);
+            if scale >= 1.0 {
+                scale_nearest_into(rgba_data, width, height, scale as u32, &mut scaled_buffer);
+            } else {
+                self.scale_mode.resize(rgba_data, (width, height), (new_width, new_height), opaque, &mut scaled_buffer);
+            }
+            Some((new_width, new_height))
+        } else if width > self.max_size.0 || height > self.max_size.1 {
+            // Scale down large images to fit terminal better
+            let scale = f32::min(self.max_size.0 as f32 / width as f32, self.max_size.1 as f32 / height as f32);
             let new_width = (width as f32 * scale) as u32;
             let new_height = (height as f32 * scale) as u32;
             tracing::trace!("Scaling {}x{} -> {}x{}", width, height, new_width, new_height);
-            (scale_image(rgba_data, width, height, new_width, new_height), new_width, new_height)
+            self.scale_mode.resize(rgba_data, (width, height), (new_width, new_height), opaque, &mut scaled_buffer);
+            Some((new_width, new_height))
         } else {
-            (rgba_data.to_vec(), width, height)
+            None
         };
+        let (scaled_data, scaled_width, scaled_height) = match resized {
+            Some((new_width, new_height)) => (&scaled_buffer[..], new_width, new_height),
+            None => (rgba_data, width, height),
+        };
+        let mut packed_buffer = std::mem::take(&mut self.packed);
+        let (pixel_data, format) = if opaque {
+            pack_rgb_into(scaled_data, &mut packed_buffer);
+            (&packed_buffer[..], 24)
+        } else {
+            (scaled_data, 32)
+        };
+        let scaled = Instant::now();
 
-        let mut stdout = io::stdout().lock();
-
-        // Delete previous image if dimensions changed
-        if self.last_width != scaled_width || self.last_height != scaled_height {
-            write!(stdout, "\x1b_Ga=d;\x1b\\")?;
-            self.last_width = scaled_width;
-            self.last_height = scaled_height;
-        }
+        // Only damage to an unscaled frame, over the one shown before, can be sent on its own
+        let full_refresh = self.full_refresh_due();
+        let edit = match self.last_shown {
+            Some(shown)
+                if !full_refresh
+                    && shown.placement == placement
+                    && (scaled_width, scaled_height) == (width, height)
+                    && (self.last_width, self.last_height) == (width, height) =>
+            {
+                damaged_regions(damage, width, height).map(|regions| (shown.image_id, regions))
+            }
+            _ => None,
+        };
 
-        // Move cursor to top-left
-        write!(stdout, "\x1b[H")?;
+        // Buffer the whole frame so a refresh's delete and the new image
+        // reach the terminal in one write, without a blank frame in between
+        let mut out = std::mem::take(&mut self.frame_out);
+        out.clear();
+        let quiet = if self.request_acks { 0 } else { 2 };
 
-        // Send uncompressed for now (compression has display issues with Kitty)
-        let encoded = BASE64.encode(&scaled_data);
-        let compression_flag = "";
-
-        // Send image in chunks
-        let chunks: Vec<&str> = encoded
-            .as_bytes()
-            .chunks(CHUNK_SIZE)
-            .map(|c| std::str::from_utf8(c).unwrap())
-            .collect();
-
-        for (i, chunk) in chunks.iter().enumerate() {
-            let is_last = i == chunks.len() - 1;
-            let is_first = i == 0;
-
-            if is_first {
-                // First chunk: include all parameters
-                // a=T: transmit and display
-                // f=32: RGBA format
-                // s,v: source width, height
-                // o=z: zstd compression (optional)
-                // m=0/1: more chunks flag
-                // i: image id for replacement
-                // q=2: suppress responses
-                write!(
-                    stdout,
-                    "\x1b_Ga=T,f=32,s={},v={}{},m={},i={},q=2;{}\x1b\\",
-                    scaled_width,
-                    scaled_height,
+        let sent_id = if let Some((image_id, regions)) = edit {
+            // Draw the damaged regions over the image on screen
+            // a=f, r=1: edit the image's (only) frame
+            // x,y: where the region goes; s,v: its size
+            for (i, region) in regions.iter().enumerate() {
+                let bytes_per_pixel = (format / 8) as usize;
+                let (x, y) = (region.loc.x as usize, region.loc.y as usize);
+                let (w, h) = (region.size.w as usize, region.size.h as usize);
+                self.region.clear();
+                for row in y..y + h {
+                    let start = (row * width as usize + x) * bytes_per_pixel;
+                    self.region.extend_from_slice(&pixel_data[start..start + w * bytes_per_pixel]);
+                }
+                let (payload, compression_flag) = compress(&self.region, self.compression)?;
+                self.region_encoded.clear();
+                BASE64.encode_string(&payload, &mut self.region_encoded);
+                // Only the last region is acknowledged, which settles the others
+                let control = format!(
+                    "a=f,r=1,i={},x={},y={},s={},v={},f={}{},q={}",
+                    image_id,
+                    x,
+                    y,
+                    w,
+                    h,
+                    format,
                     compression_flag,
-                    if is_last { 0 } else { 1 },
-                    self.image_id,
-                    chunk
-                )?;
-            } else {
-                // Continuation chunk
-                write!(
-                    stdout,
-                    "\x1b_Gm={};{}\x1b\\",
-                    if is_last { 0 } else { 1 },
-                    chunk
-                )?;
+                    if i + 1 == regions.len() { quiet } else { 2 },
+                );
+                write_chunked(&mut out, &control, &self.region_encoded)?;
+            }
+            tracing::trace!("Sent {} damaged region(s) of image {}", regions.len(), image_id);
+            image_id
+        } else {
+            if full_refresh {
+                // Anti-ghosting: drop every image and its data, not just placements
+                tracing::debug!("Full refresh");
+                write!(out, "\x1b_Ga=d,d=A,q=2;\x1b\\")?;
+                self.last_width = scaled_width;
+                self.last_height = scaled_height;
+            } else if self.last_width != scaled_width || self.last_height != scaled_height {
+                // Delete previous image if dimensions changed
+                write!(out, "\x1b_Ga=d;\x1b\\")?;
+                self.last_width = scaled_width;
+                self.last_height = scaled_height;
             }
-        }
 
-        stdout.flush()?;
+            // Move the cursor to where the image starts
+            write!(out, "\x1b[{};{}H", placement.row + 1, placement.col + 1)?;
 
-        // Cycle image ID for next frame (allows replacement)
-        self.image_id = if self.image_id >= 1000 { 1 } else { self.image_id + 1 };
+            let (payload, compression_flag) = compress(pixel_data, self.compression)?;
+            let encoded = self.encoder.encode(&payload);
 
-        Ok(())
-    }
+            // a=T: transmit and display
+            // f=24/32: RGB or RGBA format
+            // s,v: source width, height
+            // X,Y: pixel offset within the cursor's cell
+            // o=z: zlib compression (optional)
+            // i: image id for replacement
+            // q=2: suppress responses (q=0 when acknowledgements are wanted)
+            let control = format!(
+                "a=T,f={},s={},v={},X={},Y={}{},i={},q={}",
+                format, scaled_width, scaled_height, placement.x, placement.y, compression_flag, self.image_id, quiet,
+            );
+            write_chunked(&mut out, &control, encoded)?;
 
-    /// Get terminal size in pixels (if available)
-    pub fn query_terminal_size_pixels() -> Result<(u32, u32)> {
-        // Try to use TIOCGWINSZ to get pixel dimensions
-        use std::os::unix::io::AsRawFd;
+            // Placing the image moved the cursor
+            if let Some(cursor) = &self.text_cursor {
+                write!(out, "{}", cursor.escape())?;
+            }
 
-        let stdout = io::stdout();
-        let fd = stdout.as_raw_fd();
+            // Cycle image ID for next frame (allows replacement)
+            let sent_id = self.image_id;
+            self.image_id = if self.image_id >= 1000 { 1 } else { self.image_id + 1 };
+            sent_id
+        };
 
-        let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
-        let result = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) };
+        self.output.write_all(&out)?;
+        self.output.flush()?;
+        self.frame_out = out;
+        self.scaled = scaled_buffer;
+        self.packed = packed_buffer;
+        self.last_shown = Some(Shown { hash, placement, image_id: sent_id });
+        self.last_timings = FrameTimings {
+            scale: scaled - started,
+            encode: scaled.elapsed(),
+        };
 
-        if result == 0 && winsize.ws_xpixel > 0 && winsize.ws_ypixel > 0 {
-            Ok((winsize.ws_xpixel as u32, winsize.ws_ypixel as u32))
-        } else {
-            // Fallback: estimate based on cell size
-            let (cols, rows) = crossterm::terminal::size()?;
-            // Assume typical cell size of 10x20 pixels
-            Ok((cols as u32 * 10, rows as u32 * 20))
+        Ok(Some(sent_id))
+    }
+
+    fn last_timings(&self) -> FrameTimings {
+        self.last_timings
+    }
+
+    fn apply_fallback(&mut self, fallback: Fallback) {
+        match fallback {
+            Fallback::Uncompressed => {
+                tracing::warn!("Terminal keeps rejecting frames, sending them uncompressed");
+                self.compression = Compression::None;
+            }
+            Fallback::MaxSize(width, height) => {
+                tracing::warn!("Terminal keeps rejecting frames, scaling them down to {}x{}", width, height);
+                self.max_size = (width, height);
+            }
         }
+        // In case the frame on screen was rejected too
+        self.last_shown = None;
     }
 
-    /// Get terminal size in characters
-    pub fn query_terminal_size_chars() -> Result<(u16, u16)> {
-        Ok(crossterm::terminal::size()?)
+    fn leave_terminal(&mut self) -> Result<()> {
+        // Images and their data
+        write!(self.output, "\x1b_Ga=d,d=A,q=2;\x1b\\")?;
+        self.output.flush()?;
+        self.last_shown = None;
+        Ok(())
     }
 }
 
@@ -165,42 +786,316 @@ impl Default for KittyGraphics {
     }
 }
 
-/// Scale RGBA image data using bilinear interpolation
-fn scale_image(data: &[u8], src_width: u32, src_height: u32, dst_width: u32, dst_height: u32) -> Vec<u8> {
-    let mut result = vec![0u8; (dst_width * dst_height * 4) as usize];
-
-    let x_ratio = src_width as f32 / dst_width as f32;
-    let y_ratio = src_height as f32 / dst_height as f32;
+/// Write `request` and read stdin until `parse` recognizes the reply
+///
+/// Raw mode is enabled for the duration of the query if it isn't already,
+/// and restored afterwards so the caller's terminal state is unchanged.
+pub(super) fn query_terminal<T>(request: &str, timeout: Duration, parse: impl Fn(&[u8]) -> Option<T>) -> Option<T> {
+    use std::os::unix::io::AsRawFd;
 
-    for dst_y in 0..dst_height {
-        for dst_x in 0..dst_width {
-            let src_x = dst_x as f32 * x_ratio;
-            let src_y = dst_y as f32 * y_ratio;
+    let stdin_fd = io::stdin().as_raw_fd();
+    if unsafe { libc::isatty(stdin_fd) } != 1 {
+        return None;
+    }
 
-            let x0 = src_x.floor() as u32;
-            let y0 = src_y.floor() as u32;
-            let x1 = (x0 + 1).min(src_width - 1);
-            let y1 = (y0 + 1).min(src_height - 1);
+    let was_raw = crossterm::terminal::is_raw_mode_enabled().unwrap_or(false);
+    if !was_raw && crossterm::terminal::enable_raw_mode().is_err() {
+        return None;
+    }
 
-            let x_frac = src_x - x0 as f32;
-            let y_frac = src_y - y0 as f32;
+    let result = (|| {
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{}", request).ok()?;
+        stdout.flush().ok()?;
+        drop(stdout);
 
-            let dst_idx = ((dst_y * dst_width + dst_x) * 4) as usize;
+        let deadline = Instant::now() + timeout;
+        let mut response = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
 
-            for c in 0..4 {
-                let p00 = data[((y0 * src_width + x0) * 4) as usize + c] as f32;
-                let p10 = data[((y0 * src_width + x1) * 4) as usize + c] as f32;
-                let p01 = data[((y1 * src_width + x0) * 4) as usize + c] as f32;
-                let p11 = data[((y1 * src_width + x1) * 4) as usize + c] as f32;
+            let mut pollfd = libc::pollfd { fd: stdin_fd, events: libc::POLLIN, revents: 0 };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as i32) };
+            if ready <= 0 {
+                return None;
+            }
 
-                let top = p00 * (1.0 - x_frac) + p10 * x_frac;
-                let bottom = p01 * (1.0 - x_frac) + p11 * x_frac;
-                let value = top * (1.0 - y_frac) + bottom * y_frac;
+            let mut buf = [0u8; 64];
+            let n = unsafe { libc::read(stdin_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n <= 0 {
+                return None;
+            }
+            response.extend_from_slice(&buf[..n as usize]);
 
-                result[dst_idx + c] = value as u8;
+            if let Some(reply) = parse(&response) {
+                return Some(reply);
             }
         }
+    })();
+
+    if !was_raw {
+        let _ = crossterm::terminal::disable_raw_mode();
     }
 
     result
 }
+
+/// Parse an `OSC 11 ; rgb:RRRR/GGGG/BBBB` background color report
+///
+/// Components have 1 to 4 hex digits each and are scaled to 8 bits. The
+/// reply ends with BEL or ST depending on the terminal.
+fn parse_background_report(data: &[u8]) -> Option<[u8; 3]> {
+    let start = data.windows(9).position(|w| w == b"\x1b]11;rgb:")? + 9;
+    let rest = &data[start..];
+    let end = rest.iter().position(|&b| b == 0x07 || b == 0x1b)?;
+    let body = std::str::from_utf8(&rest[..end]).ok()?;
+
+    let mut rgb = [0u8; 3];
+    let mut components = body.split('/');
+    for channel in &mut rgb {
+        let hex = components.next()?;
+        if hex.is_empty() || hex.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        let max = (1u32 << (4 * hex.len())) - 1;
+        *channel = (value * 255 / max) as u8;
+    }
+    components.next().is_none().then_some(rgb)
+}
+
+/// Parse a `CSI 4 ; height ; width t` text area size report into (width, height)
+fn parse_text_area_report(data: &[u8]) -> Option<(u32, u32)> {
+    let start = data.windows(4).position(|w| w == b"\x1b[4;")? + 4;
+    let rest = &data[start..];
+    let end = rest.iter().position(|&b| b == b't')?;
+    let body = std::str::from_utf8(&rest[..end]).ok()?;
+
+    let (height, width) = body.split_once(';')?;
+    let height: u32 = height.parse().ok()?;
+    let width: u32 = width.parse().ok()?;
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// Drop the alpha byte of every RGBA pixel, into `rgb`
+fn pack_rgb_into(rgba: &[u8], rgb: &mut Vec<u8>) {
+    rgb.clear();
+    rgb.extend(rgba.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reused_chunks_encode_like_a_fresh_encoding() {
+        let mut encoder = ChunkEncoder::default();
+        let mut frame: Vec<u8> = (0..RAW_CHUNK_SIZE * 3 + 100).map(|i| (i * 7) as u8).collect();
+        assert_eq!(encoder.encode(&frame), BASE64.encode(&frame));
+
+        // Change only the second and the trailing partial chunk
+        frame[RAW_CHUNK_SIZE + 5] ^= 0xff;
+        frame[RAW_CHUNK_SIZE * 3 + 50] ^= 0xff;
+        assert_eq!(encoder.encode(&frame), BASE64.encode(&frame));
+
+        frame.truncate(RAW_CHUNK_SIZE * 2 + 1);
+        assert_eq!(encoder.encode(&frame), BASE64.encode(&frame));
+    }
+
+    /// Base64 encoding time of a 1920x1080 RGB frame with one changed chunk,
+    /// reusing the previous frame's encoding and encoding it all afresh
+    ///
+    /// `cargo test --release chunk_encoding_cost -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn chunk_encoding_cost() {
+        let (width, height) = (1920, 1080);
+        let mut frame: Vec<u8> = (0..width * height * 3).map(|i| (i % 251) as u8).collect();
+        let runs = 10;
+        let middle = frame.len() / 2;
+
+        let mut encoder = ChunkEncoder::default();
+        encoder.encode(&frame);
+        let started = Instant::now();
+        for run in 0..runs {
+            // A mostly static frame: a blinking cursor's worth of change
+            frame[middle] = run as u8;
+            std::hint::black_box(encoder.encode(&frame));
+        }
+        println!("ChunkEncoder: {:?} per frame", started.elapsed() / runs);
+
+        let started = Instant::now();
+        for run in 0..runs {
+            frame[middle] = run as u8;
+            std::hint::black_box(BASE64.encode(&frame));
+        }
+        println!("BASE64.encode: {:?} per frame", started.elapsed() / runs);
+    }
+
+    #[test]
+    fn letterbox_uses_whole_number_scales_and_centers() {
+        // 100x50 frame in a 350x200 px terminal of 35x10 cells (10x20 px each)
+        let (scale, placement) = letterbox(100, 50, (35, 10, 350, 200));
+        assert_eq!(scale, 3.0);
+        // 300x150 shown, leaving 25 px on each side and 25 px above
+        assert_eq!(placement, Placement { col: 2, row: 1, x: 5, y: 5 });
+
+        // Too large for 1x: falls back to fitting
+        let (scale, placement) = letterbox(700, 200, (35, 10, 350, 200));
+        assert_eq!(scale, 0.5);
+        assert_eq!(placement, Placement { col: 0, row: 2, x: 0, y: 10 });
+    }
+
+    #[test]
+    fn text_cursor_goes_to_the_one_based_cell() {
+        let cursor = TextCursor::default();
+        assert_eq!(cursor.escape(), "\x1b[1;1H\x1b[?25h");
+        cursor.set(4, 2);
+        assert_eq!(cursor.clone().escape(), "\x1b[3;5H\x1b[?25h");
+    }
+
+    /// Output that records everything written, shared with the test
+    #[derive(Clone, Default)]
+    struct Recorder(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn unchanged_frames_are_not_resent() {
+        let output = Recorder::default();
+        let mut kitty = KittyGraphics::with_output(output.clone());
+        let frame = [0x20u8; 4 * 4 * 4];
+
+        assert_eq!(kitty.display_frame(4, 4, &frame, true, &[]).unwrap(), Some(1));
+        let written = output.0.borrow().len();
+        assert!(written > 0);
+        let hash = kitty.last_hash();
+
+        assert_eq!(kitty.display_frame(4, 4, &frame, true, &[]).unwrap(), None);
+        assert_eq!(output.0.borrow().len(), written);
+        assert_eq!(kitty.last_hash(), hash);
+
+        let mut changed = frame;
+        changed[5] = 0x21;
+        assert_eq!(kitty.display_frame(4, 4, &changed, true, &[]).unwrap(), Some(2));
+        assert!(output.0.borrow().len() > written);
+        assert_ne!(kitty.last_hash(), hash);
+    }
+
+    #[test]
+    fn scaled_frames_reuse_the_previous_buffers() {
+        let mut kitty = KittyGraphics::with_output(io::sink());
+        kitty.max_size = (4, 4);
+        let mut frame = [0x20u8; 8 * 8 * 4];
+        let buffers = |kitty: &KittyGraphics| {
+            [(kitty.scaled.as_ptr(), kitty.scaled.capacity()), (kitty.packed.as_ptr(), kitty.packed.capacity())]
+        };
+        kitty.display_frame(8, 8, &frame, true, &[]).unwrap();
+        assert_eq!(kitty.scaled.len(), 4 * 4 * 4);
+        let first = buffers(&kitty);
+        for run in 0..3 {
+            frame[0] = run;
+            kitty.display_frame(8, 8, &frame, true, &[]).unwrap();
+            assert_eq!(buffers(&kitty), first);
+        }
+
+        // The output and the damaged regions' buffers are kept too
+        let mut kitty = KittyGraphics::with_output(io::sink());
+        let buffers = |kitty: &KittyGraphics| {
+            [
+                (kitty.frame_out.as_ptr(), kitty.frame_out.capacity()),
+                (kitty.region.as_ptr(), kitty.region.capacity()),
+                (kitty.region_encoded.as_ptr(), kitty.region_encoded.capacity()),
+            ]
+        };
+        kitty.display_frame(8, 8, &frame, true, &[]).unwrap();
+        let damage = [Rectangle::new((2, 3).into(), (2, 2).into())];
+        frame[(3 * 8 + 2) * 4] = 1;
+        kitty.display_frame(8, 8, &frame, true, &damage).unwrap();
+        assert_eq!(kitty.region.len(), 2 * 2 * 3);
+        let first = buffers(&kitty);
+        for run in 2..5 {
+            frame[(3 * 8 + 2) * 4] = run;
+            assert_eq!(kitty.display_frame(8, 8, &frame, true, &damage).unwrap(), Some(1));
+            assert_eq!(buffers(&kitty), first);
+        }
+    }
+
+    #[test]
+    fn damage_is_drawn_over_the_image_on_screen() {
+        let output = Recorder::default();
+        let mut kitty = KittyGraphics::with_output(output.clone());
+        let mut frame = [0x20u8; 8 * 8 * 4];
+        assert_eq!(kitty.display_frame(8, 8, &frame, true, &[]).unwrap(), Some(1));
+        output.0.borrow_mut().clear();
+
+        // Two changed pixels at (2, 3) and (3, 3)
+        frame[(3 * 8 + 2) * 4..(3 * 8 + 4) * 4].copy_from_slice(&[1, 2, 3, 0xff, 4, 5, 6, 0xff]);
+        let damage = [Rectangle::new((2, 3).into(), (2, 1).into())];
+        assert_eq!(kitty.display_frame(8, 8, &frame, true, &damage).unwrap(), Some(1));
+
+        let written = String::from_utf8(output.0.take()).unwrap();
+        let control = "\x1b_Ga=f,r=1,i=1,x=2,y=3,s=2,v=1,f=24,q=2,m=0;";
+        let payload = written.strip_prefix(control).and_then(|s| s.strip_suffix("\x1b\\")).unwrap();
+        assert_eq!(BASE64.decode(payload).unwrap(), [1, 2, 3, 4, 5, 6]);
+
+        // Damage covering everything is a new image
+        frame[0] = 9;
+        let damage = [Rectangle::from_size((8, 8).into())];
+        assert_eq!(kitty.display_frame(8, 8, &frame, true, &damage).unwrap(), Some(2));
+        assert!(String::from_utf8(output.0.take()).unwrap().contains("a=T,"));
+    }
+
+    #[test]
+    fn zlib_payload_decompresses_to_the_frame() {
+        use std::io::Read;
+
+        let rgba: Vec<u8> = (0..64 * 64 * 4).map(|i| (i % 251) as u8).collect();
+        let (payload, flag) = compress(&rgba, Compression::Zlib).unwrap();
+        assert_eq!(flag, ",o=z");
+        assert!(payload.len() < rgba.len());
+
+        let mut decompressed = Vec::new();
+        flate2::read::ZlibDecoder::new(&payload[..]).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, rgba);
+
+        let (payload, flag) = compress(&rgba, Compression::None).unwrap();
+        assert_eq!((&payload[..], flag), (&rgba[..], ""));
+    }
+
+    #[test]
+    fn rgb_packing_drops_alpha() {
+        let mut rgb = vec![9; 12];
+        pack_rgb_into(&[1, 2, 3, 0xff, 4, 5, 6, 0x00], &mut rgb);
+        assert_eq!(rgb, vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn background_report_is_scaled_to_8_bits() {
+        assert_eq!(parse_background_report(b"\x1b]11;rgb:ffff/8080/0000\x1b\\"), Some([0xff, 0x80, 0x00]));
+        assert_eq!(parse_background_report(b"\x1b]11;rgb:f/8/0\x07"), Some([0xff, 0x88, 0x00]));
+        // Still waiting for the terminator
+        assert_eq!(parse_background_report(b"\x1b]11;rgb:ffff/8080/00"), None);
+    }
+
+    #[test]
+    fn cells_are_measured_from_the_startup_size() {
+        let cell = CellSize::measured((1280, 720), 128, 36);
+        assert_eq!(cell, CellSize { width: 10, height: 20, forced: false });
+        assert_eq!(cell.pixels(100, 50), (1000, 1000));
+        // No cells to measure
+        assert_eq!(CellSize::measured((0, 720), 0, 36).width, 1);
+    }
+}
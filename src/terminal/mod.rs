@@ -1,5 +1,27 @@
+#[cfg(feature = "terminal")]
+mod backend;
+mod diagnostics;
+mod events;
+#[cfg(feature = "terminal")]
 mod input;
+#[cfg(feature = "terminal")]
+mod iterm2;
+#[cfg(feature = "terminal")]
 mod kitty;
+mod pacing;
+#[cfg(feature = "terminal")]
+mod sixel;
 
+#[cfg(feature = "terminal")]
+pub use backend::*;
+pub use diagnostics::*;
+pub use events::*;
+#[cfg(feature = "terminal")]
 pub use input::*;
+#[cfg(feature = "terminal")]
+pub use iterm2::*;
+#[cfg(feature = "terminal")]
 pub use kitty::*;
+pub use pacing::*;
+#[cfg(feature = "terminal")]
+pub use sixel::*;
@@ -0,0 +1,85 @@
+//! Backpressure from the terminal's image display acknowledgements
+//!
+//! Every transmitted frame is tracked until the terminal answers for its
+//! image id. While too many frames are outstanding the terminal is "behind"
+//! and new frames are held back, so a slow link delays frames rather than
+//! queueing them up.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub struct FramePacer {
+    /// Image ids sent but not yet acknowledged, oldest first
+    in_flight: VecDeque<(u32, Instant)>,
+    max_in_flight: usize,
+    /// How long to wait for an acknowledgement before giving up on it
+    timeout: Duration,
+}
+
+impl FramePacer {
+    pub fn new(max_in_flight: usize, timeout: Duration) -> Self {
+        Self {
+            in_flight: VecDeque::new(),
+            max_in_flight: max_in_flight.max(1),
+            timeout,
+        }
+    }
+
+    /// Whether the terminal has no room for another frame yet
+    pub fn is_behind(&self) -> bool {
+        self.in_flight.len() >= self.max_in_flight
+    }
+
+    /// Number of frames transmitted but not yet acknowledged
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Record a frame transmitted under `image_id`
+    pub fn sent(&mut self, image_id: u32) {
+        self.in_flight.push_back((image_id, Instant::now()));
+    }
+
+    /// Handle the terminal's response for `image_id`
+    ///
+    /// The terminal answers in order, so older outstanding frames are
+    /// settled as well. Returns whether `image_id` was outstanding.
+    pub fn acknowledge(&mut self, image_id: u32) -> bool {
+        match self.in_flight.iter().position(|&(id, _)| id == image_id) {
+            Some(index) => {
+                self.in_flight.drain(..=index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Give up on frames that went unacknowledged for too long
+    ///
+    /// Returns how many were dropped; a terminal that never acknowledges
+    /// ends up paced by the timeout instead of stalling.
+    pub fn expire(&mut self) -> usize {
+        let before = self.in_flight.len();
+        self.in_flight.retain(|&(_, sent)| sent.elapsed() < self.timeout);
+        before - self.in_flight.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn behind_once_the_queue_is_full() {
+        let mut pacer = FramePacer::new(2, Duration::from_secs(1));
+        pacer.sent(1);
+        assert!(!pacer.is_behind());
+        pacer.sent(2);
+        assert!(pacer.is_behind());
+
+        // Acknowledging a later frame settles the earlier ones too
+        assert!(pacer.acknowledge(2));
+        assert_eq!(pacer.in_flight(), 0);
+        assert!(!pacer.acknowledge(1));
+    }
+}
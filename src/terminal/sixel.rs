@@ -0,0 +1,186 @@
+//! Sixel output, for terminals without Kitty graphics (xterm, mlterm, foot)
+//!
+//! Frames are reduced to a fixed 6x6x6 color cube, which every Sixel
+//! terminal's 256 color registers hold, and sent whole: Sixel has no image
+//! ids to draw damage over. Transparent pixels are drawn over black.
+
+use super::backend::WholeFrameOutput;
+use super::kitty::TextCursor;
+use super::{FrameTimings, RenderBackend};
+use anyhow::Result;
+use smithay::utils::{Buffer, Rectangle};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Levels per channel in the palette
+const LEVELS: usize = 6;
+const PALETTE_SIZE: usize = LEVELS * LEVELS * LEVELS;
+
+pub struct SixelGraphics {
+    output: WholeFrameOutput,
+}
+
+impl SixelGraphics {
+    pub fn new() -> Self {
+        Self { output: WholeFrameOutput::new() }
+    }
+
+    /// Write frames to `output` instead of stdout
+    #[cfg(test)]
+    fn with_output(output: impl Write + 'static) -> Self {
+        Self { output: WholeFrameOutput::with_output(output) }
+    }
+
+    /// Return the terminal's cursor to the pointer's cell after every frame
+    pub fn set_text_cursor(&mut self, cursor: Option<TextCursor>) {
+        self.output.text_cursor = cursor;
+    }
+}
+
+impl Default for SixelGraphics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderBackend for SixelGraphics {
+    #[tracing::instrument(level = "debug", skip(self, rgba_data, _damage))]
+    fn display_frame(
+        &mut self,
+        width: u32,
+        height: u32,
+        rgba_data: &[u8],
+        opaque: bool,
+        _damage: &[Rectangle<i32, Buffer>],
+    ) -> Result<Option<u32>> {
+        self.output.send(width, height, rgba_data, opaque, || {
+            let started = Instant::now();
+            let indices = quantize(rgba_data, opaque);
+            // Display mode: the image goes to the top-left corner and doesn't scroll the screen
+            let mut encoded = b"\x1b[?80h".to_vec();
+            encoded.extend(encode(&indices, width as usize, height as usize));
+            Ok((encoded, FrameTimings { scale: Duration::ZERO, encode: started.elapsed() }))
+        })
+    }
+
+    fn last_timings(&self) -> FrameTimings {
+        self.output.last_timings
+    }
+
+    fn leave_terminal(&mut self) -> Result<()> {
+        // Sixel images scroll with the text again
+        self.output.clear("\x1b[?80l")
+    }
+}
+
+/// Palette index of every pixel, alpha blended over black unless `opaque`
+fn quantize(rgba: &[u8], opaque: bool) -> Vec<u8> {
+    let level = |channel: u8, alpha: u8| {
+        let channel = if opaque { channel as usize } else { channel as usize * alpha as usize / 255 };
+        (channel * (LEVELS - 1) + 127) / 255
+    };
+    rgba.chunks_exact(4)
+        .map(|pixel| {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|channel| level(channel, pixel[3]));
+            (r * LEVELS * LEVELS + g * LEVELS + b) as u8
+        })
+        .collect()
+}
+
+/// A Sixel image of palette `indices`, `width` pixels a row
+///
+/// Each band of six rows is sent as one line of sixels per color it uses,
+/// with runs of the same sixel compressed.
+fn encode(indices: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    // Aspect ratio 1:1, and the image size up front
+    write!(out, "\x1bP0;1;0q\"1;1;{};{}", width, height).unwrap();
+    for index in 0..PALETTE_SIZE {
+        let percent = |level: usize| level * 100 / (LEVELS - 1);
+        let (r, g, b) = (index / (LEVELS * LEVELS), index / LEVELS % LEVELS, index % LEVELS);
+        write!(out, "#{};2;{};{};{}", index, percent(r), percent(g), percent(b)).unwrap();
+    }
+
+    // Sixels of the current band, per color
+    let mut sixels = vec![0u8; PALETTE_SIZE * width];
+    let mut used = [false; PALETTE_SIZE];
+    for band in (0..height).step_by(6) {
+        used.fill(false);
+        for row in band..(band + 6).min(height) {
+            let bit = 1 << (row - band);
+            for (x, &index) in indices[row * width..(row + 1) * width].iter().enumerate() {
+                sixels[index as usize * width + x] |= bit;
+                used[index as usize] = true;
+            }
+        }
+
+        let mut first = true;
+        for index in (0..PALETTE_SIZE).filter(|&index| used[index]) {
+            let line = &mut sixels[index * width..(index + 1) * width];
+            if !first {
+                // Back to the start of the band for the next color
+                out.push(b'$');
+            }
+            first = false;
+            write!(out, "#{}", index).unwrap();
+            let drawn = line.iter().rposition(|&bits| bits != 0).map_or(0, |last| last + 1);
+            write_runs(&mut out, &line[..drawn]);
+            line.fill(0);
+        }
+        // Next band
+        out.push(b'-');
+    }
+    out.extend_from_slice(b"\x1b\\");
+    out
+}
+
+/// Append `bits` as sixel characters, repeats of four or more as `!<count><sixel>`
+fn write_runs(out: &mut Vec<u8>, bits: &[u8]) {
+    let mut rest = bits;
+    while let Some(&first) = rest.first() {
+        let run = rest.iter().take_while(|&&bits| bits == first).count();
+        let sixel = b'?' + first;
+        if run >= 4 {
+            write!(out, "!{}", run).unwrap();
+            out.push(sixel);
+        } else {
+            out.extend(std::iter::repeat_n(sixel, run));
+        }
+        rest = &rest[run..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn colors_snap_to_the_cube() {
+        let rgba = [0xff, 0, 0, 0xff, 0x33, 0x66, 0x99, 0xff, 0xff, 0xff, 0xff, 0];
+        // Red, an exact cube color, and transparent white over black
+        assert_eq!(quantize(&rgba, false), vec![5 * 36, 36 + 2 * 6 + 3, 0]);
+        assert_eq!(quantize(&rgba[8..], true), vec![215]);
+    }
+
+    #[test]
+    fn bands_are_sent_per_color_with_runs() {
+        // 5x7: a red first column over black, in two bands
+        let mut indices = vec![0u8; 5 * 7];
+        for row in 0..7 {
+            indices[row * 5] = 180;
+        }
+        let encoded = String::from_utf8(encode(&indices, 5, 7)).unwrap();
+        let body = &encoded[encoded.find("#215;2;100;100;100").unwrap() + 18..];
+        // Sixel bits are rows 0-5 of the band: all six is '~', just the first row '@'
+        assert_eq!(body, "#0?!4~$#180~-#0?!4@$#180@-\x1b\\");
+    }
+
+    #[test]
+    fn unchanged_frames_are_not_resent() {
+        let mut sixel = SixelGraphics::with_output(io::sink());
+        let frame = [0x80u8; 4 * 4];
+        assert_eq!(sixel.display_frame(2, 2, &frame, true, &[]).unwrap(), Some(1));
+        assert_eq!(sixel.display_frame(2, 2, &frame, true, &[]).unwrap(), None);
+    }
+}
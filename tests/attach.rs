@@ -0,0 +1,49 @@
+//! termui as a process: --attach shows another termui's windows
+
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+
+#[test]
+fn attaching_needs_a_compositor_listening() {
+    let runtime_dir = std::env::temp_dir().join(format!("termui-attach-missing-{}", std::process::id()));
+    std::fs::create_dir_all(&runtime_dir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_termui"))
+        .args(["--headless", "--attach", "termui-nobody"])
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("No compositor to attach to"), "{}", stderr);
+    let _ = std::fs::remove_dir_all(&runtime_dir);
+}
+
+#[test]
+fn the_viewer_shows_frames_until_the_compositor_exits() {
+    let runtime_dir = std::env::temp_dir().join(format!("termui-attach-{}", std::process::id()));
+    std::fs::create_dir_all(&runtime_dir).unwrap();
+    // A window for two seconds, then the session ends with the command
+    let mut compositor = Command::new(env!("CARGO_BIN_EXE_termui"))
+        .args(["--headless", "--print-socket", "--exit-on", "child-exit", "--"])
+        .args(["sh", "-c", "\"$0\" & sleep 2; kill $!", env!("CARGO_BIN_EXE_color-test")])
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(compositor.stderr.take().unwrap()).lines();
+    let socket = stderr.find_map(|line| line.unwrap().strip_prefix("WAYLAND_DISPLAY=").map(str::to_string)).unwrap();
+    // Keep the compositor's logging from filling the pipe
+    std::thread::spawn(move || stderr.for_each(drop));
+
+    let viewer = Command::new(env!("CARGO_BIN_EXE_termui"))
+        .args(["--headless", "--attach", &socket])
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .output()
+        .unwrap();
+    assert!(compositor.wait().unwrap().success());
+    assert!(viewer.status.success(), "the viewer exited with {}", viewer.status);
+    // Frames go out as Kitty graphics
+    assert!(String::from_utf8_lossy(&viewer.stdout).contains("\x1b_G"));
+    let _ = std::fs::remove_dir_all(&runtime_dir);
+}
@@ -0,0 +1,42 @@
+//! termui as a process: several clients share one session
+
+use std::process::Command;
+
+#[test]
+fn clients_the_command_starts_share_its_session() {
+    let runtime_dir = std::env::temp_dir().join(format!("termui-clients-{}", std::process::id()));
+    std::fs::create_dir_all(&runtime_dir).unwrap();
+    // The command starts a second client next to its own and waits for
+    // both; the session lasts as long as it does
+    let output = Command::new(env!("CARGO_BIN_EXE_termui"))
+        .args(["--headless", "--exit-on", "child-exit", "--", "sh", "-c", "\"$0\" & \"$0\"; wait"])
+        .arg(env!("CARGO_BIN_EXE_test-client"))
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "termui exited with {}", output.status);
+
+    // The clients print next to whatever graphics go to the terminal
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("✓ xdg_wm_base found!").count(), 2, "{}", stdout);
+    let _ = std::fs::remove_dir_all(&runtime_dir);
+}
+
+#[test]
+fn the_socket_can_be_printed_for_more_clients() {
+    let runtime_dir = std::env::temp_dir().join(format!("termui-print-socket-{}", std::process::id()));
+    std::fs::create_dir_all(&runtime_dir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_termui"))
+        .args(["--headless", "--print-socket", "--", "sh", "-c", "echo \"client on $WAYLAND_DISPLAY\""])
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "termui exited with {}", output.status);
+
+    // The command gets the socket printed
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let socket = stderr.lines().find_map(|line| line.strip_prefix("WAYLAND_DISPLAY=")).unwrap();
+    assert!(stderr.contains(&format!("XDG_RUNTIME_DIR={}", runtime_dir.display())), "{}", stderr);
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&format!("client on {}", socket)));
+    let _ = std::fs::remove_dir_all(&runtime_dir);
+}
@@ -0,0 +1,29 @@
+//! termui as a process: --record saves the frames for --replay
+
+use std::process::Command;
+
+#[test]
+fn recorded_frames_are_numbered_pngs_with_timestamps() {
+    let runtime_dir = std::env::temp_dir().join(format!("termui-record-{}", std::process::id()));
+    let recording = runtime_dir.join("recording");
+    std::fs::create_dir_all(&runtime_dir).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_termui"))
+        .args(["--headless", "--exit-on", "child-exit", "--record"])
+        .arg(&recording)
+        .args(["--", "sh", "-c", "\"$0\" & sleep 1; kill $!", env!("CARGO_BIN_EXE_color-test")])
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "termui exited with {}", status);
+
+    // The loading frame, then the window's
+    let timestamps = std::fs::read_to_string(recording.join("timestamps")).unwrap();
+    let names: Vec<&str> = timestamps.lines().map(|line| line.split(' ').next().unwrap()).collect();
+    assert!(names.len() >= 2, "{}", timestamps);
+    for (number, name) in (1..).zip(&names) {
+        assert_eq!(*name, format!("{}.png", number));
+        image::open(recording.join(name)).unwrap();
+    }
+    let _ = std::fs::remove_dir_all(&runtime_dir);
+}
@@ -0,0 +1,35 @@
+//! termui as a process: termination signals end it through the usual cleanup
+
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+#[test]
+fn sigterm_ends_the_session_cleanly() {
+    let runtime_dir = std::env::temp_dir().join(format!("termui-signals-{}", std::process::id()));
+    std::fs::create_dir_all(&runtime_dir).unwrap();
+    let mut termui = Command::new(env!("CARGO_BIN_EXE_termui"))
+        .args(["--headless", "--no-spawn", "--keep-alive"])
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // Up and running once it tells where to connect; the pipe stays open
+    // for whatever else it prints
+    let mut stderr = BufReader::new(termui.stderr.take().unwrap()).lines();
+    assert!(stderr.any(|line| line.unwrap().starts_with("WAYLAND_DISPLAY=")));
+
+    unsafe { libc::kill(termui.id() as i32, libc::SIGTERM) };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        if let Some(status) = termui.try_wait().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "termui is still running after SIGTERM");
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    assert!(status.success(), "termui exited with {}", status);
+    let _ = std::fs::remove_dir_all(&runtime_dir);
+}